@@ -0,0 +1,295 @@
+use std::io::{Error, ErrorKind};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use futures::{stream, StreamExt};
+use log::info;
+use serde::Serialize;
+use serde_json::json;
+use tokio::io;
+
+use crate::transport::api::{OpResponse, QueryResponse};
+
+/// The usage of the `bench` subcommand, printed when its options are invalid.
+pub const BENCH_USAGE: &str = "Usage: distribuito bench [--target <ip:port>] [--api-key <key>] \
+[--table <name>] [--rows <n>] [--batch-size <n>] [--queries <n>] [--concurrency <n>] \
+[--categories <n>] [--keep]";
+
+/// The options of the `bench` subcommand, supplied as `--name value` arguments.
+#[derive(Debug, Clone)]
+pub struct BenchOptions {
+    /// The address of the instance which receives the workload, usually the master.
+    target: String,
+    api_key: Option<String>,
+    table: String,
+    rows: u64,
+    batch_size: u64,
+    queries: u64,
+    concurrency: usize,
+    categories: u64,
+    /// Whether to keep the synthetic table after the run.
+    keep: bool,
+}
+
+impl Default for BenchOptions {
+    fn default() -> Self {
+        Self {
+            target: "127.0.0.1:7001".to_string(),
+            api_key: None,
+            table: "bench".to_string(),
+            rows: 100_000,
+            batch_size: 1_000,
+            queries: 100,
+            concurrency: 4,
+            categories: 10,
+            keep: false,
+        }
+    }
+}
+
+impl BenchOptions {
+    pub fn parse(args: &[String]) -> io::Result<Self> {
+        let mut options = Self::default();
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            if arg == "--keep" {
+                options.keep = true;
+                continue;
+            }
+
+            let value = args.next().ok_or_else(|| {
+                Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("Missing value for {}", arg),
+                )
+            })?;
+            match arg.as_str() {
+                "--target" => options.target = value.clone(),
+                "--api-key" => options.api_key = Some(value.clone()),
+                "--table" => options.table = value.clone(),
+                "--rows" => options.rows = parse_number(arg, value)?,
+                "--batch-size" => options.batch_size = parse_number(arg, value)?,
+                "--queries" => options.queries = parse_number(arg, value)?,
+                "--concurrency" => options.concurrency = parse_number(arg, value)?,
+                "--categories" => options.categories = parse_number(arg, value)?,
+                _ => {
+                    return Err(Error::new(
+                        ErrorKind::InvalidInput,
+                        format!("Unknown bench option {}", arg),
+                    ))
+                }
+            }
+        }
+
+        if options.batch_size == 0 || options.concurrency == 0 || options.categories == 0 {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "The batch size, concurrency and categories must be greater than 0",
+            ));
+        }
+
+        Ok(options)
+    }
+}
+
+fn parse_number<T: FromStr>(arg: &str, value: &str) -> io::Result<T> {
+    value.parse().map_err(|_| {
+        Error::new(
+            ErrorKind::InvalidInput,
+            format!("The value of {} must be a number, got {}", arg, value),
+        )
+    })
+}
+
+/// A client of the api which measures the latency of each request.
+struct BenchClient {
+    client: reqwest::Client,
+    target: String,
+    api_key: Option<String>,
+}
+
+impl BenchClient {
+    async fn post<I: Serialize>(&self, path: &str, body: &I) -> io::Result<serde_json::Value> {
+        let mut request = self
+            .client
+            .post(format!("http://{}/{}", self.target, path))
+            .json(body);
+        if let Some(api_key) = &self.api_key {
+            request = request.bearer_auth(api_key);
+        }
+
+        let response = request
+            .send()
+            .await
+            .map_err(|e| Error::other(format!("Error while sending the request: {}", e)))?;
+
+        response
+            .json()
+            .await
+            .map_err(|e| Error::other(format!("Error while deserializing the response: {}", e)))
+    }
+
+    /// Sends a ddl or write request, which succeeds only if the api replies with a success.
+    async fn execute<I: Serialize>(&self, path: &str, body: &I) -> io::Result<Duration> {
+        let start = Instant::now();
+        let response = self.post(path, body).await?;
+        let elapsed = start.elapsed();
+
+        let response: OpResponse = serde_json::from_value(response)?;
+        response
+            .into_result()
+            .map_err(|e| Error::other(format!("The request to /{} failed: {}", path, e)))?;
+
+        Ok(elapsed)
+    }
+
+    /// Sends a query, returning its latency and the number of returned rows.
+    async fn query<I: Serialize>(&self, body: &I) -> io::Result<(Duration, usize)> {
+        let start = Instant::now();
+        let response = self.post("query", body).await?;
+        let elapsed = start.elapsed();
+
+        let response: QueryResponse = serde_json::from_value(response)?;
+        let (_, rows) = response.into_table_data()?;
+
+        Ok((elapsed, rows.len()))
+    }
+}
+
+/// Reports the throughput and the latency percentiles of a phase of the workload.
+fn report(phase: &str, elapsed: Duration, units: u64, unit: &str, mut latencies: Vec<Duration>) {
+    latencies.sort();
+    let percentile = |p: f64| -> f64 {
+        if latencies.is_empty() {
+            return 0.0;
+        }
+
+        let index = ((latencies.len() as f64 * p).ceil() as usize).clamp(1, latencies.len()) - 1;
+        latencies[index].as_secs_f64() * 1000.0
+    };
+
+    println!(
+        "{}: {} {} in {:.2}s ({:.1} {}/s), {} requests, latency p50 {:.2}ms p90 {:.2}ms p99 {:.2}ms max {:.2}ms",
+        phase,
+        units,
+        unit,
+        elapsed.as_secs_f64(),
+        units as f64 / elapsed.as_secs_f64().max(f64::EPSILON),
+        unit,
+        latencies.len(),
+        percentile(0.5),
+        percentile(0.9),
+        percentile(0.99),
+        percentile(1.0),
+    );
+}
+
+/// Builds a batch of synthetic rows, deterministic in the index of the batch so that runs are
+/// comparable.
+fn build_batch(options: &BenchOptions, batch: u64) -> serde_json::Value {
+    let start = batch * options.batch_size;
+    let end = (start + options.batch_size).min(options.rows);
+
+    let values: Vec<_> = (start..end)
+        .map(|i| {
+            json!([
+                i.wrapping_mul(7919) % 1000,
+                (i % 100) as f64 / 10.0,
+                format!("category_{}", i % options.categories)
+            ])
+        })
+        .collect();
+
+    json!({
+        "insert": ["value", "weight", "category"],
+        "into": options.table,
+        "values": values,
+    })
+}
+
+/// Runs the insert and query workloads against the target and prints their results.
+pub async fn run_bench(options: BenchOptions) -> io::Result<()> {
+    let client = BenchClient {
+        client: reqwest::Client::new(),
+        target: options.target.clone(),
+        api_key: options.api_key.clone(),
+    };
+
+    info!(
+        "Running the benchmark against {} on table {}",
+        options.target, options.table
+    );
+
+    // The table might be left over by a previous run, so we don't care if dropping it fails.
+    let drop_table = json!({ "name": options.table });
+    let _ = client.execute("drop_table", &drop_table).await;
+    client
+        .execute(
+            "create_table",
+            &json!({
+                "name": options.table,
+                "columns": [
+                    { "name": "value", "ty": "integer" },
+                    { "name": "weight", "ty": "float" },
+                    { "name": "category", "ty": "string" },
+                ],
+            }),
+        )
+        .await?;
+
+    let batches = options.rows.div_ceil(options.batch_size);
+    let start = Instant::now();
+    let latencies: Vec<io::Result<Duration>> = stream::iter(0..batches)
+        .map(|batch| {
+            let client = &client;
+            let body = build_batch(&options, batch);
+            async move { client.execute("insert", &body).await }
+        })
+        .buffer_unordered(options.concurrency)
+        .collect()
+        .await;
+    let latencies = latencies.into_iter().collect::<io::Result<Vec<_>>>()?;
+    report("insert", start.elapsed(), options.rows, "rows", latencies);
+
+    // The queries alternate between a full scan and an aggregation, to measure both paths.
+    let workload = [
+        json!({
+            "select": ["value", "weight", "category"],
+            "from": options.table,
+        }),
+        json!({
+            "select": ["category", "count(value)", "sum(value)", "avg(weight)"],
+            "from": options.table,
+            "group_by": ["category"],
+        }),
+    ];
+
+    let start = Instant::now();
+    let results: Vec<io::Result<(Duration, usize)>> = stream::iter(0..options.queries)
+        .map(|query| {
+            let client = &client;
+            let body = &workload[query as usize % workload.len()];
+            async move { client.query(body).await }
+        })
+        .buffer_unordered(options.concurrency)
+        .collect()
+        .await;
+    let results = results.into_iter().collect::<io::Result<Vec<_>>>()?;
+    let returned_rows = results.iter().map(|(_, rows)| *rows as u64).sum::<u64>();
+    let latencies = results.into_iter().map(|(latency, _)| latency).collect();
+    report(
+        "query",
+        start.elapsed(),
+        options.queries,
+        "queries",
+        latencies,
+    );
+    println!("query: {} rows returned", returned_rows);
+
+    if !options.keep {
+        client.execute("drop_table", &drop_table).await?;
+    }
+
+    Ok(())
+}
@@ -1,11 +1,11 @@
+use std::collections::{HashMap, HashSet};
+use std::io::{Error, ErrorKind};
 use std::path::Path;
 
-use serde::Deserialize;
-use tokio::fs::{create_dir_all, read_to_string};
+use serde::{Deserialize, Serialize};
+use tokio::fs::{create_dir_all, read_to_string, File};
 use tokio::io;
 
-use crate::io::file::create_file;
-
 #[derive(Debug, Deserialize)]
 #[serde(rename_all(deserialize = "lowercase"))]
 pub enum InstanceRole {
@@ -25,6 +25,394 @@ impl<'a> From<&'a InstanceRole> for &'a str {
 #[derive(Debug, Deserialize)]
 pub struct Instance {
     pub ip_port: String,
+    /// The addresses of the instances holding a copy of the data of this instance, which receive
+    /// the same writes and can serve its reads.
+    #[serde(default)]
+    pub replicas: Vec<String>,
+    /// The address on which the instance serves its admin endpoints, if it listens for them
+    /// separately. The replicas are always reached on their own address, so they must serve the
+    /// admin endpoints there.
+    #[serde(default)]
+    pub admin_ip_port: Option<String>,
+    /// The id of the instance, which must match the `node_id` in its config and is shared by its
+    /// replicas.
+    #[serde(default)]
+    pub node_id: u16,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all(deserialize = "lowercase"))]
+pub enum ScheduleMode {
+    #[default]
+    Append,
+    Overwrite,
+}
+
+/// A saved query which is periodically executed and whose results are written into a table.
+#[derive(Debug, Deserialize)]
+pub struct Schedule {
+    /// The cron expression, including the seconds field, which determines when the query runs.
+    pub cron: String,
+    pub query: String,
+    #[serde(default)]
+    pub params: HashMap<String, serde_json::Value>,
+    pub into: String,
+    #[serde(default)]
+    pub mode: ScheduleMode,
+}
+
+fn default_partition_interval_secs() -> u64 {
+    60 * 60 * 24
+}
+
+pub fn default_retention_interval_secs() -> u64 {
+    60 * 60
+}
+
+/// The retention policy applied to all tables of the database, which also drops the rows older than
+/// the TTL of the tables setting one.
+#[derive(Debug, Deserialize)]
+pub struct Retention {
+    /// The age in seconds after which the rows of the tables without a TTL are dropped, which are
+    /// kept forever if not set.
+    #[serde(default)]
+    pub max_age_secs: Option<u64>,
+    /// How often in seconds the expired rows are dropped.
+    #[serde(default = "default_retention_interval_secs")]
+    pub interval_secs: u64,
+}
+
+fn default_merging_interval_secs() -> u64 {
+    60 * 60
+}
+
+/// The merging of the small partitions of all tables, so that tables receiving few rows don't end
+/// up with many tiny files to open on each scan.
+#[derive(Debug, Deserialize)]
+pub struct Merging {
+    /// The number of rows below which a partition is merged with the adjacent ones, until the
+    /// merged partition has at least as many rows.
+    pub min_partition_rows: u64,
+    /// How often in seconds the small partitions are merged.
+    #[serde(default = "default_merging_interval_secs")]
+    pub interval_secs: u64,
+}
+
+fn default_compaction_interval_secs() -> u64 {
+    60 * 60
+}
+
+fn default_min_deleted_rows() -> u64 {
+    1
+}
+
+/// The compaction of the partitions of all tables with deleted rows, whose records are dropped
+/// from their files instead of being skipped by every scan.
+#[derive(Debug, Deserialize)]
+pub struct Compaction {
+    /// The number of deleted rows from which a partition is compacted.
+    #[serde(default = "default_min_deleted_rows")]
+    pub min_deleted_rows: u64,
+    /// The fraction of the rows of a partition which must be deleted for it to be compacted.
+    #[serde(default)]
+    pub min_deleted_fraction: f64,
+    /// How often in seconds the partitions are compacted.
+    #[serde(default = "default_compaction_interval_secs")]
+    pub interval_secs: u64,
+}
+
+fn default_memtable_max_rows() -> usize {
+    10_000
+}
+
+fn default_memtable_flush_interval_ms() -> u64 {
+    1000
+}
+
+/// The buffering of the inserts in memory, where they are queryable right away, before being
+/// written to the files of their table in batches, so that small inserts don't each pay for
+/// appending to all the files.
+///
+/// Only the inserts into the tables with no durability are buffered, since the rows which aren't
+/// flushed yet are lost if the instance crashes. The inserts into the tables with a unique column
+/// aren't buffered either, since their values are checked against the files.
+#[derive(Debug, Deserialize)]
+pub struct Memtable {
+    /// The number of rows buffered for a table from which they are flushed right away.
+    #[serde(default = "default_memtable_max_rows")]
+    pub max_rows: usize,
+    /// How often in milliseconds the buffered rows are flushed.
+    #[serde(default = "default_memtable_flush_interval_ms")]
+    pub flush_interval_ms: u64,
+}
+
+fn default_otlp_interval_secs() -> u64 {
+    10
+}
+
+/// The export of the traces of the requests and of the metrics to an OpenTelemetry collector,
+/// with the OTLP/HTTP protocol encoded in JSON.
+#[derive(Debug, Deserialize)]
+pub struct Otlp {
+    /// The url of the collector, e.g. `http://localhost:4318`, to which the traces are sent on
+    /// `/v1/traces` and the metrics on `/v1/metrics`.
+    pub endpoint: String,
+    /// How often in seconds the traces and metrics are exported.
+    #[serde(default = "default_otlp_interval_secs")]
+    pub interval_secs: u64,
+}
+
+fn default_cold_storage_interval_secs() -> u64 {
+    60 * 60
+}
+
+/// The object store to which old partitions are moved, in order to keep long histories without
+/// using local disk.
+#[derive(Debug, Deserialize)]
+pub struct ColdStorage {
+    /// The url of the object store, either `file:///path` or `http(s)://host/bucket`.
+    pub url: String,
+    /// The age in seconds after which partitions are moved to the object store.
+    pub after_secs: u64,
+    /// How often in seconds the old partitions are moved.
+    #[serde(default = "default_cold_storage_interval_secs")]
+    pub interval_secs: u64,
+}
+
+/// The use of direct I/O for scans, which bypasses the page cache so that big scans don't evict
+/// the data of the tables which are queried often.
+#[derive(Debug, Deserialize)]
+pub struct DirectIo {
+    /// The minimum number of rows of a table for its scans to use direct I/O.
+    #[serde(default)]
+    pub min_scan_rows: u64,
+}
+
+fn default_query_timeout_ms() -> u64 {
+    10_000
+}
+
+/// The limit of the queries running at the same time on each table.
+#[derive(Debug, Deserialize)]
+pub struct QueryConcurrency {
+    pub max_queries_per_table: usize,
+    /// The time in milliseconds a query waits for the others to finish before failing.
+    #[serde(default = "default_query_timeout_ms")]
+    pub timeout_ms: u64,
+}
+
+fn default_max_queued_queries() -> usize {
+    1_000
+}
+
+/// The limit of the queries running at the same time on the instance, whatever their tables. The
+/// queries exceeding it wait in a queue, from which the queries with a higher priority are admitted
+/// first.
+#[derive(Debug, Deserialize)]
+pub struct AdmissionControl {
+    pub max_concurrent_queries: usize,
+    /// The number of queries which can wait, after which the following queries fail immediately.
+    #[serde(default = "default_max_queued_queries")]
+    pub max_queued_queries: usize,
+    /// The time in milliseconds a query waits in the queue before failing.
+    #[serde(default = "default_query_timeout_ms")]
+    pub timeout_ms: u64,
+}
+
+fn default_hedging_after_ms() -> u64 {
+    100
+}
+
+/// The hedging of the queries sent to shards with replicas, which sends the query to a replica too
+/// if the shard is slow to reply and takes the first successful response.
+#[derive(Debug, Deserialize)]
+pub struct Hedging {
+    /// The time in milliseconds after which the query is sent to a replica.
+    #[serde(default = "default_hedging_after_ms")]
+    pub after_ms: u64,
+}
+
+/// The limit of the size of the query results, which fails the queries returning more instead of
+/// building responses too large for the memory of the instance.
+#[derive(Debug, Deserialize)]
+pub struct ResultLimit {
+    /// The maximum number of rows, or groups for aggregates, of a result.
+    #[serde(default)]
+    pub max_rows: Option<u64>,
+    /// The maximum size in bytes of the values of a result, estimated from their JSON encoding.
+    #[serde(default)]
+    pub max_bytes: Option<u64>,
+}
+
+fn default_stats_sync_interval_secs() -> u64 {
+    5
+}
+
+/// The syncing of the table stats to disk, which otherwise stay in the OS cache and can be lost on
+/// a crash.
+#[derive(Debug, Deserialize)]
+pub struct StatsSync {
+    /// How often in seconds the stats of all tables are synced.
+    #[serde(default = "default_stats_sync_interval_secs")]
+    pub interval_secs: u64,
+}
+
+impl Default for StatsSync {
+    fn default() -> Self {
+        Self {
+            interval_secs: default_stats_sync_interval_secs(),
+        }
+    }
+}
+
+/// The source of the current time of the instance.
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all(deserialize = "lowercase"))]
+pub enum ClockSource {
+    /// The wall clock of the machine.
+    #[default]
+    System,
+    /// The wall clock when the instance started, advanced by the monotonic clock, which never
+    /// jumps when the wall clock is adjusted.
+    Monotonic,
+}
+
+impl<'a> From<&'a ClockSource> for &'a str {
+    fn from(value: &'a ClockSource) -> Self {
+        match value {
+            ClockSource::System => "system",
+            ClockSource::Monotonic => "monotonic",
+        }
+    }
+}
+
+fn default_max_clock_skew_ms() -> u64 {
+    1_000
+}
+
+/// The clock of the instance, which gives the timestamps of the rows.
+#[derive(Debug, Deserialize)]
+pub struct ClockConfig {
+    #[serde(default)]
+    pub source: ClockSource,
+    /// The skew in milliseconds between the clocks of the master and of a shard above which the
+    /// status of the master reports a warning.
+    #[serde(default = "default_max_clock_skew_ms")]
+    pub max_skew_ms: u64,
+    /// Whether the master assigns the timestamps of the rows it forwards to the shards, so that
+    /// the rows of all shards are ordered by the same clock.
+    #[serde(default)]
+    pub master_timestamps: bool,
+}
+
+impl Default for ClockConfig {
+    fn default() -> Self {
+        Self {
+            source: ClockSource::default(),
+            max_skew_ms: default_max_clock_skew_ms(),
+            master_timestamps: false,
+        }
+    }
+}
+
+/// The writes accepted by a slave, which should only come from its master to keep the data of the
+/// cluster consistent.
+#[derive(Debug, Deserialize)]
+pub struct SlaveWrites {
+    /// The token which the master sends along its requests, the same on all instances of the
+    /// cluster.
+    pub master_token: String,
+    /// The address of the master, to which the writes of external clients are redirected.
+    #[serde(default)]
+    pub master_ip_port: Option<String>,
+}
+
+/// The addresses on which the instance listens, so that the admin endpoints can be firewalled to
+/// an internal network.
+#[derive(Debug, Deserialize)]
+pub struct Listen {
+    /// The address of the api, e.g. `0.0.0.0:7001` to listen on all interfaces, which defaults to
+    /// the address of the instance.
+    #[serde(default)]
+    pub api_ip_port: Option<String>,
+    /// The address of the admin endpoints (users, key rotation, usage, stats and metrics), which
+    /// are served along the api if missing.
+    #[serde(default)]
+    pub admin_ip_port: Option<String>,
+}
+
+/// The sizes of the runtimes on which the queries and the writes run, separately from each other
+/// and from the other requests, so that heavy scans can't slow down the ingestion and vice versa.
+#[derive(Debug, Deserialize)]
+pub struct Pools {
+    /// The number of threads running the queries.
+    pub read_threads: usize,
+    /// The number of threads running the inserts and the other writes.
+    pub write_threads: usize,
+}
+
+/// A key used to encrypt files, identified by its version.
+#[derive(Debug, Deserialize)]
+pub struct EncryptionKey {
+    /// The version of the key, which is stored in the header of each file encrypted with it.
+    #[serde(default)]
+    pub version: u32,
+    /// The key as 64 hex characters.
+    pub key: Option<String>,
+    /// A shell command printing the key as 64 hex characters, used to fetch the key from a key
+    /// management service instead of storing it in the config.
+    pub key_command: Option<String>,
+}
+
+/// The keys used to encrypt the files of all tables at rest.
+#[derive(Debug, Deserialize)]
+pub struct EncryptionConfig {
+    /// The key used to encrypt all the files written from now on.
+    #[serde(flatten)]
+    pub active_key: EncryptionKey,
+    /// The keys which were rotated out, kept to read the files which weren't re-encrypted yet.
+    #[serde(default)]
+    pub previous_keys: Vec<EncryptionKey>,
+}
+
+/// When the files written by an insert are synced to disk, which trades the latency of the inserts
+/// for the rows kept after a crash of the machine.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Durability {
+    /// The files are never synced, leaving it to the OS, so the last inserts might be lost.
+    #[default]
+    None,
+    /// The files are synced together once the whole batch is written.
+    Batch,
+    /// Each file is synced as soon as it's written, so that the stats are never on disk before the
+    /// rows they count.
+    Always,
+}
+
+/// The backend storing the files of the database.
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageBackend {
+    /// Files are stored on the local disk, under the database path.
+    #[default]
+    Filesystem,
+    /// Files are kept in memory and lost when the instance stops, which is useful for tests and
+    /// demos.
+    Memory,
+    /// Files are stored on the local disk like the filesystem storage, but read and written via
+    /// io_uring, which requires Linux and the `io-uring` feature.
+    Uring,
+}
+
+impl<'a> From<&'a StorageBackend> for &'a str {
+    fn from(value: &'a StorageBackend) -> Self {
+        match value {
+            StorageBackend::Filesystem => "filesystem",
+            StorageBackend::Memory => "memory",
+            StorageBackend::Uring => "uring",
+        }
+    }
 }
 
 #[derive(Debug, Deserialize)]
@@ -34,19 +422,194 @@ pub struct Config {
     pub database_name: String,
     pub database_path: String,
     pub instances: Vec<Instance>,
+    /// The id of the instance, stored in the upper bits of the ids of the rows it writes so that
+    /// they are unique in the cluster. It must be different on each instance, except for replicas
+    /// which use the id of the instance they copy, and must not change once rows are written.
+    ///
+    /// It can only be left to 0 by a master without instances, since its rows are the only ones.
+    #[serde(default)]
+    pub node_id: u16,
+    /// The size in seconds of the time window covered by each partition of a table.
+    #[serde(default = "default_partition_interval_secs")]
+    pub partition_interval_secs: u64,
+    #[serde(default)]
+    pub schedules: Vec<Schedule>,
+    #[serde(default)]
+    pub retention: Option<Retention>,
+    #[serde(default)]
+    pub merging: Option<Merging>,
+    #[serde(default)]
+    pub compaction: Option<Compaction>,
+    #[serde(default)]
+    pub memtable: Option<Memtable>,
+    #[serde(default)]
+    pub cold_storage: Option<ColdStorage>,
+    #[serde(default)]
+    pub encryption: Option<EncryptionConfig>,
+    /// The key of the admin user, which enables the authentication of all requests via bearer
+    /// keys. It must be the same on all the instances of the cluster.
+    #[serde(default)]
+    pub admin_key: Option<String>,
+    #[serde(default)]
+    pub storage: StorageBackend,
+    #[serde(default)]
+    pub direct_io: Option<DirectIo>,
+    #[serde(default)]
+    pub slave_writes: Option<SlaveWrites>,
+    #[serde(default)]
+    pub hedging: Option<Hedging>,
+    #[serde(default)]
+    pub query_concurrency: Option<QueryConcurrency>,
+    #[serde(default)]
+    pub admission_control: Option<AdmissionControl>,
+    #[serde(default)]
+    pub stats_sync: StatsSync,
+    #[serde(default)]
+    pub result_limit: Option<ResultLimit>,
+    #[serde(default)]
+    pub listen: Option<Listen>,
+    #[serde(default)]
+    pub pools: Option<Pools>,
+    #[serde(default)]
+    pub otlp: Option<Otlp>,
+    #[serde(default)]
+    pub clock: ClockConfig,
+    /// The durability of the inserts into the tables which don't set their own.
+    #[serde(default)]
+    pub durability: Durability,
 }
 
 impl Config {
     pub async fn from_file<P: AsRef<Path>>(path: P) -> io::Result<Self> {
         // We create all the necessary dirs and the config file if not existing.
+        // The config is always on the local disk, since it's read before the storage is known.
+        let config_path = path.as_ref().join("config.json");
         create_dir_all(&path).await?;
-        create_file("config.json", &path).await?;
+        File::options()
+            .create(true)
+            .append(true)
+            .open(&config_path)
+            .await?;
 
         // We load the config as string and parse it into the object.
-        let config_path = path.as_ref().join("config.json");
         let config_data = read_to_string(&config_path).await?;
         let config: Config = serde_json::from_str(&config_data)?;
+        config.validate()?;
 
         Ok(config)
     }
+
+    /// Returns the address on which the api is served.
+    pub fn api_listen_ip_port(&self) -> &str {
+        self.listen
+            .as_ref()
+            .and_then(|l| l.api_ip_port.as_deref())
+            .unwrap_or(&self.database_ip_port)
+    }
+
+    /// Returns the address on which the admin endpoints are served, if different from the api.
+    pub fn admin_listen_ip_port(&self) -> Option<&str> {
+        self.listen
+            .as_ref()
+            .and_then(|l| l.admin_ip_port.as_deref())
+    }
+
+    /// Checks that the instances and their replicas don't include this instance or the same
+    /// instance twice, which would make the requests be forwarded more than once to the same
+    /// instance, and that the admin endpoints don't listen on the address of the api.
+    fn validate(&self) -> io::Result<()> {
+        let mut ip_ports = HashSet::new();
+        let all_ip_ports = self
+            .instances
+            .iter()
+            .flat_map(|i| std::iter::once(&i.ip_port).chain(i.replicas.iter()));
+        for ip_port in all_ip_ports {
+            if *ip_port == self.database_ip_port {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("The instance {} can't be one of its own instances", ip_port),
+                ));
+            }
+
+            if !ip_ports.insert(ip_port.as_str()) {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("The instance {} is listed more than once", ip_port),
+                ));
+            }
+        }
+
+        self.validate_node_ids()?;
+
+        if self.admin_listen_ip_port() == Some(self.api_listen_ip_port()) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "The admin endpoints must listen on a different address than the api",
+            ));
+        }
+
+        if self
+            .admission_control
+            .as_ref()
+            .is_some_and(|a| a.max_concurrent_queries == 0)
+        {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "The admission control must allow at least one concurrent query",
+            ));
+        }
+
+        if let Some(pools) = &self.pools {
+            if pools.read_threads == 0 || pools.write_threads == 0 {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    "The read and write pools must have at least one thread",
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Checks that the instances of a cluster have distinct ids, which aren't 0, so that their rows
+    /// never get the same ids.
+    fn validate_node_ids(&self) -> io::Result<()> {
+        let is_standalone =
+            matches!(self.instance_role, InstanceRole::Master) && self.instances.is_empty();
+        if is_standalone {
+            return Ok(());
+        }
+
+        if self.node_id == 0 {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "The node id of an instance of a cluster must be set to a value other than 0",
+            ));
+        }
+
+        let mut node_ids = HashSet::from([self.node_id]);
+        for instance in self.instances.iter() {
+            if instance.node_id == 0 {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!(
+                        "The node id of the instance {} must be set to a value other than 0",
+                        instance.ip_port
+                    ),
+                ));
+            }
+
+            if !node_ids.insert(instance.node_id) {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!(
+                        "The node id {} of the instance {} is already used by another instance",
+                        instance.node_id, instance.ip_port
+                    ),
+                ));
+            }
+        }
+
+        Ok(())
+    }
 }
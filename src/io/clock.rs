@@ -0,0 +1,71 @@
+use std::sync::OnceLock;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use log::info;
+
+use crate::config::{ClockConfig, ClockSource};
+
+/// The source of the current time of the instance, which gives the timestamps of the rows and
+/// the cutoffs of the background tasks.
+pub trait Clock: Send + Sync {
+    /// Returns the time in milliseconds since the epoch.
+    fn now_millis(&self) -> u64;
+
+    /// Returns the time in seconds since the epoch.
+    fn now_secs(&self) -> u64 {
+        self.now_millis() / 1000
+    }
+}
+
+/// The wall clock of the machine.
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now_millis(&self) -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64
+    }
+}
+
+/// A clock starting from the wall clock when the instance started and then advancing with the
+/// monotonic clock, so that it never jumps when the wall clock is adjusted, e.g. by NTP.
+pub struct MonotonicClock {
+    start_millis: u64,
+    start: Instant,
+}
+
+impl MonotonicClock {
+    pub fn new() -> Self {
+        Self {
+            start_millis: SystemClock.now_millis(),
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Clock for MonotonicClock {
+    fn now_millis(&self) -> u64 {
+        self.start_millis + self.start.elapsed().as_millis() as u64
+    }
+}
+
+static CLOCK: OnceLock<Box<dyn Clock>> = OnceLock::new();
+
+/// Sets the clock used by the instance, which is the wall clock unless configured otherwise.
+pub fn init_clock(config: &ClockConfig) {
+    let clock: Box<dyn Clock> = match config.source {
+        ClockSource::System => Box::new(SystemClock),
+        ClockSource::Monotonic => Box::new(MonotonicClock::new()),
+    };
+    info!(
+        "Using the {} clock",
+        <&ClockSource as Into<&str>>::into(&config.source)
+    );
+    let _ = CLOCK.set(clock);
+}
+
+pub fn clock() -> &'static dyn Clock {
+    CLOCK.get_or_init(|| Box::new(SystemClock)).as_ref()
+}
@@ -0,0 +1,471 @@
+use std::cmp::{max, min};
+use std::fmt::{Debug, Formatter};
+use std::io::{Error, ErrorKind, SeekFrom};
+
+use tokio::io;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt, BufStream};
+
+use crate::io::encryption::{
+    encryption, Cipher, Encryption, ENCRYPTED_BLOCK_OVERHEAD, ENCRYPTED_BLOCK_SIZE,
+    ENCRYPTION_HEADER_SIZE, ENCRYPTION_MAGIC,
+};
+use crate::io::storage::StorageFile;
+
+/// The magic number at the start of the data of every data file, followed by its format version.
+const FORMAT_MAGIC: &[u8; 4] = b"DSTF";
+/// The size of the header of the data of a data file, made of the magic number and the format
+/// version.
+const FORMAT_HEADER_SIZE: u64 = 8;
+/// The version of the layout of the data files written by this version of the database, which is
+/// increased by every change of the layout of any file.
+///
+/// The files written before the versions existed have no header, and are considered version 0.
+pub const FORMAT_VERSION: u32 = 1;
+
+/// A file storing the data of a table.
+///
+/// Depending on the configuration, the file is either stored as is or encrypted, which is
+/// transparent to the users of the file since all positions and lengths refer to the plaintext.
+///
+/// The data starts with a header with the version of its format, which is also transparent to the
+/// users of the file. Like for encryption, the header of an empty file is written along its first
+/// data, so that the files which are never written stay empty.
+pub struct DataFile {
+    inner: Inner,
+    version: u32,
+    has_header: bool,
+}
+
+impl Debug for DataFile {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DataFile")
+            .field("encrypted", &matches!(self.inner, Inner::Encrypted(_)))
+            .field("version", &self.version)
+            .finish()
+    }
+}
+
+enum Inner {
+    Plain(BufStream<Box<dyn StorageFile>>),
+    Encrypted(Box<EncryptedFile>),
+}
+
+impl Inner {
+    async fn read_exact(&mut self, buffer: &mut [u8]) -> io::Result<()> {
+        match self {
+            Inner::Plain(file) => file.read_exact(buffer).await.map(|_| ()),
+            Inner::Encrypted(file) => file.read_exact(buffer).await,
+        }
+    }
+
+    async fn write_all(&mut self, buffer: &[u8]) -> io::Result<()> {
+        match self {
+            Inner::Plain(file) => file.write_all(buffer).await,
+            Inner::Encrypted(file) => file.write_all(buffer).await,
+        }
+    }
+
+    async fn seek(&mut self, position: SeekFrom) -> io::Result<u64> {
+        match self {
+            Inner::Plain(file) => file.seek(position).await,
+            Inner::Encrypted(file) => file.seek(position),
+        }
+    }
+
+    async fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Inner::Plain(file) => file.flush().await,
+            Inner::Encrypted(file) => file.flush().await,
+        }
+    }
+
+    async fn len(&mut self) -> io::Result<u64> {
+        match self {
+            Inner::Plain(file) => {
+                file.flush().await?;
+                file.get_ref().len().await
+            }
+            Inner::Encrypted(file) => Ok(file.length),
+        }
+    }
+
+    async fn truncate(&mut self) -> io::Result<()> {
+        match self {
+            Inner::Plain(file) => {
+                file.flush().await?;
+                file.get_ref().set_len(0).await?;
+                file.seek(SeekFrom::Start(0)).await.map(|_| ())
+            }
+            Inner::Encrypted(file) => file.truncate().await,
+        }
+    }
+}
+
+impl DataFile {
+    /// Opens the file, which must have the format version of this version of the database.
+    pub async fn from_file(file: Box<dyn StorageFile>) -> io::Result<Self> {
+        let file = Self::from_file_with_any_version(file).await?;
+        if file.version > FORMAT_VERSION {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "The file has format version {}, which is newer than the version {} supported \
+                    by this instance",
+                    file.version, FORMAT_VERSION
+                ),
+            ));
+        }
+        if file.version < FORMAT_VERSION {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "The file has format version {}, which must be upgraded to version {}",
+                    file.version, FORMAT_VERSION
+                ),
+            ));
+        }
+
+        Ok(file)
+    }
+
+    /// Opens the file whatever its format version, so that it can be upgraded.
+    pub async fn from_file_with_any_version(mut file: Box<dyn StorageFile>) -> io::Result<Self> {
+        let length = file.len().await?;
+
+        // Files are encrypted only if they start with the magic number, so that files written
+        // before enabling encryption can still be read. New files are encrypted if encryption is
+        // enabled.
+        let is_encrypted = if length >= ENCRYPTION_HEADER_SIZE {
+            let mut magic = [0u8; ENCRYPTION_MAGIC.len()];
+            file.read_exact(&mut magic).await?;
+            file.seek(SeekFrom::Start(0)).await?;
+
+            magic == *ENCRYPTION_MAGIC
+        } else {
+            length == 0 && encryption().is_some()
+        };
+
+        let inner = if is_encrypted {
+            let Some(encryption) = encryption() else {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    "The file is encrypted but no encryption key is configured",
+                ));
+            };
+
+            Inner::Encrypted(Box::new(
+                EncryptedFile::open(file, encryption, length).await?,
+            ))
+        } else {
+            Inner::Plain(BufStream::new(file))
+        };
+
+        let mut file = Self {
+            inner,
+            version: FORMAT_VERSION,
+            has_header: false,
+        };
+        let data_length = file.inner.len().await?;
+        if data_length > 0 {
+            let mut header = [0u8; FORMAT_HEADER_SIZE as usize];
+            if data_length >= FORMAT_HEADER_SIZE {
+                file.inner.read_exact(&mut header).await?;
+            }
+            file.has_header = header[..FORMAT_MAGIC.len()] == *FORMAT_MAGIC;
+            file.version = match file.has_header {
+                true => u32::from_le_bytes(header[FORMAT_MAGIC.len()..].try_into().unwrap()),
+                false => 0,
+            };
+            file.inner.seek(SeekFrom::Start(file.header_size())).await?;
+        }
+
+        Ok(file)
+    }
+
+    /// Returns the version of the format of the data of the file.
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    pub async fn read_exact(&mut self, buffer: &mut [u8]) -> io::Result<()> {
+        self.inner.read_exact(buffer).await
+    }
+
+    pub async fn write_all(&mut self, buffer: &[u8]) -> io::Result<()> {
+        if !self.has_header && self.version == FORMAT_VERSION {
+            let position = self.inner.seek(SeekFrom::Current(0)).await?;
+            let mut header = FORMAT_MAGIC.to_vec();
+            header.extend_from_slice(&u32::to_le_bytes(FORMAT_VERSION));
+            self.inner.seek(SeekFrom::Start(0)).await?;
+            self.inner.write_all(&header).await?;
+            self.inner
+                .seek(SeekFrom::Start(FORMAT_HEADER_SIZE + position))
+                .await?;
+            self.has_header = true;
+        }
+
+        self.inner.write_all(buffer).await
+    }
+
+    pub async fn seek(&mut self, position: SeekFrom) -> io::Result<u64> {
+        let header_size = self.header_size();
+        let position = match position {
+            SeekFrom::Start(offset) => SeekFrom::Start(offset + header_size),
+            position => position,
+        };
+
+        self.inner
+            .seek(position)
+            .await?
+            .checked_sub(header_size)
+            .ok_or_else(|| {
+                Error::new(
+                    ErrorKind::InvalidInput,
+                    "Invalid seek to a position before the data of the file",
+                )
+            })
+    }
+
+    pub async fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush().await
+    }
+
+    /// Returns the length of the data in the file.
+    pub async fn len(&mut self) -> io::Result<u64> {
+        Ok(self.inner.len().await?.saturating_sub(self.header_size()))
+    }
+
+    /// Removes all the data from the file.
+    pub async fn truncate(&mut self) -> io::Result<()> {
+        self.inner.truncate().await?;
+        // The empty file gets the header of the current version with its next data.
+        self.version = FORMAT_VERSION;
+        self.has_header = false;
+
+        Ok(())
+    }
+
+    /// Returns the version of the key the file is encrypted with, if encrypted.
+    pub fn key_version(&self) -> Option<u32> {
+        match &self.inner {
+            Inner::Plain(_) => None,
+            Inner::Encrypted(file) => Some(file.cipher.key_version),
+        }
+    }
+
+    pub async fn sync_all(&mut self) -> io::Result<()> {
+        self.flush().await?;
+        match &mut self.inner {
+            Inner::Plain(file) => file.get_ref().sync_all().await,
+            Inner::Encrypted(file) => file.file.sync_all().await,
+        }
+    }
+
+    pub async fn sync_data(&mut self) -> io::Result<()> {
+        self.flush().await?;
+        match &mut self.inner {
+            Inner::Plain(file) => file.get_ref().sync_data().await,
+            Inner::Encrypted(file) => file.file.sync_data().await,
+        }
+    }
+
+    fn header_size(&self) -> u64 {
+        match self.has_header {
+            true => FORMAT_HEADER_SIZE,
+            false => 0,
+        }
+    }
+}
+
+struct Block {
+    index: u64,
+    data: Vec<u8>,
+    dirty: bool,
+}
+
+/// A file encrypted in blocks of [`ENCRYPTED_BLOCK_SIZE`] bytes, each with its own nonce and
+/// authentication tag, so that any position can be read without decrypting the whole file.
+///
+/// The layout of the file is as follows:
+/// - [`ENCRYPTION_HEADER_SIZE`] bytes for the magic number and the key version
+/// - A sequence of blocks, all full except for the last one, made of the nonce, the ciphertext and
+///   the tag
+///
+/// Writes are buffered in the block they fall into, which is encrypted and written when a
+/// different block is accessed or the file is flushed.
+struct EncryptedFile {
+    file: Box<dyn StorageFile>,
+    cipher: &'static Cipher,
+    has_header: bool,
+    position: u64,
+    length: u64,
+    block: Option<Block>,
+}
+
+const PLAIN_BLOCK_SIZE: u64 = ENCRYPTED_BLOCK_SIZE as u64;
+const FULL_BLOCK_SIZE: u64 = (ENCRYPTED_BLOCK_SIZE + ENCRYPTED_BLOCK_OVERHEAD) as u64;
+
+impl EncryptedFile {
+    async fn open(
+        mut file: Box<dyn StorageFile>,
+        encryption: &'static Encryption,
+        physical_length: u64,
+    ) -> io::Result<Self> {
+        // New files are encrypted with the active key, whereas existing files keep using the key
+        // they were encrypted with until they are re-encrypted.
+        let has_header = physical_length > 0;
+        let cipher = if has_header {
+            let mut header = [0u8; ENCRYPTION_HEADER_SIZE as usize];
+            file.read_exact(&mut header).await?;
+            encryption.cipher_for_header(&header)?
+        } else {
+            encryption.active_cipher()
+        };
+
+        Ok(Self {
+            file,
+            cipher,
+            has_header,
+            position: 0,
+            length: Self::plaintext_length(physical_length),
+            block: None,
+        })
+    }
+
+    fn plaintext_length(physical_length: u64) -> u64 {
+        let Some(blocks_length) = physical_length.checked_sub(ENCRYPTION_HEADER_SIZE) else {
+            return 0;
+        };
+
+        let full_blocks = blocks_length / FULL_BLOCK_SIZE;
+        let last_block = blocks_length % FULL_BLOCK_SIZE;
+
+        full_blocks * PLAIN_BLOCK_SIZE + last_block.saturating_sub(ENCRYPTED_BLOCK_OVERHEAD as u64)
+    }
+
+    async fn load_block(&mut self, index: u64) -> io::Result<&mut Block> {
+        if self.block.as_ref().is_none_or(|b| b.index != index) {
+            self.flush_block().await?;
+
+            let start = index * PLAIN_BLOCK_SIZE;
+            let data = if start >= self.length {
+                vec![]
+            } else {
+                let plain_size = min(PLAIN_BLOCK_SIZE, self.length - start) as usize;
+                let mut block = vec![0u8; plain_size + ENCRYPTED_BLOCK_OVERHEAD];
+                self.file
+                    .seek(SeekFrom::Start(
+                        ENCRYPTION_HEADER_SIZE + index * FULL_BLOCK_SIZE,
+                    ))
+                    .await?;
+                self.file.read_exact(&mut block).await?;
+
+                self.cipher.decrypt_block(&block)?
+            };
+
+            self.block = Some(Block {
+                index,
+                data,
+                dirty: false,
+            });
+        }
+
+        Ok(self.block.as_mut().unwrap())
+    }
+
+    async fn flush_block(&mut self) -> io::Result<()> {
+        let Some(block) = self.block.as_mut().filter(|b| b.dirty) else {
+            return Ok(());
+        };
+
+        if !self.has_header {
+            self.file.seek(SeekFrom::Start(0)).await?;
+            self.file.write_all(&self.cipher.header()).await?;
+            self.has_header = true;
+        }
+
+        let encrypted_block = self.cipher.encrypt_block(&block.data)?;
+        self.file
+            .seek(SeekFrom::Start(
+                ENCRYPTION_HEADER_SIZE + block.index * FULL_BLOCK_SIZE,
+            ))
+            .await?;
+        self.file.write_all(&encrypted_block).await?;
+        block.dirty = false;
+
+        Ok(())
+    }
+
+    async fn read_exact(&mut self, buffer: &mut [u8]) -> io::Result<()> {
+        if self.position + buffer.len() as u64 > self.length {
+            return Err(Error::new(ErrorKind::UnexpectedEof, "early eof"));
+        }
+
+        let mut read = 0;
+        while read < buffer.len() {
+            let offset = (self.position % PLAIN_BLOCK_SIZE) as usize;
+            let block = self.load_block(self.position / PLAIN_BLOCK_SIZE).await?;
+
+            let size = min(buffer.len() - read, block.data.len() - offset);
+            buffer[read..read + size].copy_from_slice(&block.data[offset..offset + size]);
+
+            read += size;
+            self.position += size as u64;
+        }
+
+        Ok(())
+    }
+
+    async fn write_all(&mut self, buffer: &[u8]) -> io::Result<()> {
+        let mut written = 0;
+        while written < buffer.len() {
+            let offset = (self.position % PLAIN_BLOCK_SIZE) as usize;
+            let block = self.load_block(self.position / PLAIN_BLOCK_SIZE).await?;
+
+            let size = min(buffer.len() - written, ENCRYPTED_BLOCK_SIZE - offset);
+            if block.data.len() < offset + size {
+                block.data.resize(offset + size, 0);
+            }
+            block.data[offset..offset + size].copy_from_slice(&buffer[written..written + size]);
+            block.dirty = true;
+
+            written += size;
+            self.position += size as u64;
+            self.length = max(self.length, self.position);
+        }
+
+        Ok(())
+    }
+
+    fn seek(&mut self, position: SeekFrom) -> io::Result<u64> {
+        let position = match position {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => self.length.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+        };
+
+        self.position = position.ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                "Invalid seek to a negative or overflowing position",
+            )
+        })?;
+
+        Ok(self.position)
+    }
+
+    async fn flush(&mut self) -> io::Result<()> {
+        self.flush_block().await?;
+        self.file.flush().await
+    }
+
+    async fn truncate(&mut self) -> io::Result<()> {
+        self.file.set_len(0).await?;
+        self.has_header = false;
+        self.position = 0;
+        self.length = 0;
+        self.block = None;
+
+        Ok(())
+    }
+}
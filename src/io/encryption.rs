@@ -0,0 +1,186 @@
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind};
+use std::iter;
+use std::process::Command;
+use std::sync::OnceLock;
+
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use log::info;
+use tokio::io;
+
+use crate::config::{EncryptionConfig, EncryptionKey};
+
+/// The magic number at the start of every encrypted file.
+pub const ENCRYPTION_MAGIC: &[u8; 4] = b"DSTE";
+/// The size of the header of an encrypted file, made of the magic number and the key version.
+pub const ENCRYPTION_HEADER_SIZE: u64 = 8;
+/// The size of the plaintext of each encrypted block.
+pub const ENCRYPTED_BLOCK_SIZE: usize = 4096;
+/// The overhead of each encrypted block, made of the nonce and the authentication tag.
+pub const ENCRYPTED_BLOCK_OVERHEAD: usize = NONCE_SIZE + TAG_SIZE;
+
+const NONCE_SIZE: usize = 12;
+const TAG_SIZE: usize = 16;
+const KEY_SIZE: usize = 32;
+
+static ENCRYPTION: OnceLock<Encryption> = OnceLock::new();
+
+/// Enables the encryption of all the files written from now on.
+pub fn init_encryption(config: &EncryptionConfig) -> io::Result<()> {
+    let encryption = Encryption::new(config)?;
+    info!(
+        "Encryption at rest enabled with key version {}",
+        encryption.active_key_version
+    );
+    let _ = ENCRYPTION.set(encryption);
+
+    Ok(())
+}
+
+/// Returns the encryption which must be used for files, if enabled.
+pub fn encryption() -> Option<&'static Encryption> {
+    ENCRYPTION.get()
+}
+
+fn load_key(config: &EncryptionKey) -> io::Result<[u8; KEY_SIZE]> {
+    let hex_key = match (&config.key, &config.key_command) {
+        (Some(key), _) => key.clone(),
+        (None, Some(key_command)) => {
+            // The key command is a hook to fetch the key from a key management service, which
+            // must print the key to the standard output.
+            let output = Command::new("sh").arg("-c").arg(key_command).output()?;
+            if !output.status.success() {
+                return Err(Error::other(format!(
+                    "The key command failed with status {}",
+                    output.status
+                )));
+            }
+
+            String::from_utf8_lossy(&output.stdout).to_string()
+        }
+        (None, None) => {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "Either a key or a key command must be supplied for key version {}",
+                    config.version
+                ),
+            ))
+        }
+    };
+
+    let key = hex::decode(hex_key.trim())
+        .map_err(|e| Error::new(ErrorKind::InvalidInput, format!("Invalid key: {}", e)))?;
+
+    key.try_into().map_err(|_| {
+        Error::new(
+            ErrorKind::InvalidInput,
+            format!("The key must be {} bytes long", KEY_SIZE),
+        )
+    })
+}
+
+/// Returns the version of the key used to encrypt a file, given its header.
+fn key_version(header: &[u8]) -> u32 {
+    u32::from_le_bytes(header[4..8].try_into().unwrap())
+}
+
+/// All the keys known to the database, of which only the active one is used to encrypt new files.
+pub struct Encryption {
+    ciphers: HashMap<u32, Cipher>,
+    pub active_key_version: u32,
+}
+
+impl Encryption {
+    fn new(config: &EncryptionConfig) -> io::Result<Self> {
+        let mut ciphers = HashMap::new();
+        for key in iter::once(&config.active_key).chain(config.previous_keys.iter()) {
+            let cipher = Cipher::new(&load_key(key)?, key.version);
+            if ciphers.insert(key.version, cipher).is_some() {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("The key version {} is supplied more than once", key.version),
+                ));
+            }
+        }
+
+        Ok(Self {
+            ciphers,
+            active_key_version: config.active_key.version,
+        })
+    }
+
+    /// Returns the cipher with which new files are encrypted.
+    pub fn active_cipher(&self) -> &Cipher {
+        &self.ciphers[&self.active_key_version]
+    }
+
+    /// Returns the cipher with which a file was encrypted, given its header.
+    pub fn cipher_for_header(&self, header: &[u8]) -> io::Result<&Cipher> {
+        let key_version = key_version(header);
+        self.ciphers.get(&key_version).ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "The file was encrypted with key version {} which is not configured",
+                    key_version
+                ),
+            )
+        })
+    }
+}
+
+pub struct Cipher {
+    cipher: Aes256Gcm,
+    pub key_version: u32,
+}
+
+impl Cipher {
+    fn new(key: &[u8; KEY_SIZE], key_version: u32) -> Self {
+        Self {
+            cipher: Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key)),
+            key_version,
+        }
+    }
+
+    pub fn header(&self) -> [u8; ENCRYPTION_HEADER_SIZE as usize] {
+        let mut header = [0u8; ENCRYPTION_HEADER_SIZE as usize];
+        header[..4].copy_from_slice(ENCRYPTION_MAGIC);
+        header[4..].copy_from_slice(&u32::to_le_bytes(self.key_version));
+
+        header
+    }
+    /// Encrypts a block, returning the nonce followed by the ciphertext and its tag.
+    pub fn encrypt_block(&self, plaintext: &[u8]) -> io::Result<Vec<u8>> {
+        // Every write of a block uses a fresh nonce, since the last block of a file is rewritten
+        // every time data is appended to it.
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = self
+            .cipher
+            .encrypt(&nonce, plaintext)
+            .map_err(|_| Error::other("Error while encrypting block"))?;
+
+        let mut block = Vec::with_capacity(NONCE_SIZE + ciphertext.len());
+        block.extend_from_slice(&nonce);
+        block.extend_from_slice(&ciphertext);
+
+        Ok(block)
+    }
+
+    pub fn decrypt_block(&self, block: &[u8]) -> io::Result<Vec<u8>> {
+        if block.len() < ENCRYPTED_BLOCK_OVERHEAD {
+            return Err(Error::new(ErrorKind::InvalidData, "The block is truncated"));
+        }
+
+        let (nonce, ciphertext) = block.split_at(NONCE_SIZE);
+        self.cipher
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| {
+                Error::new(
+                    ErrorKind::InvalidData,
+                    "Error while decrypting block, the file is corrupted or the key is wrong",
+                )
+            })
+    }
+}
@@ -1,12 +1,17 @@
-use std::io::ErrorKind;
+use serde::{Deserialize, Serialize};
+use std::io::SeekFrom;
+use std::io::{Error, ErrorKind};
 use std::path::Path;
-use tokio::fs::File;
 use tokio::io;
-use tokio::io::{AsyncReadExt, AsyncWriteExt, BufStream};
+
+use crate::io::data_file::{DataFile, FORMAT_VERSION};
+use crate::io::storage::storage;
+
+const COPY_BUFFER_SIZE: u64 = 64 * 1024;
 
 pub async fn create_file<P: AsRef<Path>>(file_name: &str, path: P) -> io::Result<()> {
     let file_path = path.as_ref().join(file_name);
-    if let Err(error) = File::create_new(file_path.clone()).await {
+    if let Err(error) = storage().create_new(&file_path).await {
         if error.kind() == ErrorKind::AlreadyExists {
             return Ok(());
         }
@@ -17,30 +22,116 @@ pub async fn create_file<P: AsRef<Path>>(file_name: &str, path: P) -> io::Result
     Ok(())
 }
 
-pub async fn create_and_open_file<P: AsRef<Path>>(file_name: &str, path: P) -> io::Result<File> {
+pub async fn create_and_open_file<P: AsRef<Path>>(
+    file_name: &str,
+    path: P,
+) -> io::Result<DataFile> {
     let file_path = path.as_ref().join(file_name);
-    let Ok(file) = File::create_new(file_path.clone()).await else {
-        return File::options().read(true).write(true).open(file_path).await;
+    let file = match storage().create_new(&file_path).await {
+        Ok(file) => file,
+        Err(_) => storage().open(&file_path, true).await?,
     };
 
+    DataFile::from_file(file).await
+}
+
+pub async fn open_append_file<P: AsRef<Path>>(file_name: &str, path: P) -> io::Result<DataFile> {
+    // The file is not opened in append mode, since encrypted files need to rewrite their last
+    // block when appending.
+    let file_path = path.as_ref().join(file_name);
+    let file = storage().open(&file_path, true).await?;
+
+    let mut file = DataFile::from_file(file).await?;
+    file.seek(SeekFrom::End(0)).await?;
+
     Ok(file)
 }
 
-pub async fn open_append_file<P: AsRef<Path>>(file_name: &str, path: P) -> io::Result<File> {
+pub async fn open_read_file<P: AsRef<Path>>(file_name: &str, path: P) -> io::Result<DataFile> {
     let file_path = path.as_ref().join(file_name);
-    File::options().append(true).open(file_path).await
+    let file = storage().open(&file_path, false).await?;
+
+    DataFile::from_file(file).await
 }
 
-pub async fn open_read_file<P: AsRef<Path>>(file_name: &str, path: P) -> io::Result<File> {
+/// Opens a file for reading while bypassing the OS page cache, so that big scans don't evict the
+/// data of other tables.
+pub async fn open_direct_read_file<P: AsRef<Path>>(
+    file_name: &str,
+    path: P,
+) -> io::Result<DataFile> {
     let file_path = path.as_ref().join(file_name);
-    File::options().read(true).open(file_path).await
+    let file = storage().open_direct(&file_path).await?;
+
+    DataFile::from_file(file).await
 }
 
-pub async fn read_or(
-    file: &mut BufStream<File>,
-    buffer: &mut [u8],
-    default: &[u8],
+/// Returns the length of the data in the file, which differs from the size on disk if the file is
+/// encrypted.
+pub async fn data_file_len<P: AsRef<Path>>(file_name: &str, path: P) -> io::Result<u64> {
+    open_read_file(file_name, path).await?.len().await
+}
+
+/// Rewrites the data of a file starting at `offset` into a new file, which then atomically
+/// replaces the old one.
+///
+/// The new file is encrypted with the active key if encryption is enabled.
+pub async fn rewrite_data_file(
+    mut file: DataFile,
+    file_path: &Path,
+    offset: u64,
 ) -> io::Result<()> {
+    let tmp_file_path = file_path.with_extension("dsto.tmp");
+    let mut tmp_file = DataFile::from_file(storage().create(&tmp_file_path).await?).await?;
+
+    copy_data_file(&mut file, &mut tmp_file, offset).await?;
+    tmp_file.sync_all().await?;
+
+    storage().rename(&tmp_file_path, file_path).await
+}
+
+/// Upgrades the data file to the current format version, returning whether it was rewritten.
+///
+/// Each version after the first is upgraded from the previous one in turn, so that a file written
+/// by any older version of the database can be read.
+pub async fn upgrade_data_file(file_path: &Path) -> io::Result<bool> {
+    let file =
+        DataFile::from_file_with_any_version(storage().open(file_path, false).await?).await?;
+    match file.version() {
+        FORMAT_VERSION => Ok(false),
+        // The data of the files written before the versions is the same, without the header.
+        0 => {
+            rewrite_data_file(file, file_path, 0).await?;
+            Ok(true)
+        }
+        version => Err(Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "The file {} has format version {}, which can't be upgraded to version {}",
+                file_path.display(),
+                version,
+                FORMAT_VERSION
+            ),
+        )),
+    }
+}
+
+/// Appends the data of `file` starting at `offset` to the current position of `to`.
+pub async fn copy_data_file(file: &mut DataFile, to: &mut DataFile, offset: u64) -> io::Result<()> {
+    let mut remaining = file.len().await?.saturating_sub(offset);
+    file.seek(SeekFrom::Start(offset)).await?;
+    let mut buffer = vec![0u8; COPY_BUFFER_SIZE as usize];
+    while remaining > 0 {
+        let size = remaining.min(COPY_BUFFER_SIZE) as usize;
+        file.read_exact(&mut buffer[..size]).await?;
+        to.write_all(&buffer[..size]).await?;
+        remaining -= size as u64;
+    }
+
+    Ok(())
+}
+
+pub async fn read_or(file: &mut DataFile, buffer: &mut [u8], default: &[u8]) -> io::Result<()> {
     if let Err(error) = file.read_exact(buffer).await {
         if error.kind() == ErrorKind::UnexpectedEof {
             return file.write_all(default).await;
@@ -51,3 +142,34 @@ pub async fn read_or(
 
     Ok(())
 }
+
+pub async fn read_json_or_default<T, P>(file_name: &str, path: P) -> io::Result<T>
+where
+    T: for<'a> Deserialize<'a> + Default,
+    P: AsRef<Path>,
+{
+    let file_path = path.as_ref().join(file_name);
+    match storage().read(&file_path).await {
+        Ok(data) if data.trim_ascii().is_empty() => Ok(T::default()),
+        Ok(data) => Ok(serde_json::from_slice(&data)?),
+        Err(error) if error.kind() == ErrorKind::NotFound => Ok(T::default()),
+        Err(error) => Err(error),
+    }
+}
+
+pub async fn write_json<T: Serialize, P: AsRef<Path>>(
+    file_name: &str,
+    path: P,
+    value: &T,
+) -> io::Result<()> {
+    storage().create_dir_all(path.as_ref()).await?;
+
+    // We write to a temporary file which is then renamed, so that a crash never leaves a partially
+    // written file behind.
+    let file_path = path.as_ref().join(file_name);
+    let tmp_file_path = path.as_ref().join(format!("{}.tmp", file_name));
+    storage()
+        .write(&tmp_file_path, serde_json::to_vec_pretty(value)?)
+        .await?;
+    storage().rename(&tmp_file_path, &file_path).await
+}
@@ -1 +1,6 @@
+pub mod clock;
+pub mod data_file;
+pub mod encryption;
 pub mod file;
+pub mod object_store;
+pub mod storage;
@@ -0,0 +1,90 @@
+use std::io::Error;
+use std::path::PathBuf;
+
+use reqwest::Client;
+use tokio::fs::{create_dir_all, read, remove_file, write};
+use tokio::io;
+
+/// An object store used to hold the data which is not kept on the local disk.
+///
+/// The store is configured via a url, which can either be:
+/// - `file:///some/path` for a directory, for example backed by a network filesystem.
+/// - `http(s)://host/bucket` for an http store which supports `PUT`, `GET` and `DELETE` of objects.
+#[derive(Debug, Clone)]
+pub enum ObjectStore {
+    Filesystem { path: PathBuf },
+    Http { client: Client, base_url: String },
+}
+
+impl ObjectStore {
+    pub fn from_url(url: &str) -> Self {
+        match url.strip_prefix("file://") {
+            Some(path) => ObjectStore::Filesystem {
+                path: PathBuf::from(path),
+            },
+            None => ObjectStore::Http {
+                client: Client::new(),
+                base_url: url.trim_end_matches('/').to_string(),
+            },
+        }
+    }
+
+    pub async fn put(&self, key: &str, data: Vec<u8>) -> io::Result<()> {
+        match self {
+            ObjectStore::Filesystem { path } => {
+                let object_path = path.join(key);
+                if let Some(parent) = object_path.parent() {
+                    create_dir_all(parent).await?;
+                }
+
+                write(object_path, data).await
+            }
+            ObjectStore::Http { client, base_url } => {
+                let request = client.put(format!("{}/{}", base_url, key)).body(data);
+                send(request).await.map(|_| ())
+            }
+        }
+    }
+
+    pub async fn get(&self, key: &str) -> io::Result<Vec<u8>> {
+        match self {
+            ObjectStore::Filesystem { path } => read(path.join(key)).await,
+            ObjectStore::Http { client, base_url } => {
+                let request = client.get(format!("{}/{}", base_url, key));
+                send(request).await
+            }
+        }
+    }
+
+    pub async fn delete(&self, key: &str) -> io::Result<()> {
+        match self {
+            ObjectStore::Filesystem { path } => remove_file(path.join(key)).await,
+            ObjectStore::Http { client, base_url } => {
+                let request = client.delete(format!("{}/{}", base_url, key));
+                send(request).await.map(|_| ())
+            }
+        }
+    }
+}
+
+async fn send(request: reqwest::RequestBuilder) -> io::Result<Vec<u8>> {
+    let response = request
+        .send()
+        .await
+        .and_then(|r| r.error_for_status())
+        .map_err(|e| {
+            Error::other(format!(
+                "Error while sending the request to the object store: {}",
+                e
+            ))
+        })?;
+
+    let body = response.bytes().await.map_err(|e| {
+        Error::other(format!(
+            "Error while reading the response of the object store: {}",
+            e
+        ))
+    })?;
+
+    Ok(body.to_vec())
+}
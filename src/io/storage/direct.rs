@@ -0,0 +1,215 @@
+use std::fs::File;
+use std::future::Future;
+use std::io::{Error, ErrorKind, SeekFrom};
+use std::os::unix::fs::{FileExt, OpenOptionsExt};
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use futures::future::{ready, BoxFuture};
+use futures::FutureExt;
+use tokio::io;
+use tokio::io::{AsyncRead, AsyncSeek, AsyncWrite, ReadBuf};
+use tokio::task::{spawn_blocking, JoinHandle};
+
+use crate::io::storage::StorageFile;
+
+/// The alignment of the buffers, offsets and lengths of direct reads, which is the logical block
+/// size of most devices.
+const DIRECT_IO_ALIGNMENT: usize = 4096;
+/// The size of each direct read, big enough to amortize the lack of kernel read-ahead.
+const DIRECT_IO_CHUNK_SIZE: usize = 1024 * 1024;
+
+/// A buffer whose data starts at an address aligned to [`DIRECT_IO_ALIGNMENT`].
+struct AlignedBuffer {
+    data: Vec<u8>,
+    offset: usize,
+}
+
+impl AlignedBuffer {
+    fn new() -> Self {
+        let data = vec![0u8; DIRECT_IO_CHUNK_SIZE + DIRECT_IO_ALIGNMENT];
+        let offset = data.as_ptr().align_offset(DIRECT_IO_ALIGNMENT);
+
+        Self { data, offset }
+    }
+
+    fn as_slice(&self) -> &[u8] {
+        &self.data[self.offset..self.offset + DIRECT_IO_CHUNK_SIZE]
+    }
+
+    fn as_mut_slice(&mut self) -> &mut [u8] {
+        &mut self.data[self.offset..self.offset + DIRECT_IO_CHUNK_SIZE]
+    }
+}
+
+/// A chunk of the file read from `start`, of which only the first `length` bytes are valid.
+struct Chunk {
+    buffer: AlignedBuffer,
+    start: u64,
+    length: usize,
+}
+
+type ChunkRead = JoinHandle<io::Result<Chunk>>;
+
+/// A read only file opened with `O_DIRECT`, so that its reads bypass the page cache.
+///
+/// Reads at any position are served from aligned chunks, since direct reads must be aligned.
+pub struct DirectFile {
+    file: Arc<File>,
+    position: u64,
+    chunk: Option<Chunk>,
+    pending: Option<ChunkRead>,
+}
+
+impl DirectFile {
+    /// Opens the file with direct I/O, or returns `None` if the filesystem doesn't support it.
+    pub async fn open(path: &Path) -> io::Result<Option<Self>> {
+        let path = path.to_path_buf();
+        let file = spawn_blocking(move || {
+            File::options()
+                .read(true)
+                .custom_flags(libc::O_DIRECT)
+                .open(path)
+        })
+        .await
+        .map_err(Error::other)?;
+
+        let file = match file {
+            Ok(file) => file,
+            Err(error) if error.raw_os_error() == Some(libc::EINVAL) => return Ok(None),
+            Err(error) => return Err(error),
+        };
+
+        Ok(Some(Self {
+            file: Arc::new(file),
+            position: 0,
+            chunk: None,
+            pending: None,
+        }))
+    }
+
+    fn read_chunk(&mut self) -> ChunkRead {
+        let file = self.file.clone();
+        let start = self.position - self.position % DIRECT_IO_ALIGNMENT as u64;
+        let buffer = self.chunk.take().map(|c| c.buffer);
+
+        spawn_blocking(move || {
+            let mut buffer = buffer.unwrap_or_else(AlignedBuffer::new);
+            let length = file.read_at(buffer.as_mut_slice(), start)?;
+
+            Ok(Chunk {
+                buffer,
+                start,
+                length,
+            })
+        })
+    }
+}
+
+impl AsyncRead for DirectFile {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        loop {
+            if let Some(chunk) = &this.chunk {
+                let end = chunk.start + chunk.length as u64;
+                if chunk.start <= this.position && this.position < end {
+                    let offset = (this.position - chunk.start) as usize;
+                    let size = buf.remaining().min(chunk.length - offset);
+                    buf.put_slice(&chunk.buffer.as_slice()[offset..offset + size]);
+                    this.position += size as u64;
+
+                    return Poll::Ready(Ok(()));
+                }
+
+                // A short chunk containing the position means that we reached the end of the file.
+                if chunk.length < DIRECT_IO_CHUNK_SIZE && chunk.start <= this.position {
+                    return Poll::Ready(Ok(()));
+                }
+            }
+
+            if this.pending.is_none() {
+                this.pending = Some(this.read_chunk());
+            }
+
+            let read = match Pin::new(this.pending.as_mut().unwrap()).poll(cx) {
+                Poll::Ready(read) => read,
+                Poll::Pending => return Poll::Pending,
+            };
+            this.pending = None;
+            this.chunk = Some(read.map_err(Error::other)??);
+        }
+    }
+}
+
+impl AsyncWrite for DirectFile {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        _buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Poll::Ready(Err(Error::new(
+            ErrorKind::Unsupported,
+            "Files opened with direct I/O are read only",
+        )))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncSeek for DirectFile {
+    fn start_seek(self: Pin<&mut Self>, position: SeekFrom) -> io::Result<()> {
+        let this = self.get_mut();
+        let position = match position {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => this.file.metadata()?.len().checked_add_signed(offset),
+            SeekFrom::Current(offset) => this.position.checked_add_signed(offset),
+        };
+
+        this.position = position.ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                "Invalid seek to a negative or overflowing position",
+            )
+        })?;
+
+        Ok(())
+    }
+
+    fn poll_complete(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<u64>> {
+        Poll::Ready(Ok(self.position))
+    }
+}
+
+impl StorageFile for DirectFile {
+    fn len(&self) -> BoxFuture<'_, io::Result<u64>> {
+        ready(self.file.metadata().map(|m| m.len())).boxed()
+    }
+
+    fn set_len(&self, _length: u64) -> BoxFuture<'_, io::Result<()>> {
+        ready(Err(Error::new(
+            ErrorKind::Unsupported,
+            "Files opened with direct I/O are read only",
+        )))
+        .boxed()
+    }
+
+    fn sync_all(&self) -> BoxFuture<'_, io::Result<()>> {
+        ready(Ok(())).boxed()
+    }
+
+    fn sync_data(&self) -> BoxFuture<'_, io::Result<()>> {
+        ready(Ok(())).boxed()
+    }
+}
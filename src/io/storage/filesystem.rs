@@ -0,0 +1,143 @@
+use std::path::Path;
+
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use tokio::fs;
+use tokio::fs::File;
+use tokio::io;
+
+#[cfg(target_os = "linux")]
+use crate::io::storage::direct::DirectFile;
+use crate::io::storage::{Storage, StorageEntry, StorageFile};
+
+/// A storage backed by the local filesystem.
+#[derive(Debug)]
+pub struct FilesystemStorage;
+
+impl StorageFile for File {
+    fn len(&self) -> BoxFuture<'_, io::Result<u64>> {
+        async { Ok(self.metadata().await?.len()) }.boxed()
+    }
+
+    fn set_len(&self, length: u64) -> BoxFuture<'_, io::Result<()>> {
+        File::set_len(self, length).boxed()
+    }
+
+    fn sync_all(&self) -> BoxFuture<'_, io::Result<()>> {
+        File::sync_all(self).boxed()
+    }
+
+    fn sync_data(&self) -> BoxFuture<'_, io::Result<()>> {
+        File::sync_data(self).boxed()
+    }
+}
+
+impl Storage for FilesystemStorage {
+    fn create_new<'a>(&'a self, path: &'a Path) -> BoxFuture<'a, io::Result<Box<dyn StorageFile>>> {
+        async move {
+            let file = File::options()
+                .read(true)
+                .write(true)
+                .create_new(true)
+                .open(path)
+                .await?;
+
+            Ok(Box::new(file) as Box<dyn StorageFile>)
+        }
+        .boxed()
+    }
+
+    fn create<'a>(&'a self, path: &'a Path) -> BoxFuture<'a, io::Result<Box<dyn StorageFile>>> {
+        async move {
+            let file = File::options()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(path)
+                .await?;
+
+            Ok(Box::new(file) as Box<dyn StorageFile>)
+        }
+        .boxed()
+    }
+
+    fn open<'a>(
+        &'a self,
+        path: &'a Path,
+        write: bool,
+    ) -> BoxFuture<'a, io::Result<Box<dyn StorageFile>>> {
+        async move {
+            let file = File::options().read(true).write(write).open(path).await?;
+
+            Ok(Box::new(file) as Box<dyn StorageFile>)
+        }
+        .boxed()
+    }
+
+    #[cfg(target_os = "linux")]
+    fn open_direct<'a>(
+        &'a self,
+        path: &'a Path,
+    ) -> BoxFuture<'a, io::Result<Box<dyn StorageFile>>> {
+        async move {
+            // Some filesystems, like tmpfs, don't support direct I/O, in which case we fall back to
+            // a regular file.
+            match DirectFile::open(path).await? {
+                Some(file) => Ok(Box::new(file) as Box<dyn StorageFile>),
+                None => self.open(path, false).await,
+            }
+        }
+        .boxed()
+    }
+
+    fn read<'a>(&'a self, path: &'a Path) -> BoxFuture<'a, io::Result<Vec<u8>>> {
+        fs::read(path).boxed()
+    }
+
+    fn write<'a>(&'a self, path: &'a Path, data: Vec<u8>) -> BoxFuture<'a, io::Result<()>> {
+        fs::write(path, data).boxed()
+    }
+
+    fn rename<'a>(&'a self, from: &'a Path, to: &'a Path) -> BoxFuture<'a, io::Result<()>> {
+        fs::rename(from, to).boxed()
+    }
+
+    fn remove_file<'a>(&'a self, path: &'a Path) -> BoxFuture<'a, io::Result<()>> {
+        fs::remove_file(path).boxed()
+    }
+
+    fn create_dir_all<'a>(&'a self, path: &'a Path) -> BoxFuture<'a, io::Result<()>> {
+        fs::create_dir_all(path).boxed()
+    }
+
+    fn remove_dir_all<'a>(&'a self, path: &'a Path) -> BoxFuture<'a, io::Result<()>> {
+        fs::remove_dir_all(path).boxed()
+    }
+
+    fn exists<'a>(&'a self, path: &'a Path) -> BoxFuture<'a, io::Result<bool>> {
+        fs::try_exists(path).boxed()
+    }
+
+    fn read_dir<'a>(&'a self, path: &'a Path) -> BoxFuture<'a, io::Result<Vec<StorageEntry>>> {
+        async move {
+            let mut entries = vec![];
+
+            let mut dir = fs::read_dir(path).await?;
+            while let Some(entry) = dir.next_entry().await? {
+                let Ok(name) = entry.file_name().into_string() else {
+                    continue;
+                };
+
+                entries.push(StorageEntry {
+                    name,
+                    path: entry.path(),
+                    is_dir: entry.file_type().await?.is_dir(),
+                });
+            }
+
+            Ok(entries)
+        }
+        .boxed()
+    }
+}
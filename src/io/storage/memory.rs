@@ -0,0 +1,355 @@
+use std::collections::BTreeMap;
+use std::io::{Error, ErrorKind, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::task::{Context, Poll};
+
+use futures::future::{ready, BoxFuture};
+use futures::FutureExt;
+use tokio::io;
+use tokio::io::{AsyncRead, AsyncSeek, AsyncWrite, ReadBuf};
+
+use crate::io::storage::{Storage, StorageEntry, StorageFile};
+
+#[derive(Debug, Clone)]
+enum MemoryEntry {
+    File(Arc<Mutex<Vec<u8>>>),
+    Dir,
+}
+
+/// A storage which keeps all the files in memory, so its content is lost when the instance stops.
+#[derive(Debug, Default)]
+pub struct MemoryStorage {
+    entries: Mutex<BTreeMap<PathBuf, MemoryEntry>>,
+}
+
+fn not_found(path: &Path) -> Error {
+    Error::new(
+        ErrorKind::NotFound,
+        format!("The path {} doesn't exist", path.display()),
+    )
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    // The data is never left in an inconsistent state while locked, so we can ignore poisoning.
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+impl MemoryStorage {
+    fn entries(&self) -> MutexGuard<'_, BTreeMap<PathBuf, MemoryEntry>> {
+        lock(&self.entries)
+    }
+
+    fn check_parent(entries: &BTreeMap<PathBuf, MemoryEntry>, path: &Path) -> io::Result<()> {
+        match path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            Some(parent) if !matches!(entries.get(parent), Some(MemoryEntry::Dir)) => {
+                Err(not_found(parent))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn file(&self, path: &Path) -> io::Result<Arc<Mutex<Vec<u8>>>> {
+        match self.entries().get(path) {
+            Some(MemoryEntry::File(data)) => Ok(data.clone()),
+            Some(MemoryEntry::Dir) => Err(Error::other(format!(
+                "The path {} is a directory",
+                path.display()
+            ))),
+            None => Err(not_found(path)),
+        }
+    }
+
+    fn create_file(&self, path: &Path, truncate: bool) -> io::Result<Box<dyn StorageFile>> {
+        let mut entries = self.entries();
+        let data = match entries.get(path) {
+            Some(MemoryEntry::File(data)) if truncate => {
+                lock(data).clear();
+                data.clone()
+            }
+            Some(_) => {
+                return Err(Error::new(
+                    ErrorKind::AlreadyExists,
+                    format!("The path {} already exists", path.display()),
+                ))
+            }
+            None => {
+                Self::check_parent(&entries, path)?;
+                let data = Arc::new(Mutex::new(vec![]));
+                entries.insert(path.to_path_buf(), MemoryEntry::File(data.clone()));
+                data
+            }
+        };
+
+        Ok(Box::new(MemoryFile::new(data, true)))
+    }
+
+    fn write_file(&self, path: &Path, data: Vec<u8>) -> io::Result<()> {
+        let mut entries = self.entries();
+        Self::check_parent(&entries, path)?;
+        entries.insert(
+            path.to_path_buf(),
+            MemoryEntry::File(Arc::new(Mutex::new(data))),
+        );
+
+        Ok(())
+    }
+
+    fn rename_entry(&self, from: &Path, to: &Path) -> io::Result<()> {
+        let mut entries = self.entries();
+        if !entries.contains_key(from) {
+            return Err(not_found(from));
+        }
+        Self::check_parent(&entries, to)?;
+
+        // Directories are moved together with all their descendants.
+        let moved: Vec<PathBuf> = entries
+            .keys()
+            .filter(|p| p.starts_with(from))
+            .cloned()
+            .collect();
+        for path in moved {
+            let entry = entries.remove(&path).unwrap();
+            let new_path = to.join(path.strip_prefix(from).unwrap());
+            entries.insert(new_path, entry);
+        }
+
+        Ok(())
+    }
+
+    fn remove_file_entry(&self, path: &Path) -> io::Result<()> {
+        let mut entries = self.entries();
+        match entries.get(path) {
+            Some(MemoryEntry::File(_)) => {
+                entries.remove(path);
+                Ok(())
+            }
+            Some(MemoryEntry::Dir) => Err(Error::other(format!(
+                "The path {} is a directory",
+                path.display()
+            ))),
+            None => Err(not_found(path)),
+        }
+    }
+
+    fn create_dirs(&self, path: &Path) -> io::Result<()> {
+        let mut entries = self.entries();
+        for ancestor in path.ancestors().filter(|p| !p.as_os_str().is_empty()) {
+            match entries.get(ancestor) {
+                Some(MemoryEntry::File(_)) => {
+                    return Err(Error::new(
+                        ErrorKind::AlreadyExists,
+                        format!("The path {} is a file", ancestor.display()),
+                    ))
+                }
+                Some(MemoryEntry::Dir) => {}
+                None => {
+                    entries.insert(ancestor.to_path_buf(), MemoryEntry::Dir);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn remove_dirs(&self, path: &Path) -> io::Result<()> {
+        let mut entries = self.entries();
+        if !matches!(entries.get(path), Some(MemoryEntry::Dir)) {
+            return Err(not_found(path));
+        }
+        entries.retain(|p, _| !p.starts_with(path));
+
+        Ok(())
+    }
+
+    fn list_dir(&self, path: &Path) -> io::Result<Vec<StorageEntry>> {
+        let entries = self.entries();
+        if !matches!(entries.get(path), Some(MemoryEntry::Dir)) {
+            return Err(not_found(path));
+        }
+
+        Ok(entries
+            .iter()
+            .filter(|(p, _)| p.parent() == Some(path))
+            .filter_map(|(p, entry)| {
+                Some(StorageEntry {
+                    name: p.file_name()?.to_str()?.to_string(),
+                    path: p.clone(),
+                    is_dir: matches!(entry, MemoryEntry::Dir),
+                })
+            })
+            .collect())
+    }
+}
+
+impl Storage for MemoryStorage {
+    fn create_new<'a>(&'a self, path: &'a Path) -> BoxFuture<'a, io::Result<Box<dyn StorageFile>>> {
+        ready(self.create_file(path, false)).boxed()
+    }
+
+    fn create<'a>(&'a self, path: &'a Path) -> BoxFuture<'a, io::Result<Box<dyn StorageFile>>> {
+        ready(self.create_file(path, true)).boxed()
+    }
+
+    fn open<'a>(
+        &'a self,
+        path: &'a Path,
+        write: bool,
+    ) -> BoxFuture<'a, io::Result<Box<dyn StorageFile>>> {
+        let file = self
+            .file(path)
+            .map(|data| Box::new(MemoryFile::new(data, write)) as Box<dyn StorageFile>);
+
+        ready(file).boxed()
+    }
+
+    fn read<'a>(&'a self, path: &'a Path) -> BoxFuture<'a, io::Result<Vec<u8>>> {
+        ready(self.file(path).map(|data| lock(&data).clone())).boxed()
+    }
+
+    fn write<'a>(&'a self, path: &'a Path, data: Vec<u8>) -> BoxFuture<'a, io::Result<()>> {
+        ready(self.write_file(path, data)).boxed()
+    }
+
+    fn rename<'a>(&'a self, from: &'a Path, to: &'a Path) -> BoxFuture<'a, io::Result<()>> {
+        ready(self.rename_entry(from, to)).boxed()
+    }
+
+    fn remove_file<'a>(&'a self, path: &'a Path) -> BoxFuture<'a, io::Result<()>> {
+        ready(self.remove_file_entry(path)).boxed()
+    }
+
+    fn create_dir_all<'a>(&'a self, path: &'a Path) -> BoxFuture<'a, io::Result<()>> {
+        ready(self.create_dirs(path)).boxed()
+    }
+
+    fn remove_dir_all<'a>(&'a self, path: &'a Path) -> BoxFuture<'a, io::Result<()>> {
+        ready(self.remove_dirs(path)).boxed()
+    }
+
+    fn exists<'a>(&'a self, path: &'a Path) -> BoxFuture<'a, io::Result<bool>> {
+        ready(Ok(self.entries().contains_key(path))).boxed()
+    }
+
+    fn read_dir<'a>(&'a self, path: &'a Path) -> BoxFuture<'a, io::Result<Vec<StorageEntry>>> {
+        ready(self.list_dir(path)).boxed()
+    }
+}
+
+/// An open file of a [`MemoryStorage`], which shares its data with all the other open handles.
+struct MemoryFile {
+    data: Arc<Mutex<Vec<u8>>>,
+    position: u64,
+    writable: bool,
+}
+
+impl MemoryFile {
+    fn new(data: Arc<Mutex<Vec<u8>>>, writable: bool) -> Self {
+        Self {
+            data,
+            position: 0,
+            writable,
+        }
+    }
+}
+
+/// Returns an empty read-only file, which stands in for a file that doesn't exist yet.
+pub fn empty_file() -> Box<dyn StorageFile> {
+    Box::new(MemoryFile::new(Arc::default(), false))
+}
+
+impl AsyncRead for MemoryFile {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let data = lock(&this.data);
+
+        let start = (this.position as usize).min(data.len());
+        let size = buf.remaining().min(data.len() - start);
+        buf.put_slice(&data[start..start + size]);
+        this.position += size as u64;
+
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncWrite for MemoryFile {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        if !this.writable {
+            return Poll::Ready(Err(Error::new(
+                ErrorKind::PermissionDenied,
+                "The file is not opened for writing",
+            )));
+        }
+
+        let mut data = lock(&this.data);
+        let start = this.position as usize;
+        if data.len() < start + buf.len() {
+            data.resize(start + buf.len(), 0);
+        }
+        data[start..start + buf.len()].copy_from_slice(buf);
+        this.position += buf.len() as u64;
+
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncSeek for MemoryFile {
+    fn start_seek(self: Pin<&mut Self>, position: SeekFrom) -> io::Result<()> {
+        let this = self.get_mut();
+        let position = match position {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => (lock(&this.data).len() as u64).checked_add_signed(offset),
+            SeekFrom::Current(offset) => this.position.checked_add_signed(offset),
+        };
+
+        this.position = position.ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                "Invalid seek to a negative or overflowing position",
+            )
+        })?;
+
+        Ok(())
+    }
+
+    fn poll_complete(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<u64>> {
+        Poll::Ready(Ok(self.position))
+    }
+}
+
+impl StorageFile for MemoryFile {
+    fn len(&self) -> BoxFuture<'_, io::Result<u64>> {
+        ready(Ok(lock(&self.data).len() as u64)).boxed()
+    }
+
+    fn set_len(&self, length: u64) -> BoxFuture<'_, io::Result<()>> {
+        lock(&self.data).resize(length as usize, 0);
+        ready(Ok(())).boxed()
+    }
+
+    fn sync_all(&self) -> BoxFuture<'_, io::Result<()>> {
+        ready(Ok(())).boxed()
+    }
+
+    fn sync_data(&self) -> BoxFuture<'_, io::Result<()>> {
+        ready(Ok(())).boxed()
+    }
+}
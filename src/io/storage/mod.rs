@@ -0,0 +1,119 @@
+use std::fmt::Debug;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use futures::future::BoxFuture;
+use log::info;
+use tokio::io;
+use tokio::io::{AsyncRead, AsyncSeek, AsyncWrite};
+
+use crate::config::StorageBackend;
+use crate::io::storage::filesystem::FilesystemStorage;
+use crate::io::storage::memory::MemoryStorage;
+
+#[cfg(target_os = "linux")]
+pub mod direct;
+pub mod filesystem;
+pub mod memory;
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+pub mod uring;
+
+/// An open file of a [`Storage`].
+pub trait StorageFile: AsyncRead + AsyncWrite + AsyncSeek + Send + Sync + Unpin {
+    fn len(&self) -> BoxFuture<'_, io::Result<u64>>;
+
+    fn set_len(&self, length: u64) -> BoxFuture<'_, io::Result<()>>;
+
+    /// Makes sure all the written data is durably stored.
+    fn sync_all(&self) -> BoxFuture<'_, io::Result<()>>;
+
+    /// Makes sure the written data is durably stored, without the metadata which isn't needed to
+    /// read it back.
+    fn sync_data(&self) -> BoxFuture<'_, io::Result<()>>;
+}
+
+/// An entry of a directory of a [`Storage`].
+#[derive(Debug, Clone)]
+pub struct StorageEntry {
+    pub name: String,
+    pub path: PathBuf,
+    pub is_dir: bool,
+}
+
+/// The operations on files and directories needed by the database, which mirror the ones of the
+/// filesystem.
+pub trait Storage: Debug + Send + Sync {
+    /// Creates a new file, failing with [`std::io::ErrorKind::AlreadyExists`] if it exists.
+    fn create_new<'a>(&'a self, path: &'a Path) -> BoxFuture<'a, io::Result<Box<dyn StorageFile>>>;
+
+    /// Creates a file, truncating it if it exists.
+    fn create<'a>(&'a self, path: &'a Path) -> BoxFuture<'a, io::Result<Box<dyn StorageFile>>>;
+
+    fn open<'a>(
+        &'a self,
+        path: &'a Path,
+        write: bool,
+    ) -> BoxFuture<'a, io::Result<Box<dyn StorageFile>>>;
+
+    /// Opens a file for reading while bypassing the OS page cache, if supported by the storage.
+    fn open_direct<'a>(
+        &'a self,
+        path: &'a Path,
+    ) -> BoxFuture<'a, io::Result<Box<dyn StorageFile>>> {
+        self.open(path, false)
+    }
+
+    fn read<'a>(&'a self, path: &'a Path) -> BoxFuture<'a, io::Result<Vec<u8>>>;
+
+    fn write<'a>(&'a self, path: &'a Path, data: Vec<u8>) -> BoxFuture<'a, io::Result<()>>;
+
+    fn rename<'a>(&'a self, from: &'a Path, to: &'a Path) -> BoxFuture<'a, io::Result<()>>;
+
+    fn remove_file<'a>(&'a self, path: &'a Path) -> BoxFuture<'a, io::Result<()>>;
+
+    fn create_dir_all<'a>(&'a self, path: &'a Path) -> BoxFuture<'a, io::Result<()>>;
+
+    fn remove_dir_all<'a>(&'a self, path: &'a Path) -> BoxFuture<'a, io::Result<()>>;
+
+    fn exists<'a>(&'a self, path: &'a Path) -> BoxFuture<'a, io::Result<bool>>;
+
+    fn read_dir<'a>(&'a self, path: &'a Path) -> BoxFuture<'a, io::Result<Vec<StorageEntry>>>;
+}
+
+static STORAGE: OnceLock<Box<dyn Storage>> = OnceLock::new();
+
+/// Selects the storage used for all the files of the database.
+pub fn init_storage(backend: &StorageBackend) -> io::Result<()> {
+    let storage: Box<dyn Storage> = match backend {
+        StorageBackend::Filesystem => Box::new(FilesystemStorage),
+        StorageBackend::Memory => Box::new(MemoryStorage::default()),
+        StorageBackend::Uring => uring_storage()?,
+    };
+    info!(
+        "Using the {} storage",
+        <&StorageBackend as Into<&str>>::into(backend)
+    );
+    let _ = STORAGE.set(storage);
+
+    Ok(())
+}
+
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+fn uring_storage() -> io::Result<Box<dyn Storage>> {
+    Ok(Box::new(uring::UringStorage::new()?))
+}
+
+#[cfg(not(all(target_os = "linux", feature = "io-uring")))]
+fn uring_storage() -> io::Result<Box<dyn Storage>> {
+    use std::io::{Error, ErrorKind};
+
+    Err(Error::new(
+        ErrorKind::Unsupported,
+        "The io_uring storage is available only on Linux with the io-uring feature",
+    ))
+}
+
+/// Returns the storage of the database, which is the filesystem unless configured otherwise.
+pub fn storage() -> &'static dyn Storage {
+    STORAGE.get_or_init(|| Box::new(FilesystemStorage)).as_ref()
+}
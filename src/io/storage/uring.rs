@@ -0,0 +1,468 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::future::Future;
+use std::io::{Error, ErrorKind, SeekFrom};
+use std::os::fd::AsRawFd;
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::mpsc::{channel, Receiver, Sender, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::thread;
+
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use io_uring::{opcode, squeue, types, IoUring};
+use log::info;
+use tokio::io;
+use tokio::io::{AsyncRead, AsyncSeek, AsyncWrite, ReadBuf};
+use tokio::sync::oneshot;
+
+use crate::io::storage::filesystem::FilesystemStorage;
+use crate::io::storage::{Storage, StorageEntry, StorageFile};
+
+/// The number of entries of the submission queue of the ring.
+const RING_ENTRIES: u32 = 256;
+
+enum UringOp {
+    Read {
+        offset: u64,
+        length: usize,
+    },
+    Write {
+        offset: u64,
+        data: Vec<u8>,
+    },
+    /// Syncs the file, only with the metadata needed to read its data if `data_only`.
+    Fsync {
+        data_only: bool,
+    },
+}
+
+/// The outcome of an operation, made of the number of bytes read or written and the buffer which
+/// was used, truncated to the read bytes for reads.
+type UringResult = io::Result<(usize, Vec<u8>)>;
+
+struct UringRequest {
+    /// The file is kept alive until the operation completes, so that its descriptor is never
+    /// closed and reused while the kernel still refers to it.
+    file: Arc<File>,
+    op: UringOp,
+    reply: oneshot::Sender<UringResult>,
+}
+
+/// A handle to the thread which owns the ring and submits the operations of all the files.
+///
+/// The thread drains all the requests queued since the last submission and submits them with a
+/// single syscall, so that the reads and writes of concurrent scans and inserts are batched.
+#[derive(Debug, Clone)]
+struct Uring {
+    requests: Arc<Mutex<Sender<UringRequest>>>,
+}
+
+impl Uring {
+    fn new() -> io::Result<Self> {
+        let ring = IoUring::new(RING_ENTRIES)?;
+        let (sender, receiver) = channel();
+        thread::Builder::new()
+            .name("distribuito-uring".to_string())
+            .spawn(move || run_ring(ring, receiver))?;
+
+        Ok(Self {
+            requests: Arc::new(Mutex::new(sender)),
+        })
+    }
+
+    fn submit(&self, file: Arc<File>, op: UringOp) -> oneshot::Receiver<UringResult> {
+        let (reply, receiver) = oneshot::channel();
+        let request = UringRequest { file, op, reply };
+
+        // If the ring thread is gone, the reply is dropped and the receiver reports the error.
+        let _ = self
+            .requests
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .send(request);
+
+        receiver
+    }
+}
+
+struct InFlight {
+    _file: Arc<File>,
+    buffer: Vec<u8>,
+    reply: oneshot::Sender<UringResult>,
+}
+
+fn build_entry(request: &mut UringRequest, user_data: u64) -> (squeue::Entry, Vec<u8>) {
+    let fd = types::Fd(request.file.as_raw_fd());
+    match &mut request.op {
+        UringOp::Read { offset, length } => {
+            let mut buffer = vec![0u8; *length];
+            let entry = opcode::Read::new(fd, buffer.as_mut_ptr(), buffer.len() as u32)
+                .offset(*offset)
+                .build()
+                .user_data(user_data);
+
+            (entry, buffer)
+        }
+        UringOp::Write { offset, data } => {
+            let buffer = std::mem::take(data);
+            let entry = opcode::Write::new(fd, buffer.as_ptr(), buffer.len() as u32)
+                .offset(*offset)
+                .build()
+                .user_data(user_data);
+
+            (entry, buffer)
+        }
+        UringOp::Fsync { data_only } => {
+            let flags = match data_only {
+                true => types::FsyncFlags::DATASYNC,
+                false => types::FsyncFlags::empty(),
+            };
+            let entry = opcode::Fsync::new(fd)
+                .flags(flags)
+                .build()
+                .user_data(user_data);
+
+            (entry, vec![])
+        }
+    }
+}
+
+fn run_ring(mut ring: IoUring, receiver: Receiver<UringRequest>) {
+    let mut in_flight: HashMap<u64, InFlight> = HashMap::new();
+    let mut next_user_data = 0u64;
+
+    loop {
+        // We block waiting for requests only if there is nothing to complete, otherwise we take
+        // whatever was queued in the meantime.
+        let mut requests = vec![];
+        if in_flight.is_empty() {
+            match receiver.recv() {
+                Ok(request) => requests.push(request),
+                Err(_) => return,
+            }
+        }
+        loop {
+            match receiver.try_recv() {
+                Ok(request) => requests.push(request),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) if in_flight.is_empty() && requests.is_empty() => {
+                    return
+                }
+                Err(TryRecvError::Disconnected) => break,
+            }
+        }
+
+        for mut request in requests {
+            let user_data = next_user_data;
+            next_user_data = next_user_data.wrapping_add(1);
+
+            let (entry, buffer) = build_entry(&mut request, user_data);
+            // The buffer is moved into the in flight operations, which doesn't move its heap
+            // allocation, so the pointer in the entry stays valid until the operation completes.
+            in_flight.insert(
+                user_data,
+                InFlight {
+                    _file: request.file,
+                    buffer,
+                    reply: request.reply,
+                },
+            );
+
+            // SAFETY: the buffer and the file of the entry are owned by `in_flight` until the
+            // completion of the operation is reaped.
+            while unsafe { ring.submission().push(&entry) }.is_err() {
+                // The submission queue is full, so we submit what we have to make room.
+                if let Err(error) = ring.submit() {
+                    fail_all(&mut in_flight, error);
+                    return;
+                }
+            }
+        }
+
+        if let Err(error) = ring.submit_and_wait(1) {
+            if error.kind() == ErrorKind::Interrupted {
+                continue;
+            }
+
+            fail_all(&mut in_flight, error);
+            return;
+        }
+
+        for completion in ring.completion() {
+            let Some(mut operation) = in_flight.remove(&completion.user_data()) else {
+                continue;
+            };
+
+            let result = completion.result();
+            let reply = if result < 0 {
+                Err(Error::from_raw_os_error(-result))
+            } else {
+                let size = result as usize;
+                operation.buffer.truncate(size);
+                Ok((size, operation.buffer))
+            };
+            let _ = operation.reply.send(reply);
+        }
+    }
+}
+
+fn fail_all(in_flight: &mut HashMap<u64, InFlight>, error: Error) {
+    info!("The io_uring thread failed: {}", error);
+    for (_, operation) in in_flight.drain() {
+        let _ = operation
+            .reply
+            .send(Err(Error::new(error.kind(), error.to_string())));
+    }
+}
+
+async fn wait(receiver: oneshot::Receiver<UringResult>) -> UringResult {
+    receiver.await.map_err(|_| ring_gone())?
+}
+
+fn ring_gone() -> Error {
+    Error::new(ErrorKind::BrokenPipe, "The io_uring thread is not running")
+}
+
+/// A storage which uses io_uring for the reads and writes of files, and the filesystem for
+/// everything else.
+#[derive(Debug, Clone)]
+pub struct UringStorage {
+    uring: Uring,
+}
+
+impl UringStorage {
+    pub fn new() -> io::Result<Self> {
+        Ok(Self {
+            uring: Uring::new()?,
+        })
+    }
+
+    async fn open_with(
+        &self,
+        options: &mut tokio::fs::OpenOptions,
+        path: &Path,
+    ) -> io::Result<Box<dyn StorageFile>> {
+        let file = options.open(path).await?.into_std().await;
+
+        Ok(Box::new(UringFile {
+            file: Arc::new(file),
+            uring: self.uring.clone(),
+            position: 0,
+            pending_read: None,
+            pending_write: None,
+        }))
+    }
+}
+
+impl Storage for UringStorage {
+    fn create_new<'a>(&'a self, path: &'a Path) -> BoxFuture<'a, io::Result<Box<dyn StorageFile>>> {
+        async move {
+            let mut options = tokio::fs::File::options();
+            options.read(true).write(true).create_new(true);
+            self.open_with(&mut options, path).await
+        }
+        .boxed()
+    }
+
+    fn create<'a>(&'a self, path: &'a Path) -> BoxFuture<'a, io::Result<Box<dyn StorageFile>>> {
+        async move {
+            let mut options = tokio::fs::File::options();
+            options.read(true).write(true).create(true).truncate(true);
+            self.open_with(&mut options, path).await
+        }
+        .boxed()
+    }
+
+    fn open<'a>(
+        &'a self,
+        path: &'a Path,
+        write: bool,
+    ) -> BoxFuture<'a, io::Result<Box<dyn StorageFile>>> {
+        async move {
+            let mut options = tokio::fs::File::options();
+            options.read(true).write(write);
+            self.open_with(&mut options, path).await
+        }
+        .boxed()
+    }
+
+    fn read<'a>(&'a self, path: &'a Path) -> BoxFuture<'a, io::Result<Vec<u8>>> {
+        FilesystemStorage.read(path)
+    }
+
+    fn write<'a>(&'a self, path: &'a Path, data: Vec<u8>) -> BoxFuture<'a, io::Result<()>> {
+        FilesystemStorage.write(path, data)
+    }
+
+    fn rename<'a>(&'a self, from: &'a Path, to: &'a Path) -> BoxFuture<'a, io::Result<()>> {
+        FilesystemStorage.rename(from, to)
+    }
+
+    fn remove_file<'a>(&'a self, path: &'a Path) -> BoxFuture<'a, io::Result<()>> {
+        FilesystemStorage.remove_file(path)
+    }
+
+    fn create_dir_all<'a>(&'a self, path: &'a Path) -> BoxFuture<'a, io::Result<()>> {
+        FilesystemStorage.create_dir_all(path)
+    }
+
+    fn remove_dir_all<'a>(&'a self, path: &'a Path) -> BoxFuture<'a, io::Result<()>> {
+        FilesystemStorage.remove_dir_all(path)
+    }
+
+    fn exists<'a>(&'a self, path: &'a Path) -> BoxFuture<'a, io::Result<bool>> {
+        FilesystemStorage.exists(path)
+    }
+
+    fn read_dir<'a>(&'a self, path: &'a Path) -> BoxFuture<'a, io::Result<Vec<StorageEntry>>> {
+        FilesystemStorage.read_dir(path)
+    }
+}
+
+/// A file whose reads and writes are submitted to the ring at its current position.
+struct UringFile {
+    file: Arc<File>,
+    uring: Uring,
+    position: u64,
+    pending_read: Option<oneshot::Receiver<UringResult>>,
+    pending_write: Option<oneshot::Receiver<UringResult>>,
+}
+
+fn poll_pending(
+    pending: &mut Option<oneshot::Receiver<UringResult>>,
+    cx: &mut Context<'_>,
+) -> Poll<UringResult> {
+    let receiver = pending.as_mut().expect("an operation must be pending");
+    let result = match Pin::new(receiver).poll(cx) {
+        Poll::Ready(result) => result.map_err(|_| ring_gone())?,
+        Poll::Pending => return Poll::Pending,
+    };
+    *pending = None;
+
+    Poll::Ready(result)
+}
+
+impl AsyncRead for UringFile {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if this.pending_read.is_none() {
+            let op = UringOp::Read {
+                offset: this.position,
+                length: buf.remaining(),
+            };
+            this.pending_read = Some(this.uring.submit(this.file.clone(), op));
+        }
+
+        let (size, data) = match poll_pending(&mut this.pending_read, cx) {
+            Poll::Ready(result) => result?,
+            Poll::Pending => return Poll::Pending,
+        };
+        // The caller must poll again with the same buffer, but we never trust its size.
+        let size = size.min(buf.remaining());
+        buf.put_slice(&data[..size]);
+        this.position += size as u64;
+
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncWrite for UringFile {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        if this.pending_write.is_none() {
+            let op = UringOp::Write {
+                offset: this.position,
+                data: buf.to_vec(),
+            };
+            this.pending_write = Some(this.uring.submit(this.file.clone(), op));
+        }
+
+        let (size, _) = match poll_pending(&mut this.pending_write, cx) {
+            Poll::Ready(result) => result?,
+            Poll::Pending => return Poll::Pending,
+        };
+        this.position += size as u64;
+
+        Poll::Ready(Ok(size))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        // Writes are completed before returning, so there is nothing to flush.
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncSeek for UringFile {
+    fn start_seek(self: Pin<&mut Self>, position: SeekFrom) -> io::Result<()> {
+        let this = self.get_mut();
+        let position = match position {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => this.file.metadata()?.len().checked_add_signed(offset),
+            SeekFrom::Current(offset) => this.position.checked_add_signed(offset),
+        };
+
+        this.position = position.ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                "Invalid seek to a negative or overflowing position",
+            )
+        })?;
+
+        Ok(())
+    }
+
+    fn poll_complete(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<u64>> {
+        Poll::Ready(Ok(self.position))
+    }
+}
+
+impl StorageFile for UringFile {
+    fn len(&self) -> BoxFuture<'_, io::Result<u64>> {
+        let file = self.file.clone();
+        async move {
+            tokio::task::spawn_blocking(move || Ok(file.metadata()?.len()))
+                .await
+                .map_err(|e| Error::new(ErrorKind::Other, e))?
+        }
+        .boxed()
+    }
+
+    fn set_len(&self, length: u64) -> BoxFuture<'_, io::Result<()>> {
+        let file = self.file.clone();
+        async move {
+            tokio::task::spawn_blocking(move || file.set_len(length))
+                .await
+                .map_err(|e| Error::new(ErrorKind::Other, e))?
+        }
+        .boxed()
+    }
+
+    fn sync_all(&self) -> BoxFuture<'_, io::Result<()>> {
+        let receiver = self
+            .uring
+            .submit(self.file.clone(), UringOp::Fsync { data_only: false });
+        async move { wait(receiver).await.map(|_| ()) }.boxed()
+    }
+
+    fn sync_data(&self) -> BoxFuture<'_, io::Result<()>> {
+        let receiver = self
+            .uring
+            .submit(self.file.clone(), UringOp::Fsync { data_only: true });
+        async move { wait(receiver).await.map(|_| ()) }.boxed()
+    }
+}
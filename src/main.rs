@@ -1,17 +1,61 @@
 use std::env;
 use std::io::{Error, ErrorKind};
+use std::net::SocketAddr;
 use std::path::PathBuf;
+use std::process;
 use std::sync::Arc;
 
-use axum::{routing::post, Router};
+use axum::middleware::from_fn_with_state;
+use axum::routing::{delete, get, post};
+use axum::Router;
 use log::info;
 
+use crate::bench::{run_bench, BenchOptions, BENCH_USAGE};
 use crate::config::{Config, InstanceRole};
-use crate::transport::api::{create_table, insert, query, DatabaseState};
+use crate::io::clock::init_clock;
+use crate::io::encryption::init_encryption;
+use crate::io::storage::init_storage;
+use crate::system::audit::create_audit_log;
+use crate::system::compaction::spawn_compaction;
+use crate::system::idempotency::IdempotencyKeys;
+use crate::system::insert_sequences::{spawn_insert_sequences_save, InsertSequences};
+use crate::system::memtable::{flush_memtables, spawn_memtable_flush};
+use crate::system::merging::spawn_merging;
+use crate::system::placement::Placements;
+use crate::system::prepared_query::PreparedQueries;
+use crate::system::read_only::ReadOnlyMode;
+use crate::system::retention::spawn_retention;
+use crate::system::saved_query::SavedQueries;
+use crate::system::scheduler::spawn_schedules;
+use crate::system::session::Sessions;
+use crate::system::stats_sync::{spawn_stats_sync, sync_stats};
+use crate::system::telemetry::spawn_telemetry;
+use crate::system::tiering::spawn_tiering;
+use crate::system::usage::{spawn_usage_save, Usage};
+use crate::system::users::Users;
+use crate::table::lock::{QueryAdmission, QuerySlots, TableLocks};
+use crate::table::upgrade::upgrade_database;
+use crate::transport::api::{
+    check_unique, compact, create_index, create_table, delete_rows, delete_user, drop_table,
+    execute, explain, export_schema, export_table_schema, get_compression_advice, get_metrics,
+    get_read_only, get_session, get_stats, get_usage, import_schema, insert, list_tables,
+    list_users, prepare, query, query_batch, rotate_key, run_query, save_query, save_user,
+    set_read_only, set_session, status, truncate_table, vacuum, DatabaseState,
+};
+use crate::transport::auth::authenticate;
+use crate::transport::ingest::ingest;
+use crate::transport::origin::detect_origin;
+use crate::transport::pool::{run_in_read_pool, run_in_write_pool, WorkerPools};
+use crate::transport::role::enforce_role;
+use crate::transport::session::resolve_session;
 use crate::transport::shard::Shards;
+use crate::transport::trace::trace_request;
+use crate::transport::ui::ui;
 
+mod bench;
 mod config;
 mod io;
+mod system;
 mod table;
 mod transport;
 
@@ -32,6 +76,24 @@ fn config_path() -> tokio::io::Result<PathBuf> {
 async fn main() {
     tracing_subscriber::fmt::init();
 
+    // The bench subcommand runs a workload against a running cluster instead of starting an
+    // instance.
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.first().is_some_and(|a| a == "bench") {
+        let options = match BenchOptions::parse(&args[1..]) {
+            Ok(options) => options,
+            Err(error) => {
+                eprintln!("{}\n{}", error, BENCH_USAGE);
+                process::exit(2);
+            }
+        };
+        if let Err(error) = run_bench(options).await {
+            eprintln!("The bench failed: {}", error);
+            process::exit(1);
+        }
+        return;
+    }
+
     let config_path = config_path().unwrap();
     let config = Config::from_file(config_path).await.unwrap();
 
@@ -42,25 +104,193 @@ async fn main() {
         config.database_ip_port
     );
 
-    let shards = if matches!(config.instance_role, InstanceRole::Master) {
+    init_clock(&config.clock);
+    init_storage(&config.storage).unwrap();
+
+    if let Some(encryption) = &config.encryption {
+        init_encryption(encryption).unwrap();
+    }
+    upgrade_database(&config).await.unwrap();
+
+    let is_master = matches!(config.instance_role, InstanceRole::Master);
+    let shards = if is_master {
         Some(Shards::new(&config))
     } else {
         None
     };
 
-    let ip_port = config.database_ip_port.clone();
+    let saved_queries = SavedQueries::load(&config).await.unwrap();
+    let users = Users::load(&config).await.unwrap();
+    let usage = Usage::load(&config).await.unwrap();
+    let placements = Placements::load(&config).await.unwrap();
+    let insert_sequences = InsertSequences::load(&config).await.unwrap();
+    let read_only = ReadOnlyMode::load(&config).await.unwrap();
+
+    let api_ip_port = config.api_listen_ip_port().to_string();
+    let admin_ip_port = config.admin_listen_ip_port().map(|a| a.to_string());
+    let query_slots = QuerySlots::new(config.query_concurrency.as_ref());
+    let query_admission = QueryAdmission::new(config.admission_control.as_ref());
+    let pools = WorkerPools::new(config.pools.as_ref()).unwrap();
 
     let app_state = DatabaseState {
         config: Arc::new(config),
         shards: Arc::new(shards),
+        saved_queries: Arc::new(saved_queries),
+        table_locks: Arc::new(TableLocks::default()),
+        query_slots: Arc::new(query_slots),
+        query_admission: Arc::new(query_admission),
+        users: Arc::new(users),
+        usage: Arc::new(usage),
+        placements: Arc::new(placements),
+        sessions: Arc::new(Sessions::default()),
+        prepared_queries: Arc::new(PreparedQueries::default()),
+        idempotency_keys: Arc::new(IdempotencyKeys::default()),
+        insert_sequences: Arc::new(insert_sequences),
+        pools: Arc::new(pools),
+        read_only: Arc::new(read_only),
     };
 
-    let app = Router::new()
+    create_audit_log(app_state.config.clone()).await.unwrap();
+
+    spawn_retention(app_state.clone());
+    spawn_tiering(app_state.clone());
+    spawn_merging(app_state.clone());
+    spawn_compaction(app_state.clone());
+    spawn_memtable_flush(app_state.clone());
+    spawn_telemetry(app_state.clone());
+    spawn_stats_sync(app_state.clone());
+    spawn_insert_sequences_save(app_state.clone());
+    spawn_usage_save(app_state.clone());
+
+    // Scheduled queries run only on the master, since it's the only instance which sees the
+    // results of the entire cluster.
+    if is_master {
+        spawn_schedules(app_state.clone());
+    }
+
+    // The writes are routed separately, since slaves might only accept them from the master.
+    let writes = Router::new()
         .route("/create_table", post(create_table))
+        .route("/drop_table", post(drop_table))
+        .route("/truncate_table", post(truncate_table))
+        .route("/create_index", post(create_index))
         .route("/insert", post(insert))
+        .route("/delete", post(delete_rows))
+        .route("/vacuum", post(vacuum))
+        .route("/ingest", post(ingest))
+        .route("/import_schema", post(import_schema))
+        .route_layer(from_fn_with_state(app_state.clone(), enforce_role))
+        .route_layer(from_fn_with_state(app_state.clone(), trace_request))
+        .route_layer(from_fn_with_state(app_state.clone(), run_in_write_pool));
+
+    // The queries are routed separately, since they might run on their own pool.
+    let reads = Router::new()
         .route("/query", post(query))
-        .with_state(app_state);
+        .route("/query_batch", post(query_batch))
+        .route("/explain", post(explain))
+        .route("/check_unique", post(check_unique))
+        .route("/run/:name", post(run_query))
+        .route("/execute", post(execute))
+        .route_layer(from_fn_with_state(app_state.clone(), trace_request))
+        .route_layer(from_fn_with_state(app_state.clone(), run_in_read_pool));
+
+    // The admin endpoints are routed separately, since they might be served on another address.
+    let admin = Router::new()
+        .route("/rotate_key", post(rotate_key))
+        .route("/admin/users", post(save_user).get(list_users))
+        .route("/admin/users/:name", delete(delete_user))
+        .route("/admin/read_only", post(set_read_only).get(get_read_only))
+        .route("/admin/compact", post(compact))
+        .route("/usage", get(get_usage))
+        .route("/stats", get(get_stats))
+        .route("/compression_advice/:name", get(get_compression_advice))
+        .route("/metrics", get(get_metrics));
+
+    let mut api = Router::new()
+        .merge(writes)
+        .merge(reads)
+        .route("/save_query", post(save_query))
+        .route("/prepare", post(prepare))
+        .route("/status", get(status))
+        .route("/tables", get(list_tables))
+        .route("/export_schema", get(export_schema))
+        .route("/export_schema/:name", get(export_table_schema))
+        .route("/session", get(get_session).post(set_session));
+    let admin = match admin_ip_port {
+        Some(admin_ip_port) => Some((admin_ip_port, with_layers(admin, &app_state))),
+        None => {
+            api = api.merge(admin);
+            None
+        }
+    };
+
+    let api = with_layers(api, &app_state)
+        // The dashboard is public since it only authenticates its calls to the api.
+        .route("/ui", get(ui));
+
+    info!("Serving the api on {}", api_ip_port);
+    let api_server = serve(api_ip_port, api.with_state(app_state.clone()));
+    match admin {
+        Some((admin_ip_port, admin)) => {
+            info!("Serving the admin endpoints on {}", admin_ip_port);
+            let admin_server = serve(admin_ip_port, admin.with_state(app_state.clone()));
+            tokio::try_join!(api_server, admin_server).unwrap();
+        }
+        None => api_server.await.unwrap(),
+    }
+
+    // The buffered rows are flushed, and the stats, the insert sequences and the usage written
+    // since the last periodic sync are saved before exiting.
+    info!("Shutting down, flushing the memtables");
+    if let Err(error) = flush_memtables(&app_state).await {
+        info!("Error while flushing the memtables: {}", error);
+    }
+    info!("Shutting down, syncing the table stats");
+    if let Err(error) = sync_stats(&app_state).await {
+        info!("Error while syncing the table stats: {}", error);
+    }
+    if let Err(error) = app_state.insert_sequences.save().await {
+        info!("Error while saving the insert sequences: {}", error);
+    }
+    if let Err(error) = app_state.usage.save().await {
+        info!("Error while saving the usage: {}", error);
+    }
+}
+
+/// Applies the middlewares which identify the caller of the requests.
+fn with_layers(router: Router<DatabaseState>, state: &DatabaseState) -> Router<DatabaseState> {
+    router
+        .layer(from_fn_with_state(state.clone(), resolve_session))
+        .layer(from_fn_with_state(state.clone(), authenticate))
+        .layer(from_fn_with_state(state.clone(), detect_origin))
+}
+
+/// Serves the router on `ip_port` until the instance is asked to stop.
+async fn serve(ip_port: String, router: Router) -> tokio::io::Result<()> {
+    let listener = tokio::net::TcpListener::bind(ip_port).await?;
+    axum::serve(
+        listener,
+        router.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .with_graceful_shutdown(shutdown_signal())
+    .await
+}
+
+/// Resolves when the instance is asked to stop, either with ctrl-c or SIGTERM.
+#[cfg(unix)]
+async fn shutdown_signal() {
+    let ctrl_c = tokio::signal::ctrl_c();
+    let mut terminate =
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()).unwrap();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate.recv() => {}
+    }
+}
 
-    let listener = tokio::net::TcpListener::bind(ip_port).await.unwrap();
-    axum::serve(listener, app).await.unwrap();
+/// Resolves when the instance is asked to stop with ctrl-c, since SIGTERM only exists on Unix.
+#[cfg(not(unix))]
+async fn shutdown_signal() {
+    let _ = tokio::signal::ctrl_c().await;
 }
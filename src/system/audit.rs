@@ -0,0 +1,80 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use serde_json::json;
+use tokio::io;
+
+use crate::config::Config;
+use crate::io::clock::clock;
+use crate::table::column::{Column, ColumnType};
+use crate::table::schema::TableOptions;
+use crate::table::table::TableDefinition;
+use crate::transport::api::DatabaseState;
+
+/// The name of the system table holding the audit log, which can be queried like any other table
+/// but can't be written to.
+pub const AUDIT_TABLE_NAME: &str = "__audit";
+
+/// The caller recorded for the operations performed by the scheduled queries.
+pub const SCHEDULER_CALLER: &str = "scheduler";
+
+/// Creates the table of the audit log if not existing.
+pub async fn create_audit_log(config: Arc<Config>) -> io::Result<()> {
+    let columns = vec![
+        Column::new("timestamp".to_string(), ColumnType::Integer),
+        Column::new("caller".to_string(), ColumnType::String),
+        Column::new("operation".to_string(), ColumnType::String),
+        Column::new("table_name".to_string(), ColumnType::String),
+        Column::new("rows".to_string(), ColumnType::Integer),
+    ];
+    TableDefinition::create(
+        config,
+        AUDIT_TABLE_NAME.to_string(),
+        columns,
+        HashMap::new(),
+        HashSet::new(),
+        HashSet::new(),
+        TableOptions::default(),
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// Appends to the audit log an operation performed by `caller` on a table.
+pub async fn record_audit_entry(
+    state: &DatabaseState,
+    caller: &str,
+    operation: &str,
+    table_name: &str,
+    rows: usize,
+) -> io::Result<()> {
+    let timestamp = clock().now_secs();
+
+    let table_lock = state.table_locks.get(AUDIT_TABLE_NAME);
+    let _guard = table_lock.write().await;
+
+    let table_definition =
+        TableDefinition::open(state.config.clone(), AUDIT_TABLE_NAME.to_string()).await?;
+    let mut table = table_definition.load().await?;
+    table
+        .insert(
+            vec![
+                "timestamp".to_string(),
+                "caller".to_string(),
+                "operation".to_string(),
+                "table_name".to_string(),
+                "rows".to_string(),
+            ],
+            vec![vec![
+                json!(timestamp),
+                json!(caller),
+                json!(operation),
+                json!(table_name),
+                json!(rows),
+            ]],
+            Some(timestamp),
+            None,
+        )
+        .await
+}
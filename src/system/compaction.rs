@@ -0,0 +1,84 @@
+use std::time::Duration;
+
+use log::info;
+use tokio::io;
+use tokio::time::interval;
+
+use crate::table::table::TableDefinition;
+use crate::transport::api::DatabaseState;
+
+/// Spawns the task which periodically compacts the partitions of all tables with deleted rows, if
+/// configured.
+pub fn spawn_compaction(state: DatabaseState) {
+    let Some(compaction) = &state.config.compaction else {
+        return;
+    };
+
+    let min_deleted_rows = compaction.min_deleted_rows;
+    let min_deleted_fraction = compaction.min_deleted_fraction;
+    let interval_secs = compaction.interval_secs;
+    info!(
+        "Partitions with at least {min_deleted_rows} deleted rows, and {min_deleted_fraction} of \
+        their rows deleted, compacted every {interval_secs}s"
+    );
+
+    tokio::spawn(async move {
+        let mut interval = interval(Duration::from_secs(interval_secs));
+        loop {
+            interval.tick().await;
+
+            // The files are left untouched in read-only mode, e.g. while they are backed up.
+            if state.read_only.is_enabled().await {
+                continue;
+            }
+
+            if let Err(error) =
+                compact_tables(&state, None, min_deleted_rows, min_deleted_fraction).await
+            {
+                info!("Error while compacting partitions: {}", error);
+            }
+        }
+    });
+}
+
+/// Spawns the task which compacts right away all the partitions with deleted rows of the table, or
+/// of all tables if none is given.
+pub fn spawn_manual_compaction(state: DatabaseState, table_name: Option<String>) {
+    tokio::spawn(async move {
+        info!("Started compacting the partitions with deleted rows");
+        match compact_tables(&state, table_name.as_deref(), 1, 0.0).await {
+            Ok(compacted_partitions) => info!(
+                "Finished compacting {} partitions with deleted rows",
+                compacted_partitions
+            ),
+            Err(error) => info!("Error while compacting partitions: {}", error),
+        }
+    });
+}
+
+async fn compact_tables(
+    state: &DatabaseState,
+    table_name: Option<&str>,
+    min_deleted_rows: u64,
+    min_deleted_fraction: f64,
+) -> io::Result<usize> {
+    let table_names = match table_name {
+        Some(table_name) => vec![table_name.to_string()],
+        None => TableDefinition::list(&state.config).await?,
+    };
+
+    let mut compacted_partitions = 0;
+    for table_name in table_names {
+        let table_lock = state.table_locks.get(&table_name);
+        let _guard = table_lock.write().await;
+
+        let table_definition = TableDefinition::open(state.config.clone(), table_name).await?;
+        let table = table_definition.load().await?;
+        compacted_partitions += table
+            .compact(min_deleted_rows, min_deleted_fraction)
+            .await?
+            .partitions;
+    }
+
+    Ok(compacted_partitions)
+}
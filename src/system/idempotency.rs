@@ -0,0 +1,80 @@
+use std::collections::{HashMap, VecDeque};
+use std::future::Future;
+use std::io::{Error, ErrorKind};
+use std::sync::{Arc, Mutex};
+
+use tokio::io;
+use tokio::sync::OnceCell;
+
+use crate::transport::api::OpResponse;
+
+/// The number of idempotency keys whose outcomes are kept, after which the oldest are forgotten.
+const MAX_IDEMPOTENCY_KEYS: usize = 10_000;
+
+/// A request sent with an idempotency key, with the outcome of its first execution.
+#[derive(Debug)]
+struct IdempotentRequest {
+    /// The body of the request, to reject the reuse of a key for a different request.
+    request: String,
+    outcome: OnceCell<OpResponse>,
+}
+
+#[derive(Debug, Default)]
+struct Outcomes {
+    requests: HashMap<String, Arc<IdempotentRequest>>,
+    /// The keys in the order in which they were first used.
+    keys: VecDeque<String>,
+}
+
+/// The outcomes of the requests sent with an idempotency key, so that their replays return the
+/// outcome of the first execution instead of executing the request again.
+///
+/// The outcomes are kept in memory only for the most recent keys, since the replays are expected
+/// shortly after the first request, and are lost when the instance restarts.
+#[derive(Debug, Default)]
+pub struct IdempotencyKeys {
+    outcomes: Mutex<Outcomes>,
+}
+
+impl IdempotencyKeys {
+    /// Executes `op` unless a request with the same `key` was already executed, in which case its
+    /// outcome is returned. A replay sent while the first request is executing waits for it.
+    pub async fn execute(
+        &self,
+        key: String,
+        request: String,
+        op: impl Future<Output = OpResponse>,
+    ) -> io::Result<OpResponse> {
+        let idempotent_request = {
+            let mut outcomes = self.outcomes.lock().unwrap();
+            match outcomes.requests.get(&key) {
+                Some(idempotent_request) => idempotent_request.clone(),
+                None => {
+                    let idempotent_request = Arc::new(IdempotentRequest {
+                        request: request.clone(),
+                        outcome: OnceCell::new(),
+                    });
+                    outcomes
+                        .requests
+                        .insert(key.clone(), idempotent_request.clone());
+                    outcomes.keys.push_back(key.clone());
+                    if outcomes.keys.len() > MAX_IDEMPOTENCY_KEYS {
+                        if let Some(oldest_key) = outcomes.keys.pop_front() {
+                            outcomes.requests.remove(&oldest_key);
+                        }
+                    }
+                    idempotent_request
+                }
+            }
+        };
+
+        if idempotent_request.request != request {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "The idempotency key was already used for a different request",
+            ));
+        }
+
+        Ok(idempotent_request.outcome.get_or_init(|| op).await.clone())
+    }
+}
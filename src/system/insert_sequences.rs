@@ -0,0 +1,127 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use log::info;
+use serde::{Deserialize, Serialize};
+use tokio::io;
+use tokio::sync::RwLock;
+use tokio::time::interval;
+
+use crate::config::Config;
+use crate::io::clock::clock;
+use crate::io::file::{read_json_or_default, write_json};
+use crate::system::build_system_path;
+use crate::transport::api::DatabaseState;
+
+const INSERT_SEQUENCES_FILE_NAME: &str = "insert_sequences.json";
+
+/// How long the sequence of an applied insert is kept, which is far longer than the master keeps
+/// retrying an insert.
+const APPLIED_SEQUENCE_RETENTION_SECS: u64 = 60 * 60;
+
+/// How often the sequences recorded since the last save are written to disk.
+const SAVE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The sequences of the inserts applied to a table.
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct AppliedSequences {
+    /// The time in seconds at which each insert was applied, by its sequence.
+    #[serde(default)]
+    applied_at: BTreeMap<u64, u64>,
+}
+
+/// The sequences of the inserts which the master sent to this instance, so that an insert retried
+/// by the master after an ambiguous failure, like a timeout, is applied only once.
+///
+/// The sequences are recorded in memory and saved periodically, so the ones recorded since the last
+/// save are lost if the instance crashes.
+#[derive(Debug)]
+pub struct InsertSequences {
+    path: PathBuf,
+    sequences: RwLock<HashMap<String, AppliedSequences>>,
+    changed: AtomicBool,
+}
+
+impl InsertSequences {
+    pub async fn load(config: &Config) -> io::Result<Self> {
+        let path = build_system_path(config);
+        let sequences: HashMap<String, AppliedSequences> =
+            read_json_or_default(INSERT_SEQUENCES_FILE_NAME, &path).await?;
+
+        info!("Loaded the insert sequences of {} tables", sequences.len());
+
+        Ok(Self {
+            path,
+            sequences: RwLock::new(sequences),
+            changed: AtomicBool::new(false),
+        })
+    }
+
+    /// Returns whether the insert with the sequence was already applied to the table.
+    pub async fn is_applied(&self, table_name: &str, sequence: u64) -> bool {
+        self.sequences
+            .read()
+            .await
+            .get(table_name)
+            .is_some_and(|a| a.applied_at.contains_key(&sequence))
+    }
+
+    /// Records that the insert with the sequence was applied to the table.
+    pub async fn record(&self, table_name: &str, sequence: u64) {
+        let mut sequences = self.sequences.write().await;
+        sequences
+            .entry(table_name.to_string())
+            .or_default()
+            .applied_at
+            .insert(sequence, clock().now_secs());
+        self.changed.store(true, Ordering::Relaxed);
+    }
+
+    pub async fn remove(&self, table_name: &str) {
+        if self.sequences.write().await.remove(table_name).is_some() {
+            self.changed.store(true, Ordering::Relaxed);
+        }
+    }
+
+    /// Writes the sequences to disk if they changed since the last save, forgetting the ones which
+    /// are too old to be retried.
+    pub async fn save(&self) -> io::Result<()> {
+        if !self.changed.swap(false, Ordering::Relaxed) {
+            return Ok(());
+        }
+
+        let mut sequences = self.sequences.write().await;
+        let cutoff = clock()
+            .now_secs()
+            .saturating_sub(APPLIED_SEQUENCE_RETENTION_SECS);
+        for applied_sequences in sequences.values_mut() {
+            applied_sequences
+                .applied_at
+                .retain(|_, applied_at| *applied_at >= cutoff);
+        }
+        sequences.retain(|_, a| !a.applied_at.is_empty());
+
+        let result = write_json(INSERT_SEQUENCES_FILE_NAME, &self.path, &*sequences).await;
+        if result.is_err() {
+            self.changed.store(true, Ordering::Relaxed);
+        }
+
+        result
+    }
+}
+
+/// Spawns the task which periodically saves the sequences of the applied inserts.
+pub fn spawn_insert_sequences_save(state: DatabaseState) {
+    tokio::spawn(async move {
+        let mut interval = interval(SAVE_INTERVAL);
+        loop {
+            interval.tick().await;
+
+            if let Err(error) = state.insert_sequences.save().await {
+                info!("Error while saving the insert sequences: {}", error);
+            }
+        }
+    });
+}
@@ -0,0 +1,65 @@
+use crate::table::column::{Column, ColumnType, ColumnValue};
+use crate::table::cursor::Row;
+
+/// The virtual tables describing the database, which are queried like the other tables but built
+/// from the state of the cluster.
+#[derive(Debug, Clone, Copy)]
+pub enum SystemTable {
+    /// The tables of the database, with their number of rows in the cluster.
+    Tables,
+    /// The columns of all the tables.
+    Columns,
+    /// The shards of the master, with whether they are reachable.
+    Shards,
+}
+
+impl SystemTable {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "__tables" => Some(SystemTable::Tables),
+            "__columns" => Some(SystemTable::Columns),
+            "__shards" => Some(SystemTable::Shards),
+            _ => None,
+        }
+    }
+
+    pub fn columns(&self) -> Vec<Column> {
+        let columns: &[(&str, ColumnType)] = match self {
+            SystemTable::Tables => &[
+                ("name", ColumnType::String),
+                ("rows", ColumnType::Integer),
+                ("columns", ColumnType::Integer),
+            ],
+            SystemTable::Columns => &[
+                ("table", ColumnType::String),
+                ("name", ColumnType::String),
+                ("type", ColumnType::String),
+            ],
+            SystemTable::Shards => &[
+                ("ip_port", ColumnType::String),
+                ("reachable", ColumnType::Integer),
+                ("error", ColumnType::String),
+            ],
+        };
+
+        columns
+            .iter()
+            .map(|(name, ty)| Column::new(name.to_string(), *ty))
+            .collect()
+    }
+
+    /// Builds a row of the table from its values, in the order of the columns.
+    pub fn row(&self, values: Vec<ColumnValue>) -> Option<Row<ColumnValue>> {
+        Row::from_components(0, 0, self.columns().into_iter().zip(values))
+    }
+}
+
+impl<'a> From<&'a SystemTable> for &'a str {
+    fn from(value: &'a SystemTable) -> Self {
+        match value {
+            SystemTable::Tables => "__tables",
+            SystemTable::Columns => "__columns",
+            SystemTable::Shards => "__shards",
+        }
+    }
+}
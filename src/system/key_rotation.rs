@@ -0,0 +1,36 @@
+use log::info;
+use tokio::io;
+
+use crate::table::table::TableDefinition;
+use crate::transport::api::DatabaseState;
+
+/// Spawns the task which re-encrypts with the active key all the files encrypted with a previous
+/// key, after which the previous keys can be removed from the config.
+///
+/// Tables are re-encrypted one at a time, so the other tables stay available in the meantime.
+pub fn spawn_key_rotation(state: DatabaseState) {
+    tokio::spawn(async move {
+        info!("Started re-encrypting files with the active key");
+        match rotate_key(&state).await {
+            Ok(reencrypted_files) => info!(
+                "Finished re-encrypting {} files with the active key",
+                reencrypted_files
+            ),
+            Err(error) => info!("Error while re-encrypting files: {}", error),
+        }
+    });
+}
+
+async fn rotate_key(state: &DatabaseState) -> io::Result<usize> {
+    let mut reencrypted_files = 0;
+    for table_name in TableDefinition::list(&state.config).await? {
+        let table_lock = state.table_locks.get(&table_name);
+        let _guard = table_lock.write().await;
+
+        let table_definition = TableDefinition::open(state.config.clone(), table_name).await?;
+        let table = table_definition.load().await?;
+        reencrypted_files += table.rotate_key().await?;
+    }
+
+    Ok(reencrypted_files)
+}
@@ -0,0 +1,77 @@
+use std::time::Duration;
+
+use log::info;
+use tokio::io;
+use tokio::time::interval;
+
+use crate::table::memtable::memtables;
+use crate::table::table::TableDefinition;
+use crate::transport::api::DatabaseState;
+
+/// Spawns the task which periodically flushes the rows buffered in the memtables of the tables to
+/// their files, if the memtable is configured.
+pub fn spawn_memtable_flush(state: DatabaseState) {
+    let Some(memtable) = &state.config.memtable else {
+        return;
+    };
+
+    let flush_interval_ms = memtable.flush_interval_ms;
+    info!(
+        "Inserts buffered up to {} rows and flushed every {flush_interval_ms}ms",
+        memtable.max_rows
+    );
+
+    tokio::spawn(async move {
+        let mut interval = interval(Duration::from_millis(flush_interval_ms));
+        loop {
+            interval.tick().await;
+
+            // The files are left untouched in read-only mode, e.g. while they are backed up.
+            if state.read_only.is_enabled().await {
+                continue;
+            }
+
+            if let Err(error) = flush_memtables(&state).await {
+                info!("Error while flushing the memtables: {}", error);
+            }
+        }
+    });
+}
+
+/// Flushes the rows buffered in the memtables of all tables to their files and records the
+/// sequences of their inserts as applied.
+///
+/// An error while flushing a table doesn't stop the others from being flushed, and the first one is
+/// returned once all were tried.
+pub async fn flush_memtables(state: &DatabaseState) -> io::Result<()> {
+    let mut result = Ok(());
+    for table_name in memtables().table_names() {
+        if let Err(error) = flush_memtable(state, &table_name).await {
+            info!(
+                "Error while flushing the memtable of table {}: {}",
+                table_name, error
+            );
+            if result.is_ok() {
+                result = Err(error);
+            }
+        }
+    }
+
+    result
+}
+
+async fn flush_memtable(state: &DatabaseState, table_name: &str) -> io::Result<()> {
+    let table_lock = state.table_locks.get(table_name);
+    let _guard = table_lock.write().await;
+
+    let table_definition =
+        TableDefinition::open(state.config.clone(), table_name.to_string()).await?;
+    let mut table = table_definition.load().await?;
+    table.flush_memtable().await?;
+
+    for sequence in memtables().take_flushed_sequences(table_name) {
+        state.insert_sequences.record(table_name, sequence).await;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,58 @@
+use std::time::Duration;
+
+use log::info;
+use tokio::io;
+use tokio::time::interval;
+
+use crate::io::clock::clock;
+use crate::table::table::TableDefinition;
+use crate::transport::api::DatabaseState;
+
+/// Spawns the task which periodically merges the small partitions of all tables, if configured.
+pub fn spawn_merging(state: DatabaseState) {
+    let Some(merging) = &state.config.merging else {
+        return;
+    };
+
+    let min_partition_rows = merging.min_partition_rows;
+    let interval_secs = merging.interval_secs;
+    info!("Partitions with less than {min_partition_rows} rows merged every {interval_secs}s");
+
+    tokio::spawn(async move {
+        let mut interval = interval(Duration::from_secs(interval_secs));
+        loop {
+            interval.tick().await;
+
+            // The files are left untouched in read-only mode, e.g. while they are backed up.
+            if state.read_only.is_enabled().await {
+                continue;
+            }
+
+            // Only the partitions whose window is over are merged, since the others still receive
+            // rows.
+            let now = clock().now_secs();
+            if let Err(error) = merge_small_partitions(&state, min_partition_rows, now).await {
+                info!("Error while merging partitions: {}", error);
+            }
+        }
+    });
+}
+
+async fn merge_small_partitions(
+    state: &DatabaseState,
+    min_partition_rows: u64,
+    cutoff: u64,
+) -> io::Result<()> {
+    for table_name in TableDefinition::list(&state.config).await? {
+        let table_lock = state.table_locks.get(&table_name);
+        let _guard = table_lock.write().await;
+
+        let table_definition = TableDefinition::open(state.config.clone(), table_name).await?;
+        let mut table = table_definition.load().await?;
+        table
+            .merge_small_partitions(min_partition_rows, cutoff)
+            .await?;
+    }
+
+    Ok(())
+}
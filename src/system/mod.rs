@@ -0,0 +1,34 @@
+use std::path::PathBuf;
+
+use crate::config::Config;
+
+pub mod audit;
+pub mod compaction;
+pub mod idempotency;
+pub mod insert_sequences;
+pub mod introspection;
+pub mod key_rotation;
+pub mod memtable;
+pub mod merging;
+pub mod placement;
+pub mod prepared_query;
+pub mod read_only;
+pub mod retention;
+pub mod saved_query;
+pub mod scheduler;
+pub mod session;
+pub mod stats_sync;
+pub mod telemetry;
+pub mod tiering;
+pub mod usage;
+pub mod users;
+
+/// Builds the path of the directory which contains the system tables of the database.
+pub fn build_system_path(config: &Config) -> PathBuf {
+    let mut path_buf = PathBuf::new();
+    path_buf.push(config.database_path.clone());
+    path_buf.push(config.database_name.clone());
+    path_buf.push("__system");
+
+    path_buf
+}
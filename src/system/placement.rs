@@ -0,0 +1,79 @@
+use std::collections::{BTreeSet, HashMap};
+use std::path::PathBuf;
+
+use log::info;
+use tokio::io;
+use tokio::sync::RwLock;
+
+use crate::config::Config;
+use crate::io::file::{read_json_or_default, write_json};
+use crate::system::build_system_path;
+
+const PLACEMENTS_FILE_NAME: &str = "placements.json";
+
+/// The shards which store data of each table, so that queries are sent only to them.
+///
+/// Tables without a placement, like the ones created before placements were tracked, are stored
+/// on all the shards.
+#[derive(Debug)]
+pub struct Placements {
+    path: PathBuf,
+    placements: RwLock<HashMap<String, BTreeSet<String>>>,
+}
+
+impl Placements {
+    pub async fn load(config: &Config) -> io::Result<Self> {
+        let path = build_system_path(config);
+        let placements: HashMap<String, BTreeSet<String>> =
+            read_json_or_default(PLACEMENTS_FILE_NAME, &path).await?;
+
+        info!("Loaded placements of {} tables", placements.len());
+
+        Ok(Self {
+            path,
+            placements: RwLock::new(placements),
+        })
+    }
+
+    /// Returns the addresses of the shards storing data of the table, or `None` if unknown.
+    pub async fn get(&self, table_name: &str) -> Option<BTreeSet<String>> {
+        self.placements.read().await.get(table_name).cloned()
+    }
+
+    /// Starts tracking the placement of a new table, which isn't stored on any shard yet.
+    pub async fn create(&self, table_name: &str) -> io::Result<()> {
+        let mut placements = self.placements.write().await;
+        placements.insert(table_name.to_string(), BTreeSet::new());
+
+        write_json(PLACEMENTS_FILE_NAME, &self.path, &*placements).await
+    }
+
+    /// Records that the shards at `ip_ports` store data of the table.
+    ///
+    /// Tables without a placement are left untracked, since their data might be on any shard.
+    pub async fn add(&self, table_name: &str, ip_ports: &[&str]) -> io::Result<()> {
+        let mut placements = self.placements.write().await;
+        let Some(placement) = placements.get_mut(table_name) else {
+            return Ok(());
+        };
+
+        let mut changed = false;
+        for ip_port in ip_ports {
+            changed |= placement.insert(ip_port.to_string());
+        }
+        if !changed {
+            return Ok(());
+        }
+
+        write_json(PLACEMENTS_FILE_NAME, &self.path, &*placements).await
+    }
+
+    pub async fn remove(&self, table_name: &str) -> io::Result<()> {
+        let mut placements = self.placements.write().await;
+        if placements.remove(table_name).is_none() {
+            return Ok(());
+        }
+
+        write_json(PLACEMENTS_FILE_NAME, &self.path, &*placements).await
+    }
+}
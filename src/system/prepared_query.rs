@@ -0,0 +1,112 @@
+use std::collections::BTreeMap;
+use std::io::{Error, ErrorKind};
+use std::sync::Mutex;
+
+use tokio::io;
+
+use crate::table::expression::{Expression, OrderItem, SelectItem};
+use crate::transport::api::QueryRequest;
+
+/// The number of prepared queries which are kept, after which the oldest are forgotten.
+const MAX_PREPARED_QUERIES: usize = 10_000;
+
+/// A query which is parsed once when prepared and then executed with different parameters, which
+/// are bound to its `$1`-style placeholders.
+#[derive(Debug, Clone)]
+pub struct PreparedQuery {
+    /// The session which prepared the query, which is the only one that can execute it.
+    session_name: String,
+    pub request: QueryRequest,
+    pub expressions: PreparedExpressions,
+}
+
+/// The expressions of a prepared query, parsed when it's prepared, in which the placeholders are
+/// bound to the parameters of each execution.
+#[derive(Debug, Clone)]
+pub struct PreparedExpressions {
+    pub select: Vec<SelectItem>,
+    /// The grouped expressions, unless the query is grouped by all its selected ones.
+    pub group_by: Option<Vec<Expression>>,
+    pub filter: Option<Expression>,
+    pub order_by: Option<Vec<OrderItem>>,
+    /// The expressions of the subquery whose result is queried, if any.
+    pub subquery: Option<Box<PreparedExpressions>>,
+}
+
+impl PreparedExpressions {
+    /// Returns the number of parameters of the query, which is its highest placeholder, including
+    /// the ones of its subquery.
+    pub fn parameters(&self) -> usize {
+        let expressions = self
+            .select
+            .iter()
+            .map(|s| &s.expression)
+            .chain(self.group_by.iter().flatten())
+            .chain(self.filter.iter())
+            .chain(self.order_by.iter().flatten().map(|o| &o.expression));
+
+        expressions
+            .map(|e| e.parameters())
+            .chain(self.subquery.iter().map(|s| s.parameters()))
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+#[derive(Debug, Default)]
+struct Queries {
+    /// The prepared queries by id, which are increasing, so the first is the oldest.
+    queries: BTreeMap<u64, PreparedQuery>,
+    next_id: u64,
+}
+
+/// The queries prepared by the callers.
+///
+/// The prepared queries are kept in memory, so they are lost when the instance restarts.
+#[derive(Debug, Default)]
+pub struct PreparedQueries {
+    queries: Mutex<Queries>,
+}
+
+impl PreparedQueries {
+    /// Stores the query prepared by the session of `session_name`, returning its id.
+    pub fn prepare(
+        &self,
+        session_name: String,
+        request: QueryRequest,
+        expressions: PreparedExpressions,
+    ) -> u64 {
+        let mut queries = self.queries.lock().unwrap();
+        let id = queries.next_id;
+        queries.next_id += 1;
+        queries.queries.insert(
+            id,
+            PreparedQuery {
+                session_name,
+                request,
+                expressions,
+            },
+        );
+        if queries.queries.len() > MAX_PREPARED_QUERIES {
+            queries.queries.pop_first();
+        }
+
+        id
+    }
+
+    pub fn get(&self, session_name: &str, id: u64) -> io::Result<PreparedQuery> {
+        self.queries
+            .lock()
+            .unwrap()
+            .queries
+            .get(&id)
+            .filter(|q| q.session_name == session_name)
+            .cloned()
+            .ok_or_else(|| {
+                Error::new(
+                    ErrorKind::NotFound,
+                    format!("The prepared query {} does not exist", id),
+                )
+            })
+    }
+}
@@ -0,0 +1,80 @@
+use std::io::{Error, ErrorKind};
+use std::path::PathBuf;
+
+use log::info;
+use serde::{Deserialize, Serialize};
+use tokio::io;
+use tokio::sync::RwLock;
+
+use crate::config::Config;
+use crate::io::file::{read_json_or_default, write_json};
+use crate::system::build_system_path;
+
+const READ_ONLY_FILE_NAME: &str = "read_only.json";
+
+/// Whether the instance rejects the writes, with the reason given by the admin who enabled it.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct ReadOnlyState {
+    pub enabled: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+/// The read-only mode of the instance, in which the inserts and the table changes are rejected
+/// and the background tasks leave the files untouched, e.g. while taking a backup.
+///
+/// The mode is persisted, so that a restart doesn't accept writes again by accident.
+#[derive(Debug)]
+pub struct ReadOnlyMode {
+    path: PathBuf,
+    state: RwLock<ReadOnlyState>,
+}
+
+impl ReadOnlyMode {
+    pub async fn load(config: &Config) -> io::Result<Self> {
+        let path = build_system_path(config);
+        let state: ReadOnlyState = read_json_or_default(READ_ONLY_FILE_NAME, &path).await?;
+
+        if state.enabled {
+            info!("The instance is in read-only mode");
+        }
+
+        Ok(Self {
+            path,
+            state: RwLock::new(state),
+        })
+    }
+
+    pub async fn get(&self) -> ReadOnlyState {
+        self.state.read().await.clone()
+    }
+
+    pub async fn is_enabled(&self) -> bool {
+        self.state.read().await.enabled
+    }
+
+    pub async fn set(&self, new_state: ReadOnlyState) -> io::Result<()> {
+        let mut state = self.state.write().await;
+        write_json(READ_ONLY_FILE_NAME, &self.path, &new_state).await?;
+        *state = new_state;
+
+        Ok(())
+    }
+
+    /// Fails if the instance is in read-only mode, so that the write is rejected.
+    pub async fn check_writable(&self) -> io::Result<()> {
+        let state = self.state.read().await;
+        if !state.enabled {
+            return Ok(());
+        }
+
+        let reason = match &state.reason {
+            Some(reason) => format!(": {}", reason),
+            None => String::new(),
+        };
+        Err(Error::new(
+            ErrorKind::ReadOnlyFilesystem,
+            format!("The instance is in read-only mode{}", reason),
+        ))
+    }
+}
@@ -0,0 +1,55 @@
+use std::time::Duration;
+
+use log::info;
+use tokio::io;
+use tokio::time::interval;
+
+use crate::config::default_retention_interval_secs;
+use crate::io::clock::clock;
+use crate::table::table::TableDefinition;
+use crate::transport::api::DatabaseState;
+
+/// Spawns the task which periodically drops the rows that are older than the TTL of their table,
+/// or than the configured retention for the tables without one.
+pub fn spawn_retention(state: DatabaseState) {
+    let retention = state.config.retention.as_ref();
+    let interval_secs = retention.map_or_else(default_retention_interval_secs, |r| r.interval_secs);
+    if let Some(max_age_secs) = retention.and_then(|r| r.max_age_secs) {
+        info!("Retention of {max_age_secs}s applied every {interval_secs}s");
+    }
+
+    tokio::spawn(async move {
+        let mut interval = interval(Duration::from_secs(interval_secs));
+        loop {
+            interval.tick().await;
+
+            // The files are left untouched in read-only mode, e.g. while they are backed up.
+            if state.read_only.is_enabled().await {
+                continue;
+            }
+
+            if let Err(error) = apply_retention(&state).await {
+                info!("Error while applying retention: {}", error);
+            }
+        }
+    });
+}
+
+async fn apply_retention(state: &DatabaseState) -> io::Result<()> {
+    let now = clock().now_secs();
+    for table_name in TableDefinition::list(&state.config).await? {
+        let table_lock = state.table_locks.get(&table_name);
+        let _guard = table_lock.write().await;
+
+        let table_definition = TableDefinition::open(state.config.clone(), table_name).await?;
+        let Some(max_age_secs) = table_definition.max_age_secs() else {
+            continue;
+        };
+        let mut table = table_definition.load().await?;
+        table
+            .apply_retention(now.saturating_sub(max_age_secs))
+            .await?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,165 @@
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind};
+use std::path::PathBuf;
+
+use log::info;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::io;
+use tokio::sync::RwLock;
+
+use crate::config::Config;
+use crate::io::file::{read_json_or_default, write_json};
+use crate::system::build_system_path;
+use crate::transport::api::QueryRequest;
+
+const SAVED_QUERIES_FILE_NAME: &str = "saved_queries.json";
+
+/// A named query which can be run by name, optionally with parameters.
+///
+/// The query is stored as a template in which every `$param` placeholder is substituted at run
+/// time. A string which consists only of a placeholder is replaced with the parameter value as is,
+/// so that also non-string values (e.g. numbers or lists of columns) can be supplied.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct SavedQuery {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    pub query: Value,
+}
+
+impl SavedQuery {
+    pub fn validate(&self) -> io::Result<()> {
+        if self.name.is_empty() || !self.name.chars().all(|c| c.is_alphanumeric() || c == '_') {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "The name of a saved query must contain only alphanumeric characters and underscores",
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Builds the [`QueryRequest`] of this saved query by substituting all its placeholders with
+    /// the supplied parameters.
+    pub fn instantiate(&self, params: &HashMap<String, Value>) -> io::Result<QueryRequest> {
+        let query = substitute(self.query.clone(), params)?;
+
+        serde_json::from_value(query).map_err(|e| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "The saved query '{}' is not a valid query: {}",
+                    self.name, e
+                ),
+            )
+        })
+    }
+}
+
+fn substitute(value: Value, params: &HashMap<String, Value>) -> io::Result<Value> {
+    match value {
+        Value::String(string) => {
+            // If the entire string is a placeholder, we replace it with the value itself.
+            if let Some(param) = string.strip_prefix('$') {
+                if is_param_name(param) {
+                    return get_param(params, param).cloned();
+                }
+            }
+
+            Ok(Value::String(substitute_in_string(&string, params)?))
+        }
+        Value::Array(values) => Ok(Value::Array(
+            values
+                .into_iter()
+                .map(|v| substitute(v, params))
+                .collect::<io::Result<_>>()?,
+        )),
+        Value::Object(map) => Ok(Value::Object(
+            map.into_iter()
+                .map(|(k, v)| Ok((k, substitute(v, params)?)))
+                .collect::<io::Result<_>>()?,
+        )),
+        value => Ok(value),
+    }
+}
+
+fn substitute_in_string(string: &str, params: &HashMap<String, Value>) -> io::Result<String> {
+    let mut result = String::with_capacity(string.len());
+
+    let mut rest = string;
+    while let Some(start) = rest.find('$') {
+        result.push_str(&rest[..start]);
+
+        let after = &rest[start + 1..];
+        let end = after
+            .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+            .unwrap_or(after.len());
+        if end == 0 {
+            // A lone `$` is not a placeholder, thus we keep it.
+            result.push('$');
+        } else {
+            match get_param(params, &after[..end])? {
+                Value::String(value) => result.push_str(value),
+                value => result.push_str(&value.to_string()),
+            }
+        }
+
+        rest = &after[end..];
+    }
+    result.push_str(rest);
+
+    Ok(result)
+}
+
+fn is_param_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_alphanumeric() || c == '_')
+}
+
+fn get_param<'a>(params: &'a HashMap<String, Value>, name: &str) -> io::Result<&'a Value> {
+    params.get(name).ok_or_else(|| {
+        Error::new(
+            ErrorKind::InvalidInput,
+            format!("Missing value for parameter '{}'", name),
+        )
+    })
+}
+
+/// The system table of saved queries, which is persisted in the system directory of the database.
+#[derive(Debug)]
+pub struct SavedQueries {
+    path: PathBuf,
+    queries: RwLock<HashMap<String, SavedQuery>>,
+}
+
+impl SavedQueries {
+    pub async fn load(config: &Config) -> io::Result<Self> {
+        let path = build_system_path(config);
+        let queries: HashMap<String, SavedQuery> =
+            read_json_or_default(SAVED_QUERIES_FILE_NAME, &path).await?;
+
+        info!("Loaded {} saved queries", queries.len());
+
+        Ok(Self {
+            path,
+            queries: RwLock::new(queries),
+        })
+    }
+
+    pub async fn save(&self, saved_query: SavedQuery) -> io::Result<()> {
+        saved_query.validate()?;
+
+        let mut queries = self.queries.write().await;
+        queries.insert(saved_query.name.clone(), saved_query);
+        write_json(SAVED_QUERIES_FILE_NAME, &self.path, &*queries).await
+    }
+
+    pub async fn get(&self, name: &str) -> io::Result<SavedQuery> {
+        self.queries.read().await.get(name).cloned().ok_or_else(|| {
+            Error::new(
+                ErrorKind::NotFound,
+                format!("The saved query '{}' does not exist", name),
+            )
+        })
+    }
+}
@@ -0,0 +1,92 @@
+use std::str::FromStr;
+
+use chrono::Utc;
+use log::info;
+use tokio::io;
+use tokio::time::sleep;
+
+use crate::config::{Schedule, ScheduleMode};
+use crate::system::audit::SCHEDULER_CALLER;
+use crate::table::table::TableDefinition;
+use crate::transport::api::{
+    execute_create_table, execute_insert, execute_query, CreateTableRequest, DatabaseState,
+    InsertRequest,
+};
+
+/// Spawns a task for each schedule in the config, which runs the scheduled saved query every time
+/// its cron expression fires.
+pub fn spawn_schedules(state: DatabaseState) {
+    for index in 0..state.config.schedules.len() {
+        let state = state.clone();
+        tokio::spawn(async move { run_schedule(state, index).await });
+    }
+}
+
+async fn run_schedule(state: DatabaseState, index: usize) {
+    let schedule = &state.config.schedules[index];
+    let cron = match cron::Schedule::from_str(&schedule.cron) {
+        Ok(cron) => cron,
+        Err(error) => {
+            info!(
+                "Invalid cron expression '{}' for scheduled query '{}': {}",
+                schedule.cron, schedule.query, error
+            );
+            return;
+        }
+    };
+
+    info!(
+        "Scheduled query '{}' into '{}' with cron '{}'",
+        schedule.query, schedule.into, schedule.cron
+    );
+
+    for next_run in cron.upcoming_owned(Utc) {
+        let delay = (next_run - Utc::now()).to_std().unwrap_or_default();
+        sleep(delay).await;
+
+        match execute_schedule(&state, schedule).await {
+            Ok(rows) => info!(
+                "Scheduled query '{}' wrote {} rows into '{}'",
+                schedule.query, rows, schedule.into
+            ),
+            Err(error) => info!(
+                "Error while running scheduled query '{}': {}",
+                schedule.query, error
+            ),
+        }
+    }
+}
+
+async fn execute_schedule(state: &DatabaseState, schedule: &Schedule) -> io::Result<usize> {
+    let saved_query = state.saved_queries.get(&schedule.query).await?;
+    let query_request = saved_query.instantiate(&schedule.params)?;
+    let (columns, values) = execute_query(state, query_request)
+        .await
+        .into_table_data()?;
+
+    // The overwritten rows are removed under the same lock as the insert of the new ones, so the
+    // destination table keeps its options and indexes and is never seen half written.
+    let replace = matches!(schedule.mode, ScheduleMode::Overwrite);
+
+    // Without rows we have nothing to append, and nothing to overwrite if the destination table
+    // doesn't exist yet.
+    if values.is_empty()
+        && (!replace || !TableDefinition::exists(&state.config, &schedule.into).await?)
+    {
+        return Ok(0);
+    }
+
+    // Creating a table is idempotent, so we can do it on every run to make sure that the
+    // destination table exists.
+    if !values.is_empty() {
+        let create_table = CreateTableRequest::new(schedule.into.clone(), columns.clone());
+        execute_create_table(state, create_table, SCHEDULER_CALLER, None).await?;
+    }
+
+    let rows = values.len();
+    let column_names = columns.iter().map(|c| c.name().to_string()).collect();
+    let insert = InsertRequest::new(column_names, schedule.into.clone(), values).replacing(replace);
+    execute_insert(state, insert, SCHEDULER_CALLER).await?;
+
+    Ok(rows)
+}
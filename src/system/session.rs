@@ -0,0 +1,137 @@
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tokio::io;
+use tokio::sync::RwLock;
+
+use crate::config::Config;
+
+/// The format in which the query results are returned.
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    #[default]
+    Json,
+    /// A header with the names of the columns followed by a line per row.
+    Csv,
+}
+
+impl OutputFormat {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "json" => Some(OutputFormat::Json),
+            "csv" => Some(OutputFormat::Csv),
+            _ => None,
+        }
+    }
+}
+
+/// How the null values of the query results are returned.
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NullHandling {
+    #[default]
+    Null,
+    /// The null values are replaced by the default value of their column type, e.g. `0` for the
+    /// integers and an empty string for the strings.
+    Default,
+}
+
+impl NullHandling {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "null" => Some(NullHandling::Null),
+            "default" => Some(NullHandling::Default),
+            _ => None,
+        }
+    }
+}
+
+/// The defaults applied to the queries of a caller, unless its requests override them.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct SessionSettings {
+    /// The time in milliseconds after which the queries fail.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout_ms: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output_format: Option<OutputFormat>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub null_handling: Option<NullHandling>,
+    /// The database which the queries target, which must be the one served by the instance.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub database: Option<String>,
+}
+
+impl SessionSettings {
+    /// Returns the settings with the ones set in `overrides` replacing these.
+    pub fn merge(self, overrides: SessionSettings) -> Self {
+        Self {
+            timeout_ms: overrides.timeout_ms.or(self.timeout_ms),
+            output_format: overrides.output_format.or(self.output_format),
+            null_handling: overrides.null_handling.or(self.null_handling),
+            database: overrides.database.or(self.database),
+        }
+    }
+
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout_ms.map(Duration::from_millis)
+    }
+
+    pub fn output_format(&self) -> OutputFormat {
+        self.output_format.unwrap_or_default()
+    }
+
+    pub fn null_handling(&self) -> NullHandling {
+        self.null_handling.unwrap_or_default()
+    }
+
+    /// Checks that the settings can be applied to the queries of the instance.
+    pub fn validate(&self, config: &Config) -> io::Result<()> {
+        if self.timeout_ms == Some(0) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "The timeout of the session must be greater than 0",
+            ));
+        }
+
+        if let Some(database) = self
+            .database
+            .as_ref()
+            .filter(|d| **d != config.database_name)
+        {
+            return Err(Error::new(
+                ErrorKind::NotFound,
+                format!("The database {} is not served by this instance", database),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// The session settings of the callers, identified by the user of their api key or by their ip
+/// address if authentication is disabled.
+///
+/// The sessions are kept in memory, so they are lost when the instance restarts.
+#[derive(Debug, Default)]
+pub struct Sessions {
+    sessions: RwLock<HashMap<String, SessionSettings>>,
+}
+
+impl Sessions {
+    pub async fn get(&self, name: &str) -> SessionSettings {
+        self.sessions
+            .read()
+            .await
+            .get(name)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Replaces the settings of the session of `name`.
+    pub async fn set(&self, name: String, settings: SessionSettings) {
+        self.sessions.write().await.insert(name, settings);
+    }
+}
@@ -0,0 +1,43 @@
+use std::time::Duration;
+
+use log::info;
+use tokio::io;
+use tokio::time::interval;
+
+use crate::table::table::TableDefinition;
+use crate::transport::api::DatabaseState;
+
+/// Spawns the task which periodically syncs the stats of all tables to disk.
+pub fn spawn_stats_sync(state: DatabaseState) {
+    let interval_secs = state.config.stats_sync.interval_secs;
+    if interval_secs == 0 {
+        return;
+    }
+
+    info!("Table stats synced to disk every {interval_secs}s");
+
+    tokio::spawn(async move {
+        let mut interval = interval(Duration::from_secs(interval_secs));
+        loop {
+            interval.tick().await;
+
+            if let Err(error) = sync_stats(&state).await {
+                info!("Error while syncing the table stats: {}", error);
+            }
+        }
+    });
+}
+
+/// Syncs the stats of all tables to disk, waiting for the running writes of each table.
+pub async fn sync_stats(state: &DatabaseState) -> io::Result<()> {
+    for table_name in TableDefinition::list(&state.config).await? {
+        let table_lock = state.table_locks.get(&table_name);
+        let _guard = table_lock.read().await;
+
+        let table_definition = TableDefinition::open(state.config.clone(), table_name).await?;
+        let mut table = table_definition.load().await?;
+        table.sync_stats().await?;
+    }
+
+    Ok(())
+}
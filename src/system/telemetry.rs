@@ -0,0 +1,335 @@
+use std::collections::hash_map::RandomState;
+use std::future::Future;
+use std::hash::{BuildHasher, Hasher};
+use std::io::{Error, ErrorKind};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use log::info;
+use reqwest::Client;
+use serde_json::{json, Value};
+use tokio::io;
+use tokio::time::interval;
+
+use crate::table::metrics::{column_writes, ColumnWrites};
+use crate::transport::api::DatabaseState;
+
+/// The header with the W3C trace context of a request, which makes the spans of a shard children
+/// of the span of the master.
+pub const TRACEPARENT_HEADER: &str = "traceparent";
+
+/// The number of finished spans kept until the next export, after which new spans are dropped.
+const MAX_BUFFERED_SPANS: usize = 10_000;
+
+/// The spans finished since the last export.
+static FINISHED_SPANS: OnceLock<Mutex<Vec<FinishedSpan>>> = OnceLock::new();
+
+tokio::task_local! {
+    /// The span of the request handled by the task, if the requests are traced.
+    static CURRENT_SPAN: Arc<Span>;
+}
+
+/// The ids identifying a span within its trace.
+#[derive(Debug, Clone, Copy)]
+pub struct SpanContext {
+    trace_id: u128,
+    span_id: u64,
+}
+
+impl SpanContext {
+    /// Parses the `traceparent` header, e.g. `00-<32 hex trace id>-<16 hex span id>-01`.
+    pub fn from_traceparent(traceparent: &str) -> Option<Self> {
+        let mut parts = traceparent.split('-');
+        let (Some("00"), Some(trace_id), Some(span_id)) =
+            (parts.next(), parts.next(), parts.next())
+        else {
+            return None;
+        };
+
+        Some(Self {
+            trace_id: u128::from_str_radix(trace_id, 16).ok()?,
+            span_id: u64::from_str_radix(span_id, 16).ok()?,
+        })
+    }
+
+    pub fn traceparent(&self) -> String {
+        format!("00-{:032x}-{:016x}-01", self.trace_id, self.span_id)
+    }
+}
+
+/// The kind of a span, with the values of the OTLP protocol.
+#[derive(Debug, Clone, Copy)]
+pub enum SpanKind {
+    /// The handling of a request received by the instance.
+    Server = 2,
+    /// A request sent by the instance to a shard.
+    Client = 3,
+}
+
+/// A span which is running, whose attributes can be set until it's finished.
+#[derive(Debug)]
+pub struct Span {
+    context: SpanContext,
+    parent_span_id: Option<u64>,
+    name: String,
+    kind: SpanKind,
+    start: SystemTime,
+    attributes: Mutex<Vec<(String, String)>>,
+}
+
+impl Span {
+    /// Starts a span, which is the root of a new trace if it has no parent.
+    pub fn start(name: String, kind: SpanKind, parent: Option<SpanContext>) -> Self {
+        let trace_id = match parent {
+            Some(parent) => parent.trace_id,
+            None => ((random_id() as u128) << 64) | random_id() as u128,
+        };
+
+        Self {
+            context: SpanContext {
+                trace_id,
+                span_id: random_id(),
+            },
+            parent_span_id: parent.map(|p| p.span_id),
+            name,
+            kind,
+            start: SystemTime::now(),
+            attributes: Mutex::new(vec![]),
+        }
+    }
+
+    pub fn context(&self) -> SpanContext {
+        self.context
+    }
+
+    pub fn set_attribute(&self, key: &str, value: String) {
+        self.attributes
+            .lock()
+            .unwrap()
+            .push((key.to_string(), value));
+    }
+
+    /// Finishes the span, which is exported with the next batch.
+    pub fn finish(&self, error: Option<String>) {
+        let span = FinishedSpan {
+            context: self.context,
+            parent_span_id: self.parent_span_id,
+            name: self.name.clone(),
+            kind: self.kind,
+            start: self.start,
+            end: SystemTime::now(),
+            attributes: self.attributes.lock().unwrap().clone(),
+            error,
+        };
+
+        let mut finished_spans = FINISHED_SPANS.get_or_init(Default::default).lock().unwrap();
+        if finished_spans.len() < MAX_BUFFERED_SPANS {
+            finished_spans.push(span);
+        }
+    }
+}
+
+#[derive(Debug)]
+struct FinishedSpan {
+    context: SpanContext,
+    parent_span_id: Option<u64>,
+    name: String,
+    kind: SpanKind,
+    start: SystemTime,
+    end: SystemTime,
+    attributes: Vec<(String, String)>,
+    error: Option<String>,
+}
+
+/// Runs the future with the span as the current span of the task.
+pub async fn in_span<F: Future>(span: Arc<Span>, future: F) -> F::Output {
+    CURRENT_SPAN.scope(span, future).await
+}
+
+/// Returns the context of the current span, if the request handled by the task is traced.
+pub fn current_span() -> Option<SpanContext> {
+    CURRENT_SPAN.try_with(|s| s.context()).ok()
+}
+
+/// Sets an attribute of the current span, if the request handled by the task is traced.
+pub fn set_span_attribute(key: &str, value: impl FnOnce() -> String) {
+    let _ = CURRENT_SPAN.try_with(|s| s.set_attribute(key, value()));
+}
+
+/// Returns a random id, using the random keys of the hasher of the standard library.
+fn random_id() -> u64 {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u64(COUNTER.fetch_add(1, Ordering::Relaxed));
+    hasher.finish()
+}
+
+/// Spawns the task which periodically exports the spans and the metrics to the configured
+/// OpenTelemetry collector, if any.
+pub fn spawn_telemetry(state: DatabaseState) {
+    let Some(otlp) = &state.config.otlp else {
+        return;
+    };
+
+    let endpoint = otlp.endpoint.clone();
+    let interval_secs = otlp.interval_secs;
+    info!("Traces and metrics exported to {endpoint} every {interval_secs}s");
+
+    tokio::spawn(async move {
+        let client = Client::new();
+        let mut interval = interval(Duration::from_secs(interval_secs));
+        loop {
+            interval.tick().await;
+
+            let resource = resource(&state);
+            if let Err(error) = export_spans(&client, &endpoint, &resource).await {
+                info!("Error while exporting the spans: {}", error);
+            }
+            if let Err(error) = export_metrics(&client, &endpoint, &resource).await {
+                info!("Error while exporting the metrics: {}", error);
+            }
+        }
+    });
+}
+
+/// The resource describing this instance in the exported data.
+fn resource(state: &DatabaseState) -> Value {
+    json!({
+        "attributes": [
+            attribute("service.name", "distribuito".to_string()),
+            attribute("service.instance.id", state.config.database_ip_port.clone()),
+            attribute("db.namespace", state.config.database_name.clone()),
+        ]
+    })
+}
+
+async fn export_spans(client: &Client, endpoint: &str, resource: &Value) -> io::Result<()> {
+    let finished_spans =
+        std::mem::take(&mut *FINISHED_SPANS.get_or_init(Default::default).lock().unwrap());
+    if finished_spans.is_empty() {
+        return Ok(());
+    }
+
+    let spans: Vec<Value> = finished_spans
+        .into_iter()
+        .map(|span| {
+            json!({
+                "traceId": format!("{:032x}", span.context.trace_id),
+                "spanId": format!("{:016x}", span.context.span_id),
+                "parentSpanId": span.parent_span_id.map(|id| format!("{:016x}", id)).unwrap_or_default(),
+                "name": span.name,
+                "kind": span.kind as u8,
+                "startTimeUnixNano": unix_nanos(span.start),
+                "endTimeUnixNano": unix_nanos(span.end),
+                "attributes": span
+                    .attributes
+                    .into_iter()
+                    .map(|(key, value)| attribute(&key, value))
+                    .collect::<Vec<_>>(),
+                "status": match span.error {
+                    Some(error) => json!({"code": 2, "message": error}),
+                    None => json!({"code": 1}),
+                },
+            })
+        })
+        .collect();
+
+    let body = json!({
+        "resourceSpans": [{
+            "resource": resource,
+            "scopeSpans": [{"scope": {"name": "distribuito"}, "spans": spans}],
+        }]
+    });
+    post(client, endpoint, "/v1/traces", body).await
+}
+
+async fn export_metrics(client: &Client, endpoint: &str, resource: &Value) -> io::Result<()> {
+    let writes = column_writes();
+    if writes.is_empty() {
+        return Ok(());
+    }
+
+    let now = unix_nanos(SystemTime::now());
+    let data_points = |value: fn(&ColumnWrites) -> u64| {
+        writes
+            .iter()
+            .map(|(table, column, column_writes)| {
+                json!({
+                    "attributes": [
+                        attribute("table", table.clone()),
+                        attribute("column", column.clone()),
+                    ],
+                    "timeUnixNano": now,
+                    "asInt": value(column_writes).to_string(),
+                })
+            })
+            .collect::<Vec<_>>()
+    };
+    // The sums are cumulative since the instance started, like the counters of `/metrics`.
+    let metrics = vec![
+        json!({
+            "name": "distribuito.column.bytes_written",
+            "description": "The bytes written to the files of the column.",
+            "unit": "By",
+            "sum": {
+                "aggregationTemporality": 2,
+                "isMonotonic": true,
+                "dataPoints": data_points(|w| w.bytes_written),
+            },
+        }),
+        json!({
+            "name": "distribuito.column.records_written",
+            "description": "The records written to the files of the column.",
+            "unit": "{record}",
+            "sum": {
+                "aggregationTemporality": 2,
+                "isMonotonic": true,
+                "dataPoints": data_points(|w| w.records_written),
+            },
+        }),
+    ];
+
+    let body = json!({
+        "resourceMetrics": [{
+            "resource": resource,
+            "scopeMetrics": [{"scope": {"name": "distribuito"}, "metrics": metrics}],
+        }]
+    });
+    post(client, endpoint, "/v1/metrics", body).await
+}
+
+async fn post(client: &Client, endpoint: &str, path: &str, body: Value) -> io::Result<()> {
+    let url = format!("{}{}", endpoint.trim_end_matches('/'), path);
+    let response = client.post(&url).json(&body).send().await.map_err(|e| {
+        Error::new(
+            ErrorKind::InvalidData,
+            format!("Error while sending the request: {}", e),
+        )
+    })?;
+
+    let status = response.status();
+    if !status.is_success() {
+        let body = response.text().await.unwrap_or_default();
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("The collector replied with status {}: {}", status, body),
+        ));
+    }
+
+    Ok(())
+}
+
+fn attribute(key: &str, value: String) -> Value {
+    json!({"key": key, "value": {"stringValue": value}})
+}
+
+/// Returns the time in nanoseconds since the epoch, as a string since OTLP encodes 64 bits
+/// integers as strings in JSON.
+fn unix_nanos(time: SystemTime) -> String {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos()
+        .to_string()
+}
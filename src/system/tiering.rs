@@ -0,0 +1,55 @@
+use std::time::Duration;
+
+use log::info;
+use tokio::io;
+use tokio::time::interval;
+
+use crate::io::clock::clock;
+use crate::table::table::TableDefinition;
+use crate::transport::api::DatabaseState;
+
+/// Spawns the task which periodically moves the old partitions of all tables to the configured
+/// cold storage, if any.
+pub fn spawn_tiering(state: DatabaseState) {
+    let Some(cold_storage) = &state.config.cold_storage else {
+        return;
+    };
+
+    let after_secs = cold_storage.after_secs;
+    let interval_secs = cold_storage.interval_secs;
+    info!(
+        "Partitions older than {after_secs}s moved to {} every {interval_secs}s",
+        cold_storage.url
+    );
+
+    tokio::spawn(async move {
+        let mut interval = interval(Duration::from_secs(interval_secs));
+        loop {
+            interval.tick().await;
+
+            // The files are left untouched in read-only mode, e.g. while they are backed up.
+            if state.read_only.is_enabled().await {
+                continue;
+            }
+
+            let now = clock().now_secs();
+            let cutoff = now.saturating_sub(after_secs);
+            if let Err(error) = evict_cold_partitions(&state, cutoff).await {
+                info!("Error while moving partitions to cold storage: {}", error);
+            }
+        }
+    });
+}
+
+async fn evict_cold_partitions(state: &DatabaseState, cutoff: u64) -> io::Result<()> {
+    for table_name in TableDefinition::list(&state.config).await? {
+        let table_lock = state.table_locks.get(&table_name);
+        let _guard = table_lock.write().await;
+
+        let table_definition = TableDefinition::open(state.config.clone(), table_name).await?;
+        let mut table = table_definition.load().await?;
+        table.evict_cold_partitions(cutoff).await?;
+    }
+
+    Ok(())
+}
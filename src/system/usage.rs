@@ -0,0 +1,209 @@
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use chrono::Utc;
+use log::info;
+use serde::{Deserialize, Serialize};
+use tokio::io;
+use tokio::sync::RwLock;
+use tokio::time::interval;
+
+use crate::config::Config;
+use crate::io::file::{read_json_or_default, write_json};
+use crate::system::build_system_path;
+use crate::system::users::User;
+use crate::transport::api::DatabaseState;
+
+const USAGE_FILE_NAME: &str = "usage.json";
+
+/// How often the usage recorded since the last save is written to disk.
+const SAVE_INTERVAL: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum QuotaPeriod {
+    Daily,
+    Monthly,
+}
+
+impl<'a> From<&'a QuotaPeriod> for &'a str {
+    fn from(value: &'a QuotaPeriod) -> Self {
+        match value {
+            QuotaPeriod::Daily => "daily",
+            QuotaPeriod::Monthly => "monthly",
+        }
+    }
+}
+
+/// A hard limit on the usage of a user within a period, after which its requests are rejected.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Quota {
+    pub period: QuotaPeriod,
+    #[serde(default)]
+    pub max_rows_written: Option<u64>,
+    #[serde(default)]
+    pub max_rows_scanned: Option<u64>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct UsageCounters {
+    pub rows_written: u64,
+    pub rows_scanned: u64,
+}
+
+impl UsageCounters {
+    fn add(&mut self, rows_written: u64, rows_scanned: u64) {
+        self.rows_written += rows_written;
+        self.rows_scanned += rows_scanned;
+    }
+}
+
+/// The usage of a user in the current day and month, and since its creation.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct UserUsage {
+    pub day: String,
+    pub daily: UsageCounters,
+    pub month: String,
+    pub monthly: UsageCounters,
+    pub total: UsageCounters,
+}
+
+impl UserUsage {
+    /// Resets the counters of the periods which are over.
+    fn roll_over(&mut self) {
+        let now = Utc::now();
+
+        let day = now.format("%Y-%m-%d").to_string();
+        if self.day != day {
+            self.day = day;
+            self.daily = UsageCounters::default();
+        }
+
+        let month = now.format("%Y-%m").to_string();
+        if self.month != month {
+            self.month = month;
+            self.monthly = UsageCounters::default();
+        }
+    }
+
+    fn counters(&self, period: QuotaPeriod) -> &UsageCounters {
+        match period {
+            QuotaPeriod::Daily => &self.daily,
+            QuotaPeriod::Monthly => &self.monthly,
+        }
+    }
+}
+
+/// The accounting of the rows written and scanned by each user.
+///
+/// The counters are kept in memory and saved periodically, so the usage recorded since the last
+/// save is lost if the instance crashes.
+#[derive(Debug)]
+pub struct Usage {
+    path: PathBuf,
+    usage: RwLock<HashMap<String, UserUsage>>,
+    changed: AtomicBool,
+}
+
+impl Usage {
+    pub async fn load(config: &Config) -> io::Result<Self> {
+        let path = build_system_path(config);
+        let usage: HashMap<String, UserUsage> =
+            read_json_or_default(USAGE_FILE_NAME, &path).await?;
+
+        info!("Loaded usage of {} users", usage.len());
+
+        Ok(Self {
+            path,
+            usage: RwLock::new(usage),
+            changed: AtomicBool::new(false),
+        })
+    }
+
+    /// Checks that the user can write `rows_written` rows and scan more rows without exceeding any
+    /// of its quotas.
+    pub async fn check_quotas(&self, user: &User, rows_written: u64) -> io::Result<()> {
+        let mut usage = self.usage.write().await;
+        let user_usage = usage.entry(user.name.clone()).or_default();
+        user_usage.roll_over();
+
+        for quota in user.quotas.iter() {
+            let counters = user_usage.counters(quota.period);
+            let exceeds_rows_written = quota
+                .max_rows_written
+                .is_some_and(|max| counters.rows_written + rows_written > max);
+            let exceeds_rows_scanned = quota
+                .max_rows_scanned
+                .is_some_and(|max| counters.rows_scanned >= max);
+
+            if exceeds_rows_written || exceeds_rows_scanned {
+                return Err(Error::new(
+                    ErrorKind::QuotaExceeded,
+                    format!(
+                        "The user '{}' exceeded its {} quota",
+                        user.name,
+                        <&QuotaPeriod as Into<&str>>::into(&quota.period)
+                    ),
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    pub async fn record(&self, user: &User, rows_written: u64, rows_scanned: u64) {
+        let mut usage = self.usage.write().await;
+        let user_usage = usage.entry(user.name.clone()).or_default();
+        user_usage.roll_over();
+
+        user_usage.daily.add(rows_written, rows_scanned);
+        user_usage.monthly.add(rows_written, rows_scanned);
+        user_usage.total.add(rows_written, rows_scanned);
+        self.changed.store(true, Ordering::Relaxed);
+    }
+
+    /// Writes the usage to disk if it changed since the last save.
+    pub async fn save(&self) -> io::Result<()> {
+        if !self.changed.swap(false, Ordering::Relaxed) {
+            return Ok(());
+        }
+
+        let usage = self.usage.read().await;
+        let result = write_json(USAGE_FILE_NAME, &self.path, &*usage).await;
+        if result.is_err() {
+            self.changed.store(true, Ordering::Relaxed);
+        }
+
+        result
+    }
+
+    /// Returns the usage of all users, or only of `user_name` if supplied.
+    pub async fn get(&self, user_name: Option<&str>) -> HashMap<String, UserUsage> {
+        let mut usage = self.usage.write().await;
+        usage
+            .iter_mut()
+            .filter(|(name, _)| user_name.is_none_or(|n| n == name.as_str()))
+            .map(|(name, user_usage)| {
+                user_usage.roll_over();
+                (name.clone(), user_usage.clone())
+            })
+            .collect()
+    }
+}
+
+/// Spawns the task which periodically saves the usage of the users.
+pub fn spawn_usage_save(state: DatabaseState) {
+    tokio::spawn(async move {
+        let mut interval = interval(SAVE_INTERVAL);
+        loop {
+            interval.tick().await;
+
+            if let Err(error) = state.usage.save().await {
+                info!("Error while saving the usage: {}", error);
+            }
+        }
+    });
+}
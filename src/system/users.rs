@@ -0,0 +1,177 @@
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind};
+use std::path::PathBuf;
+
+use log::info;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::io;
+use tokio::sync::RwLock;
+
+use crate::config::Config;
+use crate::io::file::{read_json_or_default, write_json};
+use crate::system::build_system_path;
+use crate::system::usage::Quota;
+
+const USERS_FILE_NAME: &str = "users.json";
+
+/// The name of the user authenticated with the admin key of the config.
+pub const ADMIN_USER_NAME: &str = "admin";
+
+/// The role of a user, where each role can do everything the previous ones can.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// Can query tables.
+    Reader,
+    /// Can also create, drop and write tables.
+    Writer,
+    /// Can also manage users and the database.
+    Admin,
+}
+
+/// A principal of the api, authenticated via its key.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct User {
+    pub name: String,
+    /// The sha256 hash of the key, since the key itself is never stored.
+    pub key_hash: String,
+    pub role: Role,
+    /// The tables which the user can access, or all of them if missing.
+    #[serde(default)]
+    pub allowed_tables: Option<Vec<String>>,
+    #[serde(default)]
+    pub quotas: Vec<Quota>,
+}
+
+impl User {
+    pub fn new(
+        name: String,
+        key: &str,
+        role: Role,
+        allowed_tables: Option<Vec<String>>,
+        quotas: Vec<Quota>,
+    ) -> Self {
+        Self {
+            name,
+            key_hash: hash_key(key),
+            role,
+            allowed_tables,
+            quotas,
+        }
+    }
+
+    pub fn validate(&self) -> io::Result<()> {
+        if self.name.is_empty() || !self.name.chars().all(|c| c.is_alphanumeric() || c == '_') {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "The name of a user must contain only alphanumeric characters and underscores",
+            ));
+        }
+
+        if self.name == ADMIN_USER_NAME {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("The user name {} is reserved", ADMIN_USER_NAME),
+            ));
+        }
+
+        Ok(())
+    }
+
+    pub fn can_access(&self, table_name: &str) -> bool {
+        self.allowed_tables
+            .as_ref()
+            .is_none_or(|tables| tables.iter().any(|t| t == table_name))
+    }
+}
+
+fn hash_key(key: &str) -> String {
+    hex::encode(Sha256::digest(key.as_bytes()))
+}
+
+#[derive(Debug)]
+pub struct Users {
+    path: PathBuf,
+    admin: Option<User>,
+    users: RwLock<HashMap<String, User>>,
+}
+
+impl Users {
+    pub async fn load(config: &Config) -> io::Result<Self> {
+        let path = build_system_path(config);
+        let users: HashMap<String, User> = read_json_or_default(USERS_FILE_NAME, &path).await?;
+
+        // The admin key of the config is always valid, so that users can be managed even if none
+        // was created yet.
+        let admin = config
+            .admin_key
+            .as_ref()
+            .map(|key| User::new(ADMIN_USER_NAME.to_string(), key, Role::Admin, None, vec![]));
+
+        info!("Loaded {} users", users.len());
+
+        Ok(Self {
+            path,
+            admin,
+            users: RwLock::new(users),
+        })
+    }
+
+    /// Returns `true` if requests must be authenticated, which is the case when an admin key is
+    /// configured.
+    pub fn is_enabled(&self) -> bool {
+        self.admin.is_some()
+    }
+
+    pub async fn authenticate(&self, key: &str) -> Option<User> {
+        let key_hash = hash_key(key);
+        if let Some(admin) = self.admin.as_ref().filter(|a| a.key_hash == key_hash) {
+            return Some(admin.clone());
+        }
+
+        self.users
+            .read()
+            .await
+            .values()
+            .find(|u| u.key_hash == key_hash)
+            .cloned()
+    }
+
+    pub async fn save(&self, user: User) -> io::Result<()> {
+        user.validate()?;
+
+        let mut users = self.users.write().await;
+        if users
+            .values()
+            .any(|u| u.name != user.name && u.key_hash == user.key_hash)
+        {
+            return Err(Error::new(
+                ErrorKind::AlreadyExists,
+                "The key is already used by another user",
+            ));
+        }
+
+        users.insert(user.name.clone(), user);
+        write_json(USERS_FILE_NAME, &self.path, &*users).await
+    }
+
+    pub async fn delete(&self, name: &str) -> io::Result<()> {
+        let mut users = self.users.write().await;
+        if users.remove(name).is_none() {
+            return Err(Error::new(
+                ErrorKind::NotFound,
+                format!("The user '{}' doesn't exist", name),
+            ));
+        }
+
+        write_json(USERS_FILE_NAME, &self.path, &*users).await
+    }
+
+    pub async fn list(&self) -> Vec<User> {
+        let mut users: Vec<User> = self.users.read().await.values().cloned().collect();
+        users.sort_by(|a, b| a.name.cmp(&b.name));
+
+        users
+    }
+}
@@ -1,27 +1,171 @@
-use std::collections::BTreeSet;
+use std::cmp::Reverse;
+use std::collections::{BTreeSet, BinaryHeap};
 use std::fmt::Debug;
 use std::hash::Hash;
+use std::io::{Error, ErrorKind};
 use std::ops::Div;
 
-use crate::table::column::{AggregateColumn, Column, ColumnValue};
+use tokio::io;
+
+use crate::table::column::{AggregateColumn, Column, ColumnType, ColumnValue};
 use crate::table::cursor::Row;
+use crate::table::digest::TDigest;
+use crate::table::hyperloglog::HyperLogLog;
+
+/// The precision of the fractions of the percentiles, which are stored in millionths.
+const PERCENTILE_PRECISION: f64 = 1_000_000.0;
+
+/// The maximum number of values kept by a top aggregate, since they are all sent by each shard.
+const MAX_TOP_VALUES: i64 = 1_000;
 
 #[derive(Debug, Clone, Ord, PartialOrd, Eq, PartialEq, Hash)]
 pub enum Aggregate {
     Count,
     Sum,
     Avg,
+    /// The value below which a fraction of the values fall, with the fraction in millionths.
+    Percentile(u32),
+    Median,
+    CountDistinct,
+    /// The number of distinct values estimated with a sketch of constant size.
+    ApproxDistinct,
+    /// The value of the row with the smallest timestamp.
+    First,
+    /// The value of the row with the largest timestamp.
+    Last,
+    /// The largest values, with their number.
+    Top(u32),
+    /// The average of the values, each weighted by the value of the weight column in its row.
+    WeightedAvg(String),
 }
 
-impl<'a> From<&'a str> for Aggregate {
-    fn from(value: &'a str) -> Self {
-        match value.to_lowercase().as_str() {
-            "count" => Aggregate::Count,
-            "sum" => Aggregate::Sum,
-            "avg" => Aggregate::Avg,
-            _ => Aggregate::Count,
+impl Aggregate {
+    /// Returns the aggregate computed by the function `name`, if any, excluding the percentiles
+    /// which also need their fraction.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "count" => Some(Aggregate::Count),
+            "sum" => Some(Aggregate::Sum),
+            "avg" => Some(Aggregate::Avg),
+            "median" => Some(Aggregate::Median),
+            "count_distinct" => Some(Aggregate::CountDistinct),
+            "approx_distinct" => Some(Aggregate::ApproxDistinct),
+            "first" => Some(Aggregate::First),
+            "last" => Some(Aggregate::Last),
+            _ => None,
         }
     }
+
+    /// Returns whether the function `name` is an aggregate.
+    pub fn is_aggregate(name: &str) -> bool {
+        Self::from_name(name).is_some()
+            || name.eq_ignore_ascii_case("percentile")
+            || name.eq_ignore_ascii_case("top")
+            || name.eq_ignore_ascii_case("wavg")
+    }
+
+    /// Returns the percentile below which the `fraction` of the values fall, e.g. 0.95.
+    pub fn percentile(fraction: f64) -> io::Result<Self> {
+        let millionths = (fraction * PERCENTILE_PRECISION).round();
+        // The fraction must be stored exactly, so that the aggregate is named like it was queried.
+        if !(0.0..=1.0).contains(&fraction) || millionths / PERCENTILE_PRECISION != fraction {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "The fraction {} of a percentile must be between 0 and 1, with at most 6 decimals",
+                    fraction
+                ),
+            ));
+        }
+
+        Ok(Aggregate::Percentile(millionths as u32))
+    }
+
+    /// Returns the aggregate keeping the `k` largest values, e.g. 3.
+    pub fn top(k: i64) -> io::Result<Self> {
+        if !(1..=MAX_TOP_VALUES).contains(&k) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "The number {} of top values must be between 1 and {}",
+                    k, MAX_TOP_VALUES
+                ),
+            ));
+        }
+
+        Ok(Aggregate::Top(k as u32))
+    }
+
+    /// Returns the fraction of the values below a percentile, if the aggregate is one.
+    pub fn fraction(&self) -> Option<f64> {
+        match self {
+            Aggregate::Percentile(millionths) => Some(*millionths as f64 / PERCENTILE_PRECISION),
+            Aggregate::Median => Some(0.5),
+            _ => None,
+        }
+    }
+
+    /// Returns the column of the weights of the aggregate, if it's a weighted average.
+    pub fn weight_column(&self) -> Option<&str> {
+        match self {
+            Aggregate::WeightedAvg(weight) => Some(weight),
+            _ => None,
+        }
+    }
+
+    /// Returns whether the components of the aggregate are values of the aggregated column, instead
+    /// of values of the type of the aggregate.
+    pub fn has_value_components(&self) -> bool {
+        matches!(
+            self,
+            Aggregate::CountDistinct | Aggregate::First | Aggregate::Last | Aggregate::Top(_)
+        )
+    }
+
+    /// Returns whether the first component of the aggregate is the timestamp of the row whose
+    /// value was picked, which is followed by that value.
+    pub fn has_timestamp_component(&self) -> bool {
+        matches!(self, Aggregate::First | Aggregate::Last)
+    }
+
+    /// Returns the type of the values of the aggregate computed on the values of the column.
+    pub fn value_type(&self, column: &Column) -> ColumnType {
+        match self {
+            Aggregate::Count | Aggregate::CountDistinct | Aggregate::ApproxDistinct => {
+                ColumnType::Integer
+            }
+            Aggregate::Sum | Aggregate::First | Aggregate::Last | Aggregate::Top(_) => column.ty,
+            Aggregate::Avg
+            | Aggregate::Percentile(_)
+            | Aggregate::Median
+            | Aggregate::WeightedAvg(_) => ColumnType::Float,
+        }
+    }
+
+    /// Checks that the aggregate can be computed on the values of the column.
+    pub fn check_column(&self, column: &Column) -> io::Result<()> {
+        let is_number = matches!(column.ty, ColumnType::Integer | ColumnType::Float);
+        if self.weight_column().is_some() && !is_number {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "The weighted average of {} can't be computed, since it's not a number",
+                    column.name
+                ),
+            ));
+        }
+        if self.fraction().is_some() && !is_number {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "The percentiles of {} can't be computed, since it's not a number",
+                    column.name
+                ),
+            ));
+        }
+
+        Ok(())
+    }
 }
 
 impl<'a> From<Aggregate> for &'a str {
@@ -30,6 +174,14 @@ impl<'a> From<Aggregate> for &'a str {
             Aggregate::Count => "count",
             Aggregate::Sum => "sum",
             Aggregate::Avg => "avg",
+            Aggregate::Percentile(_) => "percentile",
+            Aggregate::Median => "median",
+            Aggregate::CountDistinct => "count_distinct",
+            Aggregate::ApproxDistinct => "approx_distinct",
+            Aggregate::First => "first",
+            Aggregate::Last => "last",
+            Aggregate::Top(_) => "top",
+            Aggregate::WeightedAvg(_) => "wavg",
         }
     }
 }
@@ -48,7 +200,29 @@ where
 {
     Count(T),
     Sum(T),
-    Avg { sum: T, count: T },
+    Avg {
+        sum: T,
+        count: T,
+    },
+    Percentile {
+        fraction: f64,
+        digest: TDigest,
+    },
+    CountDistinct(BTreeSet<T>),
+    ApproxDistinct(HyperLogLog),
+    /// The value of the row with the smallest timestamp seen so far, with that timestamp.
+    First(Option<(u64, T)>),
+    /// The value of the row with the largest timestamp seen so far, with that timestamp.
+    Last(Option<(u64, T)>),
+    /// The `k` largest values seen so far, with the smallest at the top of the heap.
+    Top {
+        k: usize,
+        values: BinaryHeap<Reverse<T>>,
+    },
+    WeightedAvg {
+        weighted_sum: f64,
+        weight_sum: f64,
+    },
 }
 
 impl<T> AggregateComponents<T>
@@ -63,6 +237,22 @@ where
                 sum: T::init(aggregate_column),
                 count: T::init(aggregate_column),
             },
+            Aggregate::Percentile(_) | Aggregate::Median => AggregateComponents::Percentile {
+                fraction: aggregate_column.0.fraction().unwrap_or_default(),
+                digest: TDigest::new(),
+            },
+            Aggregate::CountDistinct => AggregateComponents::CountDistinct(BTreeSet::new()),
+            Aggregate::ApproxDistinct => AggregateComponents::ApproxDistinct(HyperLogLog::new()),
+            Aggregate::First => AggregateComponents::First(None),
+            Aggregate::Last => AggregateComponents::Last(None),
+            Aggregate::Top(k) => AggregateComponents::Top {
+                k: k as usize,
+                values: BinaryHeap::new(),
+            },
+            Aggregate::WeightedAvg(_) => AggregateComponents::WeightedAvg {
+                weighted_sum: 0.0,
+                weight_sum: 0.0,
+            },
         }
     }
 
@@ -77,17 +267,100 @@ where
                 sum: components.remove(0),
                 count: components.remove(0),
             },
+            // The components of the percentiles are the centroids of their digest.
+            Aggregate::Percentile(_) | Aggregate::Median => AggregateComponents::Percentile {
+                fraction: aggregate_column.0.fraction().unwrap_or_default(),
+                digest: TDigest::from_components(components.iter().filter_map(|c| c.to_f64())),
+            },
+            // The components of the distinct counts are the distinct values.
+            Aggregate::CountDistinct => {
+                AggregateComponents::CountDistinct(components.into_iter().collect())
+            }
+            // The components of the approximate distinct counts are the registers of their sketch.
+            Aggregate::ApproxDistinct => AggregateComponents::ApproxDistinct(
+                HyperLogLog::from_components(components.iter().filter_map(|c| c.to_i64())),
+            ),
+            // The components of the first and last values are the timestamp and the value of the
+            // picked row, or nothing if no row was picked.
+            Aggregate::First => AggregateComponents::First(timestamped_value(components)),
+            Aggregate::Last => AggregateComponents::Last(timestamped_value(components)),
+            // The components of the top values are the kept values.
+            Aggregate::Top(k) => AggregateComponents::Top {
+                k: k as usize,
+                values: components.into_iter().map(Reverse).collect(),
+            },
+            // The components of the weighted averages are the sums of the weighted values and of
+            // the weights.
+            Aggregate::WeightedAvg(_) => {
+                let mut components = components.iter().map(|c| c.to_f64().unwrap_or_default());
+                AggregateComponents::WeightedAvg {
+                    weighted_sum: components.next().unwrap_or_default(),
+                    weight_sum: components.next().unwrap_or_default(),
+                }
+            }
         }
     }
 
-    pub fn aggregate(&mut self, value: &T) {
+    /// Aggregates the value of a row with its timestamp and, for the weighted averages, its weight.
+    pub fn aggregate(&mut self, value: &T, timestamp: u64, weight: Option<&T>) {
         match self {
+            // Nulls, like the strings which don't look like numbers once cast, are skipped by sums
+            // and averages, as they have no value to add up, and by the first and last values.
+            AggregateComponents::Sum(_)
+            | AggregateComponents::Avg { .. }
+            | AggregateComponents::First(_)
+            | AggregateComponents::Last(_)
+            | AggregateComponents::Top { .. }
+                if value.is_null() => {}
             AggregateComponents::Count(count) => count.merge(MergeOp::Count, value.clone()),
             AggregateComponents::Sum(sum) => sum.merge(MergeOp::Sum, value.clone()),
             AggregateComponents::Avg { sum, count } => {
                 sum.merge(MergeOp::Sum, value.clone());
                 count.merge(MergeOp::Count, value.clone());
             }
+            AggregateComponents::Percentile { digest, .. } => {
+                if let Some(value) = value.to_f64() {
+                    digest.add(value);
+                }
+            }
+            // Like in SQL, the nulls aren't counted as a distinct value.
+            AggregateComponents::CountDistinct(values) => {
+                if !value.is_null() {
+                    values.insert(value.clone());
+                }
+            }
+            AggregateComponents::ApproxDistinct(sketch) => {
+                if !value.is_null() {
+                    sketch.add(value);
+                }
+            }
+            AggregateComponents::First(first) => {
+                if first.as_ref().is_none_or(|(t, _)| timestamp < *t) {
+                    *first = Some((timestamp, value.clone()));
+                }
+            }
+            // On equal timestamps the row read last wins, which is the one inserted last.
+            AggregateComponents::Last(last) => {
+                if last.as_ref().is_none_or(|(t, _)| timestamp >= *t) {
+                    *last = Some((timestamp, value.clone()));
+                }
+            }
+            AggregateComponents::Top { k, values } => {
+                values.push(Reverse(value.clone()));
+                if values.len() > *k {
+                    values.pop();
+                }
+            }
+            // The rows without a value or a weight are skipped, like the nulls by the averages.
+            AggregateComponents::WeightedAvg {
+                weighted_sum,
+                weight_sum,
+            } => {
+                if let (Some(value), Some(weight)) = (value.to_f64(), weight.and_then(T::to_f64)) {
+                    *weighted_sum += value * weight;
+                    *weight_sum += weight;
+                }
+            }
         }
     }
 
@@ -112,6 +385,62 @@ where
                 left_sum.merge(MergeOp::Sum, right_sum);
                 left_count.merge(MergeOp::Sum, right_count);
             }
+            (
+                AggregateComponents::Percentile {
+                    digest: ref mut left,
+                    ..
+                },
+                AggregateComponents::Percentile { digest: right, .. },
+            ) => {
+                left.merge(right);
+            }
+            (
+                AggregateComponents::CountDistinct(ref mut left),
+                AggregateComponents::CountDistinct(right),
+            ) => {
+                left.extend(right);
+            }
+            (
+                AggregateComponents::ApproxDistinct(ref mut left),
+                AggregateComponents::ApproxDistinct(right),
+            ) => {
+                left.merge(right);
+            }
+            (AggregateComponents::First(ref mut left), AggregateComponents::First(Some(right)))
+                if left.as_ref().is_none_or(|(t, _)| right.0 < *t) =>
+            {
+                *left = Some(right);
+            }
+            (AggregateComponents::Last(ref mut left), AggregateComponents::Last(Some(right)))
+                if left.as_ref().is_none_or(|(t, _)| right.0 > *t) =>
+            {
+                *left = Some(right);
+            }
+            (
+                AggregateComponents::Top {
+                    k,
+                    values: ref mut left,
+                },
+                AggregateComponents::Top { values: right, .. },
+            ) => {
+                left.extend(right);
+                while left.len() > *k {
+                    left.pop();
+                }
+            }
+            (
+                AggregateComponents::WeightedAvg {
+                    weighted_sum: ref mut left_weighted_sum,
+                    weight_sum: ref mut left_weight_sum,
+                },
+                AggregateComponents::WeightedAvg {
+                    weighted_sum: right_weighted_sum,
+                    weight_sum: right_weight_sum,
+                },
+            ) => {
+                *left_weighted_sum += right_weighted_sum;
+                *left_weight_sum += right_weight_sum;
+            }
             _ => {}
         };
     }
@@ -123,12 +452,63 @@ where
             AggregateComponents::Avg { sum, count } => {
                 (sum.clone() / count.clone(), vec![sum, count])
             }
+            AggregateComponents::Percentile {
+                fraction,
+                mut digest,
+            } => {
+                let value = digest.quantile(fraction).map_or_else(T::null, T::from_f64);
+                let components = digest.into_components().into_iter().map(T::from_f64);
+                (value, components.collect())
+            }
+            AggregateComponents::CountDistinct(values) => {
+                (T::from_count(values.len()), values.into_iter().collect())
+            }
+            AggregateComponents::ApproxDistinct(sketch) => {
+                let value = T::from_count(sketch.estimate() as usize);
+                let components = sketch.into_components().into_iter().map(T::from_i64);
+                (value, components.collect())
+            }
+            AggregateComponents::First(value) | AggregateComponents::Last(value) => match value {
+                Some((timestamp, value)) => {
+                    (value.clone(), vec![T::from_timestamp(timestamp), value])
+                }
+                None => (T::null(), vec![]),
+            },
+            // The value is the smallest of the kept values, which are all in the components from
+            // the largest.
+            AggregateComponents::Top { values, .. } => {
+                let values: Vec<T> = values.into_sorted_vec().into_iter().map(|v| v.0).collect();
+                let value = values.last().cloned().unwrap_or_else(T::null);
+                (value, values)
+            }
+            // Without weights the average is null, like the average of no values.
+            AggregateComponents::WeightedAvg {
+                weighted_sum,
+                weight_sum,
+            } => {
+                let value = match weight_sum {
+                    0.0 => T::null(),
+                    weight_sum => T::from_f64(weighted_sum / weight_sum),
+                };
+                (
+                    value,
+                    vec![T::from_f64(weighted_sum), T::from_f64(weight_sum)],
+                )
+            }
         }
     }
 }
 
+/// Returns the timestamp and the value stored in the components of a first or last value.
+fn timestamped_value<T: Aggregable<T>>(components: Vec<T>) -> Option<(u64, T)> {
+    let mut components = components.into_iter();
+    let timestamp = components.next()?.to_timestamp()?;
+    Some((timestamp, components.next()?))
+}
+
+/// The values of the grouped columns of a group, in the order of the grouped columns.
 #[derive(Debug, Clone, Ord, PartialOrd, Eq, PartialEq, Hash)]
-pub struct GroupKey<T>(pub BTreeSet<(Column, T)>)
+pub struct GroupKey<T>(pub Vec<(Column, T)>)
 where
     T: Debug + Clone + Ord + PartialOrd + Eq + PartialEq + Hash;
 
@@ -171,8 +551,20 @@ where
     pub fn add(&mut self, row: Row<T>) {
         for (aggregate_column, aggregate_components) in self.aggregates.iter_mut() {
             // TODO: take value out of the array instead of cloning.
+            // The rows which don't match the filter of the aggregate are skipped.
+            let filtered_out = aggregate_column
+                .2
+                .as_ref()
+                .is_some_and(|filter| row.named_value(filter).and_then(|m| m.to_i64()) != Some(1));
+            if filtered_out {
+                continue;
+            }
             if let Some(value) = row.value(&aggregate_column.1) {
-                aggregate_components.aggregate(value);
+                let weight = aggregate_column
+                    .0
+                    .weight_column()
+                    .and_then(|w| row.named_value(w));
+                aggregate_components.aggregate(value, row.timestamp(), weight);
             }
         }
     }
@@ -198,6 +590,24 @@ pub trait Aggregable<T> {
     fn init(aggregate_column: &AggregateColumn) -> T;
 
     fn merge(&mut self, aggregate_op: MergeOp, other: T);
+
+    fn is_null(&self) -> bool;
+
+    fn null() -> T;
+
+    fn to_f64(&self) -> Option<f64>;
+
+    fn from_f64(value: f64) -> T;
+
+    fn from_count(count: usize) -> T;
+
+    fn to_i64(&self) -> Option<i64>;
+
+    fn from_i64(value: i64) -> T;
+
+    fn from_timestamp(timestamp: u64) -> T;
+
+    fn to_timestamp(&self) -> Option<u64>;
 }
 
 impl Aggregable<ColumnValue> for ColumnValue {
@@ -206,6 +616,10 @@ impl Aggregable<ColumnValue> for ColumnValue {
             Aggregate::Count => ColumnValue::Integer(0),
             Aggregate::Sum => aggregate_column.1.ty.into(),
             Aggregate::Avg => ColumnValue::Float(0.0),
+            Aggregate::Percentile(_) | Aggregate::Median => ColumnValue::Null,
+            Aggregate::CountDistinct | Aggregate::ApproxDistinct => ColumnValue::Integer(0),
+            Aggregate::First | Aggregate::Last | Aggregate::Top(_) => ColumnValue::Null,
+            Aggregate::WeightedAvg(_) => ColumnValue::Float(0.0),
         }
     }
 
@@ -215,4 +629,50 @@ impl Aggregable<ColumnValue> for ColumnValue {
             MergeOp::Sum => self.clone() + other,
         }
     }
+
+    fn is_null(&self) -> bool {
+        matches!(self, ColumnValue::Null)
+    }
+
+    fn null() -> ColumnValue {
+        ColumnValue::Null
+    }
+
+    fn to_f64(&self) -> Option<f64> {
+        match self {
+            ColumnValue::Integer(value) => Some(*value as f64),
+            ColumnValue::Float(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    fn from_f64(value: f64) -> ColumnValue {
+        ColumnValue::Float(value)
+    }
+
+    fn from_count(count: usize) -> ColumnValue {
+        ColumnValue::Integer(count as i64)
+    }
+
+    fn to_i64(&self) -> Option<i64> {
+        match self {
+            ColumnValue::Integer(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    fn from_i64(value: i64) -> ColumnValue {
+        ColumnValue::Integer(value)
+    }
+
+    fn from_timestamp(timestamp: u64) -> ColumnValue {
+        ColumnValue::Integer(timestamp as i64)
+    }
+
+    fn to_timestamp(&self) -> Option<u64> {
+        match self {
+            ColumnValue::Integer(value) => u64::try_from(*value).ok(),
+            _ => None,
+        }
+    }
 }
@@ -0,0 +1,74 @@
+use crate::table::column::{Column, ColumnType, ColumnValue};
+
+/// The size of the bloom filter of a segment, with about 10 bits per value of a full segment,
+/// which gives a false positive rate of about 1%.
+pub const BLOOM_FILTER_SIZE: usize = 10 * 1024;
+
+/// The number of bits set by each value.
+const BLOOM_FILTER_HASHES: u64 = 7;
+
+/// Returns the name of the file of a partition with the bloom filters of the segments of a column,
+/// next to the file of the column.
+pub fn bloom_filter_file_name(column: &Column) -> String {
+    let column_file_name: String = column.into();
+    format!("{}.bloom.dsto", column_file_name)
+}
+
+/// Returns whether a bloom filter can be kept for the values of the type, which are compared for
+/// equality by their exact representation.
+pub fn supports_bloom_filter(ty: ColumnType) -> bool {
+    matches!(ty, ColumnType::Integer | ColumnType::String)
+}
+
+/// The bloom filter of the values of a segment, which tells whether a value might be in it.
+#[derive(Debug, Clone)]
+pub struct BloomFilter {
+    bits: Vec<u8>,
+}
+
+impl Default for BloomFilter {
+    fn default() -> Self {
+        Self {
+            bits: vec![0; BLOOM_FILTER_SIZE],
+        }
+    }
+}
+
+impl BloomFilter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn from_bytes(bits: &[u8]) -> Self {
+        Self {
+            bits: bits.to_vec(),
+        }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bits
+    }
+
+    pub fn insert(&mut self, value: &ColumnValue) {
+        for bit in bits(value).into_iter().flatten() {
+            self.bits[bit / 8] |= 1 << (bit % 8);
+        }
+    }
+
+    /// Returns whether the value might have been inserted, which is always the case for the values
+    /// which aren't hashed.
+    pub fn may_contain(&self, value: &ColumnValue) -> bool {
+        bits(value)
+            .is_none_or(|mut bits| bits.all(|bit| self.bits[bit / 8] & (1 << (bit % 8)) != 0))
+    }
+}
+
+/// Returns the positions of the bits of the value, picked by the double hashing of its stable hash,
+/// since the filters are stored.
+fn bits(value: &ColumnValue) -> Option<impl Iterator<Item = usize>> {
+    let hash = value.stable_hash()?;
+    let (first, second) = (hash & u32::MAX as u64, (hash >> 32) | 1);
+    let len = (BLOOM_FILTER_SIZE * 8) as u64;
+
+    Some((0..BLOOM_FILTER_HASHES).map(move |i| (first.wrapping_add(i * second) % len) as usize))
+}
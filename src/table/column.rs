@@ -6,11 +6,13 @@ use std::ops::{Add, AddAssign, Div, Mul};
 use std::path::Path;
 use std::str;
 
-use tokio::fs::read_dir;
+use serde::{Deserialize, Serialize};
 use tokio::io;
 
+use crate::io::storage::storage;
 use crate::table::aggregate::Aggregate;
-use crate::table::FromDisk;
+use crate::table::expression::{is_pseudo_column, parse_expression, parse_select_item, Expression};
+use crate::table::{fnv1a, FromDisk};
 
 const INTEGER_VALUE_SIZE: usize = std::mem::size_of::<i64>();
 const FLOAT_VALUE_SIZE: usize = std::mem::size_of::<f64>();
@@ -18,7 +20,8 @@ const FLOAT_VALUE_SIZE: usize = std::mem::size_of::<f64>();
 const STRING_VALUE_SIZE: usize = 256;
 const NULL_VALUE_SIZE: usize = 0;
 
-#[derive(Debug, Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash)]
+#[derive(Debug, Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
 pub enum ColumnType {
     Integer,
     Float,
@@ -79,6 +82,15 @@ impl ColumnValue {
     pub fn default_string() -> ColumnValue {
         ColumnValue::String("".to_string())
     }
+
+    /// Returns the stable hash of the value, which is only known for the integers and the strings.
+    pub fn stable_hash(&self) -> Option<u64> {
+        match self {
+            ColumnValue::Integer(value) => Some(fnv1a(&value.to_le_bytes())),
+            ColumnValue::String(value) => Some(fnv1a(value.as_bytes())),
+            _ => None,
+        }
+    }
 }
 
 impl From<ColumnType> for ColumnValue {
@@ -285,30 +297,49 @@ impl<'a> From<&'a Column> for String {
     }
 }
 
+/// An aggregate with the column of its values and the condition of the rows it aggregates, if
+/// it's filtered, e.g. `status = 'error'`.
 #[derive(Debug, Clone, Ord, PartialOrd, Eq, PartialEq, Hash)]
-pub struct AggregateColumn(pub Aggregate, pub Column);
+pub struct AggregateColumn(pub Aggregate, pub Column, pub Option<String>);
 
 impl From<AggregateColumn> for String {
     fn from(value: AggregateColumn) -> Self {
-        let aggregate: &str = value.0.into();
-        format!("{}({})", aggregate, value.1.name)
+        let name = match value.0 {
+            // The fraction is written like the float literals, so that the name is the same as the
+            // queried expression.
+            Aggregate::Percentile(_) => format!(
+                "percentile({}, {:?})",
+                value.1.name,
+                value.0.fraction().unwrap_or_default()
+            ),
+            Aggregate::Top(k) => format!("top({}, {})", k, value.1.name),
+            Aggregate::WeightedAvg(weight) => format!("wavg({}, {})", value.1.name, weight),
+            _ => {
+                let aggregate: &str = value.0.into();
+                format!("{}({})", aggregate, value.1.name)
+            }
+        };
+
+        match value.2 {
+            Some(filter) => format!("{} filter ({})", name, filter),
+            None => name,
+        }
     }
 }
 
-pub type QueriedColumns = (Vec<Column>, Vec<AggregateColumn>);
+/// The columns read by the queried expressions, the aggregates and the expressions whose values
+/// are aggregated, which are computed for each row before aggregating it.
+pub type QueriedColumns = (Vec<Column>, Vec<AggregateColumn>, Vec<(Expression, Column)>);
 
+/// Parses the columns from the names of the column files, in which the tables created before the
+/// schema manifest stored their columns.
 pub async fn get_columns<P: AsRef<Path>>(path: P) -> io::Result<Vec<Column>> {
     let mut columns = vec![];
 
-    let mut dir = read_dir(path.as_ref()).await?;
-    while let Some(entry) = dir.next_entry().await? {
-        if let Ok(file_type) = entry.file_type().await {
-            if file_type.is_file() {
-                if let Ok(file_name) = entry.file_name().into_string() {
-                    if let Some((column_name, column_type)) = parse_column_file_name(&file_name) {
-                        columns.push(Column::new(column_name, column_type));
-                    }
-                }
+    for entry in storage().read_dir(path.as_ref()).await? {
+        if !entry.is_dir {
+            if let Some((column_name, column_type)) = parse_column_file_name(&entry.name) {
+                columns.push(Column::new(column_name, column_type));
             }
         }
     }
@@ -344,6 +375,21 @@ pub fn parse_column_file_name(file_name: &str) -> Option<(String, ColumnType)> {
     Some((column_name.to_string(), column_type.into()))
 }
 
+/// Returns the bytes of the string as stored on disk, set to 0 after its end.
+pub fn encode_string(string: &str) -> [u8; ColumnType::String.size()] {
+    let mut bytes = [0u8; ColumnType::String.size()];
+    for (index, byte) in string
+        .as_bytes()
+        .iter()
+        .take(ColumnType::String.size())
+        .enumerate()
+    {
+        bytes[index] = *byte;
+    }
+
+    bytes
+}
+
 /// The size of the index and timestamp columns which are both of type [`ColumnType::Integer`].
 pub fn index_and_timestamp_size() -> usize {
     ColumnType::Integer.size() + ColumnType::Integer.size()
@@ -355,22 +401,77 @@ pub fn parse_and_validate_queried_columns(
 ) -> io::Result<QueriedColumns> {
     let mut parsed_columns = vec![];
     let mut parsed_aggregate_columns = vec![];
+    let mut aggregated_expressions = vec![];
 
     for queried_column in queried_columns {
-        let (aggregate, column) = try_parse_queried_column(queried_column)?;
-        let found_column = get_column(available_columns, column)?;
-        match aggregate {
-            Some(aggregate) => {
+        let select_item = parse_select_item(queried_column)?;
+        let (aggregate, expression) = select_item.expression.to_aggregate_column()?;
+        // The condition of a filtered aggregate is evaluated on each row before aggregating it,
+        // like the aggregated expressions, so the columns it reads are opened too.
+        let filter = select_item.expression.aggregate_filter();
+        if let Some(filter) = filter {
+            filter.check_condition(available_columns)?;
+            for name in filter.columns() {
+                if !parsed_columns.iter().any(|c: &Column| c.name == name) {
+                    parsed_columns.push(get_column(available_columns, name)?);
+                }
+            }
+            let filter_column = Column::new(filter.to_string(), ColumnType::Integer);
+            aggregated_expressions.push((filter.clone(), filter_column));
+        }
+        let filter = filter.map(|f| f.to_string());
+        // The weights of a weighted average are read from their own column, which is opened too.
+        if let Some(weighted_avg @ Aggregate::WeightedAvg(weight)) = &aggregate {
+            let weight_column = get_column(available_columns, weight)?;
+            weighted_avg.check_column(&weight_column)?;
+            if !parsed_columns.contains(&weight_column) {
+                parsed_columns.push(weight_column);
+            }
+        }
+        match (aggregate, expression) {
+            (Some(aggregate), Expression::Column { name, .. }) if !is_pseudo_column(name) => {
                 // We add the aggregate column in the columns too since we want to open the files
                 // of the aggregated columns too.
+                let found_column = get_column(available_columns, name)?;
+                aggregate.check_column(&found_column)?;
                 parsed_columns.push(found_column.clone());
-                parsed_aggregate_columns.push(AggregateColumn(aggregate, found_column))
+                parsed_aggregate_columns.push(AggregateColumn(aggregate, found_column, filter))
+            }
+            (Some(aggregate), expression) => {
+                // The values of the expression are computed from the columns it reads, which are
+                // the ones whose files are opened.
+                let column = expression_column(available_columns, expression)?;
+                aggregate.check_column(&column)?;
+                for name in expression.columns() {
+                    if !parsed_columns.iter().any(|c: &Column| c.name == name) {
+                        parsed_columns.push(get_column(available_columns, name)?);
+                    }
+                }
+                parsed_aggregate_columns.push(AggregateColumn(aggregate, column.clone(), filter));
+                aggregated_expressions.push((expression.clone(), column));
+            }
+            // The pseudo-columns are selected like the other columns, but their values are read
+            // from the index of the rows.
+            (None, Expression::Column { name, .. }) if is_pseudo_column(name) => {
+                parsed_columns.push(Column::new(name.clone(), ColumnType::Integer))
+            }
+            (None, Expression::Column { name, .. }) => {
+                parsed_columns.push(get_column(available_columns, name)?)
+            }
+            (None, expression) => {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("The expression {} can't be queried", expression),
+                ))
             }
-            None => parsed_columns.push(found_column),
         };
     }
 
-    Ok((parsed_columns, parsed_aggregate_columns))
+    Ok((
+        parsed_columns,
+        parsed_aggregate_columns,
+        aggregated_expressions,
+    ))
 }
 
 pub fn parse_and_validate_columns(
@@ -387,7 +488,54 @@ pub fn parse_and_validate_columns(
     Ok(parsed_columns)
 }
 
-fn get_column(available_columns: &Vec<Column>, column: &str) -> io::Result<Column> {
+/// Parses the group by expressions, returning each expression with the column of its values.
+///
+/// The values of plain columns keep their column, while the values of the other expressions get
+/// a column named after the expression.
+pub fn parse_and_validate_group_by(
+    available_columns: &Vec<Column>,
+    group_by: &Vec<String>,
+) -> io::Result<Vec<(Expression, Column)>> {
+    let mut parsed_group_by = Vec::with_capacity(group_by.len());
+    for expression in group_by {
+        let expression = parse_select_item(expression)?.expression;
+        let column = expression_column(available_columns, &expression)?;
+
+        parsed_group_by.push((expression, column));
+    }
+
+    Ok(parsed_group_by)
+}
+
+/// Returns the column of the values of an expression, which is the column itself for plain columns
+/// and a column named after the expression otherwise.
+fn expression_column(
+    available_columns: &Vec<Column>,
+    expression: &Expression,
+) -> io::Result<Column> {
+    match expression {
+        Expression::Column { name, .. } if !is_pseudo_column(name) => {
+            get_column(available_columns, name)
+        }
+        expression => Ok(Column::new(
+            expression.to_string(),
+            expression.scalar_type(available_columns)?,
+        )),
+    }
+}
+
+/// Parses the condition which the queried rows must match, checking that it's valid on the table.
+pub fn parse_and_validate_filter(
+    available_columns: &[Column],
+    filter: &str,
+) -> io::Result<Expression> {
+    let filter = parse_expression(filter)?;
+    filter.check_condition(available_columns)?;
+
+    Ok(filter)
+}
+
+pub fn get_column(available_columns: &Vec<Column>, column: &str) -> io::Result<Column> {
     available_columns
         .into_iter()
         .find(|&c| c.name == *column)
@@ -398,18 +546,21 @@ fn get_column(available_columns: &Vec<Column>, column: &str) -> io::Result<Colum
         .map(|c| c.clone())
 }
 
-pub fn try_parse_queried_column(queried_column: &str) -> io::Result<(Option<Aggregate>, &str)> {
-    let queried_column = queried_column.trim();
-    if let Some(open_paren_index) = queried_column.find('(') {
-        if let Some(close_paren_index) = queried_column.find(')') {
-            let function = (&queried_column[..open_paren_index]).trim();
-            let column = (&queried_column[open_paren_index + 1..close_paren_index]).trim();
-
-            if !function.is_empty() && !column.is_empty() {
-                return Ok((Some(function.into()), column));
-            }
-        }
-    }
-
-    Ok((None, queried_column))
+/// Parses a queried column into its aggregate, if any, the name of the column of its values and
+/// the condition of the rows it aggregates, if it's filtered.
+pub fn try_parse_queried_column(
+    queried_column: &str,
+) -> io::Result<(Option<Aggregate>, String, Option<String>)> {
+    let select_item = parse_select_item(queried_column)?;
+    let (aggregate, expression) = select_item.expression.to_aggregate_column()?;
+    let column = match expression {
+        Expression::Column { name, .. } => name.clone(),
+        expression => expression.to_string(),
+    };
+    let filter = select_item
+        .expression
+        .aggregate_filter()
+        .map(|f| f.to_string());
+
+    Ok((aggregate, column, filter))
 }
@@ -0,0 +1,97 @@
+use serde::{Deserialize, Serialize};
+
+use crate::table::column::{Column, ColumnType};
+use crate::table::dictionary::DICTIONARY_ID_SIZE;
+
+/// The minimum ratio between the raw size and the dictionary size of a column for which the
+/// dictionary encoding is suggested, since it makes each read go through the dictionary.
+const MIN_DICTIONARY_RATIO: f64 = 2.0;
+
+/// How the values of a column are stored.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ColumnEncoding {
+    /// Each value is stored with the fixed size of its type.
+    #[default]
+    Raw,
+    /// Each distinct value is stored once, and the rows store the id of their value, which is only
+    /// supported by the string columns.
+    Dictionary,
+    /// The records are stored as the varints of their differences with the previous ones, which is
+    /// only supported by the integer columns.
+    Delta,
+}
+
+impl ColumnEncoding {
+    pub fn supports(&self, ty: ColumnType) -> bool {
+        match self {
+            ColumnEncoding::Raw => true,
+            ColumnEncoding::Dictionary => ty == ColumnType::String,
+            ColumnEncoding::Delta => ty == ColumnType::Integer,
+        }
+    }
+
+    /// Returns the size of the values of the column in its records, unless the records have a
+    /// variable size.
+    pub fn value_size(&self, column: &Column) -> Option<usize> {
+        match self {
+            ColumnEncoding::Raw => Some(column.size()),
+            ColumnEncoding::Dictionary => Some(DICTIONARY_ID_SIZE),
+            ColumnEncoding::Delta => None,
+        }
+    }
+}
+
+impl<'a> From<&'a ColumnEncoding> for &'a str {
+    fn from(value: &'a ColumnEncoding) -> Self {
+        match value {
+            ColumnEncoding::Raw => "raw",
+            ColumnEncoding::Dictionary => "dictionary",
+            ColumnEncoding::Delta => "delta",
+        }
+    }
+}
+
+/// The estimated size of a column with each encoding, based on the cardinality of its values.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct CompressionAdvice {
+    pub column: String,
+    /// The encoding with which the column is stored.
+    pub encoding: ColumnEncoding,
+    pub rows: u64,
+    pub distinct_values: u64,
+    pub raw_bytes: u64,
+    pub dictionary_bytes: u64,
+    /// The raw size divided by the dictionary size, greater than 1 if the dictionary is smaller.
+    pub dictionary_ratio: f64,
+    pub suggested_encoding: ColumnEncoding,
+}
+
+impl CompressionAdvice {
+    pub fn new(column: &Column, encoding: ColumnEncoding, rows: u64, distinct_values: u64) -> Self {
+        let value_size = column.ty.size() as u64;
+        let raw_bytes = rows * value_size;
+        let dictionary_bytes = distinct_values * value_size + rows * DICTIONARY_ID_SIZE as u64;
+        let dictionary_ratio = match dictionary_bytes {
+            0 => 1.0,
+            dictionary_bytes => raw_bytes as f64 / dictionary_bytes as f64,
+        };
+        let suggested_encoding = match ColumnEncoding::Dictionary.supports(column.ty)
+            && dictionary_ratio >= MIN_DICTIONARY_RATIO
+        {
+            true => ColumnEncoding::Dictionary,
+            false => ColumnEncoding::Raw,
+        };
+
+        Self {
+            column: column.name.clone(),
+            encoding,
+            rows,
+            distinct_values,
+            raw_bytes,
+            dictionary_bytes,
+            dictionary_ratio,
+            suggested_encoding,
+        }
+    }
+}
@@ -1,14 +1,19 @@
 use std::fmt::Debug;
 use std::hash::Hash;
-use std::io::SeekFrom;
+use std::io::{Error, ErrorKind, SeekFrom};
 use std::ops::Div;
+use std::sync::Arc;
 
+use crate::io::data_file::DataFile;
 use crate::table::aggregate::{Aggregable, GroupKey, GroupValue};
 use crate::table::column::{index_and_timestamp_size, AggregateColumn, Column, ColumnType};
+use crate::table::compression::ColumnEncoding;
+use crate::table::delta::{decode_blocks, seek_block};
+use crate::table::dictionary::{Dictionary, DICTIONARY_ID_SIZE};
+use crate::table::segment::SegmentFooter;
 use crate::table::FromDisk;
-use tokio::fs::File;
 use tokio::io;
-use tokio::io::{AsyncReadExt, AsyncSeekExt, BufStream};
+use tokio::sync::mpsc::{channel, Receiver, Sender};
 
 #[derive(Debug)]
 pub struct AggregatedRow<T>
@@ -74,13 +79,24 @@ where
         self.values.iter().map(|(c, _)| c.clone()).collect()
     }
 
-    pub fn aggregate_columns(&self) -> Vec<(AggregateColumn, &T)> {
-        // We have to return `&T` since we will use that to infer the type of the aggregate, which
-        // can differ from the type of the `column` on which it is run.
-        self.aggregates
+    /// Returns the values of the grouped columns and of the aggregates.
+    pub fn values(&self) -> impl Iterator<Item = &T> {
+        let aggregate_values = self.aggregates.iter().map(|(_, v, _)| v);
+        self.values.iter().map(|(_, v)| v).chain(aggregate_values)
+    }
+
+    /// Returns the value of a grouped column or aggregate, identified by its name, e.g. `sum(a)`.
+    pub fn named_value(&self, name: &str) -> Option<&T> {
+        self.values
             .iter()
-            .map(|(c, v, _)| (c.clone(), v))
-            .collect()
+            .find(|(c, _)| c.name == name)
+            .map(|(_, v)| v)
+            .or_else(|| {
+                self.aggregates
+                    .iter()
+                    .find(|(a, _, _)| String::from(a.clone()) == name)
+                    .map(|(_, v, _)| v)
+            })
     }
 }
 
@@ -89,9 +105,7 @@ pub struct Row<T>
 where
     T: Debug + Clone + Ord + PartialOrd + Eq + PartialEq + Hash,
 {
-    #[allow(dead_code)]
     index_id: u64,
-    #[allow(dead_code)]
     timestamp: u64,
     values: Vec<(Column, T)>,
 }
@@ -123,20 +137,44 @@ where
             .map(|(_, v)| v)
     }
 
+    pub fn named_value(&self, name: &str) -> Option<&T> {
+        self.values
+            .iter()
+            .find(|(c, _)| c.name == name)
+            .map(|(_, v)| v)
+    }
+
+    pub fn index_id(&self) -> u64 {
+        self.index_id
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &T> {
+        self.values.iter().map(|(_, v)| v)
+    }
+
+    pub fn timestamp(&self) -> u64 {
+        self.timestamp
+    }
+
     pub fn columns(&self) -> Vec<Column> {
         self.values.iter().map(|(c, _)| c.clone()).collect()
     }
 
-    pub fn group(&self, group_by_columns: &Vec<Column>) -> GroupKey<T> {
-        let key = self
-            .values
+    /// Keeps only the first `len` values of the row, dropping the ones which were read only to
+    /// compute the query.
+    pub fn truncate_values(&mut self, len: usize) {
+        self.values.truncate(len);
+    }
+
+    /// Adds a value computed from the other values of the row, like a grouped expression.
+    pub fn add_value(&mut self, column: Column, value: T) {
+        self.values.push((column, value));
+    }
+
+    pub fn group(&self, group_by_columns: &[Column]) -> GroupKey<T> {
+        let key = group_by_columns
             .iter()
-            .filter_map(|(c, v)| {
-                group_by_columns
-                    .into_iter()
-                    .find(|inner_c| **inner_c == *c)?;
-                Some((c.clone(), v.clone()))
-            })
+            .filter_map(|c| Some((c.clone(), self.value(c)?.clone())))
             .collect();
 
         GroupKey(key)
@@ -170,23 +208,222 @@ where
     }
 }
 
+/// The number of bytes read ahead by a cursor, rounded down to a multiple of its record size.
+const READ_AHEAD_SIZE: usize = 64 * 1024;
+
+/// Reads the `[start, end)` ranges of the file, or the data after its current position if there
+/// are no ranges, in chunks of `chunk_size` bytes on a separate task, so that the next chunk is read
+/// while the current one is decoded.
+///
+/// At most one chunk is read ahead, and the task stops as soon as the receiver is dropped.
+fn spawn_read_ahead(
+    mut file: DataFile,
+    chunk_size: usize,
+    ranges: Option<Vec<(u64, u64)>>,
+) -> Receiver<io::Result<Vec<u8>>> {
+    let (sender, receiver) = channel(1);
+    tokio::spawn(async move {
+        if let Err(error) = read_ranges(&mut file, chunk_size, ranges, &sender).await {
+            let _ = sender.send(Err(error)).await;
+        }
+    });
+
+    receiver
+}
+
+async fn read_ranges(
+    file: &mut DataFile,
+    chunk_size: usize,
+    ranges: Option<Vec<(u64, u64)>>,
+    sender: &Sender<io::Result<Vec<u8>>>,
+) -> io::Result<()> {
+    let len = file.len().await?;
+    let ranges = match ranges {
+        Some(ranges) => ranges,
+        None => vec![(file.seek(SeekFrom::Current(0)).await?, len)],
+    };
+
+    for (start, end) in ranges {
+        file.seek(SeekFrom::Start(start)).await?;
+        let mut remaining = end.min(len).saturating_sub(start);
+        while remaining > 0 {
+            let mut chunk = vec![0u8; remaining.min(chunk_size as u64) as usize];
+            file.read_exact(&mut chunk).await?;
+            remaining -= chunk.len() as u64;
+
+            if sender.send(Ok(chunk)).await.is_err() {
+                return Ok(());
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns the position of the first of the records between `low` and `high` whose timestamp isn't
+/// before `timestamp`, which is found with a binary search since the records are appended with
+/// increasing timestamps.
+async fn seek_timestamp(
+    file: &mut DataFile,
+    record_size: u64,
+    (mut low, mut high): (u64, u64),
+    timestamp: u64,
+) -> io::Result<u64> {
+    let mut buffer = [0u8; 8];
+    while low < high {
+        let middle = low + (high - low) / 2;
+        let timestamp_offset = middle * record_size + ColumnType::Integer.size() as u64;
+        file.seek(SeekFrom::Start(timestamp_offset)).await?;
+        file.read_exact(&mut buffer).await?;
+        match u64::from_le_bytes(buffer) < timestamp {
+            true => low = middle + 1,
+            false => high = middle,
+        }
+    }
+
+    Ok(low * record_size)
+}
+
+/// How the records of a file are decoded, depending on the encoding of its column.
+#[derive(Debug, Clone, Default)]
+pub enum ColumnDecoder {
+    /// The records have the fixed size of the type of the column.
+    #[default]
+    Raw,
+    /// The records store the ids of their values in the dictionary of the column.
+    Dictionary(Arc<Dictionary>),
+    /// The records are in delta encoded blocks, which are decoded with the raw layout.
+    Delta,
+}
+
+impl From<&ColumnDecoder> for ColumnEncoding {
+    fn from(value: &ColumnDecoder) -> Self {
+        match value {
+            ColumnDecoder::Raw => ColumnEncoding::Raw,
+            ColumnDecoder::Dictionary(_) => ColumnEncoding::Dictionary,
+            ColumnDecoder::Delta => ColumnEncoding::Delta,
+        }
+    }
+}
+
+/// Returns the size of the records read by a cursor, which have no value if there's no column.
+fn record_size(column: Option<&Column>, decoder: &ColumnDecoder) -> usize {
+    let value_size = match (column, decoder) {
+        (None, _) => 0,
+        (Some(_), ColumnDecoder::Dictionary(_)) => DICTIONARY_ID_SIZE,
+        (Some(column), _) => column.size(),
+    };
+
+    index_and_timestamp_size() + value_size
+}
+
 pub struct ColumnCursor {
     pub column: Option<Column>,
-    file: BufStream<File>,
+    decoder: ColumnDecoder,
+    /// The bytes read from a delta encoded file which don't make a whole block yet.
+    encoded: Vec<u8>,
+    chunks: Receiver<io::Result<Vec<u8>>>,
+    chunk: Vec<u8>,
+    offset: usize,
 }
 
 impl ColumnCursor {
-    pub fn new(column: Option<Column>, file: BufStream<File>) -> Self {
-        Self { column, file }
+    pub fn new(column: Option<Column>, decoder: ColumnDecoder, file: DataFile) -> Self {
+        Self::with_ranges(column, decoder, file, None)
+    }
+
+    /// Creates a cursor reading the ranges of the file, or the data after its current position if
+    /// there are no ranges.
+    fn with_ranges(
+        column: Option<Column>,
+        decoder: ColumnDecoder,
+        file: DataFile,
+        ranges: Option<Vec<(u64, u64)>>,
+    ) -> Self {
+        let record_size = record_size(column.as_ref(), &decoder);
+        let chunk_size = (READ_AHEAD_SIZE / record_size).max(1) * record_size;
+
+        Self {
+            column,
+            decoder,
+            encoded: vec![],
+            chunks: spawn_read_ahead(file, chunk_size, ranges),
+            chunk: vec![],
+            offset: 0,
+        }
+    }
+
+    /// Creates a cursor starting at the first record whose timestamp isn't before `timestamp`.
+    ///
+    /// The records are appended with increasing timestamps, so the record is found with a binary
+    /// search instead of reading all the records before it. The delta encoded files start instead
+    /// at the first block with such a record.
+    pub async fn from_timestamp(
+        column: Option<Column>,
+        decoder: ColumnDecoder,
+        mut file: DataFile,
+        timestamp: u64,
+    ) -> io::Result<Self> {
+        if matches!(decoder, ColumnDecoder::Delta) {
+            seek_block(&mut file, timestamp).await?;
+            return Ok(Self::new(column, decoder, file));
+        }
+
+        let record_size = record_size(column.as_ref(), &decoder) as u64;
+        let records = file.len().await? / record_size;
+        let offset = seek_timestamp(&mut file, record_size, (0, records), timestamp).await?;
+        file.seek(SeekFrom::Start(offset)).await?;
+
+        Ok(Self::new(column, decoder, file))
+    }
+
+    /// Creates a cursor reading only the segments of the file, sorted by position, starting at the
+    /// first record whose timestamp isn't before `since`.
+    ///
+    /// Only the records of the first segment are binary searched, whereas the delta encoded files
+    /// start at the first block of the segment.
+    pub async fn from_segments(
+        column: Option<Column>,
+        decoder: ColumnDecoder,
+        mut file: DataFile,
+        segments: &[SegmentFooter],
+        since: Option<u64>,
+    ) -> io::Result<Self> {
+        let mut ranges: Vec<(u64, u64)> = vec![];
+        for segment in segments {
+            match ranges.last_mut() {
+                Some((_, end)) if *end == segment.offset => *end = segment.end(),
+                _ => ranges.push((segment.offset, segment.end())),
+            }
+        }
+
+        if let (Some(since), Some(first), Some((start, _))) =
+            (since, segments.first(), ranges.first_mut())
+        {
+            if !matches!(decoder, ColumnDecoder::Delta) {
+                let record_size = record_size(column.as_ref(), &decoder) as u64;
+                let records = (first.offset / record_size, first.end() / record_size);
+                *start = seek_timestamp(&mut file, record_size, records, since).await?;
+            }
+        }
+
+        Ok(Self::with_ranges(column, decoder, file, Some(ranges)))
     }
 
     pub async fn read<T>(&mut self) -> io::Result<RowComponent<T>>
     where
         T: FromDisk + Debug + Clone + Ord + PartialOrd + Eq + PartialEq + Hash,
     {
-        let total_size = ColumnType::Integer.size() * 2 + self.column_size();
-        let mut buffer = vec![0u8; total_size];
-        self.file.read_exact(&mut buffer).await?;
+        let total_size = record_size(self.column.as_ref(), &self.decoder);
+        if self.offset + total_size > self.chunk.len() {
+            self.next_chunk().await?;
+        }
+        if self.offset + total_size > self.chunk.len() {
+            return Err(Error::new(ErrorKind::UnexpectedEof, "early eof"));
+        }
+
+        let buffer = &self.chunk[self.offset..self.offset + total_size];
+        self.offset += total_size;
 
         let index_id = u64::from_le_bytes(buffer[..ColumnType::Integer.size()].try_into().unwrap());
         let timestamp = u64::from_le_bytes(
@@ -198,7 +435,11 @@ impl ColumnCursor {
             return Ok(RowComponent::new(index_id, timestamp, None));
         };
 
-        let data = buffer[ColumnType::Integer.size() * 2..].to_vec();
+        let data = &buffer[ColumnType::Integer.size() * 2..];
+        let data = match &self.decoder {
+            ColumnDecoder::Dictionary(dictionary) => dictionary.decode(data)?.to_vec(),
+            _ => data.to_vec(),
+        };
         Ok(RowComponent::new(
             index_id,
             timestamp,
@@ -206,13 +447,48 @@ impl ColumnCursor {
         ))
     }
 
+    /// Moves the cursor back by one record, which must be the last one that was read.
     pub async fn undo(&mut self) -> io::Result<()> {
         // We compute the total size of the column data, since we skip data with such size.
-        let size = (index_and_timestamp_size() + self.column_size()) as i64;
-        self.file.seek(SeekFrom::Current(-size)).await.map(|_| ())
+        let size = record_size(self.column.as_ref(), &self.decoder);
+        self.offset = self.offset.checked_sub(size).ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                "Only the last read record can be undone",
+            )
+        })?;
+
+        Ok(())
     }
 
-    fn column_size(&self) -> usize {
-        self.column.as_ref().map_or(0, |c| c.size())
+    async fn next_chunk(&mut self) -> io::Result<()> {
+        self.offset = 0;
+        if matches!(self.decoder, ColumnDecoder::Delta) {
+            return self.next_delta_chunk().await;
+        }
+
+        // The chunks are made of whole records, so no record is split between two chunks.
+        self.chunk = match self.chunks.recv().await {
+            Some(chunk) => chunk?,
+            None => vec![],
+        };
+
+        Ok(())
+    }
+
+    /// Decodes the next whole blocks of a delta encoded file, whose chunks can split a block.
+    async fn next_delta_chunk(&mut self) -> io::Result<()> {
+        self.chunk.clear();
+        while self.chunk.is_empty() {
+            // A partial block at the end of the file is ignored.
+            let Some(chunk) = self.chunks.recv().await else {
+                return Ok(());
+            };
+            self.encoded.extend_from_slice(&chunk?);
+            let decoded = decode_blocks(&self.encoded, &mut self.chunk)?;
+            self.encoded.drain(..decoded);
+        }
+
+        Ok(())
     }
 }
@@ -0,0 +1,182 @@
+use std::collections::HashSet;
+use std::io::{ErrorKind, SeekFrom};
+use std::path::Path;
+
+use tokio::io;
+
+use crate::io::data_file::DataFile;
+use crate::io::file::{create_and_open_file, data_file_len, open_read_file};
+use crate::io::storage::storage;
+use crate::table::column::{index_and_timestamp_size, ColumnType};
+
+/// The file of a partition listing the tombstones of its deleted rows, which have the same layout
+/// as the entries of the index: 8 bytes for the index id followed by 8 bytes for the timestamp.
+///
+/// The records of the deleted rows stay in the files of the partition, and are skipped by the
+/// scans.
+pub const DELETED_ROWS_FILE_NAME: &str = ".deleted.dsto";
+
+/// The number of records read at once while dropping the records of the deleted rows.
+const COMPACTION_CHUNK_RECORDS: u64 = 4096;
+
+/// A deleted row, with its index id and timestamp.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Tombstone {
+    pub index_id: u64,
+    pub timestamp: u64,
+}
+
+/// Returns the index ids of the deleted rows of the partition.
+pub async fn read_deleted_rows<P: AsRef<Path>>(partition_path: P) -> io::Result<HashSet<u64>> {
+    Ok(read_tombstones(partition_path)
+        .await?
+        .into_iter()
+        .map(|t| t.index_id)
+        .collect())
+}
+
+/// Returns the number of deleted rows of the partition.
+pub async fn count_deleted_rows<P: AsRef<Path>>(partition_path: P) -> io::Result<u64> {
+    match data_file_len(DELETED_ROWS_FILE_NAME, partition_path).await {
+        Ok(len) => Ok(len / index_and_timestamp_size() as u64),
+        Err(error) if error.kind() == ErrorKind::NotFound => Ok(0),
+        Err(error) => Err(error),
+    }
+}
+
+/// Appends the tombstones of the rows to the deleted rows of the partition.
+pub async fn append_tombstones<P: AsRef<Path>>(
+    partition_path: P,
+    tombstones: &[Tombstone],
+) -> io::Result<()> {
+    let mut file = create_and_open_file(DELETED_ROWS_FILE_NAME, partition_path).await?;
+    // A partial tombstone left by a crash is overwritten.
+    let entry_size = index_and_timestamp_size() as u64;
+    let len = file.len().await? / entry_size * entry_size;
+    file.seek(SeekFrom::Start(len)).await?;
+    file.write_all(&encode_tombstones(tombstones)).await?;
+
+    file.sync_all().await
+}
+
+/// Drops the tombstones older than `cutoff`, whose rows are dropped by the retention, and returns
+/// how many were dropped.
+///
+/// The tombstones are appended in the order of the deletions, so unlike the other files of the
+/// partition they aren't sorted by timestamp.
+pub async fn drop_expired_tombstones<P: AsRef<Path>>(
+    partition_path: P,
+    cutoff: u64,
+) -> io::Result<u64> {
+    let tombstones = read_tombstones(&partition_path).await?;
+    let kept: Vec<Tombstone> = tombstones
+        .iter()
+        .filter(|t| t.timestamp >= cutoff)
+        .copied()
+        .collect();
+    let dropped = (tombstones.len() - kept.len()) as u64;
+    if dropped == 0 {
+        return Ok(0);
+    }
+
+    let file_path = partition_path.as_ref().join(DELETED_ROWS_FILE_NAME);
+    let tmp_file_path = file_path.with_extension("dsto.tmp");
+    let mut tmp_file = DataFile::from_file(storage().create(&tmp_file_path).await?).await?;
+    tmp_file.write_all(&encode_tombstones(&kept)).await?;
+    tmp_file.sync_all().await?;
+    storage().rename(&tmp_file_path, &file_path).await?;
+
+    Ok(dropped)
+}
+
+/// Rewrites a file of fixed size records without the records of the deleted rows, and returns how
+/// many were dropped.
+///
+/// The file is left untouched if it has no record of the deleted rows.
+pub async fn drop_deleted_records<P: AsRef<Path>>(
+    file_path: P,
+    record_size: usize,
+    deleted_rows: &HashSet<u64>,
+) -> io::Result<u64> {
+    let file_path = file_path.as_ref();
+    let mut file = DataFile::from_file(storage().open(file_path, false).await?).await?;
+    let tmp_file_path = file_path.with_extension("dsto.tmp");
+    let mut tmp_file = DataFile::from_file(storage().create(&tmp_file_path).await?).await?;
+
+    // A crash during a write might leave a partial record at the end, which is dropped.
+    let mut remaining = file.len().await? / record_size as u64;
+    let mut dropped = 0;
+    let integer_size = ColumnType::Integer.size();
+    let mut data = vec![];
+    while remaining > 0 {
+        let records = remaining.min(COMPACTION_CHUNK_RECORDS);
+        data.resize(records as usize * record_size, 0);
+        file.read_exact(&mut data).await?;
+        let mut kept = Vec::with_capacity(data.len());
+        for record in data.chunks_exact(record_size) {
+            let index_id = u64::from_le_bytes(record[..integer_size].try_into().unwrap());
+            match deleted_rows.contains(&index_id) {
+                true => dropped += 1,
+                false => kept.extend_from_slice(record),
+            }
+        }
+        tmp_file.write_all(&kept).await?;
+        remaining -= records;
+    }
+
+    if dropped == 0 {
+        storage().remove_file(&tmp_file_path).await?;
+        return Ok(0);
+    }
+    tmp_file.sync_all().await?;
+    storage().rename(&tmp_file_path, file_path).await?;
+
+    Ok(dropped)
+}
+
+/// Removes the tombstones of the partition, once the records of its deleted rows are dropped.
+pub async fn remove_tombstones<P: AsRef<Path>>(partition_path: P) -> io::Result<()> {
+    match storage()
+        .remove_file(&partition_path.as_ref().join(DELETED_ROWS_FILE_NAME))
+        .await
+    {
+        Err(error) if error.kind() != ErrorKind::NotFound => Err(error),
+        _ => Ok(()),
+    }
+}
+
+async fn read_tombstones<P: AsRef<Path>>(partition_path: P) -> io::Result<Vec<Tombstone>> {
+    let mut file = match open_read_file(DELETED_ROWS_FILE_NAME, partition_path).await {
+        Ok(file) => file,
+        Err(error) if error.kind() == ErrorKind::NotFound => return Ok(vec![]),
+        Err(error) => return Err(error),
+    };
+
+    // A crash during a write might leave a partial tombstone at the end, which is ignored.
+    let entry_size = index_and_timestamp_size();
+    let len = file.len().await? as usize / entry_size * entry_size;
+    let mut data = vec![0u8; len];
+    file.read_exact(&mut data).await?;
+
+    let integer_size = ColumnType::Integer.size();
+    Ok(data
+        .chunks_exact(entry_size)
+        .map(|entry| {
+            let (index_id, timestamp) = entry.split_at(integer_size);
+            Tombstone {
+                index_id: u64::from_le_bytes(index_id.try_into().unwrap()),
+                timestamp: u64::from_le_bytes(timestamp.try_into().unwrap()),
+            }
+        })
+        .collect())
+}
+
+fn encode_tombstones(tombstones: &[Tombstone]) -> Vec<u8> {
+    let mut data = Vec::with_capacity(tombstones.len() * index_and_timestamp_size());
+    for tombstone in tombstones {
+        data.extend_from_slice(&u64::to_le_bytes(tombstone.index_id));
+        data.extend_from_slice(&u64::to_le_bytes(tombstone.timestamp));
+    }
+
+    data
+}
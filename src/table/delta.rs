@@ -0,0 +1,470 @@
+use std::collections::HashSet;
+use std::io::{Error, ErrorKind, SeekFrom};
+use std::path::Path;
+
+use tokio::io;
+
+use crate::io::data_file::DataFile;
+use crate::io::file::copy_data_file;
+use crate::io::storage::storage;
+use crate::table::column::ColumnType;
+
+/// The size of the header of a block: 4 bytes for the number of records, 4 bytes for the size of
+/// the payload and 8 bytes for the timestamp of the last record.
+const BLOCK_HEADER_SIZE: usize = 16;
+
+/// The size of a record decoded with the raw layout of an integer column.
+pub const DECODED_RECORD_SIZE: usize = ColumnType::Integer.size() * 3;
+
+/// A block of records of a delta encoded integer column, in which the index id, timestamp and value
+/// of each record are stored as the varint of their difference with the ones of the previous
+/// record.
+///
+/// Each insertion appends a block, which starts from zero so that it's decoded on its own, and
+/// thus the files of a partition can still be concatenated.
+#[derive(Debug, Default)]
+pub struct DeltaBlock {
+    records: u32,
+    last_timestamp: u64,
+    previous: (u64, u64, i64),
+    payload: Vec<u8>,
+}
+
+impl DeltaBlock {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records == 0
+    }
+
+    /// Adds a record to the block, returning the number of bytes it takes.
+    pub fn push(&mut self, index_id: u64, timestamp: u64, value: i64) -> usize {
+        let (previous_index_id, previous_timestamp, previous_value) = self.previous;
+        let len = self.payload.len();
+        write_varint(
+            &mut self.payload,
+            zigzag(index_id.wrapping_sub(previous_index_id) as i64),
+        );
+        write_varint(
+            &mut self.payload,
+            zigzag(timestamp.wrapping_sub(previous_timestamp) as i64),
+        );
+        write_varint(
+            &mut self.payload,
+            zigzag(value.wrapping_sub(previous_value)),
+        );
+
+        self.records += 1;
+        self.last_timestamp = timestamp;
+        self.previous = (index_id, timestamp, value);
+
+        self.payload.len() - len
+    }
+
+    /// Returns the block as stored in the file, with its header followed by its payload.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(BLOCK_HEADER_SIZE + self.payload.len());
+        data.extend_from_slice(&u32::to_le_bytes(self.records));
+        data.extend_from_slice(&u32::to_le_bytes(self.payload.len() as u32));
+        data.extend_from_slice(&u64::to_le_bytes(self.last_timestamp));
+        data.extend_from_slice(&self.payload);
+
+        data
+    }
+}
+
+/// Returns the size of the block at the start of `data`, if its header is whole.
+pub fn block_len(data: &[u8]) -> Option<usize> {
+    let (_, payload_size, _) = parse_header(data.get(..BLOCK_HEADER_SIZE)?);
+
+    Some(BLOCK_HEADER_SIZE + payload_size)
+}
+
+/// Decodes the whole blocks at the start of `data`, appending their records to `records` with the
+/// raw layout of an integer column, and returns the number of bytes decoded.
+///
+/// A block which isn't whole is left in `data`, since the rest of it wasn't read yet.
+pub fn decode_blocks(data: &[u8], records: &mut Vec<u8>) -> io::Result<usize> {
+    let mut offset = 0;
+    while let Some(header) = data.get(offset..offset + BLOCK_HEADER_SIZE) {
+        let (block_records, payload_size, _) = parse_header(header);
+        let payload_start = offset + BLOCK_HEADER_SIZE;
+        let Some(mut payload) = data.get(payload_start..payload_start + payload_size) else {
+            break;
+        };
+
+        let mut previous = (0u64, 0u64, 0i64);
+        records.reserve(block_records as usize * DECODED_RECORD_SIZE);
+        for _ in 0..block_records {
+            let index_id = previous
+                .0
+                .wrapping_add(unzigzag(read_varint(&mut payload)?) as u64);
+            let timestamp = previous
+                .1
+                .wrapping_add(unzigzag(read_varint(&mut payload)?) as u64);
+            let value = previous
+                .2
+                .wrapping_add(unzigzag(read_varint(&mut payload)?));
+            records.extend_from_slice(&u64::to_le_bytes(index_id));
+            records.extend_from_slice(&u64::to_le_bytes(timestamp));
+            records.extend_from_slice(&i64::to_le_bytes(value));
+            previous = (index_id, timestamp, value);
+        }
+
+        offset = payload_start + payload_size;
+    }
+
+    Ok(offset)
+}
+
+/// Moves the file to the first block whose last record isn't before `timestamp`, only reading the
+/// headers of the blocks before it.
+pub async fn seek_block(file: &mut DataFile, timestamp: u64) -> io::Result<()> {
+    let len = file.len().await?;
+    let mut offset = 0;
+    let mut header = [0u8; BLOCK_HEADER_SIZE];
+    while offset + BLOCK_HEADER_SIZE as u64 <= len {
+        file.seek(SeekFrom::Start(offset)).await?;
+        file.read_exact(&mut header).await?;
+        let (_, payload_size, last_timestamp) = parse_header(&header);
+        if last_timestamp >= timestamp {
+            break;
+        }
+
+        offset += (BLOCK_HEADER_SIZE + payload_size) as u64;
+    }
+    file.seek(SeekFrom::Start(offset.min(len))).await?;
+
+    Ok(())
+}
+
+/// Drops all the records of a delta encoded file whose timestamp is older than `cutoff`.
+///
+/// The blocks whose last record is expired are dropped, and the first block which isn't is encoded
+/// again without its expired records.
+pub async fn drop_expired_blocks<P: AsRef<Path>>(file_path: P, cutoff: u64) -> io::Result<()> {
+    let file_path = file_path.as_ref();
+    let mut file = DataFile::from_file(storage().open(file_path, true).await?).await?;
+    seek_block(&mut file, cutoff).await?;
+    let offset = file.seek(SeekFrom::Current(0)).await?;
+    if offset == file.len().await? {
+        return file.truncate().await;
+    }
+
+    let mut header = [0u8; BLOCK_HEADER_SIZE];
+    file.read_exact(&mut header).await?;
+    let (_, payload_size, _) = parse_header(&header);
+    let mut data = header.to_vec();
+    data.resize(BLOCK_HEADER_SIZE + payload_size, 0);
+    file.read_exact(&mut data[BLOCK_HEADER_SIZE..]).await?;
+
+    let mut records = vec![];
+    decode_blocks(&data, &mut records)?;
+    let mut block = DeltaBlock::new();
+    for record in records.chunks_exact(DECODED_RECORD_SIZE) {
+        let (index_id, rest) = record.split_at(ColumnType::Integer.size());
+        let (timestamp, value) = rest.split_at(ColumnType::Integer.size());
+        let timestamp = u64::from_le_bytes(timestamp.try_into().unwrap());
+        if timestamp >= cutoff {
+            block.push(
+                u64::from_le_bytes(index_id.try_into().unwrap()),
+                timestamp,
+                i64::from_le_bytes(value.try_into().unwrap()),
+            );
+        }
+    }
+    if offset == 0 && block.records == parse_header(&header).0 {
+        return Ok(());
+    }
+
+    let tmp_file_path = file_path.with_extension("dsto.tmp");
+    let mut tmp_file = DataFile::from_file(storage().create(&tmp_file_path).await?).await?;
+    tmp_file.write_all(&block.encode()).await?;
+    copy_data_file(&mut file, &mut tmp_file, offset + data.len() as u64).await?;
+    tmp_file.sync_all().await?;
+
+    storage().rename(&tmp_file_path, file_path).await
+}
+
+/// Rewrites a delta encoded file without the records of the deleted rows, encoding again each
+/// block on its own so that the file is still sought by block, and returns how many records were
+/// dropped.
+///
+/// The file is left untouched if it has no record of the deleted rows.
+pub async fn drop_deleted_blocks<P: AsRef<Path>>(
+    file_path: P,
+    deleted_rows: &HashSet<u64>,
+) -> io::Result<u64> {
+    let file_path = file_path.as_ref();
+    let mut file = DataFile::from_file(storage().open(file_path, false).await?).await?;
+    let tmp_file_path = file_path.with_extension("dsto.tmp");
+    let mut tmp_file = DataFile::from_file(storage().create(&tmp_file_path).await?).await?;
+
+    let len = file.len().await?;
+    let mut offset = 0;
+    let mut dropped = 0;
+    let mut header = [0u8; BLOCK_HEADER_SIZE];
+    // A crash during a write might leave a partial block at the end, which is dropped.
+    while offset + BLOCK_HEADER_SIZE as u64 <= len {
+        file.read_exact(&mut header).await?;
+        let (_, payload_size, _) = parse_header(&header);
+        if offset + (BLOCK_HEADER_SIZE + payload_size) as u64 > len {
+            break;
+        }
+        let mut data = header.to_vec();
+        data.resize(BLOCK_HEADER_SIZE + payload_size, 0);
+        file.read_exact(&mut data[BLOCK_HEADER_SIZE..]).await?;
+        offset += data.len() as u64;
+
+        let mut records = vec![];
+        decode_blocks(&data, &mut records)?;
+        let mut block = DeltaBlock::new();
+        for record in records.chunks_exact(DECODED_RECORD_SIZE) {
+            let (index_id, rest) = record.split_at(ColumnType::Integer.size());
+            let (timestamp, value) = rest.split_at(ColumnType::Integer.size());
+            let index_id = u64::from_le_bytes(index_id.try_into().unwrap());
+            if deleted_rows.contains(&index_id) {
+                dropped += 1;
+                continue;
+            }
+
+            block.push(
+                index_id,
+                u64::from_le_bytes(timestamp.try_into().unwrap()),
+                i64::from_le_bytes(value.try_into().unwrap()),
+            );
+        }
+        if !block.is_empty() {
+            tmp_file.write_all(&block.encode()).await?;
+        }
+    }
+
+    if dropped == 0 {
+        storage().remove_file(&tmp_file_path).await?;
+        return Ok(0);
+    }
+    tmp_file.sync_all().await?;
+    storage().rename(&tmp_file_path, file_path).await?;
+
+    Ok(dropped)
+}
+
+fn parse_header(header: &[u8]) -> (u32, usize, u64) {
+    let records = u32::from_le_bytes(header[..4].try_into().unwrap());
+    let payload_size = u32::from_le_bytes(header[4..8].try_into().unwrap()) as usize;
+    let last_timestamp = u64::from_le_bytes(header[8..BLOCK_HEADER_SIZE].try_into().unwrap());
+
+    (records, payload_size, last_timestamp)
+}
+
+/// Maps the signed integers to unsigned ones, so that the small negative differences also have a
+/// short varint.
+fn zigzag(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+fn unzigzag(value: u64) -> i64 {
+    ((value >> 1) as i64) ^ -((value & 1) as i64)
+}
+
+/// Writes the integer with 7 bits per byte, whose highest bit tells whether another byte follows.
+fn write_varint(data: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        data.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    data.push(value as u8);
+}
+
+fn read_varint(data: &mut &[u8]) -> io::Result<u64> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let Some((byte, rest)) = data.split_first() else {
+            break;
+        };
+        *data = rest;
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+
+    Err(Error::new(
+        ErrorKind::InvalidData,
+        "The delta encoded block is corrupted",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::path::PathBuf;
+
+    use super::*;
+
+    /// The index id, timestamp and value of a record.
+    type Record = (u64, u64, i64);
+
+    fn decode_records(data: &[u8]) -> io::Result<(usize, Vec<Record>)> {
+        let mut records = vec![];
+        let decoded = decode_blocks(data, &mut records)?;
+        let records = records
+            .chunks_exact(DECODED_RECORD_SIZE)
+            .map(|record| {
+                (
+                    u64::from_le_bytes(record[..8].try_into().unwrap()),
+                    u64::from_le_bytes(record[8..16].try_into().unwrap()),
+                    i64::from_le_bytes(record[16..].try_into().unwrap()),
+                )
+            })
+            .collect();
+
+        Ok((decoded, records))
+    }
+
+    fn encode_block(records: &[Record]) -> Vec<u8> {
+        let mut block = DeltaBlock::new();
+        for (index_id, timestamp, value) in records {
+            block.push(*index_id, *timestamp, *value);
+        }
+
+        block.encode()
+    }
+
+    /// Writes the blocks to a new file in a temporary directory, returning its path.
+    async fn write_file(name: &str, blocks: &[Vec<u8>]) -> PathBuf {
+        let path =
+            env::temp_dir().join(format!("distribuito-delta-{}-{}", std::process::id(), name));
+        let mut file = DataFile::from_file(storage().create(&path).await.unwrap())
+            .await
+            .unwrap();
+        for block in blocks {
+            file.write_all(block).await.unwrap();
+        }
+        file.sync_all().await.unwrap();
+
+        path
+    }
+
+    async fn read_file(path: &Path) -> Vec<Record> {
+        let mut file = DataFile::from_file(storage().open(path, false).await.unwrap())
+            .await
+            .unwrap();
+        let mut data = vec![0u8; file.len().await.unwrap() as usize];
+        file.seek(SeekFrom::Start(0)).await.unwrap();
+        file.read_exact(&mut data).await.unwrap();
+        storage().remove_file(path).await.unwrap();
+
+        let (decoded, records) = decode_records(&data).unwrap();
+        assert_eq!(decoded, data.len());
+        records
+    }
+
+    #[test]
+    fn test_zigzag_varint_round_trip() {
+        for value in [0, 1, -1, 63, -64, 64, i64::MIN, i64::MAX] {
+            assert_eq!(unzigzag(zigzag(value)), value);
+
+            let mut data = vec![];
+            write_varint(&mut data, zigzag(value));
+            let mut slice = data.as_slice();
+            assert_eq!(read_varint(&mut slice).unwrap(), zigzag(value));
+            assert!(slice.is_empty());
+        }
+        // The small negative differences take a single byte.
+        let mut data = vec![];
+        write_varint(&mut data, zigzag(-1));
+        assert_eq!(data.len(), 1);
+    }
+
+    #[test]
+    fn test_block_round_trip() {
+        let records = vec![
+            (5, 100, i64::MAX),
+            (3, 50, i64::MIN),
+            (u64::MAX, 0, -1),
+            (0, u64::MAX, 0),
+            (7, 7, i64::MAX),
+        ];
+        let data = encode_block(&records);
+
+        assert_eq!(block_len(&data), Some(data.len()));
+        assert_eq!(decode_records(&data).unwrap(), (data.len(), records));
+    }
+
+    #[test]
+    fn test_truncated_block_is_not_decoded() {
+        let first = encode_block(&[(0, 10, 1), (1, 20, -2)]);
+        let second = encode_block(&[(2, 30, 3)]);
+        let mut data = [first.clone(), second].concat();
+        data.pop();
+
+        let (decoded, records) = decode_records(&data).unwrap();
+        assert_eq!(decoded, first.len());
+        assert_eq!(records, vec![(0, 10, 1), (1, 20, -2)]);
+
+        // A block whose header isn't whole is also left undecoded.
+        let (decoded, records) = decode_records(&data[..first.len() + 3]).unwrap();
+        assert_eq!(decoded, first.len());
+        assert_eq!(records.len(), 2);
+    }
+
+    #[test]
+    fn test_corrupted_varint() {
+        // A varint which never ends, and one cut by the end of the payload.
+        for payload in [vec![0xff; 12], vec![0x80; 3]] {
+            let mut data = vec![];
+            data.extend_from_slice(&u32::to_le_bytes(1));
+            data.extend_from_slice(&u32::to_le_bytes(payload.len() as u32));
+            data.extend_from_slice(&u64::to_le_bytes(0));
+            data.extend_from_slice(&payload);
+
+            let error = decode_records(&data).unwrap_err();
+            assert_eq!(error.kind(), ErrorKind::InvalidData);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_drop_expired_blocks() {
+        let first = encode_block(&[(0, 10, 1), (1, 20, 2)]);
+        let second = encode_block(&[(2, 30, 3), (3, 40, -4), (4, 50, 5)]);
+
+        // The first surviving block is encoded again without its expired records.
+        let path = write_file("partial", &[first.clone(), second.clone()]).await;
+        drop_expired_blocks(&path, 40).await.unwrap();
+        assert_eq!(read_file(&path).await, vec![(3, 40, -4), (4, 50, 5)]);
+
+        let path = write_file("first", &[first.clone(), second.clone()]).await;
+        drop_expired_blocks(&path, 15).await.unwrap();
+        assert_eq!(
+            read_file(&path).await,
+            vec![(1, 20, 2), (2, 30, 3), (3, 40, -4), (4, 50, 5)]
+        );
+
+        let path = write_file("none", &[first.clone(), second.clone()]).await;
+        drop_expired_blocks(&path, 5).await.unwrap();
+        assert_eq!(read_file(&path).await.len(), 5);
+
+        let path = write_file("all", &[first, second]).await;
+        drop_expired_blocks(&path, 60).await.unwrap();
+        assert_eq!(read_file(&path).await, vec![]);
+    }
+
+    #[tokio::test]
+    async fn test_drop_deleted_blocks() {
+        let first = encode_block(&[(0, 10, 1), (1, 20, 2)]);
+        let second = encode_block(&[(2, 30, 3), (3, 40, -4)]);
+
+        let path = write_file("deleted", &[first.clone(), second.clone()]).await;
+        let deleted_rows = HashSet::from([0, 1, 3]);
+        assert_eq!(drop_deleted_blocks(&path, &deleted_rows).await.unwrap(), 3);
+        assert_eq!(read_file(&path).await, vec![(2, 30, 3)]);
+
+        let path = write_file("untouched", &[first, second]).await;
+        let deleted_rows = HashSet::from([9]);
+        assert_eq!(drop_deleted_blocks(&path, &deleted_rows).await.unwrap(), 0);
+        assert_eq!(read_file(&path).await.len(), 4);
+    }
+}
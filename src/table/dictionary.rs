@@ -0,0 +1,132 @@
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind, SeekFrom};
+use std::path::Path;
+
+use tokio::io;
+
+use crate::io::file::{create_and_open_file, open_read_file};
+use crate::table::column::Column;
+
+/// The size of the ids with which the records of a dictionary encoded column refer to their
+/// values.
+pub const DICTIONARY_ID_SIZE: usize = std::mem::size_of::<u32>();
+
+/// Returns the name of the file of the dictionary of a column, which is in the root directory of
+/// the table since the ids are shared by all its partitions.
+fn dictionary_file_name(column: &Column) -> String {
+    format!(".{}.dictionary.dsto", column.name)
+}
+
+/// The distinct values of a dictionary encoded column, whose ids are the order in which they were
+/// added.
+///
+/// The values are stored with the fixed size of the type of the column, like in the column files,
+/// so that they are decoded in the same way.
+#[derive(Debug)]
+pub struct Dictionary {
+    column: Column,
+    values: Vec<Vec<u8>>,
+    ids: HashMap<Vec<u8>, u32>,
+    /// The number of values which are stored in the file of the dictionary.
+    persisted_values: usize,
+}
+
+impl Dictionary {
+    pub async fn read<P: AsRef<Path>>(table_path: P, column: &Column) -> io::Result<Self> {
+        let mut dictionary = Self {
+            column: column.clone(),
+            values: vec![],
+            ids: HashMap::new(),
+            persisted_values: 0,
+        };
+        let mut file = match open_read_file(&dictionary_file_name(column), table_path).await {
+            Ok(file) => file,
+            Err(error) if error.kind() == ErrorKind::NotFound => return Ok(dictionary),
+            Err(error) => return Err(error),
+        };
+
+        // A crash during a write might leave a partial value at the end, which is ignored.
+        let value_size = column.size();
+        let len = file.len().await? as usize / value_size * value_size;
+        let mut data = vec![0u8; len];
+        file.read_exact(&mut data).await?;
+        for value in data.chunks_exact(value_size) {
+            dictionary.insert(value)?;
+        }
+        dictionary.persisted_values = dictionary.values.len();
+
+        Ok(dictionary)
+    }
+
+    /// Returns the id of the value, adding it to the dictionary if it's new.
+    pub fn insert(&mut self, value: &[u8]) -> io::Result<u32> {
+        if let Some(id) = self.ids.get(value) {
+            return Ok(*id);
+        }
+
+        let id = u32::try_from(self.values.len()).map_err(|_| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("The dictionary of column {} is full", self.column.name),
+            )
+        })?;
+        self.values.push(value.to_vec());
+        self.ids.insert(value.to_vec(), id);
+
+        Ok(id)
+    }
+
+    pub fn id(&self, value: &[u8]) -> io::Result<u32> {
+        self.ids.get(value).copied().ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "The value isn't in the dictionary of column {}",
+                    self.column.name
+                ),
+            )
+        })
+    }
+
+    /// Returns the value whose id is stored in `data`.
+    pub fn decode(&self, data: &[u8]) -> io::Result<&[u8]> {
+        let id = u32::from_le_bytes(data.try_into().map_err(|_| {
+            Error::new(
+                ErrorKind::InvalidData,
+                "The dictionary id has an invalid size",
+            )
+        })?);
+
+        self.values
+            .get(id as usize)
+            .map(|v| v.as_slice())
+            .ok_or_else(|| {
+                Error::new(
+                    ErrorKind::InvalidData,
+                    format!(
+                        "The dictionary of column {} has no value with id {}",
+                        self.column.name, id
+                    ),
+                )
+            })
+    }
+
+    /// Appends the values added since the dictionary was read to its file.
+    pub async fn persist<P: AsRef<Path>>(&mut self, table_path: P) -> io::Result<()> {
+        if self.persisted_values == self.values.len() {
+            return Ok(());
+        }
+
+        let mut file =
+            create_and_open_file(&dictionary_file_name(&self.column), table_path).await?;
+        // A partial value left by a crash is overwritten.
+        let offset = (self.persisted_values * self.column.size()) as u64;
+        file.seek(SeekFrom::Start(offset)).await?;
+        file.write_all(&self.values[self.persisted_values..].concat())
+            .await?;
+        file.sync_all().await?;
+        self.persisted_values = self.values.len();
+
+        Ok(())
+    }
+}
@@ -0,0 +1,212 @@
+/// The compression of the digest, which bounds its number of centroids to a small multiple of it.
+const COMPRESSION: f64 = 100.0;
+
+/// A t-digest, which summarizes a distribution of values in a bounded number of centroids and
+/// estimates its percentiles, with a higher accuracy near the extremes.
+///
+/// Two digests are merged by merging their centroids, which allows the shards to compute the
+/// digests of their rows and the master to combine them.
+#[derive(Debug, Clone, Default)]
+pub struct TDigest {
+    /// The centroids, as their mean and weight, sorted by mean once compressed.
+    centroids: Vec<(f64, f64)>,
+    /// The values added since the last compression, which are centroids of weight one.
+    buffer: Vec<f64>,
+}
+
+impl TDigest {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Builds the digest from its components, which are the means and weights of its centroids.
+    pub fn from_components(components: impl IntoIterator<Item = f64>) -> Self {
+        let mut components = components.into_iter();
+        let mut centroids = vec![];
+        while let (Some(mean), Some(weight)) = (components.next(), components.next()) {
+            centroids.push((mean, weight));
+        }
+
+        let mut digest = Self {
+            centroids,
+            buffer: vec![],
+        };
+        digest.compress();
+        digest
+    }
+
+    /// Returns the components of the digest, which are the means and weights of its centroids.
+    pub fn into_components(mut self) -> Vec<f64> {
+        self.compress();
+        self.centroids
+            .into_iter()
+            .flat_map(|(mean, weight)| [mean, weight])
+            .collect()
+    }
+
+    pub fn add(&mut self, value: f64) {
+        if value.is_nan() {
+            return;
+        }
+
+        self.buffer.push(value);
+        if self.buffer.len() as f64 >= COMPRESSION * 10.0 {
+            self.compress();
+        }
+    }
+
+    pub fn merge(&mut self, other: TDigest) {
+        self.centroids.extend(other.centroids);
+        self.buffer.extend(other.buffer);
+        self.compress();
+    }
+
+    /// Estimates the value below which the `fraction` of the values fall, returning `None` if the
+    /// digest has no values.
+    pub fn quantile(&mut self, fraction: f64) -> Option<f64> {
+        self.compress();
+
+        let total_weight: f64 = self.centroids.iter().map(|(_, w)| w).sum();
+        let target = fraction.clamp(0.0, 1.0) * total_weight;
+
+        // Each centroid is assumed to be centered on its mean, so the values between the centers of
+        // two centroids are interpolated.
+        let mut previous: Option<(f64, f64)> = None;
+        let mut cumulative_weight = 0.0;
+        for &(mean, weight) in self.centroids.iter() {
+            let center = cumulative_weight + weight / 2.0;
+            if target <= center {
+                return Some(match previous {
+                    Some((previous_mean, previous_center)) => {
+                        let ratio = (target - previous_center) / (center - previous_center);
+                        previous_mean + ratio * (mean - previous_mean)
+                    }
+                    None => mean,
+                });
+            }
+
+            previous = Some((mean, center));
+            cumulative_weight += weight;
+        }
+
+        previous.map(|(mean, _)| mean)
+    }
+
+    /// Merges the buffered values and the centroids into the fewest centroids whose weight stays
+    /// within the bound of their position, which is smaller near the extremes of the distribution.
+    fn compress(&mut self) {
+        let mut centroids = std::mem::take(&mut self.centroids);
+        centroids.extend(self.buffer.drain(..).map(|value| (value, 1.0)));
+        centroids.sort_by(|left, right| left.0.total_cmp(&right.0));
+
+        let total_weight: f64 = centroids.iter().map(|(_, w)| w).sum();
+        let mut centroids = centroids.into_iter();
+        let Some(mut current) = centroids.next() else {
+            return;
+        };
+
+        let mut weight_before = 0.0;
+        for (mean, weight) in centroids {
+            let merged_weight = current.1 + weight;
+            let q = (weight_before + merged_weight / 2.0) / total_weight;
+            let max_weight = (4.0 * total_weight * q * (1.0 - q) / COMPRESSION).max(1.0);
+
+            if merged_weight <= max_weight {
+                current.0 += (mean - current.0) * weight / merged_weight;
+                current.1 = merged_weight;
+            } else {
+                weight_before += current.1;
+                self.centroids.push(current);
+                current = (mean, weight);
+            }
+        }
+        self.centroids.push(current);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn digest(values: impl IntoIterator<Item = f64>) -> TDigest {
+        let mut digest = TDigest::new();
+        for value in values {
+            digest.add(value);
+        }
+
+        digest
+    }
+
+    #[test]
+    fn test_quantiles_of_small_inputs() {
+        assert_eq!(TDigest::new().quantile(0.5), None);
+
+        let mut single = digest([4.0]);
+        assert_eq!(single.quantile(0.0), Some(4.0));
+        assert_eq!(single.quantile(0.5), Some(4.0));
+        assert_eq!(single.quantile(1.0), Some(4.0));
+
+        let mut odd = digest([5.0, 1.0, 4.0, 2.0, 3.0]);
+        assert_eq!(odd.quantile(0.0), Some(1.0));
+        assert_eq!(odd.quantile(0.5), Some(3.0));
+        assert_eq!(odd.quantile(1.0), Some(5.0));
+
+        // The median of an even number of values is between the two middle ones.
+        let mut even = digest([1.0, 2.0, 3.0, 4.0]);
+        assert_eq!(even.quantile(0.0), Some(1.0));
+        assert_eq!(even.quantile(0.5), Some(2.5));
+        assert_eq!(even.quantile(1.0), Some(4.0));
+    }
+
+    #[test]
+    fn test_quantiles_of_large_inputs() {
+        let mut digest = digest((0..100_000).map(|v| ((v * 7919) % 100_000) as f64));
+        assert_eq!(digest.quantile(0.0), Some(0.0));
+        assert_eq!(digest.quantile(1.0), Some(99_999.0));
+        for fraction in [0.01, 0.25, 0.5, 0.75, 0.99] {
+            let expected = fraction * 100_000.0;
+            let estimate = digest.quantile(fraction).unwrap();
+            assert!(
+                (estimate - expected).abs() <= 1_000.0,
+                "estimated {} for the quantile {}",
+                estimate,
+                fraction
+            );
+        }
+    }
+
+    #[test]
+    fn test_merge_equals_union() {
+        // The small digests keep all their values, so the merge is exact.
+        let mut left = digest([1.0, 3.0, 5.0]);
+        left.merge(digest([2.0, 4.0]));
+        let mut union = digest([1.0, 2.0, 3.0, 4.0, 5.0]);
+        for fraction in [0.0, 0.25, 0.5, 0.75, 1.0] {
+            assert_eq!(left.quantile(fraction), union.quantile(fraction));
+        }
+
+        // The large ones are compressed differently, so they're only close.
+        let mut left = digest((0..50_000).map(|v| (v * 2) as f64));
+        left.merge(digest((0..50_000).map(|v| (v * 2 + 1) as f64)));
+        let mut union = digest((0..100_000).map(|v| v as f64));
+        for fraction in [0.0, 0.01, 0.5, 0.99, 1.0] {
+            let (merged, expected) = (left.quantile(fraction), union.quantile(fraction));
+            assert!(
+                (merged.unwrap() - expected.unwrap()).abs() <= 1_000.0,
+                "merged {:?} instead of {:?} for the quantile {}",
+                merged,
+                expected,
+                fraction
+            );
+        }
+    }
+
+    #[test]
+    fn test_components_round_trip() {
+        let mut digest = digest((0..10_000).map(|v| v as f64));
+        let mut restored = TDigest::from_components(digest.clone().into_components());
+        for fraction in [0.0, 0.5, 1.0] {
+            assert_eq!(restored.quantile(fraction), digest.quantile(fraction));
+        }
+    }
+}
@@ -0,0 +1,28 @@
+use serde::{Deserialize, Serialize};
+
+/// How a query would be run on a table of this instance, without running it.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct TableExplanation {
+    pub table: String,
+    /// The columns read for each scanned row, including the ones read only by the filter.
+    pub scanned_columns: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub filter: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub aggregates: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub group_by: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub order_by: Vec<String>,
+    pub distinct: bool,
+    /// Whether the query is answered from the row count of the table stats, without a scan.
+    pub counted_from_stats: bool,
+    pub partitions: usize,
+    /// The partitions overlapping the time range of the query, which are the only ones scanned.
+    pub scanned_partitions: usize,
+    /// The rows of the table, according to its stats.
+    pub table_rows: u64,
+    /// The rows scanned by the query, estimated from the rows of the table and the share of its
+    /// partitions which are scanned.
+    pub estimated_rows: u64,
+}
@@ -0,0 +1,1775 @@
+use std::cmp::Ordering;
+use std::fmt::{Display, Formatter};
+use std::io::{Error, ErrorKind};
+use std::iter::Peekable;
+use std::ops::Bound;
+use std::str::CharIndices;
+
+use chrono::{DateTime, Datelike, Days, NaiveDate, Timelike, Utc};
+use regex::Regex;
+use tokio::io;
+
+use crate::io::clock::clock;
+use crate::table::aggregate::Aggregate;
+use crate::table::column::{Column, ColumnType, ColumnValue};
+use crate::table::cursor::Row;
+use crate::table::secondary_index::ValueRange;
+
+/// The pseudo-column with the timestamp of the rows, in seconds since the epoch.
+pub const TIMESTAMP_COLUMN: &str = "__timestamp";
+
+/// The pseudo-column with the ids of the rows, which are stored with their timestamps in the index
+/// of the rows.
+pub const ROW_ID_COLUMN: &str = "__row_id";
+
+/// Returns whether the column is a pseudo-column, whose values are read from the index of the rows
+/// rather than from a column file.
+pub fn is_pseudo_column(name: &str) -> bool {
+    name == TIMESTAMP_COLUMN || name == ROW_ID_COLUMN
+}
+
+/// Returns the value of the pseudo-column for the row with the id and timestamp, if the column is a
+/// pseudo-column.
+pub fn pseudo_column_value(name: &str, index_id: u64, timestamp: u64) -> Option<ColumnValue> {
+    match name {
+        TIMESTAMP_COLUMN => Some(ColumnValue::Integer(timestamp as i64)),
+        ROW_ID_COLUMN => Some(ColumnValue::Integer(index_id as i64)),
+        _ => None,
+    }
+}
+
+/// An expression of the queried columns, e.g. `price`, `orders.price` or `sum(price)`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expression {
+    Column {
+        /// The table of the column, if the name is qualified.
+        table: Option<String>,
+        name: String,
+    },
+    /// A string or number literal, e.g. `'1h'` or `10`.
+    Literal(ColumnValue),
+    Function {
+        /// The name of the function, always lowercase.
+        name: String,
+        args: Vec<Expression>,
+    },
+    /// The conversion of the values of an expression to another type, e.g. `cast(price as float)`.
+    Cast {
+        expression: Box<Expression>,
+        ty: ColumnType,
+    },
+    Comparison {
+        op: ComparisonOp,
+        left: Box<Expression>,
+        right: Box<Expression>,
+    },
+    /// The match of a string against a pattern, in which `%` matches any sequence of characters
+    /// and `_` any single character, e.g. `name like 'a%'`.
+    Like {
+        expression: Box<Expression>,
+        pattern: Box<Expression>,
+    },
+    /// The match of a string against a regular expression, which is compiled once when the
+    /// condition is parsed, e.g. `name matches '^a.*z$'`.
+    Matches {
+        expression: Box<Expression>,
+        regex: RegexPattern,
+    },
+    /// The check that a number is within the inclusive range of two other numbers, e.g.
+    /// `__timestamp between 1700000000 and 1700003600`.
+    Between {
+        expression: Box<Expression>,
+        low: Box<Expression>,
+        high: Box<Expression>,
+    },
+    And(Box<Expression>, Box<Expression>),
+    Or(Box<Expression>, Box<Expression>),
+    Not(Box<Expression>),
+    /// An aggregate computed only on the rows matching a condition, e.g.
+    /// `count(id) filter (status = 'error')`.
+    Filter {
+        aggregate: Box<Expression>,
+        condition: Box<Expression>,
+    },
+    /// The placeholder of a prepared query, which is bound to its parameter when executed, e.g.
+    /// `$1` for the first.
+    Parameter(usize),
+}
+
+/// A compiled regular expression, compared by its pattern.
+#[derive(Debug, Clone)]
+pub struct RegexPattern(Regex);
+
+impl PartialEq for RegexPattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.as_str() == other.0.as_str()
+    }
+}
+
+impl Eq for RegexPattern {}
+
+/// The operator comparing two values in a condition.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ComparisonOp {
+    Eq,
+    NotEq,
+    Lt,
+    Gt,
+    LtEq,
+    GtEq,
+}
+
+impl ComparisonOp {
+    fn test(&self, ordering: Ordering) -> bool {
+        match self {
+            ComparisonOp::Eq => ordering.is_eq(),
+            ComparisonOp::NotEq => ordering.is_ne(),
+            ComparisonOp::Lt => ordering.is_lt(),
+            ComparisonOp::Gt => ordering.is_gt(),
+            ComparisonOp::LtEq => ordering.is_le(),
+            ComparisonOp::GtEq => ordering.is_ge(),
+        }
+    }
+
+    /// Returns the operator with its operands swapped, e.g. `>` for `<`.
+    fn flip(&self) -> Self {
+        match self {
+            ComparisonOp::Lt => ComparisonOp::Gt,
+            ComparisonOp::Gt => ComparisonOp::Lt,
+            ComparisonOp::LtEq => ComparisonOp::GtEq,
+            ComparisonOp::GtEq => ComparisonOp::LtEq,
+            op => *op,
+        }
+    }
+}
+
+impl<'a> From<&'a ComparisonOp> for &'a str {
+    fn from(value: &'a ComparisonOp) -> Self {
+        match value {
+            ComparisonOp::Eq => "=",
+            ComparisonOp::NotEq => "!=",
+            ComparisonOp::Lt => "<",
+            ComparisonOp::Gt => ">",
+            ComparisonOp::LtEq => "<=",
+            ComparisonOp::GtEq => ">=",
+        }
+    }
+}
+
+impl Expression {
+    /// Returns the aggregate of the expression, if any, and the expression whose values it reads,
+    /// which is either a column or a scalar expression like `cast(price as float)`.
+    pub fn to_aggregate_column(&self) -> io::Result<(Option<Aggregate>, &Expression)> {
+        match self {
+            Expression::Column { .. } => Ok((None, self)),
+            Expression::Filter { aggregate, .. } => match aggregate.to_aggregate_column()? {
+                (Some(aggregate), expression) => Ok((Some(aggregate), expression)),
+                (None, _) => Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("Only aggregates can be filtered, unlike {}", aggregate),
+                )),
+            },
+            Expression::Function { name, args } => {
+                let (aggregate, args) = match (name.as_str(), args.as_slice()) {
+                    ("percentile", [arg, Expression::Literal(ColumnValue::Float(fraction))]) => {
+                        (Aggregate::percentile(*fraction)?, std::slice::from_ref(arg))
+                    }
+                    ("percentile", _) => {
+                        return Err(Error::new(
+                            ErrorKind::InvalidInput,
+                            format!(
+                                "Invalid arguments in {}, e.g. percentile(price, 0.95)",
+                                self
+                            ),
+                        ))
+                    }
+                    ("top", [Expression::Literal(ColumnValue::Integer(k)), arg]) => {
+                        (Aggregate::top(*k)?, std::slice::from_ref(arg))
+                    }
+                    ("wavg", [arg, Expression::Column { name: weight, .. }]) => (
+                        Aggregate::WeightedAvg(weight.clone()),
+                        std::slice::from_ref(arg),
+                    ),
+                    ("wavg", _) => {
+                        return Err(Error::new(
+                            ErrorKind::InvalidInput,
+                            format!("Invalid arguments in {}, e.g. wavg(price, quantity)", self),
+                        ))
+                    }
+                    ("top", _) => {
+                        return Err(Error::new(
+                            ErrorKind::InvalidInput,
+                            format!("Invalid arguments in {}, e.g. top(3, revenue)", self),
+                        ))
+                    }
+                    _ => {
+                        let aggregate = Aggregate::from_name(name).ok_or_else(|| {
+                            Error::new(
+                                ErrorKind::InvalidInput,
+                                format!("Unknown function {}", name),
+                            )
+                        })?;
+                        (aggregate, args.as_slice())
+                    }
+                };
+
+                match args {
+                    [Expression::Function { name, .. }] if Aggregate::is_aggregate(name) => {
+                        Err(Error::new(
+                            ErrorKind::InvalidInput,
+                            format!("Nested aggregates are not supported in {}", self),
+                        ))
+                    }
+                    [arg] => Ok((Some(aggregate), arg)),
+                    _ => Err(Error::new(
+                        ErrorKind::InvalidInput,
+                        format!("The function {} takes exactly one argument", name),
+                    )),
+                }
+            }
+            _ => Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("The expression {} can't be queried", self),
+            )),
+        }
+    }
+
+    /// Returns the condition of the rows aggregated by the expression, if it's a filtered aggregate.
+    pub fn aggregate_filter(&self) -> Option<&Expression> {
+        match self {
+            Expression::Filter { condition, .. } => Some(condition),
+            _ => None,
+        }
+    }
+
+    /// Removes the table from the qualified column names, checking that it's one of `tables`.
+    pub fn unqualify(self, tables: &[String]) -> io::Result<Self> {
+        match self {
+            Expression::Column {
+                table: Some(column_table),
+                name,
+            } if !tables.contains(&column_table) => Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "The column {}.{} doesn't belong to the table {}",
+                    column_table,
+                    name,
+                    tables.join(" or ")
+                ),
+            )),
+            Expression::Column { name, .. } => Ok(Expression::Column { table: None, name }),
+            Expression::Literal(value) => Ok(Expression::Literal(value)),
+            Expression::Parameter(index) => Ok(Expression::Parameter(index)),
+            Expression::Function { name, args } => Ok(Expression::Function {
+                name,
+                args: args
+                    .into_iter()
+                    .map(|a| a.unqualify(tables))
+                    .collect::<io::Result<_>>()?,
+            }),
+            Expression::Cast { expression, ty } => Ok(Expression::Cast {
+                expression: Box::new(expression.unqualify(tables)?),
+                ty,
+            }),
+            Expression::Comparison { op, left, right } => Ok(Expression::Comparison {
+                op,
+                left: Box::new(left.unqualify(tables)?),
+                right: Box::new(right.unqualify(tables)?),
+            }),
+            Expression::Like {
+                expression,
+                pattern,
+            } => Ok(Expression::Like {
+                expression: Box::new(expression.unqualify(tables)?),
+                pattern: Box::new(pattern.unqualify(tables)?),
+            }),
+            Expression::Matches { expression, regex } => Ok(Expression::Matches {
+                expression: Box::new(expression.unqualify(tables)?),
+                regex,
+            }),
+            Expression::Between {
+                expression,
+                low,
+                high,
+            } => Ok(Expression::Between {
+                expression: Box::new(expression.unqualify(tables)?),
+                low: Box::new(low.unqualify(tables)?),
+                high: Box::new(high.unqualify(tables)?),
+            }),
+            Expression::And(left, right) => Ok(Expression::And(
+                Box::new(left.unqualify(tables)?),
+                Box::new(right.unqualify(tables)?),
+            )),
+            Expression::Or(left, right) => Ok(Expression::Or(
+                Box::new(left.unqualify(tables)?),
+                Box::new(right.unqualify(tables)?),
+            )),
+            Expression::Not(inner) => Ok(Expression::Not(Box::new(inner.unqualify(tables)?))),
+            Expression::Filter {
+                aggregate,
+                condition,
+            } => Ok(Expression::Filter {
+                aggregate: Box::new(aggregate.unqualify(tables)?),
+                condition: Box::new(condition.unqualify(tables)?),
+            }),
+        }
+    }
+
+    /// Returns the names of the columns read by the expression, excluding the pseudo-columns.
+    pub fn columns(&self) -> Vec<&str> {
+        match self {
+            Expression::Column { name, .. } if is_pseudo_column(name) => vec![],
+            Expression::Column { name, .. } => vec![name],
+            Expression::Literal(_) | Expression::Parameter(_) => vec![],
+            Expression::Function { args, .. } => args.iter().flat_map(|a| a.columns()).collect(),
+            Expression::Comparison { left, right, .. }
+            | Expression::Like {
+                expression: left,
+                pattern: right,
+            }
+            | Expression::And(left, right)
+            | Expression::Or(left, right)
+            | Expression::Filter {
+                aggregate: left,
+                condition: right,
+            } => {
+                let mut columns = left.columns();
+                columns.extend(right.columns());
+                columns
+            }
+            Expression::Between {
+                expression,
+                low,
+                high,
+            } => {
+                let mut columns = expression.columns();
+                columns.extend(low.columns());
+                columns.extend(high.columns());
+                columns
+            }
+            Expression::Cast { expression, .. }
+            | Expression::Matches { expression, .. }
+            | Expression::Not(expression) => expression.columns(),
+        }
+    }
+
+    /// Returns the number of parameters of the expression, which is its highest placeholder.
+    pub fn parameters(&self) -> usize {
+        match self {
+            Expression::Parameter(index) => *index,
+            expression => expression
+                .children()
+                .into_iter()
+                .map(|e| e.parameters())
+                .max()
+                .unwrap_or(0),
+        }
+    }
+
+    /// Replaces the placeholders of the expression with the literals of `params`, in which `$1` is
+    /// the first.
+    pub fn bind_parameters(self, params: &[ColumnValue]) -> io::Result<Self> {
+        match self {
+            Expression::Parameter(index) => match params.get(index - 1) {
+                Some(value) => Ok(Expression::Literal(value.clone())),
+                None => Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("Missing value for parameter ${}", index),
+                )),
+            },
+            expression => expression.try_map_children(|e| e.bind_parameters(params)),
+        }
+    }
+
+    /// Returns the expressions of which the expression is made.
+    fn children(&self) -> Vec<&Expression> {
+        match self {
+            Expression::Column { .. } | Expression::Literal(_) | Expression::Parameter(_) => {
+                vec![]
+            }
+            Expression::Function { args, .. } => args.iter().collect(),
+            Expression::Comparison { left, right, .. }
+            | Expression::Like {
+                expression: left,
+                pattern: right,
+            }
+            | Expression::And(left, right)
+            | Expression::Or(left, right)
+            | Expression::Filter {
+                aggregate: left,
+                condition: right,
+            } => vec![left, right],
+            Expression::Between {
+                expression,
+                low,
+                high,
+            } => vec![expression, low, high],
+            Expression::Cast { expression, .. }
+            | Expression::Matches { expression, .. }
+            | Expression::Not(expression) => vec![expression],
+        }
+    }
+
+    /// Rebuilds the expression with `f` applied to the expressions of which it's made.
+    fn try_map_children(
+        self,
+        mut f: impl FnMut(Expression) -> io::Result<Expression>,
+    ) -> io::Result<Self> {
+        let mut map = |e: Box<Expression>| f(*e).map(Box::new);
+        Ok(match self {
+            Expression::Column { .. } | Expression::Literal(_) | Expression::Parameter(_) => self,
+            Expression::Function { name, args } => Expression::Function {
+                name,
+                args: args.into_iter().map(&mut f).collect::<io::Result<_>>()?,
+            },
+            Expression::Cast { expression, ty } => Expression::Cast {
+                expression: map(expression)?,
+                ty,
+            },
+            Expression::Comparison { op, left, right } => Expression::Comparison {
+                op,
+                left: map(left)?,
+                right: map(right)?,
+            },
+            Expression::Like {
+                expression,
+                pattern,
+            } => Expression::Like {
+                expression: map(expression)?,
+                pattern: map(pattern)?,
+            },
+            Expression::Matches { expression, regex } => Expression::Matches {
+                expression: map(expression)?,
+                regex,
+            },
+            Expression::Between {
+                expression,
+                low,
+                high,
+            } => Expression::Between {
+                expression: map(expression)?,
+                low: map(low)?,
+                high: map(high)?,
+            },
+            Expression::And(left, right) => Expression::And(map(left)?, map(right)?),
+            Expression::Or(left, right) => Expression::Or(map(left)?, map(right)?),
+            Expression::Not(inner) => Expression::Not(map(inner)?),
+            Expression::Filter {
+                aggregate,
+                condition,
+            } => Expression::Filter {
+                aggregate: map(aggregate)?,
+                condition: map(condition)?,
+            },
+        })
+    }
+
+    /// Returns the type of the values of the scalar expression, checking that its functions exist
+    /// and are applied to arguments of the right type.
+    pub fn scalar_type(&self, available_columns: &[Column]) -> io::Result<ColumnType> {
+        match self {
+            Expression::Column { name, .. } if is_pseudo_column(name) => Ok(ColumnType::Integer),
+            Expression::Column { name, .. } => available_columns
+                .iter()
+                .find(|c| c.name == *name)
+                .map(|c| c.ty)
+                .ok_or_else(|| {
+                    Error::new(
+                        ErrorKind::Unsupported,
+                        format!("The column {} doesn't exist on table", name),
+                    )
+                }),
+            Expression::Literal(ColumnValue::Integer(_)) => Ok(ColumnType::Integer),
+            Expression::Literal(ColumnValue::Float(_)) => Ok(ColumnType::Float),
+            Expression::Literal(ColumnValue::String(_)) => Ok(ColumnType::String),
+            Expression::Literal(ColumnValue::Null) => Ok(ColumnType::Null),
+            Expression::Parameter(index) => Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("The parameter ${} is only bound in prepared queries", index),
+            )),
+            Expression::Function { name, args } => {
+                let arg_types = args
+                    .iter()
+                    .map(|a| a.scalar_type(available_columns))
+                    .collect::<io::Result<Vec<_>>>()?;
+
+                match (name.as_str(), args.as_slice(), arg_types.as_slice()) {
+                    ("lower" | "upper", [_], [ColumnType::String]) => Ok(ColumnType::String),
+                    (
+                        "time_bucket",
+                        [Expression::Literal(ColumnValue::String(interval)), _],
+                        [_, ColumnType::Integer],
+                    ) => {
+                        parse_interval(interval)?;
+                        Ok(ColumnType::Integer)
+                    }
+                    ("now", [], []) => Ok(ColumnType::Integer),
+                    (
+                        "date_trunc",
+                        [Expression::Literal(ColumnValue::String(unit)), _],
+                        [_, ColumnType::Integer],
+                    ) if truncate_timestamp(unit, 0).is_some() => Ok(ColumnType::Integer),
+                    (
+                        "extract",
+                        [Expression::Literal(ColumnValue::String(field)), _],
+                        [_, ColumnType::Integer],
+                    ) if extract_field(field, 0).is_some() => Ok(ColumnType::Integer),
+                    (
+                        "lower" | "upper" | "time_bucket" | "now" | "date_trunc" | "extract",
+                        _,
+                        _,
+                    ) => Err(Error::new(
+                        ErrorKind::InvalidInput,
+                        format!("Invalid arguments in {}", self),
+                    )),
+                    _ => Err(Error::new(
+                        ErrorKind::InvalidInput,
+                        format!("Unknown function {}", name),
+                    )),
+                }
+            }
+            Expression::Cast { expression, ty } => {
+                match expression.scalar_type(available_columns)? {
+                    ColumnType::Null => Err(Error::new(
+                        ErrorKind::InvalidInput,
+                        format!("The null value can't be cast in {}", self),
+                    )),
+                    _ => Ok(*ty),
+                }
+            }
+            _ => Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("The condition {} can't be used as a value", self),
+            )),
+        }
+    }
+
+    /// Checks that the expression is a condition whose comparisons are between values of
+    /// compatible types.
+    pub fn check_condition(&self, available_columns: &[Column]) -> io::Result<()> {
+        match self {
+            Expression::Comparison { left, right, .. } => {
+                let left_type = left.scalar_type(available_columns)?;
+                let right_type = right.scalar_type(available_columns)?;
+                let is_number =
+                    |ty: ColumnType| matches!(ty, ColumnType::Integer | ColumnType::Float);
+                let comparable =
+                    (is_number(left_type) && is_number(right_type)) || left_type == right_type;
+                if !comparable {
+                    return Err(Error::new(
+                        ErrorKind::InvalidInput,
+                        format!("The values of {} can't be compared", self),
+                    ));
+                }
+
+                Ok(())
+            }
+            Expression::Like {
+                expression,
+                pattern,
+            } => {
+                let is_string = |e: &Expression| {
+                    e.scalar_type(available_columns)
+                        .map(|ty| ty == ColumnType::String)
+                };
+                if !is_string(expression)? || !is_string(pattern)? {
+                    return Err(Error::new(
+                        ErrorKind::InvalidInput,
+                        format!("Only strings can be matched in {}", self),
+                    ));
+                }
+
+                Ok(())
+            }
+            Expression::Matches { expression, .. } => {
+                if expression.scalar_type(available_columns)? != ColumnType::String {
+                    return Err(Error::new(
+                        ErrorKind::InvalidInput,
+                        format!("Only strings can be matched in {}", self),
+                    ));
+                }
+
+                Ok(())
+            }
+            Expression::Between {
+                expression,
+                low,
+                high,
+            } => {
+                let is_number = |e: &Expression| {
+                    e.scalar_type(available_columns)
+                        .map(|ty| matches!(ty, ColumnType::Integer | ColumnType::Float))
+                };
+                if !is_number(expression)? || !is_number(low)? || !is_number(high)? {
+                    return Err(Error::new(
+                        ErrorKind::InvalidInput,
+                        format!("Only numbers can be checked in {}", self),
+                    ));
+                }
+
+                Ok(())
+            }
+            Expression::And(left, right) | Expression::Or(left, right) => {
+                left.check_condition(available_columns)?;
+                right.check_condition(available_columns)
+            }
+            Expression::Not(inner) => inner.check_condition(available_columns),
+            _ => Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("The expression {} is not a condition", self),
+            )),
+        }
+    }
+
+    /// Tests the condition on a row, whose values must include the columns read by the condition.
+    ///
+    /// Comparisons with null values are neither true nor false, like in SQL, so a row whose value
+    /// is null doesn't match `a = 1` nor `not a = 1`.
+    pub fn test(&self, row: &Row<ColumnValue>) -> Option<bool> {
+        match self {
+            Expression::Comparison { op, left, right } => {
+                let ordering = compare_values(&left.evaluate(row), &right.evaluate(row))?;
+                Some(op.test(ordering))
+            }
+            Expression::Like {
+                expression,
+                pattern,
+            } => match (expression.evaluate(row), pattern.evaluate(row)) {
+                (ColumnValue::String(value), ColumnValue::String(pattern)) => {
+                    Some(like_matches(&value, &pattern))
+                }
+                _ => None,
+            },
+            Expression::Matches { expression, regex } => match expression.evaluate(row) {
+                ColumnValue::String(value) => Some(regex.0.is_match(&value)),
+                _ => None,
+            },
+            Expression::Between {
+                expression,
+                low,
+                high,
+            } => {
+                let value = expression.evaluate(row);
+                let above_low = compare_values(&value, &low.evaluate(row))?.is_ge();
+                let below_high = compare_values(&value, &high.evaluate(row))?.is_le();
+                Some(above_low && below_high)
+            }
+            Expression::And(left, right) => match (left.test(row), right.test(row)) {
+                (Some(false), _) | (_, Some(false)) => Some(false),
+                (Some(true), Some(true)) => Some(true),
+                _ => None,
+            },
+            Expression::Or(left, right) => match (left.test(row), right.test(row)) {
+                (Some(true), _) | (_, Some(true)) => Some(true),
+                (Some(false), Some(false)) => Some(false),
+                _ => None,
+            },
+            Expression::Not(inner) => inner.test(row).map(|value| !value),
+            _ => None,
+        }
+    }
+
+    /// Returns whether the condition may match a row whose value of the column is between `min` and
+    /// `max`, or null, which is false only if none of these values can match.
+    ///
+    /// Only the comparisons of the column with literals are checked, and anything else may match.
+    pub fn may_match(&self, column: &str, min: &ColumnValue, max: &ColumnValue) -> bool {
+        let is_column = |expression: &Expression| matches!(expression, Expression::Column { name, .. } if name == column);
+        match self {
+            Expression::Comparison { op, left, right } => {
+                let (op, value) = match (left.as_ref(), right.as_ref()) {
+                    (left, Expression::Literal(value)) if is_column(left) => (*op, value),
+                    (Expression::Literal(value), right) if is_column(right) => (op.flip(), value),
+                    _ => return true,
+                };
+                let (Some(min), Some(max)) =
+                    (compare_values(min, value), compare_values(max, value))
+                else {
+                    return true;
+                };
+                match op {
+                    ComparisonOp::Eq => min.is_le() && max.is_ge(),
+                    ComparisonOp::NotEq => !(min.is_eq() && max.is_eq()),
+                    ComparisonOp::Lt => min.is_lt(),
+                    ComparisonOp::LtEq => min.is_le(),
+                    ComparisonOp::Gt => max.is_gt(),
+                    ComparisonOp::GtEq => max.is_ge(),
+                }
+            }
+            Expression::Between {
+                expression,
+                low,
+                high,
+            } if is_column(expression) => match (low.as_ref(), high.as_ref()) {
+                (Expression::Literal(low), Expression::Literal(high)) => {
+                    compare_values(max, low).is_none_or(|o| o.is_ge())
+                        && compare_values(min, high).is_none_or(|o| o.is_le())
+                }
+                _ => true,
+            },
+            Expression::And(left, right) => {
+                left.may_match(column, min, max) && right.may_match(column, min, max)
+            }
+            Expression::Or(left, right) => {
+                left.may_match(column, min, max) || right.may_match(column, min, max)
+            }
+            _ => true,
+        }
+    }
+
+    /// Returns whether the condition may match a row whose value of the column is one of the values
+    /// for which `contains` is true, or null, which is false only if the condition requires the
+    /// column to equal a value which isn't contained.
+    pub fn may_contain(&self, column: &str, contains: &dyn Fn(&ColumnValue) -> bool) -> bool {
+        let is_column = |expression: &Expression| matches!(expression, Expression::Column { name, .. } if name == column);
+        match self {
+            Expression::Comparison {
+                op: ComparisonOp::Eq,
+                left,
+                right,
+            } => match (left.as_ref(), right.as_ref()) {
+                (left, Expression::Literal(value)) if is_column(left) => contains(value),
+                (Expression::Literal(value), right) if is_column(right) => contains(value),
+                _ => true,
+            },
+            Expression::And(left, right) => {
+                left.may_contain(column, contains) && right.may_contain(column, contains)
+            }
+            Expression::Or(left, right) => {
+                left.may_contain(column, contains) || right.may_contain(column, contains)
+            }
+            _ => true,
+        }
+    }
+
+    /// Returns the range in which the values of the column must be for a row to match the
+    /// condition, if the condition requires one, e.g. `price >= 10 and price < 20`.
+    pub fn value_range(&self, column: &str) -> Option<ValueRange> {
+        let is_column = |expression: &Expression| matches!(expression, Expression::Column { name, .. } if name == column);
+        match self {
+            Expression::Comparison { op, left, right } => {
+                let (op, value) = match (left.as_ref(), right.as_ref()) {
+                    (left, Expression::Literal(value)) if is_column(left) => (*op, value.clone()),
+                    (Expression::Literal(value), right) if is_column(right) => {
+                        (op.flip(), value.clone())
+                    }
+                    _ => return None,
+                };
+                match op {
+                    ComparisonOp::Eq => {
+                        Some((Bound::Included(value.clone()), Bound::Included(value)))
+                    }
+                    ComparisonOp::NotEq => None,
+                    ComparisonOp::Lt => Some((Bound::Unbounded, Bound::Excluded(value))),
+                    ComparisonOp::LtEq => Some((Bound::Unbounded, Bound::Included(value))),
+                    ComparisonOp::Gt => Some((Bound::Excluded(value), Bound::Unbounded)),
+                    ComparisonOp::GtEq => Some((Bound::Included(value), Bound::Unbounded)),
+                }
+            }
+            Expression::Between {
+                expression,
+                low,
+                high,
+            } if is_column(expression) => match (low.as_ref(), high.as_ref()) {
+                (Expression::Literal(low), Expression::Literal(high)) => {
+                    Some((Bound::Included(low.clone()), Bound::Included(high.clone())))
+                }
+                _ => None,
+            },
+            Expression::And(left, right) => {
+                match (left.value_range(column), right.value_range(column)) {
+                    (Some(left), Some(right)) => Some((
+                        tighter_bound(left.0, right.0, Ordering::Greater),
+                        tighter_bound(left.1, right.1, Ordering::Less),
+                    )),
+                    (range, None) | (None, range) => range,
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// Evaluates the scalar expression on a row, whose values must include the columns read by
+    /// the expression.
+    pub fn evaluate(&self, row: &Row<ColumnValue>) -> ColumnValue {
+        match self {
+            Expression::Column { name, .. } if is_pseudo_column(name) => {
+                pseudo_column_value(name, row.index_id(), row.timestamp())
+                    .unwrap_or(ColumnValue::Null)
+            }
+            Expression::Column { name, .. } => {
+                row.named_value(name).cloned().unwrap_or(ColumnValue::Null)
+            }
+            Expression::Literal(value) => value.clone(),
+            Expression::Function { name, args } => {
+                let values: Vec<_> = args.iter().map(|a| a.evaluate(row)).collect();
+                match (name.as_str(), values.as_slice()) {
+                    ("lower", [ColumnValue::String(value)]) => {
+                        ColumnValue::String(value.to_lowercase())
+                    }
+                    ("upper", [ColumnValue::String(value)]) => {
+                        ColumnValue::String(value.to_uppercase())
+                    }
+                    (
+                        "time_bucket",
+                        [ColumnValue::String(interval), ColumnValue::Integer(value)],
+                    ) => match parse_interval(interval) {
+                        Ok(interval) => {
+                            ColumnValue::Integer(value - value.rem_euclid(interval as i64))
+                        }
+                        Err(_) => ColumnValue::Null,
+                    },
+                    ("now", []) => ColumnValue::Integer(clock().now_secs() as i64),
+                    ("date_trunc", [ColumnValue::String(unit), ColumnValue::Integer(value)]) => {
+                        truncate_timestamp(unit, *value)
+                            .map_or(ColumnValue::Null, ColumnValue::Integer)
+                    }
+                    ("extract", [ColumnValue::String(field), ColumnValue::Integer(value)]) => {
+                        extract_field(field, *value).map_or(ColumnValue::Null, ColumnValue::Integer)
+                    }
+                    _ => ColumnValue::Null,
+                }
+            }
+            Expression::Cast { expression, ty } => cast_value(expression.evaluate(row), *ty),
+            // The conditions are 1 for the rows which match them, like the conditions of the
+            // filtered aggregates, which are evaluated before aggregating the rows.
+            Expression::Comparison { .. }
+            | Expression::Like { .. }
+            | Expression::Matches { .. }
+            | Expression::Between { .. }
+            | Expression::And(..)
+            | Expression::Or(..)
+            | Expression::Not(_) => ColumnValue::Integer(self.test(row).unwrap_or_default() as i64),
+            _ => ColumnValue::Null,
+        }
+    }
+}
+
+/// Returns the tighter of two bounds on the same side of a range, which is the one whose value is
+/// further in the direction of `tighter`, or the excluded one if their values are equal.
+fn tighter_bound(
+    left: Bound<ColumnValue>,
+    right: Bound<ColumnValue>,
+    tighter: Ordering,
+) -> Bound<ColumnValue> {
+    let (left_value, right_value) = match (&left, &right) {
+        (Bound::Unbounded, _) => return right,
+        (_, Bound::Unbounded) => return left,
+        (
+            Bound::Included(left_value) | Bound::Excluded(left_value),
+            Bound::Included(right_value) | Bound::Excluded(right_value),
+        ) => (left_value, right_value),
+    };
+    match compare_values(right_value, left_value) {
+        Some(ordering) if ordering == tighter => right,
+        Some(Ordering::Equal) if matches!(right, Bound::Excluded(_)) => right,
+        _ => left,
+    }
+}
+
+/// Converts a value to the type, returning null if it can't be converted, like a string which
+/// doesn't look like a number when casting to a number.
+fn cast_value(value: ColumnValue, ty: ColumnType) -> ColumnValue {
+    match (value, ty) {
+        (ColumnValue::String(value), ColumnType::Integer) => value
+            .trim()
+            .parse()
+            .map_or(ColumnValue::Null, ColumnValue::Integer),
+        (ColumnValue::String(value), ColumnType::Float) => value
+            .trim()
+            .parse::<f64>()
+            .ok()
+            .filter(|value| value.is_finite())
+            .map_or(ColumnValue::Null, ColumnValue::Float),
+        (ColumnValue::Integer(value), ColumnType::Float) => ColumnValue::Float(value as f64),
+        (ColumnValue::Float(value), ColumnType::Integer) => ColumnValue::Integer(value as i64),
+        (ColumnValue::Integer(value), ColumnType::String) => ColumnValue::String(value.to_string()),
+        (ColumnValue::Float(value), ColumnType::String) => ColumnValue::String(value.to_string()),
+        (value @ ColumnValue::Integer(_), ColumnType::Integer)
+        | (value @ ColumnValue::Float(_), ColumnType::Float)
+        | (value @ ColumnValue::String(_), ColumnType::String) => value,
+        _ => ColumnValue::Null,
+    }
+}
+
+/// Matches the value against a like pattern, in which `%` matches any sequence of characters and
+/// `_` any single character.
+fn like_matches(value: &str, pattern: &str) -> bool {
+    let value: Vec<char> = value.chars().collect();
+    let pattern: Vec<char> = pattern.chars().collect();
+    let (mut value_index, mut pattern_index) = (0, 0);
+    // The position after the last `%` in the pattern and the position in the value from which it
+    // matches, which is moved forward when the rest of the pattern doesn't match.
+    let mut last_wildcard = None;
+    while value_index < value.len() {
+        match pattern.get(pattern_index) {
+            Some('%') => {
+                pattern_index += 1;
+                last_wildcard = Some((pattern_index, value_index));
+            }
+            Some(c) if *c == '_' || *c == value[value_index] => {
+                pattern_index += 1;
+                value_index += 1;
+            }
+            _ => match last_wildcard {
+                Some((wildcard_pattern_index, wildcard_value_index)) => {
+                    pattern_index = wildcard_pattern_index;
+                    value_index = wildcard_value_index + 1;
+                    last_wildcard = Some((wildcard_pattern_index, value_index));
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[pattern_index..].iter().all(|c| *c == '%')
+}
+
+/// Compares two values, with integers and floats compared as numbers, returning `None` if either
+/// is null or they have incompatible types.
+pub fn compare_values(left: &ColumnValue, right: &ColumnValue) -> Option<Ordering> {
+    match (left, right) {
+        (ColumnValue::Integer(left), ColumnValue::Integer(right)) => Some(left.cmp(right)),
+        (ColumnValue::Integer(left), ColumnValue::Float(right)) => {
+            (*left as f64).partial_cmp(right)
+        }
+        (ColumnValue::Float(left), ColumnValue::Integer(right)) => {
+            left.partial_cmp(&(*right as f64))
+        }
+        (ColumnValue::Float(left), ColumnValue::Float(right)) => left.partial_cmp(right),
+        (ColumnValue::String(left), ColumnValue::String(right)) => Some(left.cmp(right)),
+        _ => None,
+    }
+}
+
+/// Truncates a timestamp in seconds to the start of its `second`, `minute`, `hour`, `day`, `week`
+/// (starting on monday), `month` or `year` in UTC, returning `None` if the unit is unknown.
+fn truncate_timestamp(unit: &str, timestamp: i64) -> Option<i64> {
+    let date_time = DateTime::from_timestamp(timestamp, 0)?;
+    let date = date_time.date_naive();
+    let start_date = match unit {
+        "second" => return Some(timestamp),
+        "minute" => return Some(timestamp - timestamp.rem_euclid(60)),
+        "hour" => return Some(timestamp - timestamp.rem_euclid(60 * 60)),
+        "day" => date,
+        "week" => date - Days::new(date.weekday().num_days_from_monday() as u64),
+        "month" => NaiveDate::from_ymd_opt(date.year(), date.month(), 1)?,
+        "year" => NaiveDate::from_ymd_opt(date.year(), 1, 1)?,
+        _ => return None,
+    };
+
+    Some(start_date.and_hms_opt(0, 0, 0)?.and_utc().timestamp())
+}
+
+/// Extracts a field of a timestamp in seconds in UTC, returning `None` if the field is unknown.
+///
+/// The day of the week (`dow`) starts from 0 on sunday, like in SQL.
+fn extract_field(field: &str, timestamp: i64) -> Option<i64> {
+    let date_time: DateTime<Utc> = DateTime::from_timestamp(timestamp, 0)?;
+    let value = match field {
+        "second" => date_time.second(),
+        "minute" => date_time.minute(),
+        "hour" => date_time.hour(),
+        "day" => date_time.day(),
+        "dow" => date_time.weekday().num_days_from_sunday(),
+        "doy" => date_time.ordinal(),
+        "week" => date_time.iso_week().week(),
+        "month" => date_time.month(),
+        "quarter" => (date_time.month() - 1) / 3 + 1,
+        "year" => return Some(date_time.year() as i64),
+        "epoch" => return Some(timestamp),
+        _ => return None,
+    };
+
+    Some(value as i64)
+}
+
+/// Parses an interval like `30s`, `15m`, `1h` or `7d` into seconds.
+fn parse_interval(interval: &str) -> io::Result<u64> {
+    let invalid_interval = || {
+        Error::new(
+            ErrorKind::InvalidInput,
+            format!("Invalid interval '{}'", interval),
+        )
+    };
+
+    let unit_index = interval
+        .find(|c: char| !c.is_ascii_digit())
+        .ok_or_else(invalid_interval)?;
+    let (amount, unit) = interval.split_at(unit_index);
+    let amount: u64 = amount.parse().map_err(|_| invalid_interval())?;
+    let unit_secs = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 60 * 60 * 24,
+        _ => return Err(invalid_interval()),
+    };
+
+    match amount.checked_mul(unit_secs) {
+        None | Some(0) => Err(invalid_interval()),
+        Some(secs) => Ok(secs),
+    }
+}
+
+impl Display for Expression {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Expression::Column {
+                table: Some(table),
+                name,
+            } => write!(f, "{}.{}", table, name),
+            Expression::Column { table: None, name } => write!(f, "{}", name),
+            Expression::Literal(ColumnValue::String(value)) => write!(f, "{}", quote(value)),
+            // Floats are written in decimal notation with their decimal point, so that they are
+            // parsed back as floats, since the exponent notation isn't read by the parser.
+            Expression::Literal(ColumnValue::Float(value)) => match value.to_string() {
+                value if value.contains('.') => write!(f, "{}", value),
+                value => write!(f, "{}.0", value),
+            },
+            Expression::Literal(ColumnValue::Integer(value)) => write!(f, "{}", value),
+            Expression::Literal(ColumnValue::Null) => write!(f, "null"),
+            Expression::Function { name, args } => match (name.as_str(), args.as_slice()) {
+                // The field of extract is a keyword rather than a string, like in SQL.
+                ("extract", [Expression::Literal(ColumnValue::String(field)), expression]) => {
+                    write!(f, "extract({} from {})", field, expression)
+                }
+                _ => {
+                    write!(f, "{}(", name)?;
+                    for (i, arg) in args.iter().enumerate() {
+                        if i > 0 {
+                            write!(f, ", ")?;
+                        }
+                        write!(f, "{}", arg)?;
+                    }
+                    write!(f, ")")
+                }
+            },
+            Expression::Cast { expression, ty } => {
+                write!(f, "cast({} as {})", expression, <&str>::from(ty))
+            }
+            Expression::Comparison { op, left, right } => {
+                write!(f, "{} {} {}", left, <&str>::from(op), right)
+            }
+            Expression::Like {
+                expression,
+                pattern,
+            } => write!(f, "{} like {}", expression, pattern),
+            Expression::Matches { expression, regex } => {
+                write!(f, "{} matches {}", expression, quote(regex.0.as_str()))
+            }
+            Expression::Between {
+                expression,
+                low,
+                high,
+            } => write!(f, "{} between {} and {}", expression, low, high),
+            // The conditions combining other conditions are always in parentheses, so that their
+            // precedence is preserved when parsing them back.
+            Expression::And(left, right) => write!(f, "({} and {})", left, right),
+            Expression::Or(left, right) => write!(f, "({} or {})", left, right),
+            Expression::Not(inner) => write!(f, "not {}", inner),
+            Expression::Filter {
+                aggregate,
+                condition,
+            } => write!(f, "{} filter ({})", aggregate, condition),
+            Expression::Parameter(index) => write!(f, "${}", index),
+        }
+    }
+}
+
+/// Writes the string as a literal, in which the quotes are doubled, e.g. `'O''Brien'`.
+fn quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
+
+/// Reads the string of a literal, in which the quotes are doubled.
+fn unquote(literal: &str) -> String {
+    literal.replace("''", "'")
+}
+
+/// A queried expression with its optional alias, e.g. `sum(price) as total`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SelectItem {
+    pub expression: Expression,
+    pub alias: Option<String>,
+}
+
+impl Display for SelectItem {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match &self.alias {
+            Some(alias) => write!(f, "{} as {}", self.expression, alias),
+            None => write!(f, "{}", self.expression),
+        }
+    }
+}
+
+/// An expression by which the results are sorted, e.g. `sum(price) desc`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OrderItem {
+    pub expression: Expression,
+    pub descending: bool,
+}
+
+impl Display for OrderItem {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.descending {
+            true => write!(f, "{} desc", self.expression),
+            false => write!(f, "{}", self.expression),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token<'a> {
+    Identifier(&'a str),
+    Literal(&'a str),
+    Number(&'a str),
+    Comparison(ComparisonOp),
+    OpenParen,
+    CloseParen,
+    Comma,
+    Dot,
+    Parameter(usize),
+}
+
+/// A recursive descent parser of the grammar:
+///
+/// ```text
+/// select_item := expression [ "as" identifier ]
+/// order_item  := expression [ "asc" | "desc" ]
+/// expression  := and { "or" and }
+/// and         := not { "and" not }
+/// not         := "not" not | comparison
+/// comparison  := primary [ ( "=" | "!=" | "<>" | "<" | ">" | "<=" | ">=" ) primary
+///                        | [ "not" ] "like" primary
+///                        | [ "not" ] "matches" "'" regex "'"
+///                        | [ "not" ] "between" primary "and" primary ]
+/// primary     := identifier [ "." identifier ]
+///              | identifier "(" [ expression { "," expression } ] ")" [ "filter" "(" expression ")" ]
+///              | "cast" "(" expression "as" ( "integer" | "float" | "string" ) ")"
+///              | "extract" "(" identifier "from" expression ")"
+///              | "'" string "'"
+///              | "$" number
+///              | number
+///              | "(" expression ")"
+/// ```
+struct Parser<'a> {
+    input: &'a str,
+    chars: Peekable<CharIndices<'a>>,
+}
+
+impl<'a> Parser<'a> {
+    fn new(input: &'a str) -> Self {
+        Self {
+            input,
+            chars: input.char_indices().peekable(),
+        }
+    }
+
+    fn error(&self, reason: &str) -> Error {
+        Error::new(
+            ErrorKind::InvalidInput,
+            format!("Invalid expression '{}': {}", self.input, reason),
+        )
+    }
+
+    fn next_token(&mut self) -> io::Result<Option<Token<'a>>> {
+        while self.chars.next_if(|(_, c)| c.is_whitespace()).is_some() {}
+
+        let Some((start, c)) = self.chars.next() else {
+            return Ok(None);
+        };
+        let token = match c {
+            '(' => Token::OpenParen,
+            ')' => Token::CloseParen,
+            ',' => Token::Comma,
+            '.' => Token::Dot,
+            '=' => Token::Comparison(ComparisonOp::Eq),
+            '!' if self.chars.next_if(|(_, c)| *c == '=').is_some() => {
+                Token::Comparison(ComparisonOp::NotEq)
+            }
+            '<' if self.chars.next_if(|(_, c)| *c == '=').is_some() => {
+                Token::Comparison(ComparisonOp::LtEq)
+            }
+            '<' if self.chars.next_if(|(_, c)| *c == '>').is_some() => {
+                Token::Comparison(ComparisonOp::NotEq)
+            }
+            '<' => Token::Comparison(ComparisonOp::Lt),
+            '>' if self.chars.next_if(|(_, c)| *c == '=').is_some() => {
+                Token::Comparison(ComparisonOp::GtEq)
+            }
+            '>' => Token::Comparison(ComparisonOp::Gt),
+            '\'' => {
+                // A quote in the string is doubled, like in SQL.
+                let end = loop {
+                    let (end, _) = self
+                        .chars
+                        .find(|(_, c)| *c == '\'')
+                        .ok_or_else(|| self.error("unterminated string"))?;
+                    if self.chars.next_if(|(_, c)| *c == '\'').is_none() {
+                        break end;
+                    }
+                };
+                Token::Literal(&self.input[start + 1..end])
+            }
+            '$' if self.chars.peek().is_some_and(|(_, c)| c.is_ascii_digit()) => {
+                let mut end = start + 1;
+                while let Some((i, _)) = self.chars.next_if(|(_, c)| c.is_ascii_digit()) {
+                    end = i + 1;
+                }
+                match self.input[start + 1..end].parse() {
+                    Ok(index) if index > 0 => Token::Parameter(index),
+                    _ => {
+                        return Err(self.error(&format!(
+                            "invalid parameter {}, the parameters are numbered from $1",
+                            &self.input[start..end]
+                        )))
+                    }
+                }
+            }
+            c if c.is_ascii_digit()
+                || (c == '-' && self.chars.peek().is_some_and(|(_, c)| c.is_ascii_digit())) =>
+            {
+                let mut end = start + 1;
+                while let Some((i, _)) =
+                    self.chars.next_if(|(_, c)| c.is_ascii_digit() || *c == '.')
+                {
+                    end = i + 1;
+                }
+                Token::Number(&self.input[start..end])
+            }
+            c if is_identifier_char(c) => {
+                let mut end = start + c.len_utf8();
+                while let Some((i, c)) = self.chars.next_if(|(_, c)| is_identifier_char(*c)) {
+                    end = i + c.len_utf8();
+                }
+                Token::Identifier(&self.input[start..end])
+            }
+            c => return Err(self.error(&format!("unexpected character '{}'", c))),
+        };
+
+        Ok(Some(token))
+    }
+
+    fn peek_token(&mut self) -> io::Result<Option<Token<'a>>> {
+        let chars = self.chars.clone();
+        let token = self.next_token();
+        self.chars = chars;
+
+        token
+    }
+
+    /// Consumes the next token if it's the keyword, ignoring its case.
+    fn next_keyword(&mut self, keyword: &str) -> io::Result<bool> {
+        match self.peek_token()? {
+            Some(Token::Identifier(identifier)) if identifier.eq_ignore_ascii_case(keyword) => {
+                self.next_token()?;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    /// Consumes the keyword, optionally preceded by `not`, returning whether it's negated.
+    fn next_negatable_keyword(&mut self, keyword: &str) -> io::Result<Option<bool>> {
+        if self.next_keyword(keyword)? {
+            return Ok(Some(false));
+        }
+
+        let chars = self.chars.clone();
+        if self.next_keyword("not")? && self.next_keyword(keyword)? {
+            return Ok(Some(true));
+        }
+        self.chars = chars;
+
+        Ok(None)
+    }
+
+    fn expect_identifier(&mut self) -> io::Result<&'a str> {
+        match self.next_token()? {
+            Some(Token::Identifier(identifier)) => Ok(identifier),
+            _ => Err(self.error("expected a name")),
+        }
+    }
+
+    fn parse_select_item(&mut self) -> io::Result<SelectItem> {
+        let expression = self.parse_expression()?;
+
+        let alias = match self.next_token()? {
+            None => None,
+            Some(Token::Identifier(keyword)) if keyword.eq_ignore_ascii_case("as") => {
+                Some(self.expect_identifier()?.to_string())
+            }
+            Some(_) => return Err(self.error("unexpected input after the expression")),
+        };
+
+        if self.next_token()?.is_some() {
+            return Err(self.error("unexpected input after the alias"));
+        }
+
+        Ok(SelectItem { expression, alias })
+    }
+
+    fn parse_order_item(&mut self) -> io::Result<OrderItem> {
+        let expression = self.parse_expression()?;
+        let descending = if self.next_keyword("desc")? {
+            true
+        } else {
+            self.next_keyword("asc")?;
+            false
+        };
+
+        if self.next_token()?.is_some() {
+            return Err(self.error("unexpected input after the direction"));
+        }
+
+        Ok(OrderItem {
+            expression,
+            descending,
+        })
+    }
+
+    fn parse_expression(&mut self) -> io::Result<Expression> {
+        let mut expression = self.parse_and()?;
+        while self.next_keyword("or")? {
+            expression = Expression::Or(Box::new(expression), Box::new(self.parse_and()?));
+        }
+
+        Ok(expression)
+    }
+
+    fn parse_and(&mut self) -> io::Result<Expression> {
+        let mut expression = self.parse_not()?;
+        while self.next_keyword("and")? {
+            expression = Expression::And(Box::new(expression), Box::new(self.parse_not()?));
+        }
+
+        Ok(expression)
+    }
+
+    fn parse_not(&mut self) -> io::Result<Expression> {
+        if self.next_keyword("not")? {
+            return Ok(Expression::Not(Box::new(self.parse_not()?)));
+        }
+
+        self.parse_comparison()
+    }
+
+    fn parse_comparison(&mut self) -> io::Result<Expression> {
+        let left = self.parse_primary()?;
+        if let Some(negated) = self.next_negatable_keyword("like")? {
+            let like = Expression::Like {
+                expression: Box::new(left),
+                pattern: Box::new(self.parse_primary()?),
+            };
+            return Ok(match negated {
+                true => Expression::Not(Box::new(like)),
+                false => like,
+            });
+        }
+        if let Some(negated) = self.next_negatable_keyword("matches")? {
+            let Some(Token::Literal(pattern)) = self.next_token()? else {
+                return Err(self.error("expected a quoted regex after 'matches'"));
+            };
+            let regex = Regex::new(&unquote(pattern))
+                .map_err(|e| self.error(&format!("invalid regex '{}': {}", pattern, e)))?;
+            let matches = Expression::Matches {
+                expression: Box::new(left),
+                regex: RegexPattern(regex),
+            };
+            return Ok(match negated {
+                true => Expression::Not(Box::new(matches)),
+                false => matches,
+            });
+        }
+        if let Some(negated) = self.next_negatable_keyword("between")? {
+            let low = self.parse_primary()?;
+            if !self.next_keyword("and")? {
+                return Err(self.error("expected 'and' after the lower bound of 'between'"));
+            }
+            let between = Expression::Between {
+                expression: Box::new(left),
+                low: Box::new(low),
+                high: Box::new(self.parse_primary()?),
+            };
+            return Ok(match negated {
+                true => Expression::Not(Box::new(between)),
+                false => between,
+            });
+        }
+
+        let Some(Token::Comparison(op)) = self.peek_token()? else {
+            return Ok(left);
+        };
+        self.next_token()?;
+
+        Ok(Expression::Comparison {
+            op,
+            left: Box::new(left),
+            right: Box::new(self.parse_primary()?),
+        })
+    }
+
+    fn parse_primary(&mut self) -> io::Result<Expression> {
+        match self.peek_token()? {
+            Some(Token::Literal(value)) => {
+                self.next_token()?;
+                return Ok(Expression::Literal(ColumnValue::String(unquote(value))));
+            }
+            Some(Token::Parameter(index)) => {
+                self.next_token()?;
+                return Ok(Expression::Parameter(index));
+            }
+            Some(Token::Number(number)) => {
+                self.next_token()?;
+                return parse_number(number)
+                    .map(Expression::Literal)
+                    .ok_or_else(|| self.error(&format!("invalid number {}", number)));
+            }
+            Some(Token::OpenParen) => {
+                self.next_token()?;
+                let expression = self.parse_expression()?;
+                if self.next_token()? != Some(Token::CloseParen) {
+                    return Err(self.error("expected ')'"));
+                }
+                return Ok(expression);
+            }
+            _ => {}
+        }
+
+        let identifier = self.expect_identifier()?;
+
+        match self.peek_token()? {
+            Some(Token::OpenParen) if identifier.eq_ignore_ascii_case("cast") => {
+                self.next_token()?;
+                let expression = self.parse_expression()?;
+                if !self.next_keyword("as")? {
+                    return Err(self.error("expected 'as' in cast"));
+                }
+                let ty = match self.expect_identifier()?.to_lowercase().as_str() {
+                    "integer" => ColumnType::Integer,
+                    "float" => ColumnType::Float,
+                    "string" => ColumnType::String,
+                    ty => return Err(self.error(&format!("unknown type {}", ty))),
+                };
+                if self.next_token()? != Some(Token::CloseParen) {
+                    return Err(self.error("expected ')'"));
+                }
+
+                Ok(Expression::Cast {
+                    expression: Box::new(expression),
+                    ty,
+                })
+            }
+            Some(Token::OpenParen) if identifier.eq_ignore_ascii_case("extract") => {
+                self.next_token()?;
+                let field = self.expect_identifier()?.to_lowercase();
+                if !self.next_keyword("from")? {
+                    return Err(self.error("expected 'from' in extract"));
+                }
+                let expression = self.parse_expression()?;
+                if self.next_token()? != Some(Token::CloseParen) {
+                    return Err(self.error("expected ')'"));
+                }
+
+                Ok(Expression::Function {
+                    name: "extract".to_string(),
+                    args: vec![Expression::Literal(ColumnValue::String(field)), expression],
+                })
+            }
+            Some(Token::OpenParen) => {
+                self.next_token()?;
+                let mut args = vec![];
+                if self.peek_token()? == Some(Token::CloseParen) {
+                    self.next_token()?;
+                } else {
+                    loop {
+                        args.push(self.parse_expression()?);
+                        match self.next_token()? {
+                            Some(Token::Comma) => continue,
+                            Some(Token::CloseParen) => break,
+                            _ => return Err(self.error("expected ',' or ')'")),
+                        }
+                    }
+                }
+
+                let function = Expression::Function {
+                    name: identifier.to_lowercase(),
+                    args,
+                };
+                if !self.next_keyword("filter")? {
+                    return Ok(function);
+                }
+
+                if self.next_token()? != Some(Token::OpenParen) {
+                    return Err(self.error("expected '(' after 'filter'"));
+                }
+                let condition = self.parse_expression()?;
+                if self.next_token()? != Some(Token::CloseParen) {
+                    return Err(self.error("expected ')'"));
+                }
+
+                Ok(Expression::Filter {
+                    aggregate: Box::new(function),
+                    condition: Box::new(condition),
+                })
+            }
+            Some(Token::Dot) => {
+                self.next_token()?;
+                let name = self.expect_identifier()?;
+
+                Ok(Expression::Column {
+                    table: Some(identifier.to_string()),
+                    name: name.to_string(),
+                })
+            }
+            _ => Ok(Expression::Column {
+                table: None,
+                name: identifier.to_string(),
+            }),
+        }
+    }
+}
+
+fn parse_number(number: &str) -> Option<ColumnValue> {
+    if number.contains('.') {
+        number.parse().ok().map(ColumnValue::Float)
+    } else {
+        number.parse().ok().map(ColumnValue::Integer)
+    }
+}
+
+fn is_identifier_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// Parses a queried column, made of an expression and an optional alias.
+pub fn parse_select_item(input: &str) -> io::Result<SelectItem> {
+    Parser::new(input).parse_select_item()
+}
+
+/// Parses an expression by which the results are sorted, with its optional direction.
+pub fn parse_order_item(input: &str) -> io::Result<OrderItem> {
+    Parser::new(input).parse_order_item()
+}
+
+/// Parses an expression without an alias, like a condition.
+pub fn parse_expression(input: &str) -> io::Result<Expression> {
+    let mut parser = Parser::new(input);
+    let expression = parser.parse_expression()?;
+    if parser.next_token()?.is_some() {
+        return Err(parser.error("unexpected input after the expression"));
+    }
+
+    Ok(expression)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Parses the expression, writes it and parses it again, checking that nothing changed.
+    fn round_trip(input: &str) -> Expression {
+        let expression = parse_expression(input).unwrap();
+        let written = expression.to_string();
+        assert_eq!(
+            parse_expression(&written).unwrap(),
+            expression,
+            "{}",
+            written
+        );
+
+        expression
+    }
+
+    fn column(name: &str) -> Box<Expression> {
+        Box::new(Expression::Column {
+            table: None,
+            name: name.to_string(),
+        })
+    }
+
+    fn integer(value: i64) -> Box<Expression> {
+        Box::new(Expression::Literal(ColumnValue::Integer(value)))
+    }
+
+    fn comparison(op: ComparisonOp, name: &str, value: i64) -> Box<Expression> {
+        Box::new(Expression::Comparison {
+            op,
+            left: column(name),
+            right: integer(value),
+        })
+    }
+
+    #[test]
+    fn test_expressions_round_trip() {
+        for input in [
+            "a = 1",
+            "a != 1",
+            "a <> 1",
+            "a < 1",
+            "a <= 1",
+            "a > 1",
+            "a >= 1",
+            "a like 'x%'",
+            "a not like '_x'",
+            "a matches '^[a-z]+$'",
+            "a not matches 'x'",
+            "a between 1 and 10",
+            "a not between -1 and 1",
+            "not a = 1",
+            "not (a = 1 and b = 2)",
+            "a = 1 or b = 2 and c = 3",
+            "(a = 1 or b = 2) and c = 3",
+            "a = 1 and (b = 2 or not c = 3)",
+            "t.a = s.b",
+            "cast(a as float) > 1.5",
+            "extract(hour from ts) = 12",
+            "lower(name) = 'bob'",
+            "coalesce(a, b, 0) = $1",
+            "sum(price) filter (status = 'paid')",
+            "name = 'O''Brien'",
+            "name = ''''",
+            "a = -5",
+            "a > -2.5",
+            "a = null",
+        ] {
+            round_trip(input);
+        }
+    }
+
+    #[test]
+    fn test_precedence() {
+        // The and binds tighter than the or, and the not tighter than both.
+        assert_eq!(
+            round_trip("a = 1 or b = 2 and c = 3"),
+            Expression::Or(
+                comparison(ComparisonOp::Eq, "a", 1),
+                Box::new(Expression::And(
+                    comparison(ComparisonOp::Eq, "b", 2),
+                    comparison(ComparisonOp::Eq, "c", 3),
+                )),
+            )
+        );
+        assert_eq!(
+            round_trip("not a = 1 and b < -2"),
+            Expression::And(
+                Box::new(Expression::Not(comparison(ComparisonOp::Eq, "a", 1))),
+                comparison(ComparisonOp::Lt, "b", -2),
+            )
+        );
+        assert_eq!(
+            round_trip("(a = 1 or b = 2) and c = 3"),
+            Expression::And(
+                Box::new(Expression::Or(
+                    comparison(ComparisonOp::Eq, "a", 1),
+                    comparison(ComparisonOp::Eq, "b", 2),
+                )),
+                comparison(ComparisonOp::Eq, "c", 3),
+            )
+        );
+    }
+
+    #[test]
+    fn test_literals() {
+        assert_eq!(
+            round_trip("t.name = 'O''Brien'"),
+            Expression::Comparison {
+                op: ComparisonOp::Eq,
+                left: Box::new(Expression::Column {
+                    table: Some("t".to_string()),
+                    name: "name".to_string(),
+                }),
+                right: Box::new(Expression::Literal(ColumnValue::String(
+                    "O'Brien".to_string()
+                ))),
+            }
+        );
+        assert_eq!(round_trip("a = -7"), *comparison(ComparisonOp::Eq, "a", -7));
+        assert!(parse_expression("name = 'unterminated").is_err());
+        assert!(parse_expression("a = 1 b").is_err());
+    }
+
+    #[test]
+    fn test_select_and_order_items_round_trip() {
+        for input in ["a", "t.a", "sum(price) as total", "cast(a as string) as s"] {
+            let item = parse_select_item(input).unwrap();
+            assert_eq!(parse_select_item(&item.to_string()).unwrap(), item);
+        }
+        let item = parse_select_item("sum(t.price) AS total").unwrap();
+        assert_eq!(item.alias.as_deref(), Some("total"));
+        assert!(parse_select_item("a as").is_err());
+
+        for input in ["a", "a desc", "a asc", "sum(price) desc", "t.a DESC"] {
+            let item = parse_order_item(input).unwrap();
+            assert_eq!(parse_order_item(&item.to_string()).unwrap(), item);
+        }
+        assert!(parse_order_item("a desc").unwrap().descending);
+        assert!(!parse_order_item("a asc").unwrap().descending);
+    }
+
+    #[test]
+    fn test_like_matches() {
+        // The wildcard at the start, in the middle and at the end.
+        assert!(like_matches("hello", "%llo"));
+        assert!(!like_matches("hello", "%ll"));
+        assert!(like_matches("hello", "he%o"));
+        assert!(!like_matches("hello", "he%x"));
+        assert!(like_matches("hello", "hel%"));
+        assert!(!like_matches("hello", "hex%"));
+        assert!(like_matches("hello", "%"));
+        // Consecutive wildcards match like a single one.
+        assert!(like_matches("hello", "h%%o"));
+        assert!(like_matches("hello", "%%"));
+        assert!(like_matches("ho", "h%%o"));
+        // The single character wildcard matches a whole multibyte character.
+        assert!(like_matches("héllo", "h_llo"));
+        assert!(like_matches("日本", "__"));
+        assert!(!like_matches("日本", "_"));
+        assert!(!like_matches("日本", "___"));
+        // An empty value matches only the patterns made of `%`.
+        assert!(like_matches("", ""));
+        assert!(like_matches("", "%"));
+        assert!(!like_matches("", "_"));
+        assert!(!like_matches("", "a"));
+        // The patterns which match only after moving a wildcard forward.
+        assert!(like_matches("aXbYbZc", "a%b%c"));
+        assert!(like_matches("aXbYbZc", "a%bZc"));
+        assert!(!like_matches("aXbYbZc", "a%bY"));
+        assert!(like_matches("abababxc", "%ab_c"));
+        assert!(!like_matches("abababc", "%ab_c"));
+        assert!(like_matches("mississippi", "%iss%ppi"));
+        assert!(!like_matches("mississippi", "%iss%ppx"));
+    }
+
+    #[test]
+    fn test_parse_interval() {
+        assert_eq!(parse_interval("30s").unwrap(), 30);
+        assert_eq!(parse_interval("15m").unwrap(), 15 * 60);
+        assert_eq!(parse_interval("7d").unwrap(), 7 * 24 * 60 * 60);
+        assert!(parse_interval("0h").is_err());
+        assert!(parse_interval("999999999999999999d").is_err());
+        assert!(parse_interval("10w").is_err());
+        assert!(parse_interval("10").is_err());
+    }
+
+    #[test]
+    fn test_float_literals_round_trip() {
+        for (input, value) in [
+            ("0.000001", 0.000001),
+            ("100000000000000000000.0", 1e20),
+            ("1.5", 1.5),
+            ("-2.25", -2.25),
+            ("3.0", 3.0),
+        ] {
+            let condition = round_trip(&format!("price < {}", input));
+            let Expression::Comparison { right, .. } = condition else {
+                panic!("expected a comparison");
+            };
+            assert_eq!(*right, Expression::Literal(ColumnValue::Float(value)));
+        }
+
+        let tiny = Expression::Literal(ColumnValue::Float(1e-300));
+        assert_eq!(parse_expression(&tiny.to_string()).unwrap(), tiny);
+    }
+}
@@ -0,0 +1,157 @@
+use std::hash::{DefaultHasher, Hash, Hasher};
+
+/// The number of bits of the hash which pick the register, giving a standard error of about 1.6%.
+const PRECISION: u32 = 12;
+
+const REGISTERS: usize = 1 << PRECISION;
+
+/// The number of registers packed in each component, one per byte of an integer.
+const REGISTERS_PER_COMPONENT: usize = 8;
+
+/// A HyperLogLog sketch, which estimates the number of distinct values added to it with a fixed
+/// number of registers, whatever the number of values.
+///
+/// Two sketches are merged by keeping the largest of each register, which allows the shards to
+/// compute the sketches of their rows and the master to combine them.
+#[derive(Debug, Clone)]
+pub struct HyperLogLog {
+    /// For each register, the largest rank of the hashes which picked it.
+    registers: Vec<u8>,
+}
+
+impl Default for HyperLogLog {
+    fn default() -> Self {
+        Self {
+            registers: vec![0; REGISTERS],
+        }
+    }
+}
+
+impl HyperLogLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Builds the sketch from its components, which are its registers packed in integers.
+    pub fn from_components(components: impl IntoIterator<Item = i64>) -> Self {
+        let mut sketch = Self::new();
+        let registers = components.into_iter().flat_map(|c| c.to_le_bytes());
+        for (register, value) in sketch.registers.iter_mut().zip(registers) {
+            *register = value;
+        }
+
+        sketch
+    }
+
+    /// Returns the components of the sketch, which are its registers packed in integers, or
+    /// nothing if no value was added.
+    ///
+    /// The ranks are at most 53, so the integers are never negative.
+    pub fn into_components(self) -> Vec<i64> {
+        if self.registers.iter().all(|r| *r == 0) {
+            return vec![];
+        }
+
+        self.registers
+            .chunks(REGISTERS_PER_COMPONENT)
+            .map(|chunk| {
+                let mut bytes = [0; REGISTERS_PER_COMPONENT];
+                bytes.copy_from_slice(chunk);
+                i64::from_le_bytes(bytes)
+            })
+            .collect()
+    }
+
+    /// Adds a value, hashed with the same keys on all the instances, so that the same value picks
+    /// the same register on every shard.
+    pub fn add<T: Hash>(&mut self, value: &T) {
+        let mut hasher = DefaultHasher::new();
+        value.hash(&mut hasher);
+        let hash = hasher.finish();
+
+        let index = (hash >> (64 - PRECISION)) as usize;
+        let rank = ((hash << PRECISION).leading_zeros() + 1).min(64 - PRECISION + 1) as u8;
+        self.registers[index] = self.registers[index].max(rank);
+    }
+
+    pub fn merge(&mut self, other: HyperLogLog) {
+        for (register, other_register) in self.registers.iter_mut().zip(other.registers) {
+            *register = (*register).max(other_register);
+        }
+    }
+
+    /// Estimates the number of distinct values added to the sketch.
+    pub fn estimate(&self) -> u64 {
+        let registers = REGISTERS as f64;
+        let alpha = 0.7213 / (1.0 + 1.079 / registers);
+        let sum: f64 = self.registers.iter().map(|r| 2f64.powi(-(*r as i32))).sum();
+        let estimate = alpha * registers * registers / sum;
+
+        // Few values leave many registers empty, in which case counting them is more accurate.
+        let empty_registers = self.registers.iter().filter(|r| **r == 0).count();
+        if estimate <= 2.5 * registers && empty_registers > 0 {
+            return (registers * (registers / empty_registers as f64).ln()).round() as u64;
+        }
+
+        estimate.round() as u64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sketch(values: impl IntoIterator<Item = i64>) -> HyperLogLog {
+        let mut sketch = HyperLogLog::new();
+        for value in values {
+            sketch.add(&value);
+        }
+
+        sketch
+    }
+
+    /// Checks that the estimate is within three times the standard error of the cardinality.
+    fn assert_estimate(sketch: &HyperLogLog, cardinality: u64) {
+        let error = (sketch.estimate() as f64 - cardinality as f64).abs() / cardinality as f64;
+        assert!(
+            error <= 3.0 * 1.04 / (REGISTERS as f64).sqrt(),
+            "estimated {} for {} values",
+            sketch.estimate(),
+            cardinality
+        );
+    }
+
+    #[test]
+    fn test_estimate() {
+        assert_eq!(HyperLogLog::new().estimate(), 0);
+        assert_eq!(sketch([7, 7, 7]).estimate(), 1);
+        assert_estimate(&sketch(0..1_000), 1_000);
+        assert_estimate(&sketch(0..100_000), 100_000);
+        // The duplicated values are counted once.
+        assert_estimate(&sketch((0..200_000).map(|v| v % 50_000)), 50_000);
+    }
+
+    #[test]
+    fn test_merge_equals_union() {
+        let mut left = sketch(0..60_000);
+        left.merge(sketch(40_000..100_000));
+        let union = sketch(0..100_000);
+
+        assert_eq!(left.registers, union.registers);
+        assert_eq!(left.estimate(), union.estimate());
+    }
+
+    #[test]
+    fn test_components_round_trip() {
+        assert!(HyperLogLog::new().into_components().is_empty());
+
+        let sketch = sketch(0..10_000);
+        let components = sketch.clone().into_components();
+        assert_eq!(components.len(), REGISTERS / REGISTERS_PER_COMPONENT);
+        assert!(components.iter().all(|c| *c >= 0));
+        assert_eq!(
+            HyperLogLog::from_components(components).registers,
+            sketch.registers
+        );
+    }
+}
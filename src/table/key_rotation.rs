@@ -0,0 +1,37 @@
+use tokio::io;
+
+use crate::io::data_file::DataFile;
+use crate::io::file::rewrite_data_file;
+use crate::io::storage::storage;
+use crate::table::partition::Partition;
+
+/// Re-encrypts with the active key all the files of a partition which were encrypted with a
+/// different key, and returns how many were re-encrypted.
+///
+/// Files which are not encrypted are left untouched.
+pub async fn reencrypt_partition(
+    partition: &Partition,
+    active_key_version: u32,
+) -> io::Result<usize> {
+    let mut reencrypted_files = 0;
+
+    for entry in storage().read_dir(&partition.path).await? {
+        let file_path = entry.path;
+        if entry.is_dir || file_path.extension().is_none_or(|e| e != "dsto") {
+            continue;
+        }
+
+        let file = DataFile::from_file(storage().open(&file_path, false).await?).await?;
+        if file
+            .key_version()
+            .is_none_or(|key_version| key_version == active_key_version)
+        {
+            continue;
+        }
+
+        rewrite_data_file(file, &file_path, 0).await?;
+        reencrypted_files += 1;
+    }
+
+    Ok(reencrypted_files)
+}
@@ -0,0 +1,251 @@
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+use std::io::{Error, ErrorKind};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tokio::io;
+use tokio::sync::{oneshot, OwnedSemaphorePermit, RwLock, Semaphore};
+use tokio::time::timeout;
+
+use crate::config::{AdmissionControl, QueryConcurrency};
+
+/// Locks which serialize the operations on the same table.
+///
+/// Queries acquire the lock of a table in shared mode, whereas operations that modify the files of
+/// a table (e.g. inserts or retention) acquire it in exclusive mode.
+#[derive(Debug, Default)]
+pub struct TableLocks {
+    locks: Mutex<HashMap<String, Arc<RwLock<()>>>>,
+}
+
+impl TableLocks {
+    pub fn get(&self, table_name: &str) -> Arc<RwLock<()>> {
+        let mut locks = self.locks.lock().unwrap();
+        locks
+            .entry(table_name.to_string())
+            .or_insert_with(|| Arc::new(RwLock::new(())))
+            .clone()
+    }
+}
+
+/// Slots which limit the number of queries running at the same time on each table, so that the
+/// scans of a table can't use all the IO.
+///
+/// The queries exceeding the limit wait for a slot, up to a timeout.
+#[derive(Debug)]
+pub struct QuerySlots {
+    max_queries: Option<usize>,
+    timeout: Duration,
+    semaphores: Mutex<HashMap<String, Arc<Semaphore>>>,
+}
+
+impl QuerySlots {
+    pub fn new(config: Option<&QueryConcurrency>) -> Self {
+        Self {
+            max_queries: config.map(|c| c.max_queries_per_table),
+            timeout: Duration::from_millis(config.map_or(0, |c| c.timeout_ms)),
+            semaphores: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Waits for a slot to query the table, which is released when the returned permit is dropped.
+    ///
+    /// Returns `None` if the queries are not limited.
+    pub async fn acquire(&self, table_name: &str) -> io::Result<Option<OwnedSemaphorePermit>> {
+        let Some(max_queries) = self.max_queries else {
+            return Ok(None);
+        };
+
+        let semaphore = {
+            let mut semaphores = self.semaphores.lock().unwrap();
+            semaphores
+                .entry(table_name.to_string())
+                .or_insert_with(|| Arc::new(Semaphore::new(max_queries)))
+                .clone()
+        };
+
+        match timeout(self.timeout, semaphore.acquire_owned()).await {
+            Ok(Ok(permit)) => Ok(Some(permit)),
+            Ok(Err(error)) => Err(Error::other(error)),
+            Err(_) => Err(Error::new(
+                ErrorKind::TimedOut,
+                format!(
+                    "Too many concurrent queries on table {}, which allows {} at a time, gave up \
+                     after waiting {}ms",
+                    table_name,
+                    max_queries,
+                    self.timeout.as_millis()
+                ),
+            )),
+        }
+    }
+}
+
+/// The priority of a query, which decides the order in which the queued queries are admitted.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum QueryPriority {
+    Low,
+    #[default]
+    Normal,
+    High,
+}
+
+/// A query waiting to be admitted, which is notified when a running query hands it its slot.
+#[derive(Debug)]
+struct QueuedQuery {
+    priority: QueryPriority,
+    /// The order in which the query was queued, so that queries with the same priority are
+    /// admitted first come, first served.
+    sequence: u64,
+    admit: oneshot::Sender<()>,
+}
+
+impl PartialEq for QueuedQuery {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for QueuedQuery {}
+
+impl PartialOrd for QueuedQuery {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for QueuedQuery {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.priority
+            .cmp(&other.priority)
+            .then_with(|| other.sequence.cmp(&self.sequence))
+    }
+}
+
+#[derive(Debug, Default)]
+struct AdmissionQueue {
+    running: usize,
+    queued: BinaryHeap<QueuedQuery>,
+    next_sequence: u64,
+}
+
+impl AdmissionQueue {
+    /// Hands the slot of a finished query to the first queued query which is still waiting, or
+    /// frees it if there is none.
+    fn release(&mut self) {
+        while let Some(queued_query) = self.queued.pop() {
+            if queued_query.admit.send(()).is_ok() {
+                return;
+            }
+        }
+
+        self.running -= 1;
+    }
+}
+
+/// The admission control of the queries, which limits the queries running at the same time on the
+/// instance, so that heavy scans can't starve the small lookups, which can be sent with a higher
+/// priority to skip the queue.
+#[derive(Debug)]
+pub struct QueryAdmission {
+    max_queries: Option<usize>,
+    max_queued_queries: usize,
+    timeout: Duration,
+    queue: Arc<Mutex<AdmissionQueue>>,
+}
+
+/// The slot of an admitted query, which is handed to the next queued query when dropped.
+#[derive(Debug)]
+pub struct AdmissionPermit {
+    queue: Arc<Mutex<AdmissionQueue>>,
+}
+
+impl Drop for AdmissionPermit {
+    fn drop(&mut self) {
+        self.queue.lock().unwrap().release();
+    }
+}
+
+impl QueryAdmission {
+    pub fn new(config: Option<&AdmissionControl>) -> Self {
+        Self {
+            max_queries: config.map(|c| c.max_concurrent_queries),
+            max_queued_queries: config.map_or(0, |c| c.max_queued_queries),
+            timeout: Duration::from_millis(config.map_or(0, |c| c.timeout_ms)),
+            queue: Arc::new(Mutex::new(AdmissionQueue::default())),
+        }
+    }
+
+    /// Waits for the query to be admitted, which releases its slot when the returned permit is
+    /// dropped.
+    ///
+    /// Returns `None` if the queries are not limited.
+    pub async fn admit(&self, priority: QueryPriority) -> io::Result<Option<AdmissionPermit>> {
+        let Some(max_queries) = self.max_queries else {
+            return Ok(None);
+        };
+
+        let (sequence, mut admitted) = {
+            let mut queue = self.queue.lock().unwrap();
+            if queue.running < max_queries && queue.queued.is_empty() {
+                queue.running += 1;
+                return Ok(Some(self.permit()));
+            }
+
+            // The queries which stopped waiting, e.g. since their request was cancelled, are
+            // removed only when their turn comes, so they must not fill the queue.
+            queue.queued.retain(|q| !q.admit.is_closed());
+            if queue.queued.len() >= self.max_queued_queries {
+                return Err(Error::new(
+                    ErrorKind::WouldBlock,
+                    format!(
+                        "Too many queued queries, the instance allows {} to wait",
+                        self.max_queued_queries
+                    ),
+                ));
+            }
+
+            let (admit, admitted) = oneshot::channel();
+            let sequence = queue.next_sequence;
+            queue.next_sequence += 1;
+            queue.queued.push(QueuedQuery {
+                priority,
+                sequence,
+                admit,
+            });
+
+            (sequence, admitted)
+        };
+
+        if let Ok(Ok(())) = timeout(self.timeout, &mut admitted).await {
+            return Ok(Some(self.permit()));
+        }
+
+        // The query might have been admitted right after the timeout, in which case it runs
+        // anyway, since its slot was already handed to it.
+        let mut queue = self.queue.lock().unwrap();
+        if admitted.try_recv().is_ok() {
+            return Ok(Some(self.permit()));
+        }
+        queue.queued.retain(|q| q.sequence != sequence);
+
+        Err(Error::new(
+            ErrorKind::TimedOut,
+            format!(
+                "Too many concurrent queries, the instance allows {} at a time, gave up after \
+                 waiting {}ms",
+                max_queries,
+                self.timeout.as_millis()
+            ),
+        ))
+    }
+
+    fn permit(&self) -> AdmissionPermit {
+        AdmissionPermit {
+            queue: self.queue.clone(),
+        }
+    }
+}
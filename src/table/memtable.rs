@@ -0,0 +1,163 @@
+use std::collections::{BTreeSet, HashMap};
+use std::sync::{Mutex, OnceLock};
+
+use serde_json::Value;
+
+use crate::table::column::Column;
+
+/// The rows of an insert buffered in the memtable of a table, which share their timestamp and the
+/// bucket of their partition key, if any.
+#[derive(Debug, Clone)]
+pub struct BufferedInsert {
+    pub columns: Vec<Column>,
+    pub bucket: Option<u32>,
+    pub values: Vec<Vec<Value>>,
+    pub timestamp: u64,
+    /// The sequences of the inserts sent by the master, which are recorded once their rows are
+    /// written to the files.
+    pub sequences: Vec<u64>,
+}
+
+impl BufferedInsert {
+    /// Appends the rows of the insert if they have the same columns, bucket and timestamp, so that
+    /// they are written in a single batch, and returns whether they were appended.
+    fn merge(&mut self, other: &mut BufferedInsert) -> bool {
+        if self.columns != other.columns
+            || self.bucket != other.bucket
+            || self.timestamp != other.timestamp
+        {
+            return false;
+        }
+
+        self.values.append(&mut other.values);
+        self.sequences.append(&mut other.sequences);
+        true
+    }
+}
+
+/// The inserts buffered in memory for each table, in the order in which they were received, until
+/// they are flushed to the files of the table.
+///
+/// The memtable of a table is only changed while holding the lock of the table in exclusive mode,
+/// so that the scans holding it in shared mode see each row either in memory or in the files.
+#[derive(Debug, Default)]
+pub struct Memtables {
+    tables: Mutex<HashMap<String, Vec<BufferedInsert>>>,
+    /// The sequences of the inserts whose rows were flushed, until they are recorded as applied.
+    flushed_sequences: Mutex<HashMap<String, Vec<u64>>>,
+}
+
+static MEMTABLES: OnceLock<Memtables> = OnceLock::new();
+
+pub fn memtables() -> &'static Memtables {
+    MEMTABLES.get_or_init(Default::default)
+}
+
+impl Memtables {
+    /// Buffers the insert into the table and returns the number of rows buffered for it.
+    pub fn push(&self, table_name: &str, mut insert: BufferedInsert) -> usize {
+        let mut tables = self.tables.lock().unwrap();
+        let inserts = tables.entry(table_name.to_string()).or_default();
+        if !inserts.last_mut().is_some_and(|i| i.merge(&mut insert)) {
+            inserts.push(insert);
+        }
+
+        inserts.iter().map(|i| i.values.len()).sum()
+    }
+
+    /// Removes the inserts buffered for the table and returns them.
+    pub fn take(&self, table_name: &str) -> Vec<BufferedInsert> {
+        self.tables
+            .lock()
+            .unwrap()
+            .remove(table_name)
+            .unwrap_or_default()
+    }
+
+    /// Puts back the inserts taken from the table which couldn't be flushed, before the ones
+    /// buffered since.
+    pub fn restore(&self, table_name: &str, mut inserts: Vec<BufferedInsert>) {
+        let mut tables = self.tables.lock().unwrap();
+        let buffered_inserts = tables.entry(table_name.to_string()).or_default();
+        inserts.append(buffered_inserts);
+        *buffered_inserts = inserts;
+    }
+
+    /// Returns a copy of the inserts buffered for the table, to be scanned by a query.
+    pub fn get(&self, table_name: &str) -> Vec<BufferedInsert> {
+        self.tables
+            .lock()
+            .unwrap()
+            .get(table_name)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Returns the number of rows buffered for the table.
+    pub fn rows(&self, table_name: &str) -> u64 {
+        self.tables
+            .lock()
+            .unwrap()
+            .get(table_name)
+            .map_or(0, |inserts| {
+                inserts.iter().map(|i| i.values.len() as u64).sum()
+            })
+    }
+
+    /// Returns whether the insert with the sequence is buffered for the table or was flushed
+    /// without being recorded yet.
+    pub fn contains_sequence(&self, table_name: &str, sequence: u64) -> bool {
+        let buffered = self
+            .tables
+            .lock()
+            .unwrap()
+            .get(table_name)
+            .is_some_and(|inserts| inserts.iter().any(|i| i.sequences.contains(&sequence)));
+
+        buffered
+            || self
+                .flushed_sequences
+                .lock()
+                .unwrap()
+                .get(table_name)
+                .is_some_and(|sequences| sequences.contains(&sequence))
+    }
+
+    /// Keeps the sequences of the inserts whose rows were flushed, until they are recorded.
+    pub fn add_flushed_sequences(&self, table_name: &str, sequences: &[u64]) {
+        if sequences.is_empty() {
+            return;
+        }
+
+        self.flushed_sequences
+            .lock()
+            .unwrap()
+            .entry(table_name.to_string())
+            .or_default()
+            .extend_from_slice(sequences);
+    }
+
+    /// Removes the sequences of the inserts flushed for the table and returns them, to be recorded.
+    pub fn take_flushed_sequences(&self, table_name: &str) -> Vec<u64> {
+        self.flushed_sequences
+            .lock()
+            .unwrap()
+            .remove(table_name)
+            .unwrap_or_default()
+    }
+
+    /// Returns the names of the tables with buffered rows or flushed sequences to record.
+    pub fn table_names(&self) -> Vec<String> {
+        let mut table_names: BTreeSet<String> = self
+            .tables
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, inserts)| !inserts.is_empty())
+            .map(|(table_name, _)| table_name.clone())
+            .collect();
+        table_names.extend(self.flushed_sequences.lock().unwrap().keys().cloned());
+
+        table_names.into_iter().collect()
+    }
+}
@@ -0,0 +1,104 @@
+use std::io::{Error, ErrorKind};
+
+use tokio::io;
+
+use crate::io::data_file::DataFile;
+use crate::io::file::{copy_data_file, open_read_file};
+use crate::io::storage::storage;
+use crate::table::partition::Partition;
+
+/// The suffix of the directory in which the partitions are merged, which is never listed as a
+/// partition since its name isn't a time window.
+const MERGING_SUFFIX: &str = "merging";
+
+/// Merges the partitions of the same bucket, sorted by time, into a single partition covering all
+/// their time windows, by appending their files one after the other.
+///
+/// Since the rows are appended in insertion order, the files of the merged partition are still
+/// sorted by row and timestamp, as the scans expect.
+///
+/// The merged partition is written in a temporary directory which is then renamed, and only then
+/// are the partitions removed. A crash in between leaves them behind, which is why
+/// [`remove_merged_partitions`] must be called before merging again.
+pub async fn merge_partitions(
+    partitions: &[Partition],
+    file_names: &[String],
+) -> io::Result<Partition> {
+    let (Some((start, _)), Some((_, end))) = (
+        partitions.first().and_then(|p| p.window),
+        partitions.last().and_then(|p| p.window),
+    ) else {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "Only partitions with a time window can be merged",
+        ));
+    };
+
+    let table_path = partitions[0].path.parent().unwrap_or(&partitions[0].path);
+    let merged_partition = Partition::new(table_path, (start, end), partitions[0].bucket);
+    if storage().exists(&merged_partition.path).await? {
+        return Err(Error::new(
+            ErrorKind::AlreadyExists,
+            format!(
+                "The partition {} already exists",
+                merged_partition.path.display()
+            ),
+        ));
+    }
+
+    // A previous merge could have been interrupted while writing the files.
+    let tmp_path = merged_partition.path.with_extension(MERGING_SUFFIX);
+    if storage().exists(&tmp_path).await? {
+        storage().remove_dir_all(&tmp_path).await?;
+    }
+    storage().create_dir_all(&tmp_path).await?;
+
+    for file_name in file_names {
+        let file = storage().create(&tmp_path.join(file_name)).await?;
+        let mut merged_file = DataFile::from_file(file).await?;
+        for partition in partitions {
+            // A column could have been added after the partition was created.
+            if !storage().exists(&partition.path.join(file_name)).await? {
+                continue;
+            }
+
+            let mut file = open_read_file(file_name, &partition.path).await?;
+            copy_data_file(&mut file, &mut merged_file, 0).await?;
+        }
+        merged_file.sync_all().await?;
+    }
+
+    storage().rename(&tmp_path, &merged_partition.path).await?;
+    for partition in partitions {
+        storage().remove_dir_all(&partition.path).await?;
+    }
+
+    Ok(merged_partition)
+}
+
+/// Removes the partitions whose time window is within the one of another partition of the same
+/// bucket, which are left behind by a merge interrupted before removing them, and returns how many
+/// were removed.
+pub async fn remove_merged_partitions(partitions: &[Partition]) -> io::Result<usize> {
+    let mut removed_partitions = 0;
+    for partition in partitions {
+        let Some((start, end)) = partition.window else {
+            continue;
+        };
+
+        let is_merged = partitions.iter().any(|p| {
+            p.bucket == partition.bucket
+                && p.window.is_some_and(|(other_start, other_end)| {
+                    other_start <= start
+                        && end <= other_end
+                        && (other_start, other_end) != (start, end)
+                })
+        });
+        if is_merged {
+            storage().remove_dir_all(&partition.path).await?;
+            removed_partitions += 1;
+        }
+    }
+
+    Ok(removed_partitions)
+}
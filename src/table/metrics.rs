@@ -0,0 +1,49 @@
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+
+type TableWrites = HashMap<String, ColumnWrites>;
+
+/// The writes to the column files of each table since the instance started, by table and column.
+static COLUMN_WRITES: OnceLock<Mutex<HashMap<String, TableWrites>>> = OnceLock::new();
+
+/// The bytes and records written to the files of a column.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ColumnWrites {
+    pub bytes_written: u64,
+    pub records_written: u64,
+}
+
+/// Records the write of a record of `bytes` bytes to a file of the column.
+pub fn record_column_write(table: &str, column: &str, bytes: u64) {
+    let mut column_writes = COLUMN_WRITES.get_or_init(Default::default).lock().unwrap();
+
+    // The maps are looked up by reference first, since the names are allocated only for the first
+    // write of each column.
+    let table_writes = match column_writes.get_mut(table) {
+        Some(table_writes) => table_writes,
+        None => column_writes.entry(table.to_string()).or_default(),
+    };
+    let writes = match table_writes.get_mut(column) {
+        Some(writes) => writes,
+        None => table_writes.entry(column.to_string()).or_default(),
+    };
+    writes.bytes_written += bytes;
+    writes.records_written += 1;
+}
+
+/// Returns the writes of all the columns, sorted by table and column.
+pub fn column_writes() -> Vec<(String, String, ColumnWrites)> {
+    let column_writes = COLUMN_WRITES.get_or_init(Default::default).lock().unwrap();
+
+    let mut writes: Vec<_> = column_writes
+        .iter()
+        .flat_map(|(table, table_writes)| {
+            table_writes
+                .iter()
+                .map(|(column, writes)| (table.clone(), column.clone(), *writes))
+        })
+        .collect();
+    writes.sort_by(|left, right| (&left.0, &left.1).cmp(&(&right.0, &right.1)));
+
+    writes
+}
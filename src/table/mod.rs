@@ -1,10 +1,42 @@
 use crate::table::column::ColumnType;
 
 pub mod aggregate;
+pub mod bloom;
 pub mod column;
+pub mod compression;
 pub mod cursor;
+pub mod deletion;
+pub mod delta;
+pub mod dictionary;
+pub mod digest;
+pub mod explain;
+pub mod expression;
+pub mod hyperloglog;
+pub mod key_rotation;
+pub mod lock;
+pub mod memtable;
+pub mod merging;
+pub mod metrics;
+pub mod nulls;
+pub mod partition;
+pub mod retention;
+pub mod sampling;
+pub mod schema;
+pub mod secondary_index;
+pub mod segment;
 pub mod table;
+pub mod tiering;
+pub mod upgrade;
+pub mod validation;
 
 pub trait FromDisk {
     fn from(column_type: ColumnType, data: Vec<u8>) -> Self;
 }
+
+/// Hashes the bytes with FNV-1a, which is stable across instances and versions, unlike the hasher
+/// of the standard library, so it's used for the hashes which are stored or shared.
+pub fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
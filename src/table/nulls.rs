@@ -0,0 +1,107 @@
+use std::io::{ErrorKind, SeekFrom};
+use std::path::Path;
+
+use tokio::io;
+
+use crate::io::file::{create_and_open_file, open_read_file};
+use crate::table::column::Column;
+
+/// The size of the header of a block: 8 bytes for the id of its first row and 4 bytes for its
+/// number of rows.
+const BLOCK_HEADER_SIZE: usize = 12;
+
+/// Returns the name of the file of a partition marking the null values of a column, next to the
+/// file of the column.
+pub fn null_bitmap_file_name(column: &Column) -> String {
+    let column_file_name: String = column.into();
+    format!("{}.nulls.dsto", column_file_name)
+}
+
+/// The null markers of a column in a partition, made of a block per insertion with a bit per row,
+/// which is set if the value of the row is null.
+///
+/// The rows of an insertion have consecutive ids, so a block only stores the id of its first row.
+/// The rows inserted before the bitmaps existed, or before the column was added, aren't in any
+/// block, in which case whether they are null is only known by reading the file of the column.
+#[derive(Debug, Default)]
+pub struct NullBitmap {
+    /// The id of the first row and the number of rows of each block, sorted by id, with the offset
+    /// of the bits of the block.
+    blocks: Vec<(u64, u64, usize)>,
+    bits: Vec<u8>,
+}
+
+impl NullBitmap {
+    /// Returns whether the value of the row is null, if the row is in a block.
+    pub fn is_null(&self, row_id: u64) -> Option<bool> {
+        let block = self
+            .blocks
+            .partition_point(|(first_row_id, _, _)| *first_row_id <= row_id);
+        let (first_row_id, rows, offset) = self.blocks.get(block.checked_sub(1)?)?;
+        let row = row_id - first_row_id;
+        if row >= *rows {
+            return None;
+        }
+
+        let byte = self.bits[offset + row as usize / 8];
+        Some(byte & (1 << (row % 8)) != 0)
+    }
+}
+
+/// Reads the null bitmap of the column in the partition, which is empty if the file doesn't exist.
+pub async fn read_null_bitmap<P: AsRef<Path>>(
+    partition_path: P,
+    column: &Column,
+) -> io::Result<NullBitmap> {
+    let mut file = match open_read_file(&null_bitmap_file_name(column), partition_path).await {
+        Ok(file) => file,
+        Err(error) if error.kind() == ErrorKind::NotFound => return Ok(NullBitmap::default()),
+        Err(error) => return Err(error),
+    };
+    let mut data = vec![0u8; file.len().await? as usize];
+    file.read_exact(&mut data).await?;
+
+    let mut bitmap = NullBitmap::default();
+    let mut offset = 0;
+    // A crash during a write might leave a partial block at the end, which is ignored.
+    while let Some(header) = data.get(offset..offset + BLOCK_HEADER_SIZE) {
+        let first_row_id = u64::from_le_bytes(header[..8].try_into().unwrap());
+        let rows = u32::from_le_bytes(header[8..].try_into().unwrap()) as u64;
+        let bits_start = offset + BLOCK_HEADER_SIZE;
+        let Some(bits) = data.get(bits_start..bits_start + rows.div_ceil(8) as usize) else {
+            break;
+        };
+
+        bitmap.blocks.push((first_row_id, rows, bitmap.bits.len()));
+        bitmap.bits.extend_from_slice(bits);
+        offset = bits_start + bits.len();
+    }
+
+    Ok(bitmap)
+}
+
+/// Appends a block to the null bitmap of the column in the partition, for the rows with
+/// consecutive ids starting at `first_row_id`.
+pub async fn append_null_bitmap<P: AsRef<Path>>(
+    partition_path: P,
+    column: &Column,
+    first_row_id: u64,
+    nulls: &[bool],
+) -> io::Result<()> {
+    let mut block = Vec::with_capacity(BLOCK_HEADER_SIZE + nulls.len().div_ceil(8));
+    block.extend_from_slice(&u64::to_le_bytes(first_row_id));
+    block.extend_from_slice(&u32::to_le_bytes(nulls.len() as u32));
+    for chunk in nulls.chunks(8) {
+        let byte = chunk
+            .iter()
+            .enumerate()
+            .fold(0u8, |byte, (bit, null)| byte | ((*null as u8) << bit));
+        block.push(byte);
+    }
+
+    let mut file = create_and_open_file(&null_bitmap_file_name(column), partition_path).await?;
+    file.seek(SeekFrom::End(0)).await?;
+    file.write_all(&block).await?;
+
+    file.flush().await
+}
@@ -0,0 +1,151 @@
+use std::path::{Path, PathBuf};
+
+use tokio::io;
+
+use crate::io::storage::storage;
+use crate::table::column::ColumnValue;
+
+/// The `[since, until)` time range of the rows to read, where a missing bound is unbounded.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TimeRange {
+    pub since: Option<u64>,
+    pub until: Option<u64>,
+}
+
+impl TimeRange {
+    pub fn new(since: Option<u64>, until: Option<u64>) -> Self {
+        Self { since, until }
+    }
+
+    /// Whether the range contains all timestamps.
+    pub fn is_unbounded(&self) -> bool {
+        self.since.is_none() && self.until.is_none()
+    }
+
+    pub fn contains(&self, timestamp: u64) -> bool {
+        self.since.is_none_or(|since| timestamp >= since)
+            && self.until.is_none_or(|until| timestamp < until)
+    }
+}
+
+/// A partition of the storage of a table.
+///
+/// Rows are stored in the partition covering the time window in which they were inserted, which
+/// is a subdirectory of the table named `<start>_<end>` with the window bounds as unix timestamps.
+/// The tables with a partition key split each time window in hash buckets of the key, which are
+/// named `<start>_<end>_<bucket>`.
+///
+/// The root directory of the table is also treated as a partition without time bounds, since it
+/// contains the rows inserted before partitioning was introduced.
+#[derive(Debug, Clone)]
+pub struct Partition {
+    pub path: PathBuf,
+    /// The `[start, end)` time window of the rows in the partition, if bounded.
+    pub window: Option<(u64, u64)>,
+    /// The hash bucket of the partition key of the rows in the partition, if split by one.
+    pub bucket: Option<u32>,
+}
+
+impl Partition {
+    pub fn root<P: AsRef<Path>>(table_path: P) -> Self {
+        Self {
+            path: table_path.as_ref().to_path_buf(),
+            window: None,
+            bucket: None,
+        }
+    }
+
+    pub fn new<P: AsRef<Path>>(table_path: P, window: (u64, u64), bucket: Option<u32>) -> Self {
+        let (start, end) = window;
+        let name = match bucket {
+            Some(bucket) => format!("{}_{}_{}", start, end, bucket),
+            None => format!("{}_{}", start, end),
+        };
+
+        Self {
+            path: table_path.as_ref().join(name),
+            window: Some(window),
+            bucket,
+        }
+    }
+
+    pub fn for_timestamp<P: AsRef<Path>>(
+        table_path: P,
+        timestamp: u64,
+        interval: u64,
+        bucket: Option<u32>,
+    ) -> Self {
+        let interval = interval.max(1);
+        let start = timestamp - timestamp % interval;
+
+        Self::new(table_path, (start, start + interval), bucket)
+    }
+
+    pub async fn create(&self) -> io::Result<()> {
+        storage().create_dir_all(&self.path).await
+    }
+
+    /// Returns `true` if the partition can contain rows within the time range.
+    pub fn overlaps(&self, time_range: &TimeRange) -> bool {
+        let Some((start, end)) = self.window else {
+            return true;
+        };
+
+        time_range.since.is_none_or(|since| end > since)
+            && time_range.until.is_none_or(|until| start < until)
+    }
+
+    /// Returns `true` if all the rows of the partition are older than `cutoff`.
+    pub fn is_expired(&self, cutoff: u64) -> bool {
+        self.window.is_some_and(|(_, end)| end <= cutoff)
+    }
+}
+
+/// The maximum number of hash buckets of a partition key, which bounds the number of partitions
+/// of each time window.
+pub const MAX_PARTITION_BUCKETS: u32 = 1024;
+
+fn parse_partition_name(name: &str) -> Option<((u64, u64), Option<u32>)> {
+    let mut parts = name.split('_');
+    let window = (parts.next()?.parse().ok()?, parts.next()?.parse().ok()?);
+    let bucket = match parts.next() {
+        Some(bucket) => Some(bucket.parse().ok()?),
+        None => None,
+    };
+    if parts.next().is_some() {
+        return None;
+    }
+
+    Some((window, bucket))
+}
+
+/// Returns the hash bucket of a value of a partition key, which is only known for the integers and
+/// the strings.
+///
+/// The hash is stable across instances and versions, since the buckets are stored.
+pub fn bucket_of(value: &ColumnValue, buckets: u32) -> Option<u32> {
+    Some((value.stable_hash()? % buckets.max(1) as u64) as u32)
+}
+
+/// Lists all the partitions of a table, sorted by time with the root partition first.
+pub async fn list_partitions<P: AsRef<Path>>(table_path: P) -> io::Result<Vec<Partition>> {
+    let mut partitions = vec![];
+
+    for entry in storage().read_dir(table_path.as_ref()).await? {
+        if !entry.is_dir {
+            continue;
+        }
+
+        if let Some((window, bucket)) = parse_partition_name(&entry.name) {
+            partitions.push(Partition {
+                path: entry.path,
+                window: Some(window),
+                bucket,
+            });
+        }
+    }
+    partitions.sort_by_key(|p| (p.window, p.bucket));
+    partitions.insert(0, Partition::root(table_path));
+
+    Ok(partitions)
+}
@@ -0,0 +1,64 @@
+use std::io::SeekFrom;
+use std::path::Path;
+
+use tokio::io;
+
+use crate::io::data_file::DataFile;
+use crate::io::file::rewrite_data_file;
+use crate::io::storage::storage;
+use crate::table::column::ColumnType;
+
+/// Drops all the records of a file whose timestamp is older than `cutoff`.
+///
+/// Since records are appended with the insertion timestamp, every file is sorted by timestamp and
+/// retention never needs to look at each record individually:
+/// - If the last record is expired, the whole file is expired and it's truncated.
+/// - If the first record is not expired, the file is left untouched.
+/// - Otherwise, the file straddles the retention window, so we binary search the first record which
+///   must be kept and rewrite the file from there.
+///
+/// Returns the number of records that were dropped.
+pub async fn drop_expired_records<P: AsRef<Path>>(
+    file_path: P,
+    record_size: usize,
+    cutoff: u64,
+) -> io::Result<u64> {
+    let file = storage().open(file_path.as_ref(), true).await?;
+    let mut file = DataFile::from_file(file).await?;
+    let records = file.len().await? / record_size as u64;
+    if records == 0 || read_timestamp(&mut file, record_size, 0).await? >= cutoff {
+        return Ok(0);
+    }
+
+    if read_timestamp(&mut file, record_size, records - 1).await? < cutoff {
+        file.truncate().await?;
+        return Ok(records);
+    }
+
+    // We look for the first record whose timestamp is within the retention window, knowing that
+    // the first record is expired and the last one isn't.
+    let (mut low, mut high) = (0, records - 1);
+    while high - low > 1 {
+        let middle = low + (high - low) / 2;
+        if read_timestamp(&mut file, record_size, middle).await? < cutoff {
+            low = middle;
+        } else {
+            high = middle;
+        }
+    }
+
+    rewrite_data_file(file, file_path.as_ref(), high * record_size as u64).await?;
+
+    Ok(high)
+}
+
+async fn read_timestamp(file: &mut DataFile, record_size: usize, record: u64) -> io::Result<u64> {
+    // The timestamp is stored right after the index id of each record.
+    let offset = record * record_size as u64 + ColumnType::Integer.size() as u64;
+    file.seek(SeekFrom::Start(offset)).await?;
+
+    let mut timestamp = [0u8; ColumnType::Integer.size()];
+    file.read_exact(&mut timestamp).await?;
+
+    Ok(u64::from_le_bytes(timestamp))
+}
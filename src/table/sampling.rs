@@ -0,0 +1,80 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::io::{Error, ErrorKind};
+
+use serde::{Deserialize, Serialize};
+use tokio::io;
+
+/// The share of the rows read by a query, so that exploratory queries on big tables are cheap.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum Sample {
+    /// The approximate number of rows to read, e.g. `1000`.
+    Rows(u64),
+    /// The fraction of the rows to read, e.g. `0.01`.
+    Fraction(f64),
+}
+
+impl Sample {
+    pub fn validate(&self) -> io::Result<()> {
+        let valid = match self {
+            Sample::Rows(rows) => *rows > 0,
+            Sample::Fraction(fraction) => *fraction > 0.0 && *fraction <= 1.0,
+        };
+        if !valid {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "The sample must be a number of rows greater than 0 or a fraction between 0 and 1",
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Splits the sampled rows among `instances`, each sampling its own rows.
+    pub fn split(self, instances: usize) -> Self {
+        match self {
+            Sample::Rows(rows) => Sample::Rows(rows.div_ceil(instances as u64)),
+            Sample::Fraction(_) => self,
+        }
+    }
+
+    /// Returns the fraction of the `rows` of a table which are read.
+    pub fn fraction(&self, rows: u64) -> f64 {
+        match self {
+            Sample::Rows(_) if rows == 0 => 1.0,
+            Sample::Rows(sampled_rows) => (*sampled_rows as f64 / rows as f64).min(1.0),
+            Sample::Fraction(fraction) => *fraction,
+        }
+    }
+}
+
+/// Decides which rows of a scan are read, keeping each with the probability of the fraction.
+#[derive(Debug)]
+pub struct Sampler {
+    fraction: f64,
+    state: u64,
+}
+
+impl Sampler {
+    pub fn new(fraction: f64) -> Self {
+        // The seed uses the random keys of the hasher of the standard library, so that each query
+        // reads different rows.
+        let seed = RandomState::new().build_hasher().finish();
+        Self {
+            fraction,
+            state: seed | 1,
+        }
+    }
+
+    /// Returns whether the next row is read.
+    pub fn keep(&mut self) -> bool {
+        // A xorshift generator is enough, since the rows only need to be spread evenly.
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+
+        let value = (self.state >> 11) as f64 / (1u64 << 53) as f64;
+        value < self.fraction
+    }
+}
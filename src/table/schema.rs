@@ -0,0 +1,226 @@
+use std::collections::{HashMap, HashSet};
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+
+use log::info;
+use serde::{Deserialize, Serialize};
+use tokio::io;
+
+use crate::config::Durability;
+use crate::io::file::write_json;
+use crate::io::storage::storage;
+use crate::table::column::{get_columns, Column, ColumnType};
+use crate::table::compression::ColumnEncoding;
+
+/// The file of a table describing its columns.
+pub const SCHEMA_FILE_NAME: &str = ".schema.dsto";
+
+/// The definition of a column in the schema manifest, which can be extended with new metadata as
+/// long as the new fields have a default.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ColumnDefinition {
+    pub name: String,
+    pub ty: ColumnType,
+    #[serde(default)]
+    pub encoding: ColumnEncoding,
+    /// Whether the segments of the column have a bloom filter of their values.
+    #[serde(default)]
+    pub bloom_filter: bool,
+    /// Whether each partition has a secondary index of the values of the column.
+    #[serde(default)]
+    pub indexed: bool,
+    /// Whether the values of the column can't be repeated, which is checked with its secondary
+    /// index.
+    #[serde(default)]
+    pub unique: bool,
+}
+
+/// The column by whose values the rows of a table are split in hash buckets within each time
+/// window, so that the scans filtering it by a value only read the partitions of its bucket.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct PartitionKey {
+    pub column: String,
+    pub buckets: u32,
+}
+
+/// The settings of a table, most of which default to the ones of the config when they aren't set.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct TableOptions {
+    /// When the files written by the inserts into the table are synced to disk.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub durability: Option<Durability>,
+    /// The age in seconds after which the rows of the table are dropped.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ttl_secs: Option<u64>,
+    /// The size in seconds of the time window covered by each partition of the table.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub partition_interval_secs: Option<u64>,
+    /// The key splitting the partitions of the table in hash buckets, which can't be changed once
+    /// set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub partition_key: Option<PartitionKey>,
+}
+
+impl TableOptions {
+    /// Replaces the options which are set in `other`.
+    pub fn update(&mut self, other: TableOptions) {
+        if other.durability.is_some() {
+            self.durability = other.durability;
+        }
+        if other.ttl_secs.is_some() {
+            self.ttl_secs = other.ttl_secs;
+        }
+        if other.partition_interval_secs.is_some() {
+            self.partition_interval_secs = other.partition_interval_secs;
+        }
+        if other.partition_key.is_some() {
+            self.partition_key = other.partition_key;
+        }
+    }
+}
+
+/// The manifest of the schema of a table, with its columns in the order in which they were
+/// defined.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct SchemaManifest {
+    pub columns: Vec<ColumnDefinition>,
+    #[serde(flatten)]
+    pub options: TableOptions,
+}
+
+impl SchemaManifest {
+    pub fn new(columns: &[Column]) -> Self {
+        Self {
+            columns: columns
+                .iter()
+                .map(|c| ColumnDefinition {
+                    name: c.name.clone(),
+                    ty: c.ty,
+                    encoding: ColumnEncoding::Raw,
+                    bloom_filter: false,
+                    indexed: false,
+                    unique: false,
+                })
+                .collect(),
+            options: TableOptions::default(),
+        }
+    }
+
+    pub fn columns(&self) -> Vec<Column> {
+        self.columns
+            .iter()
+            .map(|c| Column::new(c.name.clone(), c.ty))
+            .collect()
+    }
+
+    pub fn encodings(&self) -> HashMap<String, ColumnEncoding> {
+        self.columns
+            .iter()
+            .map(|c| (c.name.clone(), c.encoding))
+            .collect()
+    }
+
+    /// Returns the names of the columns with a bloom filter.
+    pub fn bloom_filters(&self) -> HashSet<String> {
+        self.columns
+            .iter()
+            .filter(|c| c.bloom_filter)
+            .map(|c| c.name.clone())
+            .collect()
+    }
+
+    /// Returns the names of the columns with a secondary index.
+    pub fn indexed_columns(&self) -> HashSet<String> {
+        self.columns
+            .iter()
+            .filter(|c| c.indexed)
+            .map(|c| c.name.clone())
+            .collect()
+    }
+
+    /// Returns the names of the columns whose values can't be repeated.
+    pub fn unique_columns(&self) -> HashSet<String> {
+        self.columns
+            .iter()
+            .filter(|c| c.unique)
+            .map(|c| c.name.clone())
+            .collect()
+    }
+
+    /// Marks the column as having a secondary index.
+    pub fn set_indexed(&mut self, column: &Column) {
+        for definition in self.columns.iter_mut() {
+            if definition.name == column.name && definition.ty == column.ty {
+                definition.indexed = true;
+            }
+        }
+    }
+
+    /// Adds the column, unless it's already defined, in which case it keeps its encoding, bloom
+    /// filter and constraint since its files were already written with them.
+    ///
+    /// The unique columns have a secondary index, with which their values are checked. A column
+    /// can't be added with the name of another column of a different type.
+    pub fn add(
+        &mut self,
+        column: &Column,
+        encoding: ColumnEncoding,
+        bloom_filter: bool,
+        unique: bool,
+    ) -> io::Result<()> {
+        if let Some(existing) = self.columns.iter().find(|c| c.name == column.name) {
+            if existing.ty != column.ty {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!(
+                        "Column {} already exists with type {}",
+                        column.name,
+                        <&ColumnType as Into<&str>>::into(&existing.ty)
+                    ),
+                ));
+            }
+
+            return Ok(());
+        }
+
+        self.columns.push(ColumnDefinition {
+            name: column.name.clone(),
+            ty: column.ty,
+            encoding,
+            bloom_filter,
+            indexed: unique,
+            unique,
+        });
+
+        Ok(())
+    }
+}
+
+pub async fn write_schema<P: AsRef<Path>>(
+    table_path: P,
+    schema: &SchemaManifest,
+) -> io::Result<()> {
+    write_json(SCHEMA_FILE_NAME, table_path, schema).await
+}
+
+/// Reads the schema manifest of a table.
+///
+/// The tables created before the manifest existed have their columns encoded in the names of their
+/// column files, from which the manifest is written the first time they are read.
+pub async fn read_schema<P: AsRef<Path>>(table_path: P) -> io::Result<SchemaManifest> {
+    let table_path = table_path.as_ref();
+    match storage().read(&table_path.join(SCHEMA_FILE_NAME)).await {
+        Ok(data) => Ok(serde_json::from_slice(&data)?),
+        Err(error) if error.kind() == ErrorKind::NotFound => {
+            let schema = SchemaManifest::new(&get_columns(table_path).await?);
+            write_schema(table_path, &schema).await?;
+            info!(
+                "Wrote the schema manifest of {} from the names of its column files",
+                table_path.display()
+            );
+
+            Ok(schema)
+        }
+        Err(error) => Err(error),
+    }
+}
@@ -0,0 +1,355 @@
+use std::cmp::Ordering;
+use std::io::{ErrorKind, SeekFrom};
+use std::ops::Bound;
+use std::path::Path;
+
+use log::info;
+use tokio::io;
+
+use crate::io::data_file::DataFile;
+use crate::io::file::{create_and_open_file, open_read_file};
+use crate::io::storage::storage;
+use crate::table::column::{encode_string, Column, ColumnType, ColumnValue};
+use crate::table::cursor::{ColumnCursor, ColumnDecoder};
+use crate::table::expression::compare_values;
+use crate::table::FromDisk;
+
+/// The size of the header of a run: 4 bytes for its number of entries and 8 bytes for the number of
+/// rows of the partition which it covers.
+const RUN_HEADER_SIZE: usize = 12;
+
+/// The number of runs after which the runs of an index are merged into one.
+const MAX_RUNS: usize = 16;
+
+/// Returns the name of the file of a partition with the secondary index of a column, next to the
+/// file of the column.
+pub fn secondary_index_file_name(column: &Column) -> String {
+    let column_file_name: String = column.into();
+    format!("{}.secondary.dsto", column_file_name)
+}
+
+/// The bounds of the values looked up in a secondary index.
+pub type ValueRange = (Bound<ColumnValue>, Bound<ColumnValue>);
+
+#[derive(Debug, Clone, Copy)]
+struct Run {
+    offset: u64,
+    entries: u32,
+}
+
+/// The secondary index of a column in a partition, mapping its values to the ids of the rows with
+/// them.
+///
+/// Each insertion appends a run with the values of its rows sorted, so that each run is binary
+/// searched, and the runs are merged once there are too many. The index is derived from the file of
+/// the column, and is rebuilt from it whenever its runs don't cover all the rows of the partition,
+/// like after a crash before a run was written or once the retention dropped the expired rows.
+#[derive(Debug)]
+pub struct SecondaryIndex {
+    column: Column,
+    runs: Vec<Run>,
+    /// The number of rows of the partition covered by the runs.
+    rows: u64,
+}
+
+impl SecondaryIndex {
+    /// Reads the index of the column in the partition, which has `rows` rows.
+    pub async fn read<P: AsRef<Path>>(
+        partition_path: P,
+        column: &Column,
+        decoder: ColumnDecoder,
+        rows: u64,
+    ) -> io::Result<Self> {
+        let partition_path = partition_path.as_ref();
+        let mut index = Self {
+            column: column.clone(),
+            runs: vec![],
+            rows: 0,
+        };
+        match open_read_file(&secondary_index_file_name(column), partition_path).await {
+            Ok(mut file) => index.read_runs(&mut file).await?,
+            Err(error) if error.kind() == ErrorKind::NotFound => {}
+            Err(error) => return Err(error),
+        }
+
+        if index.rows != rows {
+            index.rebuild(partition_path, decoder, rows).await?;
+        }
+
+        Ok(index)
+    }
+
+    /// Appends a run with the values of the rows of an insertion, which added `rows` rows to the
+    /// partition, merging the runs if there are too many.
+    pub async fn append<P: AsRef<Path>>(
+        &mut self,
+        partition_path: P,
+        mut entries: Vec<(ColumnValue, u64)>,
+        rows: u64,
+    ) -> io::Result<()> {
+        let partition_path = partition_path.as_ref();
+        if self.runs.len() >= MAX_RUNS {
+            let mut file =
+                open_read_file(&secondary_index_file_name(&self.column), partition_path).await?;
+            for run in self.runs.clone() {
+                entries.extend(self.read_entries(&mut file, run, 0..run.entries).await?);
+            }
+
+            return self
+                .replace(partition_path, entries, self.rows + rows)
+                .await;
+        }
+
+        // A partial run left by a crash is overwritten.
+        let offset = self.end();
+        let run = Run {
+            offset,
+            entries: entries.len() as u32,
+        };
+        let data = encode_run(&self.column, entries, rows);
+        let mut file =
+            create_and_open_file(&secondary_index_file_name(&self.column), partition_path).await?;
+        file.seek(SeekFrom::Start(offset)).await?;
+        file.write_all(&data).await?;
+        file.flush().await?;
+        self.runs.push(run);
+        self.rows += rows;
+
+        Ok(())
+    }
+
+    /// Returns the sorted ids of the rows whose values are in the range.
+    pub async fn lookup<P: AsRef<Path>>(
+        &self,
+        partition_path: P,
+        range: &ValueRange,
+    ) -> io::Result<Vec<u64>> {
+        let mut row_ids = vec![];
+        if self.runs.is_empty() || !is_comparable(self.column.ty, range) {
+            return Ok(row_ids);
+        }
+
+        let mut file =
+            open_read_file(&secondary_index_file_name(&self.column), partition_path).await?;
+        for run in self.runs.iter() {
+            // The entries before the range are the ones below its start, and the entries in it
+            // are the ones which aren't above its end.
+            let start = self
+                .partition_point(&mut file, *run, |value| match &range.0 {
+                    Bound::Included(low) => compare_values(value, low).is_some_and(|o| o.is_lt()),
+                    Bound::Excluded(low) => compare_values(value, low).is_some_and(|o| o.is_le()),
+                    Bound::Unbounded => false,
+                })
+                .await?;
+            let end = self
+                .partition_point(&mut file, *run, |value| match &range.1 {
+                    Bound::Included(high) => compare_values(value, high).is_some_and(|o| o.is_le()),
+                    Bound::Excluded(high) => compare_values(value, high).is_some_and(|o| o.is_lt()),
+                    Bound::Unbounded => true,
+                })
+                .await?;
+            if start < end {
+                let entries = self.read_entries(&mut file, *run, start..end).await?;
+                row_ids.extend(entries.into_iter().map(|(_, row_id)| row_id));
+            }
+        }
+        row_ids.sort_unstable();
+
+        Ok(row_ids)
+    }
+
+    fn entry_size(&self) -> usize {
+        self.column.size() + ColumnType::Integer.size()
+    }
+
+    fn end(&self) -> u64 {
+        self.runs.last().map_or(0, |r| {
+            r.offset + (RUN_HEADER_SIZE + r.entries as usize * self.entry_size()) as u64
+        })
+    }
+
+    /// Reads the headers of the whole runs of the file.
+    async fn read_runs(&mut self, file: &mut DataFile) -> io::Result<()> {
+        let len = file.len().await?;
+        let mut header = [0u8; RUN_HEADER_SIZE];
+        loop {
+            let offset = self.end();
+            if offset + RUN_HEADER_SIZE as u64 > len {
+                break;
+            }
+            file.seek(SeekFrom::Start(offset)).await?;
+            file.read_exact(&mut header).await?;
+            let entries = u32::from_le_bytes(header[..4].try_into().unwrap());
+            let rows = u64::from_le_bytes(header[4..].try_into().unwrap());
+            let run = Run { offset, entries };
+            // A crash during a write might leave a partial run at the end, which is ignored.
+            if offset + (RUN_HEADER_SIZE + entries as usize * self.entry_size()) as u64 > len {
+                break;
+            }
+
+            self.runs.push(run);
+            self.rows += rows;
+        }
+
+        Ok(())
+    }
+
+    async fn read_entries(
+        &self,
+        file: &mut DataFile,
+        run: Run,
+        entries: std::ops::Range<u32>,
+    ) -> io::Result<Vec<(ColumnValue, u64)>> {
+        let entry_size = self.entry_size();
+        let offset = run.offset + (RUN_HEADER_SIZE + entries.start as usize * entry_size) as u64;
+        file.seek(SeekFrom::Start(offset)).await?;
+        let mut data = vec![0u8; entries.len() * entry_size];
+        file.read_exact(&mut data).await?;
+
+        Ok(data
+            .chunks_exact(entry_size)
+            .map(|entry| parse_entry(&self.column, entry))
+            .collect())
+    }
+
+    /// Returns the position in the run of the first entry whose value doesn't satisfy `predicate`,
+    /// which must be true for all the values before it.
+    async fn partition_point<F>(
+        &self,
+        file: &mut DataFile,
+        run: Run,
+        predicate: F,
+    ) -> io::Result<u32>
+    where
+        F: Fn(&ColumnValue) -> bool,
+    {
+        let (mut low, mut high) = (0, run.entries);
+        while low < high {
+            let middle = low + (high - low) / 2;
+            let (value, _) = self
+                .read_entries(file, run, middle..middle + 1)
+                .await?
+                .remove(0);
+            match predicate(&value) {
+                true => low = middle + 1,
+                false => high = middle,
+            }
+        }
+
+        Ok(low)
+    }
+
+    /// Rebuilds the index from the records of the column file, replacing the file of the index.
+    async fn rebuild(
+        &mut self,
+        partition_path: &Path,
+        decoder: ColumnDecoder,
+        rows: u64,
+    ) -> io::Result<()> {
+        let column_file_name: String = (&self.column).into();
+        // The column could have been added after the partition was created, in which case it has
+        // no values in the partition.
+        let mut entries = vec![];
+        match open_read_file(&format!("{}.dsto", column_file_name), partition_path).await {
+            Ok(file) => {
+                let mut cursor = ColumnCursor::new(Some(self.column.clone()), decoder, file);
+                loop {
+                    match cursor.read::<ColumnValue>().await {
+                        Ok(record) => {
+                            if let Some(value) = record.value {
+                                entries.push((value, record.index_id));
+                            }
+                        }
+                        Err(error) if error.kind() == ErrorKind::UnexpectedEof => break,
+                        Err(error) => return Err(error),
+                    }
+                }
+            }
+            Err(error) if error.kind() == ErrorKind::NotFound => {}
+            Err(error) => return Err(error),
+        }
+
+        self.replace(partition_path, entries, rows).await?;
+        info!(
+            "Rebuilt the secondary index of column {} in {}",
+            self.column.name,
+            partition_path.display()
+        );
+
+        Ok(())
+    }
+
+    /// Replaces the file of the index with a single run of the entries, covering `rows` rows.
+    async fn replace(
+        &mut self,
+        partition_path: &Path,
+        entries: Vec<(ColumnValue, u64)>,
+        rows: u64,
+    ) -> io::Result<()> {
+        let run = Run {
+            offset: 0,
+            entries: entries.len() as u32,
+        };
+        let data = encode_run(&self.column, entries, rows);
+
+        let file_path = partition_path.join(secondary_index_file_name(&self.column));
+        let tmp_file_path = file_path.with_extension("dsto.tmp");
+        let mut tmp_file = DataFile::from_file(storage().create(&tmp_file_path).await?).await?;
+        tmp_file.write_all(&data).await?;
+        tmp_file.sync_all().await?;
+        storage().rename(&tmp_file_path, &file_path).await?;
+        self.runs = vec![run];
+        self.rows = rows;
+
+        Ok(())
+    }
+}
+
+/// Returns whether the bounds of the range can be compared with the values of the type, since
+/// otherwise no value is in the range.
+fn is_comparable(ty: ColumnType, range: &ValueRange) -> bool {
+    [&range.0, &range.1].into_iter().all(|bound| match bound {
+        Bound::Included(value) | Bound::Excluded(value) => {
+            compare_values(&ty.into(), value).is_some()
+        }
+        Bound::Unbounded => true,
+    })
+}
+
+/// Encodes a run with the entries sorted by value, then by row id.
+fn encode_run(column: &Column, mut entries: Vec<(ColumnValue, u64)>, rows: u64) -> Vec<u8> {
+    entries.sort_by(|(a, a_row_id), (b, b_row_id)| {
+        let ordering = match (a, b) {
+            (ColumnValue::Float(a), ColumnValue::Float(b)) => a.total_cmp(b),
+            (a, b) => a.partial_cmp(b).unwrap_or(Ordering::Equal),
+        };
+        ordering.then(a_row_id.cmp(b_row_id))
+    });
+
+    let entry_size = column.size() + ColumnType::Integer.size();
+    let mut data = Vec::with_capacity(RUN_HEADER_SIZE + entries.len() * entry_size);
+    data.extend_from_slice(&u32::to_le_bytes(entries.len() as u32));
+    data.extend_from_slice(&u64::to_le_bytes(rows));
+    for (value, row_id) in entries {
+        let start = data.len();
+        match value {
+            ColumnValue::Integer(value) => data.extend_from_slice(&i64::to_le_bytes(value)),
+            ColumnValue::Float(value) => data.extend_from_slice(&f64::to_le_bytes(value)),
+            ColumnValue::String(value) => data.extend_from_slice(&encode_string(&value)),
+            ColumnValue::Null => {}
+        }
+        data.resize(start + column.size(), 0);
+        data.extend_from_slice(&u64::to_le_bytes(row_id));
+    }
+
+    data
+}
+
+fn parse_entry(column: &Column, entry: &[u8]) -> (ColumnValue, u64) {
+    let (value, row_id) = entry.split_at(column.size());
+
+    (
+        <ColumnValue as FromDisk>::from(column.ty, value.to_vec()),
+        u64::from_le_bytes(row_id.try_into().unwrap()),
+    )
+}
@@ -0,0 +1,572 @@
+use std::cmp::Ordering;
+use std::io::{Error, ErrorKind, SeekFrom};
+use std::path::Path;
+
+use log::info;
+use tokio::io;
+
+use crate::io::data_file::DataFile;
+use crate::io::file::{create_and_open_file, open_read_file};
+use crate::io::storage::storage;
+use crate::table::bloom::{bloom_filter_file_name, BloomFilter, BLOOM_FILTER_SIZE};
+use crate::table::column::{
+    encode_string, index_and_timestamp_size, Column, ColumnType, ColumnValue,
+};
+use crate::table::compression::ColumnEncoding;
+use crate::table::cursor::ColumnDecoder;
+use crate::table::delta::{block_len, decode_blocks, DECODED_RECORD_SIZE};
+use crate::table::partition::TimeRange;
+use crate::table::FromDisk;
+
+/// The number of values after which a segment is full, so that the next values start a new one.
+///
+/// The delta encoded blocks are never split, so their segments end with the block filling them.
+pub const SEGMENT_VALUES: u32 = 8 * 1024;
+
+/// The size of the part of a footer which doesn't depend on the column: 8 bytes each for the
+/// offset and size of its segment, the ids of its first and last rows and its first and last
+/// timestamps, followed by 4 bytes each for its number of values, its encoding and its number of
+/// nulls.
+///
+/// The footer ends with the minimum and maximum values of the segment, stored like the values of
+/// the column.
+const FOOTER_FIXED_SIZE: usize = 60;
+
+/// The size of the chunks in which a column file is read to rebuild its footers.
+const REBUILD_CHUNK_SIZE: u64 = 64 * 1024;
+
+/// Returns the name of the file of a partition with the footers of the segments of a column, next
+/// to the file of the column.
+pub fn segments_file_name(column: &Column) -> String {
+    let column_file_name: String = column.into();
+    format!("{}.segments.dsto", column_file_name)
+}
+
+fn footer_size(column: &Column) -> usize {
+    FOOTER_FIXED_SIZE + column.size() * 2
+}
+
+/// The footer of a segment of a column file, describing the records in it.
+#[derive(Debug, Clone)]
+pub struct SegmentFooter {
+    /// The position of the segment in the file of the column.
+    pub offset: u64,
+    pub size: u64,
+    /// The ids of the first and last rows with a value in the segment.
+    pub first_row_id: u64,
+    pub last_row_id: u64,
+    pub first_timestamp: u64,
+    pub last_timestamp: u64,
+    pub values: u32,
+    pub encoding: ColumnEncoding,
+    /// The number of rows between the first and last rows of the segment which are null in the
+    /// column.
+    pub nulls: u32,
+    /// The zone map of the segment, with the bounds of its values.
+    pub min: ColumnValue,
+    pub max: ColumnValue,
+}
+
+impl SegmentFooter {
+    pub fn end(&self) -> u64 {
+        self.offset + self.size
+    }
+
+    fn encode(&self, column: &Column) -> Vec<u8> {
+        let mut data = Vec::with_capacity(footer_size(column));
+        let fields = [
+            self.offset,
+            self.size,
+            self.first_row_id,
+            self.last_row_id,
+            self.first_timestamp,
+            self.last_timestamp,
+        ];
+        for field in fields {
+            data.extend_from_slice(&u64::to_le_bytes(field));
+        }
+        let encoding: u32 = match self.encoding {
+            ColumnEncoding::Raw => 0,
+            ColumnEncoding::Dictionary => 1,
+            ColumnEncoding::Delta => 2,
+        };
+        for field in [self.values, encoding, self.nulls] {
+            data.extend_from_slice(&u32::to_le_bytes(field));
+        }
+        for value in [&self.min, &self.max] {
+            let start = data.len();
+            match value {
+                ColumnValue::Integer(value) => data.extend_from_slice(&i64::to_le_bytes(*value)),
+                ColumnValue::Float(value) => data.extend_from_slice(&f64::to_le_bytes(*value)),
+                ColumnValue::String(value) => data.extend_from_slice(&encode_string(value)),
+                ColumnValue::Null => {}
+            }
+            data.resize(start + column.size(), 0);
+        }
+
+        data
+    }
+
+    fn decode(data: &[u8], column: &Column) -> io::Result<Self> {
+        let field =
+            |index: usize| u64::from_le_bytes(data[index * 8..(index + 1) * 8].try_into().unwrap());
+        let small_field =
+            |offset: usize| u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap());
+        let encoding = match small_field(52) {
+            0 => ColumnEncoding::Raw,
+            1 => ColumnEncoding::Dictionary,
+            2 => ColumnEncoding::Delta,
+            encoding => {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("The segment footer has an unknown encoding {}", encoding),
+                ))
+            }
+        };
+        let (min, max) = data[FOOTER_FIXED_SIZE..].split_at(column.size());
+
+        Ok(Self {
+            offset: field(0),
+            size: field(1),
+            first_row_id: field(2),
+            last_row_id: field(3),
+            first_timestamp: field(4),
+            last_timestamp: field(5),
+            values: small_field(48),
+            encoding,
+            nulls: small_field(56),
+            min: <ColumnValue as FromDisk>::from(column.ty, min.to_vec()),
+            max: <ColumnValue as FromDisk>::from(column.ty, max.to_vec()),
+        })
+    }
+}
+
+/// The segments of the file of a column in a partition, each made of up to [`SEGMENT_VALUES`]
+/// consecutive records.
+///
+/// The footers of the segments are stored in their own file rather than after each segment, so that
+/// the records stay contiguous and the column file can still be binary searched, concatenated and
+/// rewritten as a whole. The footers are derived from the column file, and are rebuilt from it
+/// whenever they don't end where it does, like after a crash between the writes of the two files
+/// or once the retention dropped the expired records.
+///
+/// The columns with a bloom filter also store the bloom filter of each segment in another file,
+/// which is rebuilt with the footers.
+#[derive(Debug)]
+pub struct Segments {
+    column: Column,
+    decoder: ColumnDecoder,
+    footers: Vec<SegmentFooter>,
+    bloom_filters: Option<Vec<BloomFilter>>,
+    /// The number of footers which are stored unchanged in the file of the footers.
+    persisted_footers: usize,
+}
+
+impl Segments {
+    /// Reads the segments of the column in the partition, whose file has `file_len` bytes, with
+    /// their bloom filters if the column has one.
+    pub async fn read<P: AsRef<Path>>(
+        partition_path: P,
+        column: &Column,
+        decoder: ColumnDecoder,
+        file_len: u64,
+        bloom_filter: bool,
+    ) -> io::Result<Self> {
+        let partition_path = partition_path.as_ref();
+        let footers = match open_read_file(&segments_file_name(column), partition_path).await {
+            Ok(mut file) => {
+                // A crash during a write might leave a partial footer at the end, which is ignored.
+                let footer_size = footer_size(column);
+                let len = file.len().await? as usize / footer_size * footer_size;
+                let mut data = vec![0u8; len];
+                file.read_exact(&mut data).await?;
+                data.chunks_exact(footer_size)
+                    .map(|f| SegmentFooter::decode(f, column))
+                    .collect()
+            }
+            Err(error) if error.kind() == ErrorKind::NotFound => Ok(vec![]),
+            Err(error) => return Err(error),
+        };
+        let bloom_filters = match bloom_filter {
+            true => Some(read_bloom_filters(partition_path, column).await?),
+            false => None,
+        };
+
+        let mut segments = Self {
+            column: column.clone(),
+            decoder,
+            footers: vec![],
+            bloom_filters: bloom_filter.then(Vec::new),
+            persisted_footers: 0,
+        };
+        match footers {
+            Ok(footers)
+                if footers.last().map_or(0, |f| f.end()) == file_len
+                    && bloom_filters
+                        .as_ref()
+                        .is_none_or(|b| b.len() == footers.len()) =>
+            {
+                segments.persisted_footers = footers.len();
+                segments.footers = footers;
+                segments.bloom_filters = bloom_filters;
+            }
+            _ => segments.rebuild(partition_path).await?,
+        }
+
+        Ok(segments)
+    }
+
+    /// Returns the segments which can contain records within the time range, which are consecutive
+    /// since the records are sorted by timestamp.
+    pub fn overlapping(&self, time_range: &TimeRange) -> &[SegmentFooter] {
+        let start = self.footers.partition_point(|f| {
+            time_range
+                .since
+                .is_some_and(|since| f.last_timestamp < since)
+        });
+        let end = self.footers.partition_point(|f| {
+            time_range
+                .until
+                .is_none_or(|until| f.first_timestamp < until)
+        });
+
+        &self.footers[start..end.max(start)]
+    }
+
+    /// Returns the bloom filter of the segment, if the column has one.
+    pub fn bloom_filter(&self, segment: &SegmentFooter) -> Option<&BloomFilter> {
+        let index = self
+            .footers
+            .binary_search_by_key(&segment.offset, |f| f.offset)
+            .ok()?;
+
+        self.bloom_filters.as_ref()?.get(index)
+    }
+
+    /// Adds a record appended to the column file.
+    pub fn append_record(&mut self, record: &[u8]) -> io::Result<()> {
+        self.append(record.len() as u64, record, record.len())
+    }
+
+    /// Adds a delta encoded block appended to the column file, which takes `size` bytes and whose
+    /// records are decoded with the raw layout.
+    pub fn append_block(&mut self, size: u64, records: &[u8]) -> io::Result<()> {
+        self.append(size, records, DECODED_RECORD_SIZE)
+    }
+
+    /// Writes the footers which changed since they were read or written.
+    pub async fn persist<P: AsRef<Path>>(&mut self, partition_path: P) -> io::Result<()> {
+        if self.persisted_footers == self.footers.len() {
+            return Ok(());
+        }
+
+        let partition_path = partition_path.as_ref();
+        self.count_nulls(partition_path, self.persisted_footers)
+            .await?;
+        // The bloom filters are written first, so that the footers are rebuilt with them if a
+        // crash leaves the bloom filter of a changed footer behind.
+        if let Some(bloom_filters) = &self.bloom_filters {
+            let mut file =
+                create_and_open_file(&bloom_filter_file_name(&self.column), partition_path).await?;
+            let offset = (self.persisted_footers * BLOOM_FILTER_SIZE) as u64;
+            file.seek(SeekFrom::Start(offset)).await?;
+            file.write_all(&encode_bloom_filters(
+                &bloom_filters[self.persisted_footers..],
+            ))
+            .await?;
+            file.flush().await?;
+        }
+        let mut file =
+            create_and_open_file(&segments_file_name(&self.column), partition_path).await?;
+        let offset = (self.persisted_footers * footer_size(&self.column)) as u64;
+        file.seek(SeekFrom::Start(offset)).await?;
+        file.write_all(&self.encode_footers(self.persisted_footers))
+            .await?;
+        self.persisted_footers = self.footers.len();
+
+        file.flush().await
+    }
+
+    /// Adds records taking `size` bytes in the column file to the last segment if it isn't full,
+    /// or to a new one otherwise.
+    fn append(&mut self, size: u64, records: &[u8], record_size: usize) -> io::Result<()> {
+        let mut records = records.chunks_exact(record_size);
+        let Some(first) = records.next() else {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "The appended records are empty",
+            ));
+        };
+        let (first_row_id, first_timestamp) = parse_record(first);
+        let value = self.decode_value(first)?;
+        let mut values = vec![value.clone()];
+        let mut footer = SegmentFooter {
+            offset: self.footers.last().map_or(0, |f| f.end()),
+            size,
+            first_row_id,
+            last_row_id: first_row_id,
+            first_timestamp,
+            last_timestamp: first_timestamp,
+            values: 1,
+            encoding: (&self.decoder).into(),
+            nulls: 0,
+            min: value.clone(),
+            max: value,
+        };
+        for record in records {
+            (footer.last_row_id, footer.last_timestamp) = parse_record(record);
+            let value = self.decode_value(record)?;
+            extend_bounds(&mut footer, &value, &value);
+            footer.values += 1;
+            values.push(value);
+        }
+
+        let last_footer = self.footers.len().saturating_sub(1);
+        match self.footers.last_mut() {
+            Some(last) if last.values < SEGMENT_VALUES && last.encoding == footer.encoding => {
+                last.size += footer.size;
+                last.last_row_id = footer.last_row_id;
+                last.last_timestamp = footer.last_timestamp;
+                last.values += footer.values;
+                extend_bounds(last, &footer.min, &footer.max);
+                self.persisted_footers = self.persisted_footers.min(last_footer);
+            }
+            _ => {
+                self.footers.push(footer);
+                if let Some(bloom_filters) = &mut self.bloom_filters {
+                    bloom_filters.push(BloomFilter::new());
+                }
+            }
+        }
+        if let Some(bloom_filter) = self.bloom_filters.as_mut().and_then(|b| b.last_mut()) {
+            for value in values.iter() {
+                bloom_filter.insert(value);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the value of a record, which has the raw layout unless it's dictionary encoded.
+    fn decode_value(&self, record: &[u8]) -> io::Result<ColumnValue> {
+        let data = &record[index_and_timestamp_size()..];
+        let data = match &self.decoder {
+            ColumnDecoder::Dictionary(dictionary) => dictionary.decode(data)?,
+            _ => data,
+        };
+
+        Ok(<ColumnValue as FromDisk>::from(
+            self.column.ty,
+            data.to_vec(),
+        ))
+    }
+
+    /// Counts the nulls of the footers starting at `from`, which are the rows of the partition
+    /// between their first and last rows without a value in the column.
+    ///
+    /// The rows are counted in the index, whose ids increase like the ones of the column file.
+    async fn count_nulls(&mut self, partition_path: &Path, from: usize) -> io::Result<()> {
+        let mut index = match open_read_file(".index.dsto", partition_path).await {
+            Ok(index) => index,
+            Err(error) if error.kind() == ErrorKind::NotFound => return Ok(()),
+            Err(error) => return Err(error),
+        };
+        let entries = index.len().await? / index_and_timestamp_size() as u64;
+
+        for footer in self.footers[from..].iter_mut() {
+            let start = index_position(&mut index, entries, footer.first_row_id).await?;
+            let end = index_position(&mut index, entries, footer.last_row_id + 1).await?;
+            footer.nulls = (end - start).saturating_sub(footer.values as u64) as u32;
+        }
+
+        Ok(())
+    }
+
+    /// Rebuilds the footers from the records of the column file, replacing the file of the footers.
+    async fn rebuild(&mut self, partition_path: &Path) -> io::Result<()> {
+        let column_file_name: String = (&self.column).into();
+        let mut file =
+            open_read_file(&format!("{}.dsto", column_file_name), partition_path).await?;
+
+        self.footers.clear();
+        if let Some(bloom_filters) = &mut self.bloom_filters {
+            bloom_filters.clear();
+        }
+        let mut remaining = file.len().await?;
+        let mut data = vec![];
+        while remaining > 0 {
+            let mut chunk = vec![0u8; remaining.min(REBUILD_CHUNK_SIZE) as usize];
+            file.read_exact(&mut chunk).await?;
+            remaining -= chunk.len() as u64;
+            data.extend_from_slice(&chunk);
+
+            let appended = self.append_records(&data)?;
+            data.drain(..appended);
+        }
+        self.count_nulls(partition_path, 0).await?;
+
+        if let Some(bloom_filters) = &self.bloom_filters {
+            let file_path = partition_path.join(bloom_filter_file_name(&self.column));
+            let tmp_file_path = file_path.with_extension("dsto.tmp");
+            let mut tmp_file = DataFile::from_file(storage().create(&tmp_file_path).await?).await?;
+            tmp_file
+                .write_all(&encode_bloom_filters(bloom_filters))
+                .await?;
+            tmp_file.sync_all().await?;
+            storage().rename(&tmp_file_path, &file_path).await?;
+        }
+        let file_path = partition_path.join(segments_file_name(&self.column));
+        let tmp_file_path = file_path.with_extension("dsto.tmp");
+        let mut tmp_file = DataFile::from_file(storage().create(&tmp_file_path).await?).await?;
+        tmp_file.write_all(&self.encode_footers(0)).await?;
+        tmp_file.sync_all().await?;
+        storage().rename(&tmp_file_path, &file_path).await?;
+        self.persisted_footers = self.footers.len();
+
+        info!(
+            "Rebuilt the {} segments of column {} in {}",
+            self.footers.len(),
+            self.column.name,
+            partition_path.display()
+        );
+
+        Ok(())
+    }
+
+    /// Adds the whole records, or delta encoded blocks, at the start of `data` to the segments, and
+    /// returns the number of bytes added.
+    fn append_records(&mut self, data: &[u8]) -> io::Result<usize> {
+        let encoding: ColumnEncoding = (&self.decoder).into();
+        let Some(value_size) = encoding.value_size(&self.column) else {
+            let mut offset = 0;
+            let mut records = vec![];
+            while let Some(len) = block_len(&data[offset..]).filter(|l| offset + l <= data.len()) {
+                records.clear();
+                decode_blocks(&data[offset..offset + len], &mut records)?;
+                self.append_block(len as u64, &records)?;
+                offset += len;
+            }
+
+            return Ok(offset);
+        };
+
+        let records = data.chunks_exact(index_and_timestamp_size() + value_size);
+        let appended = data.len() - records.remainder().len();
+        for record in records {
+            self.append_record(record)?;
+        }
+
+        Ok(appended)
+    }
+
+    fn encode_footers(&self, from: usize) -> Vec<u8> {
+        self.footers[from..]
+            .iter()
+            .flat_map(|f| f.encode(&self.column))
+            .collect()
+    }
+}
+
+/// The ids of the rows skipped by a scan, since their values in a column are in segments which
+/// can't match its filter.
+#[derive(Debug)]
+pub struct PrunedRows {
+    /// The first and last row ids of the pruned segments, sorted and without overlaps.
+    ranges: Vec<(u64, u64)>,
+}
+
+impl PrunedRows {
+    pub fn new(mut ranges: Vec<(u64, u64)>) -> Self {
+        ranges.sort();
+        let mut merged_ranges: Vec<(u64, u64)> = Vec::with_capacity(ranges.len());
+        for (first, last) in ranges {
+            match merged_ranges.last_mut() {
+                Some((_, merged_last)) if first <= *merged_last => {
+                    *merged_last = (*merged_last).max(last)
+                }
+                _ => merged_ranges.push((first, last)),
+            }
+        }
+
+        Self {
+            ranges: merged_ranges,
+        }
+    }
+
+    pub fn contains(&self, row_id: u64) -> bool {
+        let range = self.ranges.partition_point(|(first, _)| *first <= row_id);
+        range
+            .checked_sub(1)
+            .is_some_and(|range| self.ranges[range].1 >= row_id)
+    }
+}
+
+/// Reads the bloom filters of the segments of the column in the partition, which are empty if the
+/// file doesn't exist.
+async fn read_bloom_filters(
+    partition_path: &Path,
+    column: &Column,
+) -> io::Result<Vec<BloomFilter>> {
+    let mut file = match open_read_file(&bloom_filter_file_name(column), partition_path).await {
+        Ok(file) => file,
+        Err(error) if error.kind() == ErrorKind::NotFound => return Ok(vec![]),
+        Err(error) => return Err(error),
+    };
+    // A crash during a write might leave a partial bloom filter at the end, which is ignored.
+    let len = file.len().await? as usize / BLOOM_FILTER_SIZE * BLOOM_FILTER_SIZE;
+    let mut data = vec![0u8; len];
+    file.read_exact(&mut data).await?;
+
+    Ok(data
+        .chunks_exact(BLOOM_FILTER_SIZE)
+        .map(BloomFilter::from_bytes)
+        .collect())
+}
+
+fn encode_bloom_filters(bloom_filters: &[BloomFilter]) -> Vec<u8> {
+    bloom_filters
+        .iter()
+        .flat_map(|b| b.as_bytes())
+        .copied()
+        .collect()
+}
+
+/// Extends the bounds of the values of the segment to `min` and `max`.
+///
+/// The values which aren't comparable, like the floats which aren't a number, never become a bound.
+fn extend_bounds(footer: &mut SegmentFooter, min: &ColumnValue, max: &ColumnValue) {
+    if min.partial_cmp(&footer.min) == Some(Ordering::Less) {
+        footer.min = min.clone();
+    }
+    if max.partial_cmp(&footer.max) == Some(Ordering::Greater) {
+        footer.max = max.clone();
+    }
+}
+
+/// Returns the position of the first entry of the index whose id isn't lower than `row_id`.
+async fn index_position(index: &mut DataFile, entries: u64, row_id: u64) -> io::Result<u64> {
+    let (mut low, mut high) = (0, entries);
+    let mut buffer = [0u8; ColumnType::Integer.size()];
+    while low < high {
+        let middle = low + (high - low) / 2;
+        index
+            .seek(SeekFrom::Start(middle * index_and_timestamp_size() as u64))
+            .await?;
+        index.read_exact(&mut buffer).await?;
+        match u64::from_le_bytes(buffer) < row_id {
+            true => low = middle + 1,
+            false => high = middle,
+        }
+    }
+
+    Ok(low)
+}
+
+/// Returns the index id and timestamp at the start of a record.
+fn parse_record(record: &[u8]) -> (u64, u64) {
+    let integer_size = ColumnType::Integer.size();
+    let index_id = u64::from_le_bytes(record[..integer_size].try_into().unwrap());
+    let timestamp = u64::from_le_bytes(record[integer_size..integer_size * 2].try_into().unwrap());
+
+    (index_id, timestamp)
+}
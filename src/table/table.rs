@@ -1,30 +1,132 @@
-use crate::config::Config;
+use crate::config::{Config, Durability};
+use crate::io::clock::clock;
+use crate::io::data_file::DataFile;
+use crate::io::encryption::encryption;
 use crate::io::file::{
-    create_and_open_file, create_file, open_append_file, open_read_file, read_or,
+    create_and_open_file, create_file, data_file_len, open_append_file, open_direct_read_file,
+    open_read_file, read_or,
 };
-use crate::table::aggregate::{GroupKey, GroupValue};
+use crate::io::object_store::ObjectStore;
+use crate::io::storage::memory::empty_file;
+use crate::io::storage::storage;
+use crate::table::aggregate::{Aggregate, GroupKey, GroupValue};
+use crate::table::bloom::{bloom_filter_file_name, supports_bloom_filter};
 use crate::table::column::{
-    get_columns, parse_and_validate_columns, parse_and_validate_queried_columns, AggregateColumn,
-    Column, ColumnType, ColumnValue,
+    encode_string, get_column, index_and_timestamp_size, parse_and_validate_columns,
+    parse_and_validate_filter, parse_and_validate_group_by, parse_and_validate_queried_columns,
+    AggregateColumn, Column, ColumnType, ColumnValue,
 };
-use crate::table::cursor::{AggregatedRow, ColumnCursor, Row};
+use crate::table::compression::ColumnEncoding;
+use crate::table::cursor::{AggregatedRow, ColumnCursor, ColumnDecoder, Row};
+use crate::table::deletion::{
+    append_tombstones, count_deleted_rows, drop_deleted_records, drop_expired_tombstones,
+    read_deleted_rows, remove_tombstones, Tombstone, DELETED_ROWS_FILE_NAME,
+};
+use crate::table::delta::{decode_blocks, drop_deleted_blocks, drop_expired_blocks, DeltaBlock};
+use crate::table::dictionary::Dictionary;
+use crate::table::explain::TableExplanation;
+use crate::table::expression::{
+    compare_values, is_pseudo_column, parse_order_item, pseudo_column_value, Expression, OrderItem,
+    ROW_ID_COLUMN,
+};
+use crate::table::key_rotation::reencrypt_partition;
+use crate::table::memtable::{memtables, BufferedInsert};
+use crate::table::merging::{merge_partitions, remove_merged_partitions};
+use crate::table::metrics::record_column_write;
+use crate::table::nulls::{append_null_bitmap, null_bitmap_file_name, read_null_bitmap};
+use crate::table::partition::{
+    bucket_of, list_partitions, Partition, TimeRange, MAX_PARTITION_BUCKETS,
+};
+use crate::table::retention::drop_expired_records;
+use crate::table::sampling::{Sample, Sampler};
+use crate::table::schema::{read_schema, write_schema, PartitionKey, SchemaManifest, TableOptions};
+use crate::table::secondary_index::{secondary_index_file_name, SecondaryIndex};
+use crate::table::segment::{segments_file_name, PrunedRows, Segments};
+use crate::table::tiering;
+use crate::table::tiering::is_cold;
+use crate::table::validation::validate_rows;
+use crate::table::FromDisk;
 use log::info;
 use serde_json::Value;
+use std::cmp::Ordering;
 use std::collections::hash_map::Entry;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 use std::io::{Error, ErrorKind, SeekFrom};
-use std::path::PathBuf;
-use std::sync::Arc;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
 use std::u64;
-use tokio::fs::{create_dir_all, File};
 use tokio::io;
-use tokio::io::{AsyncSeekExt, AsyncWriteExt, BufStream};
 
 fn add_extension(file_name: &str) -> String {
     format!("{}.dsto", file_name)
 }
 
+/// The tables whose stats were reconciled with their indexes since the instance started.
+static RECONCILED_TABLES: OnceLock<Mutex<HashSet<PathBuf>>> = OnceLock::new();
+
+/// Returns `true` only the first time it's called for a table since the instance started.
+fn is_first_load(table_path: &Path) -> bool {
+    RECONCILED_TABLES
+        .get_or_init(Default::default)
+        .lock()
+        .unwrap()
+        .insert(table_path.to_path_buf())
+}
+
+/// Checks that the partition key is on a column of the table whose values are hashed, with a valid
+/// number of buckets, and that it doesn't change the key of the table, since the rows already in a
+/// bucket would be in the wrong one.
+fn check_partition_key(
+    name: &str,
+    schema: &SchemaManifest,
+    partition_key: &PartitionKey,
+) -> io::Result<()> {
+    if schema
+        .options
+        .partition_key
+        .as_ref()
+        .is_some_and(|k| k != partition_key)
+    {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("The partition key of table {} can't be changed", name),
+        ));
+    }
+
+    let columns = schema.columns();
+    let Some(column) = columns.iter().find(|c| c.name == partition_key.column) else {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "The partition key of table {} is on column {} which does not exist",
+                name, partition_key.column
+            ),
+        ));
+    };
+    if !supports_bloom_filter(column.ty) {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "Column {} has type {} which can't be a partition key",
+                column.name,
+                <&ColumnType as Into<&str>>::into(&column.ty)
+            ),
+        ));
+    }
+    if partition_key.buckets == 0 || partition_key.buckets > MAX_PARTITION_BUCKETS {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "The partition key of table {} must have between 1 and {} buckets",
+                name, MAX_PARTITION_BUCKETS
+            ),
+        ));
+    }
+
+    Ok(())
+}
+
 fn build_table_path(config: &Config, table_name: &str) -> PathBuf {
     let mut path_buf = PathBuf::new();
     path_buf.push(config.database_path.clone());
@@ -34,22 +136,184 @@ fn build_table_path(config: &Config, table_name: &str) -> PathBuf {
     path_buf
 }
 
+/// What the compaction of the partitions of a table removed.
+#[derive(Debug, Default)]
+pub struct CompactionSummary {
+    pub partitions: usize,
+    /// The number of deleted rows whose records were dropped.
+    pub rows: u64,
+    /// The number of bytes by which the files of the partitions shrank.
+    pub reclaimed_bytes: u64,
+}
+
+/// Returns the size in bytes of the files of the partition, without the nested partitions.
+async fn partition_size(partition: &Partition) -> io::Result<u64> {
+    let mut size = 0;
+    for entry in storage().read_dir(&partition.path).await? {
+        if !entry.is_dir {
+            size += storage().open(&entry.path, false).await?.len().await?;
+        }
+    }
+
+    Ok(size)
+}
+
+/// Returns the number of rows of the partition, which are the entries of its index.
+async fn partition_rows(partition: &Partition) -> io::Result<u64> {
+    create_file(&add_extension(".index"), &partition.path).await?;
+    let index_size = data_file_len(&add_extension(".index"), &partition.path).await?;
+
+    Ok(index_size / index_and_timestamp_size() as u64)
+}
+
+/// Returns the value of the column as it's read once written, unless it's null or has the wrong
+/// type, in which case it isn't written.
+fn written_value(column: &Column, value: &Value) -> Option<ColumnValue> {
+    let data = match (column.ty, value) {
+        (ColumnType::Integer | ColumnType::Float, Value::Number(number)) if number.is_i64() => {
+            i64::to_le_bytes(number.as_i64()?).to_vec()
+        }
+        (ColumnType::Float, Value::Number(number)) => f64::to_le_bytes(number.as_f64()?).to_vec(),
+        (ColumnType::String, Value::String(string)) => encode_string(string).to_vec(),
+        _ => return None,
+    };
+
+    Some(<ColumnValue as FromDisk>::from(column.ty, data))
+}
+
+/// Syncs the index and the column files written by an insert to disk.
+async fn sync_rows(index: &mut TableIndex, column_files: &mut [DataFile]) -> io::Result<()> {
+    index.file.sync_data().await?;
+    for column_file in column_files.iter_mut() {
+        column_file.sync_data().await?;
+    }
+
+    Ok(())
+}
+
+/// Returns the positions of the values of the column which repeat one of the values before them,
+/// ignoring the nulls.
+pub fn repeated_values(column: &Column, values: &[Value]) -> Vec<usize> {
+    let mut written_values: Vec<(ColumnValue, usize)> = values
+        .iter()
+        .enumerate()
+        .filter_map(|(position, value)| Some((written_value(column, value)?, position)))
+        .collect();
+    written_values.sort_by(|(a, a_position), (b, b_position)| {
+        compare_values(a, b)
+            .unwrap_or(Ordering::Equal)
+            .then(a_position.cmp(b_position))
+    });
+
+    let mut positions: Vec<usize> = written_values
+        .windows(2)
+        .filter(|pair| compare_values(&pair[0].0, &pair[1].0) == Some(Ordering::Equal))
+        .map(|pair| pair[1].1)
+        .collect();
+    positions.sort_unstable();
+
+    positions
+}
+
+/// Returns whether any of the sorted row ids is between `first` and `last`.
+fn has_row_between(row_ids: &[u64], first: u64, last: u64) -> bool {
+    let position = row_ids.partition_point(|row_id| *row_id < first);
+    row_ids.get(position).is_some_and(|row_id| *row_id <= last)
+}
+
 #[derive(Debug)]
 pub struct TableDefinition {
     config: Arc<Config>,
     name: String,
     columns: Vec<Column>,
+    encodings: HashMap<String, ColumnEncoding>,
+    bloom_filters: HashSet<String>,
+    indexed_columns: HashSet<String>,
+    unique_columns: HashSet<String>,
+    options: TableOptions,
 }
 
 impl TableDefinition {
+    /// Creates the table, or adds the columns which it doesn't have yet if it exists.
+    ///
+    /// The columns missing from `encodings` are stored raw, the columns in `bloom_filters` keep a
+    /// bloom filter of the values of each segment and the values of the columns in `unique_columns`
+    /// can't be repeated. The `options` which are set replace the ones of the table.
     pub async fn create(
         config: Arc<Config>,
         name: String,
         columns: Vec<Column>,
+        encodings: HashMap<String, ColumnEncoding>,
+        bloom_filters: HashSet<String>,
+        unique_columns: HashSet<String>,
+        options: TableOptions,
     ) -> io::Result<Self> {
+        if options.ttl_secs == Some(0) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("The TTL of table {} must be positive", name),
+            ));
+        }
+        if options.partition_interval_secs == Some(0) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("The partition interval of table {} must be positive", name),
+            ));
+        }
+        for column in columns.iter() {
+            if bloom_filters.contains(&column.name) && !supports_bloom_filter(column.ty) {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!(
+                        "Column {} has type {} which can't have a bloom filter",
+                        column.name,
+                        <&ColumnType as Into<&str>>::into(&column.ty)
+                    ),
+                ));
+            }
+
+            let encoding = encodings.get(&column.name).copied().unwrap_or_default();
+            if !encoding.supports(column.ty) {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!(
+                        "Column {} has type {} which can't be {} encoded",
+                        column.name,
+                        <&ColumnType as Into<&str>>::into(&column.ty),
+                        <&ColumnEncoding as Into<&str>>::into(&encoding)
+                    ),
+                ));
+            }
+        }
+
         let table_path = build_table_path(&config, &name);
+        let mut schema = match storage().exists(&table_path).await? {
+            true => read_schema(&table_path).await?,
+            false => SchemaManifest::default(),
+        };
 
-        create_dir_all(&table_path).await?;
+        for column in columns.iter() {
+            schema.add(
+                column,
+                encodings.get(&column.name).copied().unwrap_or_default(),
+                bloom_filters.contains(&column.name),
+                unique_columns.contains(&column.name),
+            )?;
+        }
+        // The rows are routed to the shards by the value of the unique column, so that each value
+        // is checked by a single instance.
+        if schema.unique_columns().len() > 1 {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("Table {} can have only one unique column", name),
+            ));
+        }
+        if let Some(partition_key) = &options.partition_key {
+            check_partition_key(&name, &schema, partition_key)?;
+        }
+        schema.options.update(options);
+
+        storage().create_dir_all(&table_path).await?;
 
         create_file(&add_extension(".index"), &table_path).await?;
         create_file(&add_extension(".stats"), &table_path).await?;
@@ -58,38 +322,172 @@ impl TableDefinition {
             let column_file_name: String = column.into();
             create_file(&add_extension(&column_file_name), &table_path).await?;
         }
+        write_schema(&table_path, &schema).await?;
 
         info!("Created table {name} with {} columns", columns.len());
 
         Ok(Self {
             config: config.clone(),
             name,
-            columns,
+            columns: schema.columns(),
+            encodings: schema.encodings(),
+            bloom_filters: schema.bloom_filters(),
+            indexed_columns: schema.indexed_columns(),
+            unique_columns: schema.unique_columns(),
+            options: schema.options,
         })
     }
 
+    pub async fn exists(config: &Config, name: &str) -> io::Result<bool> {
+        storage().exists(&build_table_path(config, name)).await
+    }
+
     pub async fn open(config: Arc<Config>, name: String) -> io::Result<Self> {
         let table_path = build_table_path(&config, &name);
 
+        let schema = read_schema(&table_path).await?;
+
         info!("Opened table {name}");
 
         Ok(Self {
             config: config.clone(),
             name,
-            columns: get_columns(&table_path).await?,
+            columns: schema.columns(),
+            encodings: schema.encodings(),
+            bloom_filters: schema.bloom_filters(),
+            indexed_columns: schema.indexed_columns(),
+            unique_columns: schema.unique_columns(),
+            options: schema.options,
         })
     }
 
+    pub fn columns(&self) -> &Vec<Column> {
+        &self.columns
+    }
+
+    pub fn encoding(&self, column: &Column) -> ColumnEncoding {
+        self.encodings
+            .get(&column.name)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    pub fn has_bloom_filter(&self, column: &Column) -> bool {
+        self.bloom_filters.contains(&column.name)
+    }
+
+    pub fn has_secondary_index(&self, column: &Column) -> bool {
+        self.indexed_columns.contains(&column.name)
+    }
+
+    pub fn is_unique(&self, column: &Column) -> bool {
+        self.unique_columns.contains(&column.name)
+    }
+
+    /// Returns the durability of the inserts into the table, which is the one of the config unless
+    /// the table sets its own.
+    pub fn durability(&self) -> Durability {
+        self.options.durability.unwrap_or(self.config.durability)
+    }
+
+    /// Returns the age in seconds after which the rows of the table are dropped, which is the one
+    /// of the retention of the config unless the table sets its own TTL.
+    pub fn max_age_secs(&self) -> Option<u64> {
+        self.options
+            .ttl_secs
+            .or(self.config.retention.as_ref().and_then(|r| r.max_age_secs))
+    }
+
+    /// Returns the size in seconds of the time window covered by each partition of the table,
+    /// which is the one of the config unless the table sets its own.
+    ///
+    /// Changing it only affects the partitions created afterwards, since the window of each
+    /// partition is in the name of its directory.
+    pub fn partition_interval_secs(&self) -> u64 {
+        self.options
+            .partition_interval_secs
+            .unwrap_or(self.config.partition_interval_secs)
+    }
+
+    /// Returns the options set by the table.
+    pub fn options(&self) -> &TableOptions {
+        &self.options
+    }
+
+    /// Returns the key splitting the partitions of the table in hash buckets, if any.
+    pub fn partition_key(&self) -> Option<&PartitionKey> {
+        self.options.partition_key.as_ref()
+    }
+
+    /// Returns whether the rows of the partition may match the filter, which is false only if the
+    /// filter requires the partition key to equal values of other buckets.
+    fn bucket_may_match(&self, partition: &Partition, filter: Option<&Expression>) -> bool {
+        let (Some(bucket), Some(partition_key), Some(filter)) =
+            (partition.bucket, self.partition_key(), filter)
+        else {
+            return true;
+        };
+
+        filter.may_contain(&partition_key.column, &|value| {
+            bucket_of(value, partition_key.buckets).is_none_or(|b| b == bucket)
+        })
+    }
+
+    /// Returns the column whose values can't be repeated, if any.
+    pub fn unique_column(&self) -> Option<&Column> {
+        self.columns
+            .iter()
+            .find(|c| self.unique_columns.contains(&c.name))
+    }
+
+    /// Returns the names of all the tables in the database.
+    pub async fn list(config: &Config) -> io::Result<Vec<String>> {
+        let mut database_path = PathBuf::new();
+        database_path.push(config.database_path.clone());
+        database_path.push(config.database_name.clone());
+
+        let mut names = vec![];
+        if !storage().exists(&database_path).await? {
+            return Ok(names);
+        }
+
+        for entry in storage().read_dir(&database_path).await? {
+            // Directories starting with `__` are reserved for the database itself.
+            if entry.is_dir && !entry.name.starts_with("__") {
+                names.push(entry.name);
+            }
+        }
+        names.sort();
+
+        Ok(names)
+    }
+
+    pub async fn drop(config: Arc<Config>, name: String) -> io::Result<()> {
+        let table_path = build_table_path(&config, &name);
+
+        storage().remove_dir_all(&table_path).await?;
+        memtables().take(&name);
+        memtables().take_flushed_sequences(&name);
+
+        info!("Dropped table {name}");
+
+        Ok(())
+    }
+
     pub async fn load(self) -> io::Result<Table> {
         let table_path = build_table_path(&self.config, &self.name);
-        create_dir_all(&table_path).await?;
+        storage().create_dir_all(&table_path).await?;
 
-        let index_file = create_and_open_file(&add_extension(".index"), &table_path).await?;
         let stats_file = create_and_open_file(&add_extension(".stats"), &table_path).await?;
 
         info!("Loaded table {} in memory", self.name);
 
-        let stats = TableStats::from_file(stats_file).await?;
+        let mut stats = TableStats::from_file(stats_file).await?;
+        // The stats of the previous run might not have been synced before a crash, so we check
+        // them against the data once.
+        if is_first_load(&table_path) {
+            stats.reconcile(&table_path).await?;
+        }
         info!(
             "Table stats for {}: rows {}, next index: {}",
             self.name, stats.row_count, stats.next_index
@@ -98,11 +496,29 @@ impl TableDefinition {
         Ok(Table {
             definition: self,
             stats,
-            index: TableIndex::new(index_file),
+            scanned_rows: 0,
+            skipped_records: 0,
+            pruned_segments: 0,
+            pruned_partitions: 0,
+            sampler: None,
         })
     }
 }
 
+/// The number of lower bits of a row id holding the sequence of the row on its instance, whereas the
+/// upper bits hold the id of the instance.
+const ROW_SEQUENCE_BITS: u32 = 48;
+
+/// Builds the id of a row, which is unique in the cluster as long as the node ids are.
+fn row_id(node_id: u16, sequence: u64) -> u64 {
+    ((node_id as u64) << ROW_SEQUENCE_BITS) | sequence
+}
+
+/// Returns the sequence of the row on its instance.
+fn row_sequence(row_id: u64) -> u64 {
+    row_id & ((1 << ROW_SEQUENCE_BITS) - 1)
+}
+
 /// Struct representing the stats of the table.
 ///
 /// The structure of the stats file is as follows:
@@ -110,15 +526,13 @@ impl TableDefinition {
 /// - 8 bytes for storing the next index value
 #[derive(Debug)]
 pub struct TableStats {
-    file: BufStream<File>,
+    file: DataFile,
     row_count: u64,
     next_index: u64,
 }
 
 impl TableStats {
-    pub async fn from_file(file: File) -> io::Result<Self> {
-        let mut file = BufStream::new(file);
-
+    pub async fn from_file(mut file: DataFile) -> io::Result<Self> {
         // We try to read the row count or default it to 0.
         let mut row_count = [0u8; ColumnType::Integer.size()];
         read_or(&mut file, &mut row_count, &u64::to_le_bytes(0)).await?;
@@ -134,10 +548,72 @@ impl TableStats {
         })
     }
 
-    pub async fn increment(&mut self) -> io::Result<()> {
+    /// Accounts for a new row in memory, which is written to disk by [`TableStats::persist`].
+    pub fn increment(&mut self) {
         self.row_count += 1;
         self.next_index += 1;
+    }
 
+    pub async fn remove(&mut self, rows: u64) -> io::Result<()> {
+        self.row_count = self.row_count.saturating_sub(rows);
+
+        self.persist().await
+    }
+
+    /// Brings the stats up to date with the indexes of the hot partitions, so that new rows never
+    /// reuse the index of rows written after the stats were last synced.
+    async fn reconcile(&mut self, table_path: &Path) -> io::Result<()> {
+        let entry_size = index_and_timestamp_size() as u64;
+
+        let mut indexed_rows = 0;
+        let mut next_index = 0;
+        for partition in list_partitions(table_path).await? {
+            if is_cold(&partition).await? {
+                continue;
+            }
+
+            let mut index = match open_read_file(&add_extension(".index"), &partition.path).await {
+                Ok(index) => index,
+                Err(error) if error.kind() == ErrorKind::NotFound => continue,
+                Err(error) => return Err(error),
+            };
+
+            // A crash during a write might leave a partial entry at the end, which is ignored.
+            let entries = index.len().await? / entry_size;
+            if entries == 0 {
+                continue;
+            }
+            indexed_rows += entries.saturating_sub(count_deleted_rows(&partition.path).await?);
+
+            // Indexes are increasing within a partition, so the last entry has the highest one.
+            let mut last_index = [0u8; ColumnType::Integer.size()];
+            index
+                .seek(SeekFrom::Start((entries - 1) * entry_size))
+                .await?;
+            index.read_exact(&mut last_index).await?;
+            next_index = next_index.max(row_sequence(u64::from_le_bytes(last_index)) + 1);
+        }
+
+        if next_index <= self.next_index && indexed_rows <= self.row_count {
+            return Ok(());
+        }
+
+        info!(
+            "Reconciled the stats of {} with its indexes: rows {} -> {}, next index {} -> {}",
+            table_path.display(),
+            self.row_count,
+            self.row_count.max(indexed_rows),
+            self.next_index,
+            self.next_index.max(next_index)
+        );
+        self.row_count = self.row_count.max(indexed_rows);
+        self.next_index = self.next_index.max(next_index);
+        self.persist().await?;
+
+        self.file.sync_all().await
+    }
+
+    pub async fn persist(&mut self) -> io::Result<()> {
         self.file.seek(SeekFrom::Start(0)).await?;
         self.file
             .write_all(&u64::to_le_bytes(self.row_count))
@@ -147,151 +623,1316 @@ impl TableStats {
             .await?;
         self.file.flush().await?;
 
-        Ok(())
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+/// The index of a partition, whose entries are buffered in memory until flushed, so that a batch
+/// of rows is written with a single write.
+pub struct TableIndex {
+    file: DataFile,
+    entries: Vec<u8>,
+}
+
+impl TableIndex {
+    pub fn new(file: DataFile) -> Self {
+        Self {
+            file,
+            entries: vec![],
+        }
+    }
+
+    pub async fn seek_end(&mut self) -> io::Result<()> {
+        self.file.seek(SeekFrom::End(0)).await?;
+
+        Ok(())
+    }
+
+    /// Returns the timestamp of the last entry of the index, if any.
+    pub async fn last_timestamp(&mut self) -> io::Result<Option<u64>> {
+        let entry_size = index_and_timestamp_size() as u64;
+        let entries = self.file.len().await? / entry_size;
+        if entries == 0 {
+            return Ok(None);
+        }
+
+        let mut timestamp = [0u8; ColumnType::Integer.size()];
+        let timestamp_offset = entries * entry_size - ColumnType::Integer.size() as u64;
+        self.file.seek(SeekFrom::Start(timestamp_offset)).await?;
+        self.file.read_exact(&mut timestamp).await?;
+
+        Ok(Some(u64::from_le_bytes(timestamp)))
+    }
+
+    pub fn append(&mut self, row_id: u64, timestamp: u64) {
+        self.entries.extend_from_slice(&u64::to_le_bytes(row_id));
+        self.entries.extend_from_slice(&u64::to_le_bytes(timestamp));
+    }
+
+    pub async fn flush(&mut self) -> io::Result<()> {
+        self.file.write_all(&self.entries).await?;
+        self.entries.clear();
+
+        self.file.flush().await
+    }
+}
+
+pub struct Table {
+    definition: TableDefinition,
+    stats: TableStats,
+    scanned_rows: u64,
+    skipped_records: u64,
+    pruned_segments: u64,
+    pruned_partitions: u64,
+    /// The sampler of the rows read by the queries, if they read only a sample of the rows.
+    sampler: Option<Sampler>,
+}
+
+impl Table {
+    pub fn columns(&self) -> &Vec<Column> {
+        &self.definition.columns
+    }
+
+    /// Returns the number of rows of the table, including the ones buffered in its memtable.
+    pub fn row_count(&self) -> u64 {
+        self.stats.row_count + memtables().rows(&self.definition.name)
+    }
+
+    /// Syncs the stats to disk, so that they survive a crash of the machine.
+    pub async fn sync_stats(&mut self) -> io::Result<()> {
+        self.stats.file.sync_all().await
+    }
+
+    /// Returns the number of rows read from disk by the queries run on the table.
+    pub fn scanned_rows(&self) -> u64 {
+        self.scanned_rows
+    }
+
+    /// Returns the number of column records read by the queries run on the table without belonging
+    /// to the row being read, either because they belong to rows outside the time range or because
+    /// the row has no value in the column, in which case the record is read again for the next row.
+    pub fn skipped_records(&self) -> u64 {
+        self.skipped_records
+    }
+
+    /// Returns the number of segments which the queries run on the table didn't read, since their
+    /// values couldn't match the filter.
+    pub fn pruned_segments(&self) -> u64 {
+        self.pruned_segments
+    }
+
+    /// Returns the number of partitions which the queries run on the table didn't read, since
+    /// their bucket of the partition key couldn't match the filter.
+    pub fn pruned_partitions(&self) -> u64 {
+        self.pruned_partitions
+    }
+
+    /// Adds a secondary index of the column, which is built right away in the partitions which
+    /// aren't cold, and in the cold ones once they are queried.
+    pub async fn create_index(&mut self, column_name: &str) -> io::Result<()> {
+        let column = get_column(&self.definition.columns, column_name)?;
+        let mut schema = read_schema(self.table_path()).await?;
+        schema.set_indexed(&column);
+        write_schema(self.table_path(), &schema).await?;
+        self.definition.indexed_columns.insert(column.name.clone());
+
+        let decoder = self
+            .read_decoders(std::slice::from_ref(&column))
+            .await?
+            .remove(&column.name)
+            .unwrap_or_default();
+        let mut indexed_partitions = 0;
+        for partition in list_partitions(self.table_path()).await? {
+            if is_cold(&partition).await? {
+                continue;
+            }
+
+            let rows = partition_rows(&partition).await?;
+            SecondaryIndex::read(&partition.path, &column, decoder.clone(), rows).await?;
+            indexed_partitions += 1;
+        }
+        info!(
+            "Created the secondary index of column {} in {} partitions of table {}",
+            column.name, indexed_partitions, self.definition.name
+        );
+
+        Ok(())
+    }
+
+    /// Returns the values which are already stored in the column, looked up in its secondary index.
+    ///
+    /// The cold partitions aren't checked, since their files would have to be downloaded.
+    pub async fn existing_values(
+        &self,
+        column_name: &str,
+        values: &[Value],
+    ) -> io::Result<Vec<Value>> {
+        let column = get_column(&self.definition.columns, column_name)?;
+        let decoder = self
+            .read_decoders(std::slice::from_ref(&column))
+            .await?
+            .remove(&column.name)
+            .unwrap_or_default();
+        let mut remaining_values: Vec<(ColumnValue, &Value)> = values
+            .iter()
+            .filter_map(|value| Some((written_value(&column, value)?, value)))
+            .collect();
+        let mut existing_values = vec![];
+        for partition in list_partitions(self.table_path()).await? {
+            if remaining_values.is_empty() {
+                break;
+            }
+            if is_cold(&partition).await? {
+                continue;
+            }
+
+            let rows = partition_rows(&partition).await?;
+            let secondary_index =
+                SecondaryIndex::read(&partition.path, &column, decoder.clone(), rows).await?;
+            let deleted_rows = read_deleted_rows(&partition.path).await?;
+            let mut not_found_values = vec![];
+            for (written_value, value) in remaining_values {
+                let range = (
+                    Bound::Included(written_value.clone()),
+                    Bound::Included(written_value.clone()),
+                );
+                let row_ids = secondary_index.lookup(&partition.path, &range).await?;
+                match row_ids.iter().any(|row_id| !deleted_rows.contains(row_id)) {
+                    true => existing_values.push(value.clone()),
+                    false => not_found_values.push((written_value, value)),
+                }
+            }
+            remaining_values = not_found_values;
+        }
+
+        Ok(existing_values)
+    }
+
+    /// Makes the queries run on the table read only a sample of its rows.
+    pub fn sample(&mut self, sample: Sample) {
+        self.sampler = Some(Sampler::new(sample.fraction(self.stats.row_count)));
+    }
+
+    /// Explains how the query would be run on the table, without running it.
+    pub async fn explain(
+        &self,
+        columns: Vec<String>,
+        group_by_columns: Option<Vec<String>>,
+        filter: Option<String>,
+        order_by: Option<Vec<String>>,
+        distinct: bool,
+        time_range: TimeRange,
+    ) -> io::Result<TableExplanation> {
+        let plan = QueryPlan::new(
+            &self.definition.columns,
+            columns,
+            group_by_columns,
+            filter,
+            order_by,
+            distinct,
+        )?;
+
+        let partitions = list_partitions(self.table_path()).await?;
+        let scanned_partitions = partitions
+            .iter()
+            .filter(|p| {
+                p.overlaps(&time_range) && self.definition.bucket_may_match(p, plan.filter.as_ref())
+            })
+            .count();
+        let table_rows = self.stats.row_count;
+        let counted_from_stats = plan.counts_all_rows(&time_range);
+        let estimated_rows = match (counted_from_stats, partitions.len()) {
+            (true, _) | (false, 0) => 0,
+            (false, len) => table_rows * scanned_partitions as u64 / len as u64,
+        };
+
+        Ok(TableExplanation {
+            table: self.definition.name.clone(),
+            scanned_columns: match counted_from_stats {
+                true => vec![],
+                false => plan.file_columns().iter().map(|c| c.name.clone()).collect(),
+            },
+            filter: plan.filter.as_ref().map(|f| f.to_string()),
+            aggregates: plan
+                .aggregate_columns
+                .iter()
+                .map(|a| String::from(a.clone()))
+                .collect(),
+            group_by: plan.group_by.iter().map(|(e, _)| e.to_string()).collect(),
+            order_by: plan.order_by.iter().map(|o| o.to_string()).collect(),
+            distinct: plan.distinct,
+            counted_from_stats,
+            partitions: partitions.len(),
+            scanned_partitions,
+            table_rows,
+            estimated_rows,
+        })
+    }
+
+    /// Inserts the rows with the timestamp, which defaults to the current time of the clock.
+    ///
+    /// The sequence of an insert sent by the master is kept with its rows while they are buffered,
+    /// so that it's recorded as applied only once they are written.
+    pub async fn insert(
+        &mut self,
+        columns: Vec<String>,
+        values: Vec<Vec<serde_json::Value>>,
+        timestamp: Option<u64>,
+        sequence: Option<u64>,
+    ) -> io::Result<()> {
+        let columns = parse_and_validate_columns(&self.definition.columns, &columns)?;
+
+        // We validate the batch upfront, so that an invalid row doesn't leave a partial batch.
+        if let Some(error) = validate_rows(&columns, &values).first() {
+            return Err(Error::new(ErrorKind::InvalidData, error.to_string()));
+        }
+        self.check_unique_values(&columns, &values).await?;
+
+        let timestamp = timestamp.unwrap_or_else(|| clock().now_secs());
+        let buckets = self.bucket_rows(&columns, values)?;
+
+        // The rows are buffered in the memtable, if configured, and written once flushed. Only the
+        // rows of the tables which aren't synced are buffered, since they are lost on a crash, and
+        // not the ones of the tables with a unique column, whose values are looked up in the files.
+        if let Some(memtable) = &self.definition.config.memtable {
+            if self.definition.durability() == Durability::None
+                && self.definition.unique_column().is_none()
+            {
+                let last_bucket = buckets.keys().next_back().copied();
+                let mut buffered_rows = 0;
+                for (bucket, values) in buckets {
+                    let insert = BufferedInsert {
+                        columns: columns.clone(),
+                        bucket,
+                        values,
+                        timestamp,
+                        sequences: sequence
+                            .filter(|_| Some(bucket) == last_bucket)
+                            .into_iter()
+                            .collect(),
+                    };
+                    buffered_rows = memtables().push(&self.definition.name, insert);
+                }
+                if buffered_rows >= memtable.max_rows {
+                    self.flush_memtable().await?;
+                }
+
+                return Ok(());
+            }
+        }
+
+        // The buffered rows are written first, so that the rows keep the order of their inserts.
+        self.flush_memtable().await?;
+        for (bucket, values) in buckets {
+            self.write_rows(&columns, bucket, values, timestamp).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes the rows buffered in the memtable of the table to its files, in the order in which
+    /// they were buffered.
+    ///
+    /// The inserts which aren't written because of an error are buffered again.
+    pub async fn flush_memtable(&mut self) -> io::Result<()> {
+        let mut inserts: VecDeque<BufferedInsert> = memtables().take(&self.definition.name).into();
+        let mut flushed_rows = 0;
+        while let Some(insert) = inserts.pop_front() {
+            let rows = insert.values.len();
+            if let Err(error) = self
+                .write_rows(
+                    &insert.columns,
+                    insert.bucket,
+                    insert.values,
+                    insert.timestamp,
+                )
+                .await
+            {
+                memtables().restore(&self.definition.name, inserts.into());
+                return Err(error);
+            }
+            memtables().add_flushed_sequences(&self.definition.name, &insert.sequences);
+            flushed_rows += rows;
+        }
+
+        if flushed_rows > 0 {
+            info!(
+                "Flushed {} rows from the memtable of table {}",
+                flushed_rows, self.definition.name
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Splits the rows by the bucket of their partition key, if any.
+    fn bucket_rows(
+        &self,
+        columns: &[Column],
+        values: Vec<Vec<Value>>,
+    ) -> io::Result<BTreeMap<Option<u32>, Vec<Vec<Value>>>> {
+        let mut buckets: BTreeMap<Option<u32>, Vec<Vec<Value>>> = BTreeMap::new();
+        match self.definition.partition_key() {
+            Some(partition_key) => {
+                let column = get_column(&self.definition.columns, &partition_key.column)?;
+                let position = columns.iter().position(|c| *c == column);
+                for value in values {
+                    // The rows without a value of the key are in the first bucket.
+                    let bucket = position
+                        .and_then(|p| written_value(&column, &value[p]))
+                        .and_then(|v| bucket_of(&v, partition_key.buckets))
+                        .unwrap_or(0);
+                    buckets.entry(Some(bucket)).or_default().push(value);
+                }
+            }
+            None => {
+                buckets.insert(None, values);
+            }
+        }
+
+        Ok(buckets)
+    }
+
+    /// Writes the rows of the bucket with the timestamp to the partition of its time window.
+    async fn write_rows(
+        &mut self,
+        columns: &Vec<Column>,
+        bucket: Option<u32>,
+        values: Vec<Vec<Value>>,
+        timestamp: u64,
+    ) -> io::Result<()> {
+        let partition = Partition::for_timestamp(
+            self.table_path(),
+            timestamp,
+            self.definition.partition_interval_secs(),
+            bucket,
+        );
+
+        self.insert_into(&partition, columns, values, timestamp)
+            .await
+    }
+
+    /// Inserts the rows with the timestamp into the partition.
+    async fn insert_into(
+        &mut self,
+        partition: &Partition,
+        columns: &Vec<Column>,
+        values: Vec<Vec<Value>>,
+        timestamp: u64,
+    ) -> io::Result<()> {
+        partition.create().await?;
+
+        let mut index =
+            TableIndex::new(create_and_open_file(&add_extension(".index"), &partition.path).await?);
+        let mut column_files = self.open_column_files(partition, columns, false).await?;
+
+        // The rows of a partition are appended in the order of their timestamps, which the scans
+        // rely on, so a timestamp older than the last one of the partition, e.g. assigned by the
+        // master before a concurrent insert, is moved forward to it.
+        let timestamp = match index.last_timestamp().await? {
+            Some(last_timestamp) => timestamp.max(last_timestamp),
+            None => timestamp,
+        };
+
+        // The new values are added to the dictionaries before the records referring to them are
+        // written, so that a crash never leaves a record whose value is unknown.
+        let mut dictionaries = self.read_dictionaries(columns).await?;
+        let mut blocks: HashMap<String, DeltaBlock> = columns
+            .iter()
+            .filter(|c| self.definition.encoding(c) == ColumnEncoding::Delta)
+            .map(|c| (c.name.clone(), DeltaBlock::new()))
+            .collect();
+        for value in values.iter() {
+            for (inner_value, column) in value.iter().zip(columns.iter()) {
+                match inner_value {
+                    Value::String(string) => {
+                        if let Some(dictionary) = dictionaries.get_mut(&column.name) {
+                            dictionary.insert(&encode_string(string))?;
+                        }
+                    }
+                    Value::Number(number)
+                        if !number.is_i64() && blocks.contains_key(&column.name) =>
+                    {
+                        return Err(Error::new(
+                            ErrorKind::InvalidData,
+                            format!(
+                                "Column {} is delta encoded but you supplied a float",
+                                column.name
+                            ),
+                        ));
+                    }
+                    _ => {}
+                }
+            }
+        }
+        for dictionary in dictionaries.values_mut() {
+            dictionary.persist(self.table_path()).await?;
+        }
+        let dictionaries: HashMap<String, Arc<Dictionary>> = dictionaries
+            .into_iter()
+            .map(|(name, dictionary)| (name, Arc::new(dictionary)))
+            .collect();
+
+        // The segments decode the values of the records to keep the bounds of their values.
+        let mut segments = Vec::with_capacity(columns.len());
+        for (column, column_file) in columns.iter().zip(column_files.iter_mut()) {
+            let decoder = match dictionaries.get(&column.name) {
+                Some(dictionary) => ColumnDecoder::Dictionary(dictionary.clone()),
+                None if blocks.contains_key(&column.name) => ColumnDecoder::Delta,
+                None => ColumnDecoder::Raw,
+            };
+            let file_len = column_file.len().await?;
+            let bloom_filter = self.definition.has_bloom_filter(column);
+            segments.push(
+                Segments::read(&partition.path, column, decoder, file_len, bloom_filter).await?,
+            );
+        }
+
+        // The values of the indexed columns are added to their secondary indexes once the rows are
+        // written, which also cover the rows that are null in them.
+        let rows = values.len() as u64;
+        let indexed_columns: Vec<Column> = self
+            .definition
+            .columns
+            .iter()
+            .filter(|c| self.definition.has_secondary_index(c))
+            .cloned()
+            .collect();
+        let mut decoders = self.read_decoders(&indexed_columns).await?;
+        let partition_rows = partition_rows(partition).await?;
+        let mut secondary_indexes = Vec::with_capacity(indexed_columns.len());
+        for column in indexed_columns.iter() {
+            let decoder = decoders.remove(&column.name).unwrap_or_default();
+            let secondary_index =
+                SecondaryIndex::read(&partition.path, column, decoder, partition_rows).await?;
+            let position = columns.iter().position(|c| c == column);
+            let entries: Vec<(ColumnValue, u64)> = values
+                .iter()
+                .enumerate()
+                .filter_map(|(row, value)| {
+                    let value = written_value(column, &value[position?])?;
+                    Some((value, self.next_row_id() + row as u64))
+                })
+                .collect();
+            secondary_indexes.push((secondary_index, entries));
+        }
+
+        // We position ourselves at the end of the index.
+        index.seek_end().await?;
+
+        // The rows of the batch have consecutive ids, and are null in the columns which aren't
+        // inserted.
+        let first_row_id = self.next_row_id();
+        let nulls: Vec<(Column, Vec<bool>)> = self
+            .definition
+            .columns
+            .iter()
+            .map(|column| {
+                let position = columns.iter().position(|c| c == column);
+                let nulls = values
+                    .iter()
+                    .map(|value| position.is_none_or(|p| value[p].is_null()))
+                    .collect();
+                (column.clone(), nulls)
+            })
+            .collect();
+
+        // For each value we insert into the file.
+        for value in values {
+            // We add an entry in the index for each set of columns.
+            index.append(self.next_row_id(), timestamp);
+
+            for (((inner_value, column), column_file), segments) in value
+                .into_iter()
+                .zip(columns.iter())
+                .zip(column_files.iter_mut())
+                .zip(segments.iter_mut())
+            {
+                // The strings of the dictionary encoded columns are written as their ids, whereas
+                // the integers of the delta encoded columns are written in a block once all the
+                // rows are encoded.
+                match (
+                    dictionaries.get(&column.name),
+                    blocks.get_mut(&column.name),
+                    inner_value,
+                ) {
+                    (Some(dictionary), _, Value::String(string)) => {
+                        let id = u32::to_le_bytes(dictionary.id(&encode_string(&string))?);
+                        self.write_value(column, column_file, segments, timestamp, &id)
+                            .await?;
+                    }
+                    (_, Some(block), Value::Number(number)) => {
+                        let value = number.as_i64().unwrap_or_default();
+                        let size = block.push(self.next_row_id(), timestamp, value);
+                        record_column_write(&self.definition.name, &column.name, size as u64);
+                    }
+                    (_, _, inner_value) => {
+                        self.insert_value(timestamp, column, column_file, segments, inner_value)
+                            .await?;
+                    }
+                }
+            }
+
+            self.stats.increment();
+        }
+
+        for ((column, column_file), segments) in columns
+            .iter()
+            .zip(column_files.iter_mut())
+            .zip(segments.iter_mut())
+        {
+            if let Some(block) = blocks.get(&column.name).filter(|b| !b.is_empty()) {
+                let encoded_block = block.encode();
+                column_file.write_all(&encoded_block).await?;
+                let mut records = vec![];
+                decode_blocks(&encoded_block, &mut records)?;
+                segments.append_block(encoded_block.len() as u64, &records)?;
+            }
+        }
+
+        // We flush all files to make sure data is flushed to disk from the buffer.
+        index.flush().await?;
+        for column_file in column_files.iter_mut() {
+            column_file.flush().await?;
+        }
+        let durability = self.definition.durability();
+        if durability == Durability::Always {
+            sync_rows(&mut index, &mut column_files).await?;
+        }
+
+        // The null markers are written once the records are, so that they never mark a value
+        // which isn't written.
+        for (column, nulls) in nulls.iter().filter(|(_, n)| !n.is_empty()) {
+            append_null_bitmap(&partition.path, column, first_row_id, nulls).await?;
+        }
+        for segments in segments.iter_mut() {
+            segments.persist(&partition.path).await?;
+        }
+        for (mut secondary_index, entries) in secondary_indexes {
+            secondary_index
+                .append(&partition.path, entries, rows)
+                .await?;
+        }
+
+        // Once the whole batch has been written, we persist the table stats once.
+        self.stats.persist().await?;
+        if durability == Durability::Batch {
+            sync_rows(&mut index, &mut column_files).await?;
+        }
+        if durability != Durability::None {
+            self.stats.file.sync_data().await?;
+        }
+
+        Ok(())
+    }
+
+    pub async fn query(
+        &mut self,
+        columns: Vec<String>,
+        group_by_columns: Option<Vec<String>>,
+        filter: Option<String>,
+        order_by: Option<Vec<String>>,
+        distinct: bool,
+        time_range: TimeRange,
+    ) -> io::Result<(QueryResult, QuerySchema)> {
+        let plan = QueryPlan::new(
+            &self.definition.columns,
+            columns,
+            group_by_columns,
+            filter,
+            order_by,
+            distinct,
+        )?;
+        let schema = plan.schema();
+
+        if plan.counts_all_rows(&time_range) && self.sampler.is_none() {
+            return Ok((self.count_rows(plan.aggregate_columns), schema));
+        }
+
+        // We query the rows of each partition, and aggregate them afterwards if needed.
+        // Partitions outside the time range, or in the buckets of other values of the partition
+        // key, are skipped before opening any of their files.
+        let decoders = self.read_decoders(&plan.file_columns()).await?;
+        let mut rows = vec![];
+        let mut distinct_rows = plan.distinct_rows();
+        for partition in list_partitions(self.table_path()).await? {
+            if !partition.overlaps(&time_range) {
+                continue;
+            }
+            if !self
+                .definition
+                .bucket_may_match(&partition, plan.filter.as_ref())
+            {
+                self.pruned_partitions += 1;
+                continue;
+            }
+
+            self.ensure_hot(&partition).await?;
+            rows.extend(
+                self.query_values(
+                    &partition,
+                    &plan.columns,
+                    &decoders,
+                    &time_range,
+                    plan.filter.as_ref(),
+                    distinct_rows.as_mut(),
+                )
+                .await?,
+            );
+        }
+        let memtable_rows = self.query_memtable(
+            &plan.columns,
+            &time_range,
+            plan.filter.as_ref(),
+            distinct_rows.as_mut(),
+        );
+        rows.extend(memtable_rows);
+
+        Ok((plan.finish(rows)?, schema))
+    }
+
+    /// Marks the rows matching the filter within the time range as deleted and returns how many were
+    /// deleted.
+    ///
+    /// The deleted rows are listed in a tombstone file of their partition and skipped by the scans,
+    /// whereas their records stay in the files of the partition.
+    pub async fn delete(&mut self, filter: String, time_range: TimeRange) -> io::Result<u64> {
+        // The buffered rows are flushed, since the deleted rows are listed by their id.
+        self.flush_memtable().await?;
+
+        let plan = QueryPlan::new(
+            &self.definition.columns,
+            vec![ROW_ID_COLUMN.to_string()],
+            None,
+            Some(filter),
+            None,
+            false,
+        )?;
+
+        let decoders = self.read_decoders(&plan.file_columns()).await?;
+        let mut deleted_rows = 0;
+        for partition in list_partitions(self.table_path()).await? {
+            if !partition.overlaps(&time_range)
+                || !self
+                    .definition
+                    .bucket_may_match(&partition, plan.filter.as_ref())
+            {
+                continue;
+            }
+
+            self.ensure_hot(&partition).await?;
+            let tombstones: Vec<Tombstone> = self
+                .query_values(
+                    &partition,
+                    &plan.columns,
+                    &decoders,
+                    &time_range,
+                    plan.filter.as_ref(),
+                    None,
+                )
+                .await?
+                .iter()
+                .map(|row| Tombstone {
+                    index_id: row.index_id(),
+                    timestamp: row.timestamp(),
+                })
+                .collect();
+            if tombstones.is_empty() {
+                continue;
+            }
+
+            append_tombstones(&partition.path, &tombstones).await?;
+            deleted_rows += tombstones.len() as u64;
+        }
+
+        if deleted_rows > 0 {
+            self.stats.remove(deleted_rows).await?;
+            info!(
+                "Deleted {} rows from table {}",
+                deleted_rows, self.definition.name
+            );
+        }
+
+        Ok(deleted_rows)
+    }
+
+    /// Removes all the rows of the table, keeping its columns, and returns how many were removed.
+    ///
+    /// The partitions with a time window are deleted, whereas the files of the root partition, whose
+    /// names also define the columns of the table, are emptied.
+    pub async fn truncate(&mut self) -> io::Result<u64> {
+        // The discarded inserts count as applied, like the ones whose rows were written.
+        let mut buffered_rows = 0;
+        for insert in memtables().take(&self.definition.name) {
+            buffered_rows += insert.values.len();
+            memtables().add_flushed_sequences(&self.definition.name, &insert.sequences);
+        }
+
+        for partition in list_partitions(self.table_path()).await? {
+            if partition.window.is_some() {
+                if let Some(store) = self.object_store() {
+                    if is_cold(&partition).await? {
+                        let key_prefix = self.object_key_prefix(&partition);
+                        tiering::delete(&store, &key_prefix, &partition).await?;
+                    }
+                }
+
+                storage().remove_dir_all(&partition.path).await?;
+                continue;
+            }
+
+            let mut file_names = vec![add_extension(".index")];
+            for column in self.definition.columns.iter() {
+                let column_file_name: String = column.into();
+                file_names.push(add_extension(&column_file_name));
+            }
+            for file_name in file_names {
+                let file = storage()
+                    .open(&partition.path.join(file_name), true)
+                    .await?;
+                let mut file = DataFile::from_file(file).await?;
+                file.truncate().await?;
+                file.sync_all().await?;
+            }
+
+            let mut removed_file_names = vec![DELETED_ROWS_FILE_NAME.to_string()];
+            for column in self.definition.columns.iter() {
+                removed_file_names.push(null_bitmap_file_name(column));
+                removed_file_names.push(segments_file_name(column));
+                removed_file_names.push(bloom_filter_file_name(column));
+                removed_file_names.push(secondary_index_file_name(column));
+            }
+            for file_name in removed_file_names {
+                if let Err(error) = storage().remove_file(&partition.path.join(file_name)).await {
+                    if error.kind() != ErrorKind::NotFound {
+                        return Err(error);
+                    }
+                }
+            }
+        }
+
+        // The next index is kept, so that the ids of the removed rows are never reused.
+        let truncated_rows = self.stats.row_count;
+        self.stats.remove(truncated_rows).await?;
+        let truncated_rows = truncated_rows + buffered_rows as u64;
+        info!(
+            "Truncated table {}, removing {} rows",
+            self.definition.name, truncated_rows
+        );
+
+        Ok(truncated_rows)
+    }
+
+    /// Drops all the rows whose timestamp is older than `cutoff` and returns how many were dropped.
+    ///
+    /// Partitions which are entirely expired are deleted, whereas the ones straddling the cutoff
+    /// have their expired records dropped.
+    pub async fn apply_retention(&mut self, cutoff: u64) -> io::Result<u64> {
+        self.flush_memtable().await?;
+
+        let mut dropped_rows = 0;
+        for partition in list_partitions(self.table_path()).await? {
+            if partition.is_expired(cutoff) {
+                match self.object_store() {
+                    Some(store) if is_cold(&partition).await? => {
+                        let key_prefix = self.object_key_prefix(&partition);
+                        dropped_rows += tiering::delete(&store, &key_prefix, &partition).await?;
+                    }
+                    _ => {
+                        let index_size = data_file_len(&add_extension(".index"), &partition.path)
+                            .await
+                            .unwrap_or(0);
+                        let deleted_rows = count_deleted_rows(&partition.path).await?;
+                        dropped_rows += (index_size / index_and_timestamp_size() as u64)
+                            .saturating_sub(deleted_rows);
+                    }
+                }
+
+                storage().remove_dir_all(&partition.path).await?;
+            } else if partition.window.is_none_or(|(start, _)| start < cutoff) {
+                self.ensure_hot(&partition).await?;
+                dropped_rows += self.drop_expired_records(&partition, cutoff).await?;
+            }
+        }
+
+        if dropped_rows > 0 {
+            self.stats.remove(dropped_rows).await?;
+            info!(
+                "Dropped {} expired rows from table {}",
+                dropped_rows, self.definition.name
+            );
+        }
+
+        Ok(dropped_rows)
+    }
+
+    /// Moves the partitions whose rows are all older than `cutoff` to the object store and returns
+    /// how many were moved.
+    pub async fn evict_cold_partitions(&mut self, cutoff: u64) -> io::Result<usize> {
+        let Some(store) = self.object_store() else {
+            return Ok(0);
+        };
+        self.flush_memtable().await?;
+
+        let mut evicted_partitions = 0;
+        for partition in list_partitions(self.table_path()).await? {
+            // The root partition is never evicted, since it also contains the table definition.
+            if !partition.is_expired(cutoff) || is_cold(&partition).await? {
+                continue;
+            }
+
+            let key_prefix = self.object_key_prefix(&partition);
+            tiering::evict(&store, &key_prefix, &partition).await?;
+            evicted_partitions += 1;
+        }
+
+        if evicted_partitions > 0 {
+            info!(
+                "Moved {} partitions of table {} to cold storage",
+                evicted_partitions, self.definition.name
+            );
+        }
+
+        Ok(evicted_partitions)
+    }
+
+    /// Merges the runs of adjacent partitions with less than `min_rows` rows whose window ended
+    /// before `cutoff`, until each merged partition has at least `min_rows` rows, and returns how
+    /// many partitions were merged.
+    ///
+    /// Cold partitions are never merged, since their files are in the object store.
+    pub async fn merge_small_partitions(
+        &mut self,
+        min_rows: u64,
+        cutoff: u64,
+    ) -> io::Result<usize> {
+        self.flush_memtable().await?;
+
+        let mut partitions = list_partitions(self.table_path()).await?;
+        if remove_merged_partitions(&partitions).await? > 0 {
+            partitions = list_partitions(self.table_path()).await?;
+        }
+
+        // The partitions of different buckets are never merged together.
+        let mut buckets: Vec<Option<u32>> = partitions.iter().map(|p| p.bucket).collect();
+        buckets.sort();
+        buckets.dedup();
+
+        let mut runs = vec![];
+        for bucket in buckets {
+            let (mut run, mut run_rows) = (vec![], 0);
+            for partition in partitions.iter().filter(|p| p.bucket == bucket).cloned() {
+                // The root partition is never merged, since it also contains the table definition.
+                let mergeable = partition.window.is_some()
+                    && partition.is_expired(cutoff)
+                    && (self.object_store().is_none() || !is_cold(&partition).await?);
+                let rows = match mergeable {
+                    true => {
+                        let index_size = data_file_len(&add_extension(".index"), &partition.path)
+                            .await
+                            .unwrap_or(0);
+                        index_size / index_and_timestamp_size() as u64
+                    }
+                    // The partitions which can't be merged break the runs of adjacent partitions.
+                    false => u64::MAX,
+                };
+
+                if rows >= min_rows {
+                    runs.push(std::mem::take(&mut run));
+                    run_rows = 0;
+                    continue;
+                }
+
+                run.push(partition);
+                run_rows += rows;
+                if run_rows >= min_rows {
+                    runs.push(std::mem::take(&mut run));
+                    run_rows = 0;
+                }
+            }
+            runs.push(run);
+        }
+
+        let mut file_names = vec![add_extension(".index"), DELETED_ROWS_FILE_NAME.to_string()];
+        for column in self.definition.columns.iter() {
+            let column_file_name: String = column.into();
+            file_names.push(add_extension(&column_file_name));
+            file_names.push(null_bitmap_file_name(column));
+        }
+
+        let mut merged_partitions = 0;
+        for run in runs.into_iter().filter(|r| r.len() > 1) {
+            let merged_partition = merge_partitions(&run, &file_names).await?;
+            info!(
+                "Merged {} partitions of table {} into {}",
+                run.len(),
+                self.definition.name,
+                merged_partition.path.display()
+            );
+            merged_partitions += run.len();
+        }
+
+        Ok(merged_partitions)
+    }
+
+    /// Compacts the partitions with at least `min_deleted_rows` deleted rows, which are also at least
+    /// `min_deleted_fraction` of their rows, dropping the records of the deleted rows from their
+    /// files.
+    ///
+    /// Cold partitions are never compacted, since their files are in the object store.
+    pub async fn compact(
+        &self,
+        min_deleted_rows: u64,
+        min_deleted_fraction: f64,
+    ) -> io::Result<CompactionSummary> {
+        let mut summary = CompactionSummary::default();
+        let mut dropped_records = 0;
+        for partition in list_partitions(self.table_path()).await? {
+            if is_cold(&partition).await? {
+                continue;
+            }
+
+            let deleted_rows = count_deleted_rows(&partition.path).await?;
+            let rows = partition_rows(&partition).await?;
+            if deleted_rows == 0
+                || deleted_rows < min_deleted_rows
+                || (deleted_rows as f64) < rows as f64 * min_deleted_fraction
+            {
+                continue;
+            }
+
+            let size = partition_size(&partition).await?;
+            dropped_records += self.compact_partition(&partition).await?;
+            summary.partitions += 1;
+            summary.rows += deleted_rows;
+            summary.reclaimed_bytes += size.saturating_sub(partition_size(&partition).await?);
+        }
+
+        if summary.partitions > 0 {
+            info!(
+                "Compacted {} partitions of table {}, dropping {} records of deleted rows",
+                summary.partitions, self.definition.name, dropped_records
+            );
+        }
+
+        Ok(summary)
     }
-}
 
-#[derive(Debug)]
-pub struct TableIndex {
-    file: BufStream<File>,
-}
+    /// Compacts right away all the partitions with deleted rows, so that their disk space is
+    /// reclaimed.
+    pub async fn vacuum(&self) -> io::Result<CompactionSummary> {
+        self.compact(1, 0.0).await
+    }
 
-impl TableIndex {
-    pub fn new(file: File) -> Self {
-        Self {
-            file: BufStream::new(file),
+    /// Re-encrypts with the active key the files of all partitions which were encrypted with a
+    /// previous key and returns how many were re-encrypted.
+    ///
+    /// Cold partitions are fetched, re-encrypted and moved back to the object store, so that no
+    /// data encrypted with a previous key is left behind.
+    pub async fn rotate_key(&self) -> io::Result<usize> {
+        let Some(encryption) = encryption() else {
+            return Ok(0);
+        };
+
+        let mut reencrypted_files = 0;
+        for partition in list_partitions(self.table_path()).await? {
+            let was_cold = self.object_store().is_some() && is_cold(&partition).await?;
+            self.ensure_hot(&partition).await?;
+
+            reencrypted_files +=
+                reencrypt_partition(&partition, encryption.active_key_version).await?;
+
+            if let Some(store) = self.object_store().filter(|_| was_cold) {
+                tiering::evict(&store, &self.object_key_prefix(&partition), &partition).await?;
+            }
         }
-    }
 
-    pub async fn seek_end(&mut self) -> io::Result<()> {
-        self.file.seek(SeekFrom::End(0)).await?;
+        if reencrypted_files > 0 {
+            info!(
+                "Re-encrypted {} files of table {} with key version {}",
+                reencrypted_files, self.definition.name, encryption.active_key_version
+            );
+        }
 
-        Ok(())
+        Ok(reencrypted_files)
     }
 
-    pub async fn append(&mut self, timestamp: u64, stats: &TableStats) -> io::Result<()> {
-        self.file
-            .write_all(&u64::to_le_bytes(stats.next_index))
-            .await?;
-        self.file.write_all(&u64::to_le_bytes(timestamp)).await?;
+    /// Fetches the files of the partition from the object store if it's cold.
+    async fn ensure_hot(&self, partition: &Partition) -> io::Result<()> {
+        let Some(store) = self.object_store() else {
+            return Ok(());
+        };
+
+        if is_cold(partition).await? {
+            info!(
+                "Fetching partition {} of table {} from cold storage",
+                partition.path.display(),
+                self.definition.name
+            );
+            tiering::fetch(&store, &self.object_key_prefix(partition), partition).await?;
+        }
 
         Ok(())
     }
 
-    pub async fn flush(&mut self) -> io::Result<()> {
-        self.file.flush().await
+    fn object_store(&self) -> Option<ObjectStore> {
+        self.definition
+            .config
+            .cold_storage
+            .as_ref()
+            .map(|c| ObjectStore::from_url(&c.url))
     }
-}
-
-pub struct Table {
-    definition: TableDefinition,
-    stats: TableStats,
-    index: TableIndex,
-}
-
-impl Table {
-    pub async fn insert(
-        &mut self,
-        columns: Vec<String>,
-        values: Vec<Vec<serde_json::Value>>,
-    ) -> io::Result<()> {
-        let columns = parse_and_validate_columns(&self.definition.columns, &columns)?;
-        let mut column_files = self.open_column_files(&columns, false).await?;
-
-        let timestamp = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_secs();
 
-        // We position ourselves at the start of the index.
-        self.index.seek_end().await?;
+    fn object_key_prefix(&self, partition: &Partition) -> String {
+        let partition_name = partition
+            .path
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_default();
+
+        format!(
+            "{}/{}/{}",
+            self.definition.config.database_name, self.definition.name, partition_name
+        )
+    }
 
-        // For each value we insert into the file.
-        for value in values {
-            if value.len() != columns.len() {
-                return Err(Error::new(
-                    ErrorKind::InvalidData,
-                    "The values supplied do not match the number of columns",
-                ));
+    /// Drops the records of the deleted rows from the files of the partition, and returns how many
+    /// were dropped.
+    ///
+    /// The ids of the rows are kept, so the null bitmaps still mark the same rows, whereas the
+    /// segments and the secondary indexes are rebuilt by the next write or scan. The tombstones are
+    /// removed last, so that a crash midway leaves the deleted rows skipped by the scans.
+    async fn compact_partition(&self, partition: &Partition) -> io::Result<u64> {
+        let deleted_rows = read_deleted_rows(&partition.path).await?;
+        let mut dropped_records = drop_deleted_records(
+            partition.path.join(add_extension(".index")),
+            index_and_timestamp_size(),
+            &deleted_rows,
+        )
+        .await?;
+        for column in self.definition.columns.iter() {
+            let column_file_name: String = column.into();
+            let file_path = partition.path.join(add_extension(&column_file_name));
+            // A column could have been added after the partition was created.
+            if !storage().exists(&file_path).await? {
+                continue;
             }
 
-            // We add an entry in the index for each set of columns.
-            self.index.append(timestamp, &self.stats).await?;
+            dropped_records += match self.definition.encoding(column).value_size(column) {
+                Some(value_size) => {
+                    let record_size = index_and_timestamp_size() + value_size;
+                    drop_deleted_records(file_path, record_size, &deleted_rows).await?
+                }
+                None => drop_deleted_blocks(file_path, &deleted_rows).await?,
+            };
 
-            for ((inner_value, column), column_file) in value
-                .into_iter()
-                .zip(columns.iter())
-                .zip(column_files.iter_mut())
-            {
-                self.insert_value(timestamp, column, column_file, inner_value)
-                    .await?;
+            for file_name in [
+                segments_file_name(column),
+                bloom_filter_file_name(column),
+                secondary_index_file_name(column),
+            ] {
+                if let Err(error) = storage().remove_file(&partition.path.join(file_name)).await {
+                    if error.kind() != ErrorKind::NotFound {
+                        return Err(error);
+                    }
+                }
             }
-
-            // Once insertion has been done, we update the table stats and persist them.
-            self.stats.increment().await?;
-        }
-
-        // We flush all files to make sure data is flushed to disk from the buffer.
-        self.index.flush().await?;
-        for column_file in column_files.iter_mut() {
-            column_file.flush().await?;
         }
+        remove_tombstones(&partition.path).await?;
 
-        Ok(())
+        Ok(dropped_records)
     }
 
-    pub async fn query(
-        &mut self,
-        columns: Vec<String>,
-        group_by_columns: Option<Vec<String>>,
-    ) -> io::Result<QueryResult> {
-        // TODO: implement proper column deduplication via hash sets.
-        let (columns, aggregate_columns) =
-            parse_and_validate_queried_columns(&self.definition.columns, &columns)?;
-        let group_by_columns = parse_and_validate_columns(
-            &self.definition.columns,
-            &group_by_columns.unwrap_or(vec![]),
-        )?;
-        // TODO: add group by validation to make sure that the selected and grouped columns are the same.
-        let column_files = self.open_column_files(&columns, true).await?;
-
-        // We query the rows and early return in case no aggregates are supplied.
-        let rows = self.query_values(&columns, column_files).await?;
-        if aggregate_columns.is_empty() {
-            return Ok(QueryResult::Rows(rows));
+    async fn drop_expired_records(&self, partition: &Partition, cutoff: u64) -> io::Result<u64> {
+        let dropped_rows = drop_expired_records(
+            partition.path.join(add_extension(".index")),
+            index_and_timestamp_size(),
+            cutoff,
+        )
+        .await?;
+        for column in self.definition.columns.iter() {
+            let column_file_name: String = column.into();
+            let file_path = partition.path.join(add_extension(&column_file_name));
+            // The segments of the column are rebuilt by the next write or scan, since the file no
+            // longer ends where they do.
+            match self.definition.encoding(column).value_size(column) {
+                Some(value_size) => {
+                    let record_size = index_and_timestamp_size() + value_size;
+                    drop_expired_records(file_path, record_size, cutoff).await?;
+                }
+                None => drop_expired_blocks(file_path, cutoff).await?,
+            }
         }
 
-        // If aggregates are supplied, we will perform grouping in memory.
-        let aggregated_rows = self.aggregate_rows(rows, aggregate_columns, group_by_columns)?;
+        // The deleted rows were already removed from the stats when they were deleted.
+        let dropped_deleted_rows = drop_expired_tombstones(&partition.path, cutoff).await?;
 
-        Ok(QueryResult::AggregatedRows(aggregated_rows))
+        Ok(dropped_rows.saturating_sub(dropped_deleted_rows))
     }
 
     async fn query_values(
         &mut self,
+        partition: &Partition,
         columns: &Vec<Column>,
-        column_files: Vec<BufStream<File>>,
+        decoders: &HashMap<String, ColumnDecoder>,
+        time_range: &TimeRange,
+        filter: Option<&Expression>,
+        mut distinct_rows: Option<&mut DistinctRows>,
     ) -> io::Result<Vec<Row<ColumnValue>>> {
-        let index_file = self.index.file.get_ref().try_clone().await?;
-        let mut index_cursor = ColumnCursor::new(None, BufStream::new(index_file));
-        let mut column_cursors: Vec<ColumnCursor> = columns
-            .into_iter()
-            .zip(column_files.into_iter())
-            .map(|(c, f)| ColumnCursor::new(Some(c.clone()), f))
+        let index_file = self
+            .open_scan_file(&add_extension(".index"), partition)
+            .await?;
+        let deleted_rows = read_deleted_rows(&partition.path).await?;
+        // The pseudo-columns have no file, since their values are read from the index.
+        let file_columns: Vec<Column> = columns
+            .iter()
+            .filter(|c| !is_pseudo_column(&c.name))
+            .cloned()
             .collect();
+        let mut null_bitmaps = Vec::with_capacity(file_columns.len());
+        for column in file_columns.iter() {
+            null_bitmaps.push(read_null_bitmap(&partition.path, column).await?);
+        }
+        let column_files = self
+            .open_column_files(partition, &file_columns, true)
+            .await?;
+        // The records are appended with increasing timestamps, so the scan of each file starts at
+        // the first record in the time range, and the column files are only read in the segments
+        // overlapping it.
+        let mut index_cursor = match time_range.since {
+            Some(since) => {
+                ColumnCursor::from_timestamp(None, ColumnDecoder::Raw, index_file, since).await?
+            }
+            None => ColumnCursor::new(None, ColumnDecoder::Raw, index_file),
+        };
+        // The rows whose values are in the range required by the filter are looked up in the
+        // secondary index of the column, if it has one, so that only their segments are read.
+        let mut indexed_rows: Option<Vec<u64>> = None;
+        for column in file_columns.iter() {
+            let Some(range) = filter
+                .filter(|_| self.definition.has_secondary_index(column))
+                .and_then(|f| f.value_range(&column.name))
+            else {
+                continue;
+            };
+            let decoder = decoders.get(&column.name).cloned().unwrap_or_default();
+            let rows = partition_rows(partition).await?;
+            let secondary_index =
+                SecondaryIndex::read(&partition.path, column, decoder, rows).await?;
+            indexed_rows = Some(secondary_index.lookup(&partition.path, &range).await?);
+            break;
+        }
+
+        // The segments whose values can't match the filter aren't read, and the rows in them are
+        // skipped, since the filter can't match them either.
+        let mut column_cursors = Vec::with_capacity(column_files.len());
+        let mut pruned_ranges = vec![];
+        for (column, mut file) in file_columns.into_iter().zip(column_files) {
+            let file_len = file.len().await?;
+            let decoder = decoders.get(&column.name).cloned().unwrap_or_default();
+            let bloom_filter = self.definition.has_bloom_filter(&column);
+            let segments = Segments::read(
+                &partition.path,
+                &column,
+                decoder.clone(),
+                file_len,
+                bloom_filter,
+            )
+            .await?;
+            let (kept_segments, pruned_segments): (Vec<_>, Vec<_>) = segments
+                .overlapping(time_range)
+                .iter()
+                .cloned()
+                .partition(|s| {
+                    filter.is_none_or(|f| {
+                        f.may_match(&column.name, &s.min, &s.max)
+                            && indexed_rows
+                                .as_ref()
+                                .is_none_or(|r| has_row_between(r, s.first_row_id, s.last_row_id))
+                            && segments
+                                .bloom_filter(s)
+                                .is_none_or(|b| f.may_contain(&column.name, &|v| b.may_contain(v)))
+                    })
+                });
+            self.pruned_segments += pruned_segments.len() as u64;
+            pruned_ranges.extend(
+                pruned_segments
+                    .iter()
+                    .map(|s| (s.first_row_id, s.last_row_id)),
+            );
+            column_cursors.push(
+                ColumnCursor::from_segments(
+                    Some(column),
+                    decoder,
+                    file,
+                    &kept_segments,
+                    time_range.since,
+                )
+                .await?,
+            );
+        }
+        let pruned_rows = PrunedRows::new(pruned_ranges);
 
         let mut rows = vec![];
         while let Ok(index_row_component) = index_cursor.read::<ColumnValue>().await {
-            let mut row_components: Vec<(Column, ColumnValue)> =
-                Vec::with_capacity(column_cursors.len());
+            // The rows after the time range are all after its end, so the scan stops at the first.
+            if time_range
+                .until
+                .is_some_and(|until| index_row_component.timestamp >= until)
+            {
+                break;
+            }
+            self.scanned_rows += 1;
+
+            // Rows outside the time range are skipped, and their column values will be skipped
+            // while looking for the values of the next row.
+            if !time_range.contains(index_row_component.timestamp) {
+                continue;
+            }
+
+            // Deleted rows and the rows of pruned segments are skipped in the same way.
+            if deleted_rows.contains(&index_row_component.index_id)
+                || pruned_rows.contains(index_row_component.index_id)
+                || indexed_rows
+                    .as_ref()
+                    .is_some_and(|r| r.binary_search(&index_row_component.index_id).is_err())
+            {
+                continue;
+            }
 
-            for (column_index, column_cursor) in column_cursors.iter_mut().enumerate() {
-                let Some(column) = &column_cursor.column else {
-                    info!("Column cursor doesn't have a column, skipping entire row");
+            // Rows left out of the sample are skipped in the same way.
+            if self.sampler.as_mut().is_some_and(|s| !s.keep()) {
+                continue;
+            }
+
+            let mut row_components: Vec<(Column, ColumnValue)> = Vec::with_capacity(columns.len());
+
+            let mut cursors = column_cursors.iter_mut().zip(null_bitmaps.iter());
+            for column in columns {
+                if let Some(value) = pseudo_column_value(
+                    &column.name,
+                    index_row_component.index_id,
+                    index_row_component.timestamp,
+                ) {
+                    row_components.push((column.clone(), value));
+                    continue;
+                }
+
+                let Some((column_cursor, null_bitmap)) = cursors.next() else {
+                    info!("Column doesn't have a cursor, skipping entire row");
                     break;
                 };
 
+                // The rows which are null according to the bitmap have no record in the file of
+                // the column, which is thus not read.
+                if null_bitmap.is_null(index_row_component.index_id) == Some(true) {
+                    row_components.push((column.clone(), ColumnValue::Null));
+                    continue;
+                }
+
                 // By default, we assume that the column we are reading is null.
+                let column_index = row_components.len();
                 row_components.push((column.clone(), ColumnValue::Null));
 
                 // We loop and try to seek through the next column.
@@ -323,8 +1964,11 @@ impl Table {
                         // If this row has higher index id, we want to undo the read so that we
                         // can read it again for the next index.
                         column_cursor.undo().await?;
+                        self.skipped_records += 1;
                         break;
                     }
+
+                    self.skipped_records += 1;
                 }
             }
 
@@ -334,44 +1978,104 @@ impl Table {
                 index_row_component.timestamp,
                 row_components,
             );
+            // Rows which don't match the filter, or which were already read by a distinct query,
+            // are dropped right away, so that they are never kept in memory.
             if let Some(row) = row {
-                rows.push(row);
+                if filter.is_none_or(|f| f.test(&row) == Some(true))
+                    && distinct_rows.as_deref_mut().is_none_or(|d| d.insert(&row))
+                {
+                    rows.push(row);
+                }
             }
         }
 
         Ok(rows)
     }
 
-    fn aggregate_rows(
+    /// Queries the rows buffered in the memtable of the table, like [`Table::query_values`] does
+    /// for the files of a partition.
+    ///
+    /// The buffered rows get their ids once flushed, which are the next ones of the table in the
+    /// order in which they were buffered, since they are flushed in that order.
+    fn query_memtable(
         &mut self,
-        rows: Vec<Row<ColumnValue>>,
-        aggregate_columns: Vec<AggregateColumn>,
-        group_by_columns: Vec<Column>,
-    ) -> io::Result<Vec<AggregatedRow<ColumnValue>>> {
-        let mut groups = HashMap::new();
-        for row in rows {
-            // TODO: for now we group by each individual column, but we will add.
-            let group_key = row.group(&group_by_columns);
-            let group_value = groups
-                .entry(group_key)
-                .or_insert_with(|| GroupValue::<ColumnValue>::new(aggregate_columns.clone()));
-            group_value.add(row);
+        columns: &[Column],
+        time_range: &TimeRange,
+        filter: Option<&Expression>,
+        mut distinct_rows: Option<&mut DistinctRows>,
+    ) -> Vec<Row<ColumnValue>> {
+        let mut rows = vec![];
+        let mut index = self.stats.next_index;
+        for insert in memtables().get(&self.definition.name) {
+            let positions: Vec<Option<usize>> = columns
+                .iter()
+                .map(|column| insert.columns.iter().position(|c| c.name == column.name))
+                .collect();
+            for value in insert.values {
+                let index_id = row_id(self.definition.config.node_id, index);
+                index += 1;
+                if !time_range.contains(insert.timestamp) {
+                    continue;
+                }
+                self.scanned_rows += 1;
+
+                if self.sampler.as_mut().is_some_and(|s| !s.keep()) {
+                    continue;
+                }
+
+                let row_components =
+                    columns
+                        .iter()
+                        .zip(positions.iter())
+                        .map(|(column, position)| {
+                            let value =
+                                pseudo_column_value(&column.name, index_id, insert.timestamp)
+                                    .or_else(|| written_value(column, &value[(*position)?]))
+                                    .unwrap_or(ColumnValue::Null);
+                            (column.clone(), value)
+                        });
+                let row = Row::from_components(index_id, insert.timestamp, row_components);
+                if let Some(row) = row {
+                    if filter.is_none_or(|f| f.test(&row) == Some(true))
+                        && distinct_rows.as_deref_mut().is_none_or(|d| d.insert(&row))
+                    {
+                        rows.push(row);
+                    }
+                }
+            }
         }
 
-        let mut aggregated_rows = vec![];
-        for (group_key, group_value) in groups {
-            // TODO: return columns ordered in the order in which they were supplied.
-            aggregated_rows.push(AggregatedRow::from_group(group_key, group_value));
+        rows
+    }
+
+    /// Returns the counts of all the rows of the table, which are the same for all columns since
+    /// the counts include the null values.
+    fn count_rows(&self, aggregate_columns: Vec<AggregateColumn>) -> QueryResult {
+        // An empty table has no groups, like when its rows are scanned.
+        let row_count = self.row_count();
+        if row_count == 0 {
+            return QueryResult::AggregatedRows(vec![]);
         }
 
-        Ok(aggregated_rows)
+        let group_value = GroupValue::from_aggregates(
+            aggregate_columns
+                .into_iter()
+                .map(|a| (a, vec![ColumnValue::Integer(row_count as i64)]))
+                .collect(),
+        );
+
+        QueryResult::AggregatedRows(vec![AggregatedRow::from_group(
+            GroupKey(vec![]),
+            group_value,
+        )])
     }
 
     async fn insert_value(
         &mut self,
         timestamp: u64,
         column: &Column,
-        column_file: &mut BufStream<File>,
+        column_file: &mut DataFile,
+        segments: &mut Segments,
         value: serde_json::Value,
     ) -> io::Result<()> {
         // We write the data into the specific column.
@@ -392,14 +2096,18 @@ impl Table {
 
                 if number.is_i64() {
                     self.write_value(
+                        column,
                         column_file,
+                        segments,
                         timestamp,
                         &i64::to_le_bytes(number.as_i64().unwrap()),
                     )
                     .await?;
                 } else if number.is_f64() {
                     self.write_value(
+                        column,
                         column_file,
+                        segments,
                         timestamp,
                         &f64::to_le_bytes(number.as_f64().unwrap()),
                     )
@@ -423,19 +2131,18 @@ impl Table {
                     ));
                 }
 
-                // We build a string with bytes set to 0 when the string is smaller.
-                let mut bytes = [0u8; ColumnType::String.size()];
-                for (index, byte) in string
-                    .as_bytes()
-                    .iter()
-                    .take(ColumnType::String.size())
-                    .enumerate()
-                {
-                    bytes[index] = *byte;
-                }
-
-                self.write_value(column_file, timestamp, &bytes).await?;
+                self.write_value(
+                    column,
+                    column_file,
+                    segments,
+                    timestamp,
+                    &encode_string(&string),
+                )
+                .await?;
             }
+            // A null value is represented by the absence of the row in the column file, thus we
+            // don't have to write anything.
+            Value::Null => {}
             _ => return Err(Error::new(ErrorKind::Unsupported, "Unsupported value type")),
         }
 
@@ -444,43 +2151,448 @@ impl Table {
 
     async fn write_value(
         &self,
-        column_file: &mut BufStream<File>,
+        column: &Column,
+        column_file: &mut DataFile,
+        segments: &mut Segments,
         timestamp: u64,
         data: &[u8],
     ) -> io::Result<()> {
-        column_file
-            .write_all(&u64::to_le_bytes(self.stats.next_index))
-            .await?;
-        column_file.write_all(&u64::to_le_bytes(timestamp)).await?;
-        column_file.write_all(data).await?;
+        let mut record = Vec::with_capacity(index_and_timestamp_size() + data.len());
+        record.extend_from_slice(&u64::to_le_bytes(self.next_row_id()));
+        record.extend_from_slice(&u64::to_le_bytes(timestamp));
+        record.extend_from_slice(data);
+        column_file.write_all(&record).await?;
+
+        record_column_write(&self.definition.name, &column.name, record.len() as u64);
+        segments.append_record(&record)
+    }
+
+    /// Reads the dictionaries of the dictionary encoded columns, by column name.
+    async fn read_dictionaries(
+        &self,
+        columns: &[Column],
+    ) -> io::Result<HashMap<String, Dictionary>> {
+        let mut dictionaries = HashMap::new();
+        for column in columns {
+            if self.definition.encoding(column) == ColumnEncoding::Dictionary {
+                let dictionary = Dictionary::read(self.table_path(), column).await?;
+                dictionaries.insert(column.name.clone(), dictionary);
+            }
+        }
+
+        Ok(dictionaries)
+    }
+
+    /// Checks that the values of the unique column aren't repeated in the rows, nor already stored.
+    async fn check_unique_values(
+        &self,
+        columns: &[Column],
+        values: &[Vec<Value>],
+    ) -> io::Result<()> {
+        let Some(column) = self.definition.unique_column() else {
+            return Ok(());
+        };
+        let Some(position) = columns.iter().position(|c| c == column) else {
+            return Ok(());
+        };
+
+        let unique_values: Vec<Value> = values.iter().map(|v| v[position].clone()).collect();
+        if let Some(row) = repeated_values(column, &unique_values).first() {
+            return Err(Error::new(
+                ErrorKind::AlreadyExists,
+                format!(
+                    "The value {} of the unique column {} is repeated in the inserted rows",
+                    unique_values[*row], column.name
+                ),
+            ));
+        }
+        if let Some(value) = self
+            .existing_values(&column.name, &unique_values)
+            .await?
+            .first()
+        {
+            return Err(Error::new(
+                ErrorKind::AlreadyExists,
+                format!(
+                    "The value {} of the unique column {} already exists",
+                    value, column.name
+                ),
+            ));
+        }
 
         Ok(())
     }
 
+    /// Returns the decoders of the encoded columns, by column name, which are shared by the cursors
+    /// of all the partitions.
+    async fn read_decoders(
+        &self,
+        columns: &[Column],
+    ) -> io::Result<HashMap<String, ColumnDecoder>> {
+        let mut decoders: HashMap<String, ColumnDecoder> = self
+            .read_dictionaries(columns)
+            .await?
+            .into_iter()
+            .map(|(name, dictionary)| (name, ColumnDecoder::Dictionary(Arc::new(dictionary))))
+            .collect();
+        for column in columns {
+            if self.definition.encoding(column) == ColumnEncoding::Delta {
+                decoders.insert(column.name.clone(), ColumnDecoder::Delta);
+            }
+        }
+
+        Ok(decoders)
+    }
+
+    /// Returns the id of the next row written on this instance.
+    fn next_row_id(&self) -> u64 {
+        row_id(self.definition.config.node_id, self.stats.next_index)
+    }
+
+    fn table_path(&self) -> PathBuf {
+        build_table_path(&self.definition.config, &self.definition.name)
+    }
+
+    /// Opens a file of the partition for a scan, with direct I/O if the table is big enough.
+    /// Opens a file of the partition for scanning, which is empty if the file doesn't exist, so that
+    /// queries never write to the storage.
+    async fn open_scan_file(&self, file_name: &str, partition: &Partition) -> io::Result<DataFile> {
+        let use_direct_io = self
+            .definition
+            .config
+            .direct_io
+            .as_ref()
+            .is_some_and(|d| self.stats.row_count >= d.min_scan_rows);
+
+        let result = if use_direct_io {
+            open_direct_read_file(file_name, &partition.path).await
+        } else {
+            open_read_file(file_name, &partition.path).await
+        };
+        match result {
+            Err(error) if error.kind() == ErrorKind::NotFound => {
+                DataFile::from_file(empty_file()).await
+            }
+            result => result,
+        }
+    }
+
     async fn open_column_files(
         &self,
+        partition: &Partition,
         columns: &Vec<Column>,
         read_only: bool,
-    ) -> io::Result<Vec<BufStream<File>>> {
+    ) -> io::Result<Vec<DataFile>> {
         // We open all columns files since we want to append to each of them.
-        let table_path = build_table_path(&self.definition.config, &self.definition.name);
-
         let mut column_files = vec![];
         for column in columns {
             let column_file_name: String = column.into();
+
+            // A column could have been added after the partition was created, in which case the
+            // column has no values in the partition.
             let column_file = if read_only {
-                open_read_file(&add_extension(&column_file_name), &table_path).await?
+                self.open_scan_file(&add_extension(&column_file_name), partition)
+                    .await?
             } else {
-                open_append_file(&add_extension(&column_file_name), &table_path).await?
+                create_file(&add_extension(&column_file_name), &partition.path).await?;
+                open_append_file(&add_extension(&column_file_name), &partition.path).await?
             };
 
-            column_files.push(BufStream::new(column_file));
+            column_files.push(column_file);
         }
 
         Ok(column_files)
     }
 }
 
+/// The parsed and validated parts of a query, which determine the columns read for each row and
+/// how the rows are turned into the result.
+pub struct QueryPlan {
+    /// The columns read for each row, of which only the first `selected_columns` are returned.
+    columns: Vec<Column>,
+    selected_columns: usize,
+    aggregate_columns: Vec<AggregateColumn>,
+    /// The expressions whose values are aggregated, e.g. `cast(price as float)`, and the conditions
+    /// of the filtered aggregates, whose values are 1 for the rows which match them.
+    aggregated_expressions: Vec<(Expression, Column)>,
+    group_by: Vec<(Expression, Column)>,
+    filter: Option<Expression>,
+    order_by: Vec<OrderItem>,
+    /// Whether the duplicate rows are removed, which only matters without aggregates, since the
+    /// aggregated rows are already one per group.
+    distinct: bool,
+}
+
+impl QueryPlan {
+    pub fn new(
+        available_columns: &Vec<Column>,
+        columns: Vec<String>,
+        group_by_columns: Option<Vec<String>>,
+        filter: Option<String>,
+        order_by: Option<Vec<String>>,
+        distinct: bool,
+    ) -> io::Result<Self> {
+        // TODO: implement proper column deduplication via hash sets.
+        let (mut columns, aggregate_columns, aggregated_expressions) =
+            parse_and_validate_queried_columns(available_columns, &columns)?;
+        let selected_columns = columns.len();
+        let mut group_by = vec![];
+        for (expression, column) in
+            parse_and_validate_group_by(available_columns, &group_by_columns.unwrap_or(vec![]))?
+        {
+            if !group_by.iter().any(|(_, c)| *c == column) {
+                group_by.push((expression, column));
+            }
+        }
+        // The grouped columns are returned in the order in which they were selected, followed by
+        // the ones which were only grouped.
+        group_by.sort_by_key(|(_, c)| {
+            columns[..selected_columns]
+                .iter()
+                .position(|s| s == c)
+                .unwrap_or(usize::MAX)
+        });
+        let filter = filter
+            .map(|f| parse_and_validate_filter(available_columns, &f))
+            .transpose()?;
+        let order_by = order_by
+            .unwrap_or_default()
+            .iter()
+            .map(|o| parse_order_item(o))
+            .collect::<io::Result<Vec<_>>>()?;
+
+        // The columns read by the group by expressions are needed only to aggregate the rows, and
+        // the ones read by the filter only to filter them, so they are removed from the rows
+        // afterwards.
+        let mut needed_columns = vec![];
+        if !aggregate_columns.is_empty() {
+            needed_columns.extend(group_by.iter().flat_map(|(e, _)| e.columns()));
+        }
+        if let Some(filter) = &filter {
+            needed_columns.extend(filter.columns());
+        }
+        for name in needed_columns {
+            if !columns.iter().any(|c| c.name == name) {
+                columns.push(get_column(available_columns, name)?);
+            }
+        }
+        // TODO: add group by validation to make sure that the selected and grouped columns are the same.
+
+        Ok(Self {
+            columns,
+            selected_columns,
+            aggregate_columns,
+            aggregated_expressions,
+            group_by,
+            filter,
+            order_by,
+            distinct,
+        })
+    }
+
+    /// Returns the columns read from their files, which are all except the pseudo-columns.
+    fn file_columns(&self) -> Vec<Column> {
+        self.columns
+            .iter()
+            .filter(|c| !is_pseudo_column(&c.name))
+            .cloned()
+            .collect()
+    }
+
+    /// Returns the columns of the result of the query, which are known even if no row matches.
+    pub fn schema(&self) -> QuerySchema {
+        if self.aggregate_columns.is_empty() {
+            return QuerySchema {
+                columns: self.columns[..self.selected_columns].to_vec(),
+                aggregate_columns: vec![],
+            };
+        }
+
+        QuerySchema {
+            columns: self.group_by.iter().map(|(_, c)| c.clone()).collect(),
+            aggregate_columns: self
+                .aggregate_columns
+                .iter()
+                .map(|a| (a.clone(), a.0.value_type(&a.1)))
+                .collect(),
+        }
+    }
+
+    /// Returns whether the query only counts all the rows of the table, which doesn't need a scan
+    /// since the stats track how many rows the table has.
+    fn counts_all_rows(&self, time_range: &TimeRange) -> bool {
+        !self.aggregate_columns.is_empty()
+            && self.selected_columns == self.aggregate_columns.len()
+            && self
+                .aggregate_columns
+                .iter()
+                .all(|a| matches!(a.0, Aggregate::Count) && a.2.is_none())
+            && self.group_by.is_empty()
+            && self.filter.is_none()
+            && time_range.is_unbounded()
+    }
+
+    fn distinct_rows(&self) -> Option<DistinctRows> {
+        (self.distinct && self.aggregate_columns.is_empty())
+            .then(|| DistinctRows::new(self.selected_columns))
+    }
+
+    /// Runs the query on rows which are already in memory, whose values are looked up by name.
+    pub fn execute(self, rows: Vec<Row<ColumnValue>>) -> io::Result<QueryResult> {
+        let mut distinct_rows = self.distinct_rows();
+        let rows =
+            rows.into_iter()
+                .filter_map(|row| {
+                    let values = self.columns.iter().map(|c| {
+                        let value = row.named_value(&c.name).cloned().or_else(|| {
+                            pseudo_column_value(&c.name, row.index_id(), row.timestamp())
+                        });
+                        (c.clone(), value.unwrap_or(ColumnValue::Null))
+                    });
+                    let row = Row::from_components(row.index_id(), row.timestamp(), values)?;
+                    let keep = self
+                        .filter
+                        .as_ref()
+                        .is_none_or(|f| f.test(&row) == Some(true))
+                        && distinct_rows.as_mut().is_none_or(|d| d.insert(&row));
+                    keep.then_some(row)
+                })
+                .collect();
+
+        self.finish(rows)
+    }
+
+    /// Turns the read rows into the result, by aggregating and sorting them.
+    fn finish(self, mut rows: Vec<Row<ColumnValue>>) -> io::Result<QueryResult> {
+        if self.aggregate_columns.is_empty() {
+            if self.columns.len() > self.selected_columns {
+                for row in rows.iter_mut() {
+                    row.truncate_values(self.selected_columns);
+                }
+            }
+
+            let mut query_result = QueryResult::Rows(rows);
+            query_result.sort(&self.order_by);
+            return Ok(query_result);
+        }
+
+        // If aggregates are supplied, we will perform grouping in memory.
+        let aggregated_rows = aggregate_rows(
+            rows,
+            self.aggregate_columns,
+            self.aggregated_expressions,
+            self.group_by,
+        );
+
+        let mut query_result = QueryResult::AggregatedRows(aggregated_rows);
+        query_result.sort(&self.order_by);
+        Ok(query_result)
+    }
+}
+
+/// The selected values of the rows read by a distinct query, used to drop the rows whose values
+/// were already read.
+struct DistinctRows {
+    selected_columns: usize,
+    seen: HashSet<Vec<ColumnValue>>,
+}
+
+impl DistinctRows {
+    fn new(selected_columns: usize) -> Self {
+        Self {
+            selected_columns,
+            seen: HashSet::new(),
+        }
+    }
+
+    /// Returns whether the selected values of the row weren't read before.
+    fn insert(&mut self, row: &Row<ColumnValue>) -> bool {
+        let values = row.values().take(self.selected_columns).cloned().collect();
+        self.seen.insert(values)
+    }
+}
+
+fn aggregate_rows(
+    rows: Vec<Row<ColumnValue>>,
+    aggregate_columns: Vec<AggregateColumn>,
+    aggregated_expressions: Vec<(Expression, Column)>,
+    group_by: Vec<(Expression, Column)>,
+) -> Vec<AggregatedRow<ColumnValue>> {
+    let group_by_columns: Vec<Column> = group_by.iter().map(|(_, c)| c.clone()).collect();
+
+    let mut groups = HashMap::new();
+    for mut row in rows {
+        // The expressions are evaluated before grouping, so that their values are part of the
+        // group key and are aggregated like the ones of plain columns.
+        for (expression, column) in group_by.iter().chain(aggregated_expressions.iter()) {
+            if row.value(column).is_none() {
+                let value = expression.evaluate(&row);
+                row.add_value(column.clone(), value);
+            }
+        }
+
+        let group_key = row.group(&group_by_columns);
+        let group_value = groups
+            .entry(group_key)
+            .or_insert_with(|| GroupValue::<ColumnValue>::new(aggregate_columns.clone()));
+        group_value.add(row);
+    }
+
+    let mut aggregated_rows = vec![];
+    for (group_key, group_value) in groups {
+        aggregated_rows.push(AggregatedRow::from_group(group_key, group_value));
+    }
+
+    aggregated_rows
+}
+
+/// The columns of the result of a query.
+#[derive(Debug, Clone)]
+pub struct QuerySchema {
+    pub columns: Vec<Column>,
+    /// The aggregates with the type of their values, empty if the query isn't aggregated.
+    pub aggregate_columns: Vec<(AggregateColumn, ColumnType)>,
+}
+
+impl QuerySchema {
+    pub fn is_aggregated(&self) -> bool {
+        !self.aggregate_columns.is_empty()
+    }
+
+    /// Checks that the results of the same query on two tables can be concatenated, which requires
+    /// the queried columns to have the same types in both tables.
+    pub fn check_compatible(&self, other: &QuerySchema) -> io::Result<()> {
+        let aggregate_types = self.aggregate_columns.iter().map(|(a, t)| (&a.1, t));
+        let other_aggregate_types = other.aggregate_columns.iter().map(|(a, t)| (&a.1, t));
+        let columns = self
+            .columns
+            .iter()
+            .map(|c| (c, &c.ty))
+            .chain(aggregate_types);
+        let other_columns = other
+            .columns
+            .iter()
+            .map(|c| (c, &c.ty))
+            .chain(other_aggregate_types);
+        for ((column, ty), (other_column, other_ty)) in columns.zip(other_columns) {
+            if column.name != other_column.name || ty != other_ty {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!(
+                        "The column {} of type {} is incompatible with the column {} of type {}",
+                        column.name,
+                        <&ColumnType as Into<&str>>::into(ty),
+                        other_column.name,
+                        <&ColumnType as Into<&str>>::into(other_ty)
+                    ),
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
 #[derive(Debug)]
 pub enum QueryResult {
     Rows(Vec<Row<ColumnValue>>),
@@ -490,6 +2602,13 @@ pub enum QueryResult {
 impl QueryResult {
     pub fn merge(self, other: QueryResult) -> io::Result<QueryResult> {
         match (self, other) {
+            // An instance without rows of the table returns empty rows, even for aggregates.
+            (QueryResult::Rows(rows), other @ QueryResult::AggregatedRows(_))
+            | (other @ QueryResult::AggregatedRows(_), QueryResult::Rows(rows))
+                if rows.is_empty() =>
+            {
+                QueryResult::AggregatedRows(vec![]).merge(other)
+            }
             (QueryResult::Rows(left), QueryResult::Rows(right)) => {
                 Ok(QueryResult::Rows(Self::merge_rows(left, right)))
             }
@@ -544,13 +2663,105 @@ impl QueryResult {
 
         let mut aggregated_rows = vec![];
         for (group_key, group_value) in groups {
-            // TODO: return columns ordered in the order in which they were supplied.
             aggregated_rows.push(AggregatedRow::from_group(group_key, group_value));
         }
 
         aggregated_rows
     }
 
+    /// Sorts the rows by the values of the ordered expressions, which are looked up by name among
+    /// the columns and aggregates of the rows.
+    ///
+    /// Null values come after all other values, so they are last in ascending order.
+    pub fn sort(&mut self, order_by: &[OrderItem]) {
+        if order_by.is_empty() {
+            return;
+        }
+
+        let names: Vec<String> = order_by.iter().map(|o| o.expression.to_string()).collect();
+        let compare = |left: &[ColumnValue], right: &[ColumnValue]| {
+            left.iter()
+                .zip(right.iter())
+                .zip(order_by.iter())
+                .map(|((l, r), o)| match o.descending {
+                    true => r.cmp(l),
+                    false => l.cmp(r),
+                })
+                .find(|ordering| ordering.is_ne())
+                .unwrap_or(Ordering::Equal)
+        };
+
+        // The values are looked up once per row, instead of at every comparison.
+        match self {
+            QueryResult::Rows(rows) => {
+                let mut keyed_rows: Vec<_> = rows
+                    .drain(..)
+                    .map(|row| {
+                        let key: Vec<_> = names
+                            .iter()
+                            .map(|n| row.named_value(n).cloned().unwrap_or(ColumnValue::Null))
+                            .collect();
+                        (key, row)
+                    })
+                    .collect();
+                keyed_rows.sort_by(|(left, _), (right, _)| compare(left, right));
+                rows.extend(keyed_rows.into_iter().map(|(_, row)| row));
+            }
+            QueryResult::AggregatedRows(aggregated_rows) => {
+                let mut keyed_rows: Vec<_> = aggregated_rows
+                    .drain(..)
+                    .map(|row| {
+                        let key: Vec<_> = names
+                            .iter()
+                            .map(|n| row.named_value(n).cloned().unwrap_or(ColumnValue::Null))
+                            .collect();
+                        (key, row)
+                    })
+                    .collect();
+                keyed_rows.sort_by(|(left, _), (right, _)| compare(left, right));
+                aggregated_rows.extend(keyed_rows.into_iter().map(|(_, row)| row));
+            }
+        }
+    }
+
+    /// Removes the rows whose values are the same as the ones of a previous row, like the rows of
+    /// different shards which were distinct only within their shard.
+    pub fn dedup(&mut self) {
+        if let QueryResult::Rows(rows) = self {
+            let mut distinct_rows = DistinctRows::new(usize::MAX);
+            rows.retain(|row| distinct_rows.insert(row));
+        }
+    }
+
+    /// Returns the number of rows, or groups for aggregates, of the result.
+    pub fn len(&self) -> usize {
+        match self {
+            QueryResult::Rows(rows) => rows.len(),
+            QueryResult::AggregatedRows(aggregated_rows) => aggregated_rows.len(),
+        }
+    }
+
+    /// Estimates the size in bytes of the values of the result once encoded as JSON.
+    pub fn estimated_size(&self) -> usize {
+        let values: Box<dyn Iterator<Item = &ColumnValue>> = match self {
+            QueryResult::Rows(rows) => Box::new(rows.iter().flat_map(|r| r.values())),
+            QueryResult::AggregatedRows(aggregated_rows) => {
+                Box::new(aggregated_rows.iter().flat_map(|r| r.values()))
+            }
+        };
+
+        // Each value is followed by a separator.
+        values
+            .map(|value| match value {
+                ColumnValue::Integer(value) => value.to_string().len() + 1,
+                // The floats are written with at most 17 significant digits and an exponent.
+                ColumnValue::Float(_) => 25,
+                ColumnValue::String(value) => value.len() + 3,
+                ColumnValue::Null => 5,
+            })
+            .sum()
+    }
+
     pub fn is_empty(&self) -> bool {
         match self {
             QueryResult::Rows(rows) => rows.is_empty(),
@@ -0,0 +1,109 @@
+use std::io::ErrorKind;
+
+use serde::{Deserialize, Serialize};
+use tokio::io;
+
+use crate::io::file::{data_file_len, read_json_or_default, write_json};
+use crate::io::object_store::ObjectStore;
+use crate::io::storage::storage;
+use crate::table::column::index_and_timestamp_size;
+use crate::table::deletion::count_deleted_rows;
+use crate::table::partition::Partition;
+use crate::table::upgrade::upgrade_files;
+
+pub const COLD_STUB_FILE_NAME: &str = ".cold.dsto";
+
+/// The stub left in place of the files of a partition which was moved to the object store.
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct ColdStub {
+    files: Vec<String>,
+    rows: u64,
+}
+
+/// Returns `true` if the files of the partition are in the object store.
+pub async fn is_cold(partition: &Partition) -> io::Result<bool> {
+    storage()
+        .exists(&partition.path.join(COLD_STUB_FILE_NAME))
+        .await
+}
+
+/// Moves all the files of a partition to the object store, leaving only a stub behind.
+pub async fn evict(store: &ObjectStore, key_prefix: &str, partition: &Partition) -> io::Result<()> {
+    let mut stub = ColdStub::default();
+
+    for entry in storage().read_dir(&partition.path).await? {
+        let file_name = entry.name;
+
+        if file_name == ".index.dsto" {
+            let index_size = data_file_len(&file_name, &partition.path).await?;
+            let deleted_rows = count_deleted_rows(&partition.path).await?;
+            stub.rows =
+                (index_size / index_and_timestamp_size() as u64).saturating_sub(deleted_rows);
+        }
+
+        let data = storage().read(&entry.path).await?;
+        store
+            .put(&format!("{}/{}", key_prefix, file_name), data)
+            .await?;
+        stub.files.push(file_name);
+    }
+
+    // The stub is written before removing the files, so that a crash in between leaves a cold
+    // partition which can still be fetched.
+    write_json(COLD_STUB_FILE_NAME, &partition.path, &stub).await?;
+    for file_name in stub.files.iter() {
+        storage()
+            .remove_file(&partition.path.join(file_name))
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// Fetches all the files of a cold partition from the object store, making it hot again.
+pub async fn fetch(store: &ObjectStore, key_prefix: &str, partition: &Partition) -> io::Result<()> {
+    let stub: ColdStub = read_json_or_default(COLD_STUB_FILE_NAME, &partition.path).await?;
+
+    for file_name in stub.files.iter() {
+        let data = store.get(&format!("{}/{}", key_prefix, file_name)).await?;
+
+        // We write to a temporary file so that a partially fetched file is never read.
+        let tmp_file_path = partition.path.join(format!("{}.tmp", file_name));
+        storage().write(&tmp_file_path, data).await?;
+        storage()
+            .rename(&tmp_file_path, &partition.path.join(file_name))
+            .await?;
+    }
+    // The files could have been evicted by an older version of the database.
+    upgrade_files(&partition.path).await?;
+
+    // Concurrent queries could fetch the same partition, in which case the stub is already gone.
+    if let Err(error) = storage()
+        .remove_file(&partition.path.join(COLD_STUB_FILE_NAME))
+        .await
+    {
+        if error.kind() != ErrorKind::NotFound {
+            return Err(error);
+        }
+    }
+
+    Ok(())
+}
+
+/// Deletes all the files of a cold partition from the object store and returns the number of rows
+/// they contained.
+pub async fn delete(
+    store: &ObjectStore,
+    key_prefix: &str,
+    partition: &Partition,
+) -> io::Result<u64> {
+    let stub: ColdStub = read_json_or_default(COLD_STUB_FILE_NAME, &partition.path).await?;
+
+    for file_name in stub.files.iter() {
+        store
+            .delete(&format!("{}/{}", key_prefix, file_name))
+            .await?;
+    }
+
+    Ok(stub.rows)
+}
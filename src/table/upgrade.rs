@@ -0,0 +1,62 @@
+use std::path::{Path, PathBuf};
+
+use log::info;
+use tokio::io;
+
+use crate::config::Config;
+use crate::io::file::upgrade_data_file;
+use crate::io::storage::storage;
+use crate::table::schema::SCHEMA_FILE_NAME;
+use crate::table::tiering::COLD_STUB_FILE_NAME;
+
+/// Returns whether the file stores data with the layout of a format version, unlike the JSON
+/// files.
+fn is_data_file(path: &Path) -> bool {
+    let is_json = path
+        .file_name()
+        .is_some_and(|n| n == SCHEMA_FILE_NAME || n == COLD_STUB_FILE_NAME);
+
+    path.extension().is_some_and(|e| e == "dsto") && !is_json
+}
+
+/// Upgrades all the data files of the database to the current format version, and returns how
+/// many were upgraded.
+///
+/// It runs before the instance serves any request, since the files of the older versions can't be
+/// read until they are upgraded.
+pub async fn upgrade_database(config: &Config) -> io::Result<usize> {
+    let mut database_path = PathBuf::new();
+    database_path.push(config.database_path.clone());
+    database_path.push(config.database_name.clone());
+    if !storage().exists(&database_path).await? {
+        return Ok(0);
+    }
+
+    let upgraded_files = upgrade_files(&database_path).await?;
+    if upgraded_files > 0 {
+        info!(
+            "Upgraded {} files of database {} to the current format version",
+            upgraded_files, config.database_name
+        );
+    }
+
+    Ok(upgraded_files)
+}
+
+/// Upgrades the data files under the directory to the current format version, and returns how many
+/// were upgraded.
+pub async fn upgrade_files(path: &Path) -> io::Result<usize> {
+    let mut upgraded_files = 0;
+    let mut directories = vec![path.to_path_buf()];
+    while let Some(directory) = directories.pop() {
+        for entry in storage().read_dir(&directory).await? {
+            if entry.is_dir {
+                directories.push(entry.path);
+            } else if is_data_file(&entry.path) && upgrade_data_file(&entry.path).await? {
+                upgraded_files += 1;
+            }
+        }
+    }
+
+    Ok(upgraded_files)
+}
@@ -0,0 +1,77 @@
+use std::fmt::{Display, Formatter};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::table::column::{Column, ColumnType};
+
+/// A row of an insert which can't be written, with the reason.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RowError {
+    /// The position of the row in the inserted values.
+    pub row: usize,
+    /// The column of the invalid value, if the error is about a single value.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub column: Option<String>,
+    pub reason: String,
+}
+
+impl Display for RowError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match &self.column {
+            Some(column) => write!(f, "Row {}, column {}: {}", self.row, column, self.reason),
+            None => write!(f, "Row {}: {}", self.row, self.reason),
+        }
+    }
+}
+
+/// Returns the errors of all the rows which can't be written to the columns, instead of stopping
+/// at the first one.
+pub fn validate_rows(columns: &[Column], values: &[Vec<Value>]) -> Vec<RowError> {
+    let mut errors = vec![];
+    for (row, value) in values.iter().enumerate() {
+        if value.len() != columns.len() {
+            errors.push(RowError {
+                row,
+                column: None,
+                reason: format!(
+                    "The row has {} values but {} columns are inserted",
+                    value.len(),
+                    columns.len()
+                ),
+            });
+            continue;
+        }
+
+        for (column, value) in columns.iter().zip(value) {
+            if let Some(reason) = validate_value(column, value) {
+                errors.push(RowError {
+                    row,
+                    column: Some(column.name.clone()),
+                    reason,
+                });
+            }
+        }
+    }
+
+    errors
+}
+
+/// Returns the reason why the value can't be written to the column, if any.
+fn validate_value(column: &Column, value: &Value) -> Option<String> {
+    let ty = <&ColumnType as Into<&str>>::into(&column.ty);
+    match value {
+        Value::Number(_) if !matches!(column.ty, ColumnType::Integer | ColumnType::Float) => Some(
+            format!("The column has type {} but a number was supplied", ty),
+        ),
+        Value::Number(number) if !number.is_i64() && !number.is_f64() => {
+            Some("The number is not supported".to_string())
+        }
+        Value::String(_) if !matches!(column.ty, ColumnType::String) => Some(format!(
+            "The column has type {} but a string was supplied",
+            ty
+        )),
+        Value::Number(_) | Value::String(_) | Value::Null => None,
+        _ => Some("The value type is not supported".to_string()),
+    }
+}
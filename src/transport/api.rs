@@ -1,31 +1,221 @@
-use axum::extract::State;
-use axum::Json;
+use axum::extract::{Path, State};
+use axum::http::header::CONTENT_TYPE;
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::{Extension, Json};
 use log::info;
 use serde::{Deserialize, Serialize};
 use serde_json::Number;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::future::Future;
 use std::io::{Error, ErrorKind};
-use std::ops::Deref;
-use std::sync::Arc;
+use std::ops::{Add, Deref};
+use std::sync::{Arc, Mutex};
 
-use crate::config::Config;
+use crate::config::{Config, InstanceRole};
+use crate::io::clock::clock;
+use crate::io::encryption::encryption;
+use crate::system::audit::record_audit_entry;
+use crate::system::compaction::spawn_manual_compaction;
+use crate::system::idempotency::IdempotencyKeys;
+use crate::system::insert_sequences::InsertSequences;
+use crate::system::introspection::SystemTable;
+use crate::system::key_rotation::spawn_key_rotation;
+use crate::system::memtable::flush_memtables;
+use crate::system::placement::Placements;
+use crate::system::prepared_query::{PreparedExpressions, PreparedQueries};
+use crate::system::read_only::{ReadOnlyMode, ReadOnlyState};
+use crate::system::saved_query::{SavedQueries, SavedQuery};
+use crate::system::session::{NullHandling, OutputFormat, SessionSettings, Sessions};
+use crate::system::telemetry::set_span_attribute;
+use crate::system::usage::{Quota, Usage, UserUsage};
+use crate::system::users::{Role, User, Users};
 use crate::table::aggregate::Aggregate;
 use crate::table::column::{
-    try_parse_queried_column, AggregateColumn, Column as TableColumn,
+    parse_and_validate_columns, try_parse_queried_column, AggregateColumn, Column as TableColumn,
     ColumnType as TableColumnType, ColumnValue,
 };
+use crate::table::compression::{ColumnEncoding, CompressionAdvice};
 use crate::table::cursor::{AggregatedRow, Row};
-use crate::table::table::{QueryResult, TableDefinition};
-use crate::transport::shard::Shards;
+use crate::table::explain::TableExplanation;
+use crate::table::expression::{
+    parse_expression, parse_order_item, parse_select_item, Expression, OrderItem, SelectItem,
+    ROW_ID_COLUMN, TIMESTAMP_COLUMN,
+};
+use crate::table::fnv1a;
+use crate::table::lock::{QueryAdmission, QueryPriority, QuerySlots, TableLocks};
+use crate::table::memtable::memtables;
+use crate::table::metrics::column_writes;
+use crate::table::partition::TimeRange;
+use crate::table::sampling::Sample;
+use crate::table::schema::TableOptions;
+use crate::table::table::{repeated_values, QueryPlan, QueryResult, QuerySchema, TableDefinition};
+use crate::table::validation::{validate_rows, RowError};
+use crate::transport::auth::Principal;
+use crate::transport::origin::Origin;
+use crate::transport::pool::WorkerPools;
+use crate::transport::shard::{Shard, Shards};
+use crate::transport::shard_op::check_unique::CheckUnique;
+use crate::transport::shard_op::compact::Compact;
+use crate::transport::shard_op::create_index::CreateIndex;
 use crate::transport::shard_op::create_table::CreateTable;
+use crate::transport::shard_op::delete::Delete;
+use crate::transport::shard_op::delete_user::DeleteUser;
+use crate::transport::shard_op::drop_table::DropTable;
+use crate::transport::shard_op::explain::Explain;
 use crate::transport::shard_op::insert::Insert;
+use crate::transport::shard_op::list_tables::ListTables;
 use crate::transport::shard_op::query::Query;
+use crate::transport::shard_op::query_batch::QueryBatch;
+use crate::transport::shard_op::read_only::SetReadOnly;
+use crate::transport::shard_op::rotate_key::RotateKey;
+use crate::transport::shard_op::save_query::SaveQuery;
+use crate::transport::shard_op::save_user::SaveUser;
+use crate::transport::shard_op::status::Status;
+use crate::transport::shard_op::truncate_table::TruncateTable;
+use crate::transport::shard_op::vacuum::Vacuum;
 use futures::future::{join, join_all, BoxFuture, FutureExt};
 use tokio::io;
+use tokio::time::timeout;
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct CreateTableRequest {
     name: String,
     columns: Vec<Column>,
+    #[serde(flatten)]
+    options: TableOptions,
+}
+
+impl CreateTableRequest {
+    pub fn new(name: String, columns: Vec<Column>) -> Self {
+        Self {
+            name,
+            columns,
+            options: TableOptions::default(),
+        }
+    }
+}
+
+/// The schemas of tables, exported from a database to create the same tables in another one.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct SchemaDocument {
+    tables: Vec<CreateTableRequest>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct DropTableRequest {
+    name: String,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct TruncateTableRequest {
+    name: String,
+}
+
+/// The creation of a secondary index of a column of a table, which speeds up the queries filtering
+/// the column by a value or a range of values.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct CreateIndexRequest {
+    table: String,
+    column: String,
+}
+
+/// The lookup of the values of the unique column of a table which are already stored on a shard.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct CheckUniqueRequest {
+    table: String,
+    column: String,
+    values: Vec<serde_json::Value>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct CheckUniqueResponse {
+    existing: Vec<serde_json::Value>,
+}
+
+/// The deletion of the rows of a table matching a condition, e.g. `status = 'error'`.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct DeleteRequest {
+    from: String,
+    #[serde(alias = "where")]
+    filter: String,
+    /// The inclusive lower bound of the timestamp of the deleted rows.
+    #[serde(default)]
+    since: Option<u64>,
+    /// The exclusive upper bound of the timestamp of the deleted rows.
+    #[serde(default)]
+    until: Option<u64>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RotateKeyRequest {}
+
+/// The compaction of the partitions with deleted rows of a table, or of all tables if missing.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct CompactRequest {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    table: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ReadOnlyRequest {
+    pub read_only: bool,
+    /// Why the writes are rejected, which is returned along their errors.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    /// Whether the mode is applied to all the instances of the cluster, when sent to the master.
+    #[serde(default)]
+    pub cluster: bool,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ShardStatus {
+    ip_port: String,
+    reachable: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    /// The difference in milliseconds between the clock of the shard and the clock of the master.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    clock_skew_ms: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    warning: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct StatusResponse {
+    database_name: String,
+    role: String,
+    /// The time of the clock of the instance, in milliseconds since the epoch.
+    #[serde(default)]
+    time_ms: Option<u64>,
+    #[serde(default)]
+    shards: Vec<ShardStatus>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct TableInfo {
+    name: String,
+    columns: Vec<Column>,
+    rows: u64,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ColumnStats {
+    table: String,
+    column: String,
+    bytes_written: u64,
+    records_written: u64,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct SaveUserRequest {
+    name: String,
+    key: String,
+    role: Role,
+    #[serde(default)]
+    allowed_tables: Option<Vec<String>>,
+    #[serde(default)]
+    quotas: Vec<Quota>,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -34,6 +224,34 @@ pub struct Column {
     ty: ColumnType,
     #[serde(skip_serializing_if = "Option::is_none")]
     source_ty: Option<ColumnType>,
+    /// How the values of a column of a table are stored, which is raw if missing.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    encoding: Option<ColumnEncoding>,
+    /// Whether the segments of a column of a table keep a bloom filter of their values, which lets
+    /// the equality filters skip the segments without the value.
+    #[serde(default, skip_serializing_if = "is_false")]
+    bloom_filter: bool,
+    /// Whether the values of a column of a table can't be repeated, in which case the rows are
+    /// routed by it so that each value is checked on a single instance.
+    #[serde(default, skip_serializing_if = "is_false")]
+    unique: bool,
+}
+
+impl Column {
+    pub fn new(name: String, ty: ColumnType) -> Self {
+        Self {
+            name,
+            ty,
+            source_ty: None,
+            encoding: None,
+            bloom_filter: false,
+            unique: false,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
 }
 
 impl From<TableColumn> for Column {
@@ -42,6 +260,9 @@ impl From<TableColumn> for Column {
             name: value.name,
             ty: value.ty.into(),
             source_ty: None,
+            encoding: None,
+            bloom_filter: false,
+            unique: false,
         }
     }
 }
@@ -83,6 +304,18 @@ impl From<TableColumnType> for ColumnType {
     }
 }
 
+impl ColumnType {
+    /// Returns the value which replaces the nulls of the column when they are returned as defaults.
+    fn default_value(&self) -> serde_json::Value {
+        match self {
+            ColumnType::Integer => serde_json::Value::from(0),
+            ColumnType::Float => serde_json::Value::from(0.0),
+            ColumnType::String => serde_json::Value::from(""),
+            ColumnType::Null => serde_json::Value::Null,
+        }
+    }
+}
+
 impl<'a> From<&'a ColumnValue> for ColumnType {
     fn from(value: &'a ColumnValue) -> Self {
         match value {
@@ -99,9 +332,78 @@ pub struct InsertRequest {
     insert: Vec<String>,
     into: String,
     values: Vec<Vec<serde_json::Value>>,
+    /// The columns whose values determine the shard of each row, so that the rows with the same
+    /// values are stored on the same shard.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    route_by: Option<Vec<String>>,
+    /// The sequence identifying the inserts sent by the master to a shard, so that the shard
+    /// applies them only once if the master retries them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    sequence: Option<u64>,
+    /// The timestamp of the rows assigned by the master, if configured, so that the rows of all
+    /// the shards are ordered by the clock of the master.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    timestamp: Option<u64>,
+    /// Whether the valid rows are written when some rows are invalid, instead of rejecting the
+    /// whole insert.
+    #[serde(default)]
+    partial: bool,
+    /// Whether the rows replace all the rows of the table, which are removed under the same lock
+    /// as the insert.
+    #[serde(default)]
+    replace: bool,
 }
 
 impl InsertRequest {
+    pub fn new(insert: Vec<String>, into: String, values: Vec<Vec<serde_json::Value>>) -> Self {
+        Self {
+            insert,
+            into,
+            values,
+            route_by: None,
+            sequence: None,
+            timestamp: None,
+            partial: false,
+            replace: false,
+        }
+    }
+
+    /// Makes the rows replace all the rows of the table if `replace` is set.
+    pub fn replacing(self, replace: bool) -> Self {
+        Self { replace, ..self }
+    }
+
+    /// Splits the insert request into `n` insert requests, putting the rows with the same values
+    /// of the `route_by` columns in the same request.
+    ///
+    /// The same values always end up in the request at the same position, so that they are sent
+    /// to the same shard by all the insertions.
+    pub fn split_by_key(&mut self, n: usize) -> io::Result<Vec<InsertRequest>> {
+        let route_by = self.route_by.take().unwrap_or_default();
+        let key_indexes = route_by
+            .iter()
+            .map(|column| {
+                self.insert.iter().position(|c| c == column).ok_or_else(|| {
+                    Error::new(
+                        ErrorKind::InvalidInput,
+                        format!("The route by column {} is not inserted", column),
+                    )
+                })
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+
+        let mut requests: Vec<_> = (0..n)
+            .map(|_| InsertRequest::new(self.insert.clone(), self.into.clone(), vec![]))
+            .collect();
+        for row in self.values.drain(..) {
+            let key: Vec<_> = key_indexes.iter().map(|&i| row.get(i)).collect();
+            let index = route_index(&key, n)?;
+            requests[index].values.push(row);
+        }
+
+        Ok(requests)
+    }
+
     /// Splits the insert request into multiple insert requests that contain a subset of the values
     /// each.
     pub fn split(&mut self, n: usize) -> Vec<InsertRequest> {
@@ -113,344 +415,3931 @@ impl InsertRequest {
 
         // Map each chunk into a new InsertRequest
         chunks
-            .map(|chunk| InsertRequest {
-                insert: self.insert.clone(),
-                into: self.into.clone(),
-                values: chunk.to_vec(),
-            })
+            .map(|chunk| InsertRequest::new(self.insert.clone(), self.into.clone(), chunk.to_vec()))
             .collect()
     }
 }
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
-pub struct QueryRequest {
-    select: Vec<String>,
-    from: String,
-    #[serde(default)]
-    group_by: Option<Vec<String>>,
-}
-
-#[derive(Debug, Deserialize, Serialize)]
-pub struct AggregateData {
-    value: serde_json::Value,
-    components: Vec<serde_json::Value>,
+/// Returns the position of the request to which the row with the routing key is split, out of `n`.
+fn route_index(key: &[Option<&serde_json::Value>], n: usize) -> io::Result<usize> {
+    Ok((fnv1a(&serde_json::to_vec(key)?) % n as u64) as usize)
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+/// The data read by a query, either a single table, several tables with compatible columns whose
+/// rows are concatenated, e.g. `["events_2023", "events_2024"]`, or the result of another query.
+#[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(untagged)]
-pub enum QueryResponse {
-    Empty {
-        errors: Vec<String>,
-    },
-    WithAggregatedData {
-        columns: Vec<Column>,
-        aggregate_columns: Vec<Column>,
-        data: Vec<Vec<serde_json::Value>>,
-        aggregates: Vec<Vec<AggregateData>>,
-    },
-    WithData {
-        columns: Vec<Column>,
-        data: Vec<Vec<serde_json::Value>>,
-    },
+pub enum QuerySource {
+    Table(String),
+    Union(Vec<String>),
+    /// The result of the subquery is computed on the whole cluster and then queried by the instance
+    /// which received the query, so its aggregates must have an alias to be queried.
+    Query(Box<QueryRequest>),
 }
 
-impl QueryResponse {
-    pub fn to_query_result(self) -> QueryResult {
+impl QuerySource {
+    /// Returns the tables read by the query, including the ones read by its subquery.
+    pub fn tables(&self) -> &[String] {
         match self {
-            QueryResponse::Empty { .. } => {
-                info!("An empty query response was received and was converted to empty rows");
-                QueryResult::Rows(vec![])
-            }
-            QueryResponse::WithData { columns, data } => {
-                Self::build_row_query_result(columns, data)
+            QuerySource::Table(table) => std::slice::from_ref(table),
+            QuerySource::Union(tables) => tables,
+            QuerySource::Query(query) => query.from.tables(),
+        }
+    }
+
+    /// Returns the system table read by the query, which can't be queried with other tables.
+    fn system_table(&self) -> io::Result<Option<SystemTable>> {
+        match self {
+            QuerySource::Table(table) => Ok(SystemTable::from_name(table)),
+            QuerySource::Query(query) => match query.from.system_table()? {
+                Some(_) => Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    "The system tables can't be queried in subqueries",
+                )),
+                None => Ok(None),
+            },
+            QuerySource::Union(tables) => {
+                match tables.iter().find(|t| SystemTable::from_name(t).is_some()) {
+                    Some(table) => Err(Error::new(
+                        ErrorKind::InvalidInput,
+                        format!(
+                            "The system table {} can't be queried with other tables",
+                            table
+                        ),
+                    )),
+                    None => Ok(None),
+                }
             }
-            QueryResponse::WithAggregatedData {
-                columns,
-                aggregate_columns,
-                data,
-                aggregates,
-            } => Self::build_aggregated_row_query_result(
-                columns,
-                aggregate_columns,
-                data,
-                aggregates,
-            ),
         }
     }
 
-    fn build_row_query_result(
-        columns: Vec<Column>,
-        data: Vec<Vec<serde_json::Value>>,
-    ) -> QueryResult {
-        let mut rows = vec![];
-        for data_row in data {
-            let Some(row) = Row::from_components(
-                // TODO: figure out if we need propagation of index_id and timestamp.
-                0,
-                0,
-                columns
-                    .iter()
-                    .zip(data_row.into_iter())
-                    .map(|(c, v)| Self::build_column_and_column_value(c, v)),
-            ) else {
-                info!("Row skipped during conversion");
-                continue;
-            };
-            rows.push(row);
+    /// Checks that at least one table is queried and that no table is queried twice, which would
+    /// return its rows twice.
+    fn validate(&self) -> io::Result<()> {
+        if let QuerySource::Query(query) = self {
+            return query.from.validate();
         }
 
-        QueryResult::Rows(rows)
+        let tables = self.tables();
+        if tables.is_empty() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "At least one table must be queried",
+            ));
+        }
+
+        for (index, table) in tables.iter().enumerate() {
+            if tables[..index].contains(table) {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("The table {} is queried more than once", table),
+                ));
+            }
+        }
+
+        Ok(())
     }
+}
 
-    fn build_aggregated_row_query_result(
-        columns: Vec<Column>,
-        aggregate_columns: Vec<Column>,
-        data: Vec<Vec<serde_json::Value>>,
-        aggregates: Vec<Vec<AggregateData>>,
-    ) -> QueryResult {
-        let mut aggregated_rows = vec![];
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct QueryRequest {
+    select: Vec<String>,
+    from: QuerySource,
+    #[serde(default)]
+    group_by: Option<Vec<String>>,
+    /// The inclusive lower bound of the timestamp of the queried rows.
+    #[serde(default, alias = "from_timestamp")]
+    since: Option<u64>,
+    /// The exclusive upper bound of the timestamp of the queried rows.
+    #[serde(default, alias = "to_timestamp")]
+    until: Option<u64>,
+    /// The condition which the queried rows must match, e.g. `price > 10 and category = 'food'`.
+    #[serde(default, alias = "where")]
+    filter: Option<String>,
+    /// The selected expressions by which the results are sorted, e.g. `sum(price) desc`.
+    #[serde(default)]
+    order_by: Option<Vec<String>>,
+    /// Whether the rows with the same selected values are returned only once, e.g. to list the
+    /// values of a column.
+    #[serde(default)]
+    select_distinct: bool,
+    /// Whether the timestamp and the id of each row are appended to its values, as the
+    /// `__timestamp` and `__row_id` columns.
+    #[serde(default)]
+    include_timestamps: bool,
+    /// Whether the timestamps of the rows are returned next to their ids, which is set on the
+    /// queries sent to the shards so that the merged rows keep their timestamps.
+    #[serde(default, skip_serializing_if = "is_false")]
+    row_timestamps: bool,
+    /// The share of the rows which are read, either a number of rows or a fraction, to run cheap
+    /// exploratory queries whose aggregates are computed on the sampled rows only.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    sample: Option<Sample>,
+    /// The priority with which the query is admitted when the instance limits the concurrent
+    /// queries.
+    #[serde(default)]
+    priority: QueryPriority,
+}
 
-        for (data_row, aggregates_row) in data.into_iter().zip(aggregates.into_iter()) {
-            let values = columns
-                .iter()
-                .zip(data_row.into_iter())
-                .map(|(c, v)| Self::build_column_and_column_value(c, v));
+impl QueryRequest {
+    /// Parses the selected and grouped expressions, returning the request with the expressions in
+    /// their canonical form, which is the one sent to the shards and queried on the tables, and the
+    /// aliases of the expressions.
+    fn normalize(mut self) -> io::Result<(Self, HashMap<String, String>)> {
+        self.from.validate()?;
+        if let Some(sample) = &self.sample {
+            sample.validate()?;
+        }
 
-            let aggregates = aggregate_columns
-                .iter()
-                .zip(aggregates_row.into_iter())
-                .map(|(c, v)| Self::build_aggregated_row_component(c, v));
+        let mut aliases = HashMap::new();
+        let mut select = Vec::with_capacity(self.select.len());
+        let mut not_aggregated = vec![];
+        let mut is_aggregated = self.group_by.is_some();
+        for queried_column in self.select.iter() {
+            let select_item = parse_select_item(queried_column)?;
+            let expression = select_item.expression.unqualify(self.from.tables())?;
+            // The aggregates and columns are validated here too, so that invalid queries are
+            // rejected before being sent to the shards.
+            let is_aggregate = expression.to_aggregate_column()?.0.is_some();
+            is_aggregated |= is_aggregate;
 
-            let aggregated_row = AggregatedRow::new(values, aggregates);
-            aggregated_rows.push(aggregated_row);
+            let canonical = expression.to_string();
+            if let Some(alias) = select_item.alias {
+                aliases.insert(canonical.clone(), alias);
+            }
+            if !is_aggregate {
+                not_aggregated.push(canonical.clone());
+            }
+            select.push(canonical);
         }
+        self.select = select;
 
-        QueryResult::AggregatedRows(aggregated_rows)
-    }
+        if self.groups_by_all() {
+            self.group_by = Some(not_aggregated);
+        }
 
-    fn build_column_and_column_value(
-        column: &Column,
-        value: serde_json::Value,
-    ) -> (TableColumn, ColumnValue) {
-        let table_column = column.clone().into();
-        match (&column.ty, value) {
-            (ColumnType::Integer, serde_json::Value::Number(number)) => {
-                if number.is_i64() {
-                    return (table_column, ColumnValue::Integer(number.as_i64().unwrap()));
-                }
+        if self.include_timestamps {
+            if is_aggregated {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    "The timestamps can't be included in the results of aggregated queries",
+                ));
             }
-            (ColumnType::Float, serde_json::Value::Number(number)) => {
-                if number.is_f64() {
-                    return (table_column, ColumnValue::Float(number.as_f64().unwrap()));
+
+            // The columns are appended only once the results of the shards are merged, so the
+            // shards only return the timestamps of their rows.
+            self.include_timestamps = false;
+            self.row_timestamps = true;
+        }
+
+        if let Some(group_by) = self.group_by.take() {
+            let mut canonical_group_by = Vec::with_capacity(group_by.len());
+            for expression in group_by.iter() {
+                let select_item = parse_select_item(expression)?;
+                if select_item.alias.is_some() {
+                    return Err(Error::new(
+                        ErrorKind::InvalidInput,
+                        format!("The group by expression {} can't have an alias", expression),
+                    ));
                 }
+                canonical_group_by.push(
+                    select_item
+                        .expression
+                        .unqualify(self.from.tables())?
+                        .to_string(),
+                );
             }
-            (ColumnType::String, serde_json::Value::String(string)) => {
-                return (table_column, ColumnValue::String(string));
-            }
-            (ColumnType::Null, serde_json::Value::Null) => {
-                return (table_column, ColumnValue::Null);
+            self.group_by = Some(canonical_group_by);
+        }
+
+        if let Some(filter) = self.filter.take() {
+            self.filter = Some(
+                parse_expression(&filter)?
+                    .unqualify(self.from.tables())?
+                    .to_string(),
+            );
+        }
+
+        if let Some(order_by) = self.order_by.take() {
+            let mut canonical_order_by = Vec::with_capacity(order_by.len());
+            for order_item in order_by.iter() {
+                let mut order_item = parse_order_item(order_item)?;
+                order_item.expression = order_item.expression.unqualify(self.from.tables())?;
+
+                // The results can also be sorted by the alias of a selected expression.
+                let canonical = order_item.expression.to_string();
+                if let Some((aliased, _)) = aliases.iter().find(|(_, a)| **a == canonical) {
+                    order_item.expression = parse_expression(aliased)?;
+                }
+
+                let canonical = order_item.expression.to_string();
+                let is_output = self.select.contains(&canonical)
+                    || self
+                        .group_by
+                        .as_ref()
+                        .is_some_and(|g| g.contains(&canonical));
+                if !is_output {
+                    return Err(Error::new(
+                        ErrorKind::InvalidInput,
+                        format!(
+                            "The results can't be sorted by {}, since it's not selected",
+                            canonical
+                        ),
+                    ));
+                }
+                canonical_order_by.push(order_item.to_string());
             }
-            _ => {}
+            self.order_by = Some(canonical_order_by);
         }
 
-        (table_column, ColumnValue::Null)
+        Ok((self, aliases))
     }
 
-    fn build_aggregated_row_component(
-        column: &Column,
-        aggregate_data: AggregateData,
-    ) -> (AggregateColumn, ColumnValue, Vec<ColumnValue>) {
-        let (Some(aggregate), column_name) =
-            try_parse_queried_column(&column.name).expect("Error while parsing column")
-        else {
-            return (
-                AggregateColumn(Aggregate::Count, column.clone().into()),
-                ColumnValue::Null,
-                vec![],
-            );
-        };
+    /// Returns whether the query is grouped by all its selected expressions which aren't
+    /// aggregates, with `group_by: ["*"]` or `group_by: ["all"]`.
+    fn groups_by_all(&self) -> bool {
+        match self.group_by.as_deref() {
+            Some([group_by]) => {
+                let group_by = group_by.trim();
+                group_by == "*" || group_by.eq_ignore_ascii_case("all")
+            }
+            _ => false,
+        }
+    }
 
-        // Since we don't have access to the original column on which the aggregate was run, we type
-        // it to null.
-        let original_column = Column {
-            name: column_name.to_string(),
-            ty: column
-                .source_ty
+    /// Parses the expressions of the prepared request, including the ones of its subquery, so that
+    /// its executions only bind their placeholders.
+    fn parse_expressions(&self) -> io::Result<PreparedExpressions> {
+        let group_by = match self.groups_by_all() {
+            true => None,
+            false => self
+                .group_by
                 .as_ref()
-                .expect("An aggregate column must have a source type")
-                .clone(),
-            source_ty: None,
+                .map(|g| g.iter().map(|e| parse_expression(e)).collect())
+                .transpose()?,
         };
-        let (main_column, column_value) =
-            Self::build_column_and_column_value(&original_column, aggregate_data.value);
-        let aggregate_column = AggregateColumn(aggregate, main_column);
+        let subquery = match &self.from {
+            QuerySource::Query(query) => Some(Box::new(query.parse_expressions()?)),
+            _ => None,
+        };
+
+        Ok(PreparedExpressions {
+            select: self
+                .select
+                .iter()
+                .map(|s| parse_select_item(s))
+                .collect::<io::Result<_>>()?,
+            group_by,
+            filter: self.filter.as_deref().map(parse_expression).transpose()?,
+            order_by: self
+                .order_by
+                .as_ref()
+                .map(|o| o.iter().map(|o| parse_order_item(o)).collect())
+                .transpose()?,
+            subquery,
+        })
+    }
+
+    /// Replaces the expressions of the prepared request with its parsed ones, in which the
+    /// placeholders are bound to the parameters.
+    fn bind_parameters(
+        mut self,
+        expressions: &PreparedExpressions,
+        params: &[ColumnValue],
+    ) -> io::Result<Self> {
+        if let (QuerySource::Query(query), Some(subquery)) = (&mut self.from, &expressions.subquery)
+        {
+            **query = query.as_ref().clone().bind_parameters(subquery, params)?;
+        }
+
+        let bind = |e: &Expression| e.clone().bind_parameters(params);
+        self.select = expressions
+            .select
+            .iter()
+            .map(|s| {
+                let expression = bind(&s.expression)?;
+                let alias = s.alias.clone();
+                Ok(SelectItem { expression, alias }.to_string())
+            })
+            .collect::<io::Result<_>>()?;
+        if let Some(group_by) = &expressions.group_by {
+            let group_by = group_by.iter().map(|e| bind(e).map(|e| e.to_string()));
+            self.group_by = Some(group_by.collect::<io::Result<_>>()?);
+        }
+        self.filter = expressions
+            .filter
+            .as_ref()
+            .map(|e| bind(e).map(|e| e.to_string()))
+            .transpose()?;
+        if let Some(order_by) = &expressions.order_by {
+            let order_by = order_by.iter().map(|o| {
+                let expression = bind(&o.expression)?;
+                let descending = o.descending;
+                Ok(OrderItem {
+                    expression,
+                    descending,
+                }
+                .to_string())
+            });
+            self.order_by = Some(order_by.collect::<io::Result<_>>()?);
+        }
+
+        Ok(self)
+    }
+}
+
+/// Queries executed together, whose responses are returned in the same order.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct QueryBatchRequest {
+    queries: Vec<QueryRequest>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RunQueryRequest {
+    #[serde(default)]
+    params: HashMap<String, serde_json::Value>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct PrepareResponse {
+    /// The id with which the prepared query is executed.
+    id: u64,
+    parameters: usize,
+}
+
+/// The execution of a prepared query, whose `$1` placeholder is bound to the first parameter.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ExecuteRequest {
+    id: u64,
+    #[serde(default)]
+    params: Vec<serde_json::Value>,
+}
+
+/// How a query would be run on the cluster, without running it.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ExplainResponse {
+    /// The query in its canonical form, which is the one sent to the shards.
+    query: QueryRequest,
+    /// How the query would be run on each queried table of this instance.
+    #[serde(default)]
+    tables: Vec<TableExplanation>,
+    /// The subquery whose result is queried, which runs first on the whole cluster.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    subquery: Option<Box<ExplainResponse>>,
+    /// The shards to which the query would be sent, with how they would run it.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    shards: Vec<ShardExplanation>,
+    /// The rows scanned by the query on the whole cluster, estimated from the table stats.
+    estimated_rows: u64,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ShardExplanation {
+    ip_port: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    tables: Vec<TableExplanation>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct AggregateData {
+    /// The final value of the aggregate, omitted in the partial responses of the shards since it's
+    /// computed again from the merged components.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    value: Option<serde_json::Value>,
+    components: Vec<serde_json::Value>,
+}
+
+/// The response of the operations which don't return data, either a success or an error message.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OpResponse {
+    Success(String),
+    Error(String),
+}
+
+/// The response of an insert, with the rows which were rejected because they are invalid.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct InsertResponse {
+    #[serde(flatten)]
+    response: OpResponse,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    rejected_rows: Vec<RowError>,
+}
+
+impl InsertResponse {
+    pub fn new(response: OpResponse, rejected_rows: Vec<RowError>) -> Self {
+        Self {
+            response,
+            rejected_rows,
+        }
+    }
+
+    pub fn is_error(&self) -> bool {
+        matches!(self.response, OpResponse::Error(_))
+    }
+
+    /// Returns the response if it's a success, or its message as an error otherwise.
+    pub fn into_result(self) -> io::Result<Self> {
+        let response = self.response.into_result()?;
+        Ok(Self::new(response, self.rejected_rows))
+    }
+}
+
+/// The rewrite of the files of a table without its deleted rows, which reclaims their disk space.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct VacuumRequest {
+    table: String,
+}
+
+/// The number of deleted rows removed by a vacuum and the number of bytes it reclaimed.
+type VacuumTotals = (u64, u64);
+
+/// The response of a vacuum, with the number of deleted rows which were removed from the files and
+/// the number of bytes reclaimed.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct VacuumResponse {
+    #[serde(flatten)]
+    response: OpResponse,
+    #[serde(default)]
+    vacuumed_rows: u64,
+    #[serde(default)]
+    reclaimed_bytes: u64,
+}
+
+impl VacuumResponse {
+    fn from_result(result: io::Result<VacuumTotals>) -> Self {
+        match result {
+            Ok((vacuumed_rows, reclaimed_bytes)) => Self {
+                response: OpResponse::from_result(Ok(()), "Table vacuumed successfully"),
+                vacuumed_rows,
+                reclaimed_bytes,
+            },
+            Err(error) => Self {
+                response: OpResponse::from_result(Err(error), ""),
+                vacuumed_rows: 0,
+                reclaimed_bytes: 0,
+            },
+        }
+    }
+
+    /// Returns the response if it's a success, or its message as an error otherwise.
+    pub fn into_result(self) -> io::Result<Self> {
+        let response = self.response.into_result()?;
+        Ok(Self { response, ..self })
+    }
+}
+
+/// The response of a delete, with the number of rows which were deleted.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct DeleteResponse {
+    #[serde(flatten)]
+    response: OpResponse,
+    #[serde(default)]
+    deleted_rows: u64,
+}
+
+impl DeleteResponse {
+    fn from_result(result: io::Result<u64>) -> Self {
+        match result {
+            Ok(deleted_rows) => Self {
+                response: OpResponse::from_result(Ok(()), "Rows deleted successfully"),
+                deleted_rows,
+            },
+            Err(error) => Self {
+                response: OpResponse::from_result(Err(error), ""),
+                deleted_rows: 0,
+            },
+        }
+    }
+
+    /// Returns the response if it's a success, or its message as an error otherwise.
+    pub fn into_result(self) -> io::Result<Self> {
+        let response = self.response.into_result()?;
+        Ok(Self {
+            response,
+            deleted_rows: self.deleted_rows,
+        })
+    }
+}
+
+impl OpResponse {
+    fn from_result(result: io::Result<()>, success: &str) -> Self {
+        match result {
+            Ok(_) => {
+                info!("{}", success);
+                OpResponse::Success(success.to_string())
+            }
+            Err(e) => {
+                info!("{}", e);
+                OpResponse::Error(e.to_string())
+            }
+        }
+    }
+
+    /// Returns the response if it's a success, or its message as an error otherwise.
+    pub fn into_result(self) -> io::Result<Self> {
+        match self {
+            OpResponse::Success(_) => Ok(self),
+            OpResponse::Error(error) => Err(Error::other(error)),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum QueryResponse {
+    Empty {
+        errors: Vec<String>,
+        #[serde(flatten)]
+        scan_stats: ScanStats,
+    },
+    WithAggregatedData {
+        columns: Vec<Column>,
+        aggregate_columns: Vec<Column>,
+        data: Vec<Vec<serde_json::Value>>,
+        aggregates: Vec<Vec<AggregateData>>,
+        #[serde(flatten)]
+        scan_stats: ScanStats,
+    },
+    WithData {
+        columns: Vec<Column>,
+        data: Vec<Vec<serde_json::Value>>,
+        /// The ids of the rows, unique in the cluster, in the same order as the data.
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        row_ids: Vec<u64>,
+        /// The timestamps of the rows, in the same order as the data, if requested.
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        timestamps: Vec<u64>,
+        #[serde(flatten)]
+        scan_stats: ScanStats,
+    },
+}
+
+fn is_false(value: &bool) -> bool {
+    !*value
+}
+
+fn is_zero(value: &u64) -> bool {
+    *value == 0
+}
+
+/// The statistics of the scans run to compute a query response.
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize)]
+pub struct ScanStats {
+    /// The number of rows read from disk.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    scanned_rows: u64,
+    /// The number of column records read without belonging to the row being read, while aligning
+    /// the columns with the index, which grows when the columns are inserted sparsely.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    skipped_records: u64,
+    /// The number of column segments which weren't read, since their values couldn't match the
+    /// filter.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pruned_segments: u64,
+    /// The number of partitions which weren't read, since their bucket of the partition key
+    /// couldn't match the filter.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pruned_partitions: u64,
+}
+
+impl Add for ScanStats {
+    type Output = ScanStats;
+
+    fn add(self, other: ScanStats) -> ScanStats {
+        ScanStats {
+            scanned_rows: self.scanned_rows + other.scanned_rows,
+            skipped_records: self.skipped_records + other.skipped_records,
+            pruned_segments: self.pruned_segments + other.pruned_segments,
+            pruned_partitions: self.pruned_partitions + other.pruned_partitions,
+        }
+    }
+}
+
+impl QueryResponse {
+    pub fn to_query_result(self) -> QueryResult {
+        match self {
+            QueryResponse::Empty { .. } => {
+                info!("An empty query response was received and was converted to empty rows");
+                QueryResult::Rows(vec![])
+            }
+            QueryResponse::WithData {
+                columns,
+                data,
+                row_ids,
+                timestamps,
+                ..
+            } => Self::build_row_query_result(columns, data, row_ids, timestamps),
+            QueryResponse::WithAggregatedData {
+                columns,
+                aggregate_columns,
+                data,
+                aggregates,
+                ..
+            } => Self::build_aggregated_row_query_result(
+                columns,
+                aggregate_columns,
+                data,
+                aggregates,
+            ),
+        }
+    }
+
+    /// Returns the columns and the rows of the result of a subquery, in which the final values of
+    /// the aggregates are columns like the grouped ones.
+    fn into_subquery_rows(self) -> (Vec<TableColumn>, Vec<Row<ColumnValue>>) {
+        let (columns, result) = match self {
+            QueryResponse::Empty { .. } => (vec![], QueryResult::Rows(vec![])),
+            QueryResponse::WithData {
+                columns,
+                data,
+                row_ids,
+                timestamps,
+                ..
+            } => {
+                let result =
+                    Self::build_row_query_result(columns.clone(), data, row_ids, timestamps);
+                (columns, result)
+            }
+            QueryResponse::WithAggregatedData {
+                mut columns,
+                aggregate_columns,
+                data,
+                aggregates,
+                ..
+            } => {
+                let data = data
+                    .into_iter()
+                    .zip(aggregates)
+                    .map(|(mut data_row, aggregate_row)| {
+                        data_row.extend(
+                            aggregate_row
+                                .into_iter()
+                                .map(|a| a.value.unwrap_or(serde_json::Value::Null)),
+                        );
+                        data_row
+                    })
+                    .collect();
+                columns.extend(aggregate_columns);
+                let result = Self::build_row_query_result(columns.clone(), data, vec![], vec![]);
+                (columns, result)
+            }
+        };
+
+        let rows = match result {
+            QueryResult::Rows(rows) => rows,
+            QueryResult::AggregatedRows(_) => vec![],
+        };
+        (columns.into_iter().map(|c| c.into()).collect(), rows)
+    }
+
+    fn build_row_query_result(
+        columns: Vec<Column>,
+        data: Vec<Vec<serde_json::Value>>,
+        row_ids: Vec<u64>,
+        timestamps: Vec<u64>,
+    ) -> QueryResult {
+        let mut rows = vec![];
+        // Responses of older instances don't have the row ids, and the timestamps are returned
+        // only if requested.
+        let row_ids = row_ids.into_iter().chain(std::iter::repeat(0));
+        let timestamps = timestamps.into_iter().chain(std::iter::repeat(0));
+        for ((data_row, row_id), timestamp) in data.into_iter().zip(row_ids).zip(timestamps) {
+            let Some(row) = Row::from_components(
+                row_id,
+                timestamp,
+                columns
+                    .iter()
+                    .zip(data_row.into_iter())
+                    .map(|(c, v)| Self::build_column_and_column_value(c, v)),
+            ) else {
+                info!("Row skipped during conversion");
+                continue;
+            };
+            rows.push(row);
+        }
+
+        QueryResult::Rows(rows)
+    }
+
+    fn build_aggregated_row_query_result(
+        columns: Vec<Column>,
+        aggregate_columns: Vec<Column>,
+        data: Vec<Vec<serde_json::Value>>,
+        aggregates: Vec<Vec<AggregateData>>,
+    ) -> QueryResult {
+        let mut aggregated_rows = vec![];
+
+        for (data_row, aggregates_row) in data.into_iter().zip(aggregates.into_iter()) {
+            let values = columns
+                .iter()
+                .zip(data_row.into_iter())
+                .map(|(c, v)| Self::build_column_and_column_value(c, v));
+
+            let aggregates = aggregate_columns
+                .iter()
+                .zip(aggregates_row.into_iter())
+                .map(|(c, v)| Self::build_aggregated_row_component(c, v));
+
+            let aggregated_row = AggregatedRow::new(values, aggregates);
+            aggregated_rows.push(aggregated_row);
+        }
+
+        QueryResult::AggregatedRows(aggregated_rows)
+    }
+
+    fn build_column_and_column_value(
+        column: &Column,
+        value: serde_json::Value,
+    ) -> (TableColumn, ColumnValue) {
+        let table_column = column.clone().into();
+        match (&column.ty, value) {
+            (ColumnType::Integer, serde_json::Value::Number(number)) => {
+                if number.is_i64() {
+                    return (table_column, ColumnValue::Integer(number.as_i64().unwrap()));
+                }
+            }
+            (ColumnType::Float, serde_json::Value::Number(number)) => {
+                if number.is_f64() {
+                    return (table_column, ColumnValue::Float(number.as_f64().unwrap()));
+                }
+            }
+            (ColumnType::String, serde_json::Value::String(string)) => {
+                return (table_column, ColumnValue::String(string));
+            }
+            (ColumnType::Null, serde_json::Value::Null) => {
+                return (table_column, ColumnValue::Null);
+            }
+            _ => {}
+        }
+
+        (table_column, ColumnValue::Null)
+    }
+
+    fn build_aggregated_row_component(
+        column: &Column,
+        aggregate_data: AggregateData,
+    ) -> (AggregateColumn, ColumnValue, Vec<ColumnValue>) {
+        let (Some(aggregate), column_name, filter) =
+            try_parse_queried_column(&column.name).expect("Error while parsing column")
+        else {
+            return (
+                AggregateColumn(Aggregate::Count, column.clone().into(), None),
+                ColumnValue::Null,
+                vec![],
+            );
+        };
+
+        // Since we don't have access to the original column on which the aggregate was run, we type
+        // it to null.
+        let original_column = Column {
+            name: column_name,
+            ty: column
+                .source_ty
+                .as_ref()
+                .expect("An aggregate column must have a source type")
+                .clone(),
+            source_ty: None,
+            encoding: None,
+            bloom_filter: false,
+            unique: false,
+        };
+        let (main_column, column_value) = Self::build_column_and_column_value(
+            &original_column,
+            aggregate_data.value.unwrap_or_default(),
+        );
+        // The components are typed like the aggregate, unless they are values of the column, which
+        // can be preceded by the timestamp of the row whose value was picked.
+        let components_column = match aggregate.has_value_components() {
+            true => &original_column,
+            false => column,
+        };
+        let timestamp_column = Column::new(TIMESTAMP_COLUMN.to_string(), ColumnType::Integer);
+        let aggregate_components = aggregate_data
+            .components
+            .into_iter()
+            .enumerate()
+            .map(|(index, v)| {
+                let column = match index == 0 && aggregate.has_timestamp_component() {
+                    true => &timestamp_column,
+                    false => components_column,
+                };
+                Self::build_column_and_column_value(column, v).1
+            })
+            .collect();
+        let aggregate_column = AggregateColumn(aggregate, main_column, filter);
+
+        (aggregate_column, column_value, aggregate_components)
+    }
+
+    /// Converts the response into columns and rows which can be inserted into a table.
+    ///
+    /// Aggregate columns are flattened into regular columns whose name is derived from the
+    /// aggregate expression (e.g. `sum(price)` becomes `sum_price`).
+    pub fn into_table_data(self) -> io::Result<(Vec<Column>, Vec<Vec<serde_json::Value>>)> {
+        match self {
+            QueryResponse::Empty { errors, .. } if !errors.is_empty() => Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("The query failed: {}", errors.join(", ")),
+            )),
+            QueryResponse::Empty { .. } => Ok((vec![], vec![])),
+            QueryResponse::WithData { columns, data, .. } => Ok((columns, data)),
+            QueryResponse::WithAggregatedData {
+                mut columns,
+                aggregate_columns,
+                data,
+                aggregates,
+                ..
+            } => {
+                columns.extend(aggregate_columns.into_iter().map(|c| {
+                    // An aggregate of only nulls has no type, so we fall back to a string column.
+                    let ty = match c.ty {
+                        ColumnType::Null => ColumnType::String,
+                        ty => ty,
+                    };
+                    Column::new(sanitize_column_name(&c.name), ty)
+                }));
+
+                let data = data
+                    .into_iter()
+                    .zip(aggregates)
+                    .map(|(mut values, aggregates)| {
+                        values.extend(aggregates.into_iter().map(|a| a.value.unwrap_or_default()));
+                        values
+                    })
+                    .collect();
+
+                Ok((columns, data))
+            }
+        }
+    }
+
+    /// Returns the response if the query succeeded, or its errors as an error otherwise.
+    pub fn into_result(self) -> io::Result<Self> {
+        match self {
+            QueryResponse::Empty { errors, .. } if !errors.is_empty() => {
+                Err(Error::other(errors.join(", ")))
+            }
+            _ => Ok(self),
+        }
+    }
+
+    pub fn error(error: String) -> Self {
+        Self::Empty {
+            errors: vec![error],
+            scan_stats: ScanStats::default(),
+        }
+    }
+
+    /// Renames the columns which have an alias.
+    fn with_aliases(mut self, aliases: &HashMap<String, String>) -> Self {
+        let columns = match &mut self {
+            QueryResponse::Empty { .. } => return self,
+            QueryResponse::WithAggregatedData {
+                columns,
+                aggregate_columns,
+                ..
+            } => columns.iter_mut().chain(aggregate_columns.iter_mut()),
+            QueryResponse::WithData { columns, .. } => columns.iter_mut().chain([].iter_mut()),
+        };
+        for column in columns {
+            if let Some(alias) = aliases.get(&column.name) {
+                column.name = alias.clone();
+            }
+        }
+
+        self
+    }
+
+    /// Appends the timestamps and the ids of the rows to their values, as the last columns.
+    fn with_timestamp_columns(mut self) -> Self {
+        if let QueryResponse::WithData {
+            columns,
+            data,
+            row_ids,
+            timestamps,
+            ..
+        } = &mut self
+        {
+            columns.push(Column::new(
+                TIMESTAMP_COLUMN.to_string(),
+                ColumnType::Integer,
+            ));
+            columns.push(Column::new(ROW_ID_COLUMN.to_string(), ColumnType::Integer));
+            let timestamps = std::mem::take(timestamps);
+            for ((values, row_id), timestamp) in data.iter_mut().zip(row_ids.iter()).zip(timestamps)
+            {
+                values.push(serde_json::Value::from(timestamp));
+                values.push(serde_json::Value::from(*row_id));
+            }
+        }
+
+        self
+    }
+
+    /// Strips the final values of the aggregates, leaving only the components which are needed to
+    /// merge the response with the ones of the other shards.
+    fn into_partial(mut self) -> Self {
+        if let QueryResponse::WithAggregatedData { aggregates, .. } = &mut self {
+            for aggregate in aggregates.iter_mut().flatten() {
+                aggregate.value = None;
+            }
+        }
+
+        self
+    }
+
+    /// Replaces the null values of the response with the default value of their column type.
+    fn with_default_nulls(mut self) -> Self {
+        let replace_nulls = |values: &mut Vec<serde_json::Value>, columns: &[Column]| {
+            for (value, column) in values.iter_mut().zip(columns) {
+                if value.is_null() {
+                    *value = column.ty.default_value();
+                }
+            }
+        };
+
+        match &mut self {
+            QueryResponse::Empty { .. } => {}
+            QueryResponse::WithAggregatedData {
+                columns,
+                aggregate_columns,
+                data,
+                aggregates,
+                ..
+            } => {
+                data.iter_mut()
+                    .for_each(|values| replace_nulls(values, columns));
+                for row_aggregates in aggregates.iter_mut() {
+                    for (aggregate, column) in
+                        row_aggregates.iter_mut().zip(aggregate_columns.iter())
+                    {
+                        if aggregate.value.as_ref().is_some_and(|v| v.is_null()) {
+                            aggregate.value = Some(column.ty.default_value());
+                        }
+                    }
+                }
+            }
+            QueryResponse::WithData { columns, data, .. } => {
+                data.iter_mut()
+                    .for_each(|values| replace_nulls(values, columns));
+            }
+        }
+
+        self
+    }
+
+    /// Renders the response as csv, with a header with the names of the columns followed by a line
+    /// per row, and the final values of the aggregates after the values of the columns.
+    fn to_csv(&self) -> String {
+        let (columns, rows): (Vec<&Column>, Vec<Vec<&serde_json::Value>>) = match self {
+            QueryResponse::Empty { .. } => return String::new(),
+            QueryResponse::WithAggregatedData {
+                columns,
+                aggregate_columns,
+                data,
+                aggregates,
+                ..
+            } => (
+                columns.iter().chain(aggregate_columns.iter()).collect(),
+                data.iter()
+                    .zip(aggregates.iter())
+                    .map(|(values, aggregates)| {
+                        values
+                            .iter()
+                            .chain(
+                                aggregates
+                                    .iter()
+                                    .map(|a| a.value.as_ref().unwrap_or(&serde_json::Value::Null)),
+                            )
+                            .collect()
+                    })
+                    .collect(),
+            ),
+            QueryResponse::WithData { columns, data, .. } => (
+                columns.iter().collect(),
+                data.iter().map(|values| values.iter().collect()).collect(),
+            ),
+        };
+
+        let mut csv = columns
+            .iter()
+            .map(|c| escape_csv_field(&c.name))
+            .collect::<Vec<_>>()
+            .join(",");
+        csv.push('\n');
+        for row in rows {
+            let fields: Vec<String> = row
+                .into_iter()
+                .map(|value| match value {
+                    serde_json::Value::Null => String::new(),
+                    serde_json::Value::String(value) => escape_csv_field(value),
+                    value => value.to_string(),
+                })
+                .collect();
+            csv.push_str(&fields.join(","));
+            csv.push('\n');
+        }
+
+        csv
+    }
+
+    fn with_session_nulls(self, session: &SessionSettings) -> Self {
+        match session.null_handling() {
+            NullHandling::Null => self,
+            NullHandling::Default => self.with_default_nulls(),
+        }
+    }
+
+    /// Applies the output settings of the session to the response, returning the errors as json
+    /// regardless of the output format.
+    fn into_session_response(self, session: &SessionSettings) -> Response {
+        let query_response = self.with_session_nulls(session);
+
+        match (session.output_format(), &query_response) {
+            (OutputFormat::Csv, QueryResponse::Empty { errors, .. }) if !errors.is_empty() => {
+                Json(query_response).into_response()
+            }
+            (OutputFormat::Csv, _) => {
+                ([(CONTENT_TYPE, "text/csv")], query_response.to_csv()).into_response()
+            }
+            (OutputFormat::Json, _) => Json(query_response).into_response(),
+        }
+    }
+
+    /// Returns the number of rows read from disk to compute the response.
+    pub fn scanned_rows(&self) -> u64 {
+        self.scan_stats().scanned_rows
+    }
+
+    fn scan_stats(&self) -> ScanStats {
+        match self {
+            QueryResponse::Empty { scan_stats, .. }
+            | QueryResponse::WithAggregatedData { scan_stats, .. }
+            | QueryResponse::WithData { scan_stats, .. } => *scan_stats,
+        }
+    }
+
+    fn with_scan_stats(mut self, stats: ScanStats) -> Self {
+        match &mut self {
+            QueryResponse::Empty { scan_stats, .. }
+            | QueryResponse::WithAggregatedData { scan_stats, .. }
+            | QueryResponse::WithData { scan_stats, .. } => *scan_stats = stats,
+        }
+
+        self
+    }
+}
+
+fn escape_csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+fn sanitize_column_name(name: &str) -> String {
+    let sanitized: String = name
+        .chars()
+        .map(|c| if c.is_alphanumeric() { c } else { '_' })
+        .collect();
+
+    sanitized.trim_matches('_').to_string()
+}
+
+#[derive(Debug, Clone)]
+pub struct DatabaseState {
+    pub config: Arc<Config>,
+    pub shards: Arc<Option<Shards>>,
+    pub saved_queries: Arc<SavedQueries>,
+    pub table_locks: Arc<TableLocks>,
+    pub query_slots: Arc<QuerySlots>,
+    pub query_admission: Arc<QueryAdmission>,
+    pub users: Arc<Users>,
+    pub usage: Arc<Usage>,
+    pub placements: Arc<Placements>,
+    pub sessions: Arc<Sessions>,
+    pub prepared_queries: Arc<PreparedQueries>,
+    pub idempotency_keys: Arc<IdempotencyKeys>,
+    pub insert_sequences: Arc<InsertSequences>,
+    pub pools: Arc<WorkerPools>,
+    pub read_only: Arc<ReadOnlyMode>,
+}
+
+impl DatabaseState {
+    /// Returns the state to handle a request from `origin`, without the shards if the request was
+    /// forwarded by another instance, so that it's never forwarded again.
+    pub fn for_origin(self, origin: &Origin) -> Self {
+        match origin {
+            Origin::Shard(ip_port) if self.shards.is_some() => {
+                info!("Not forwarding the request of {} to the shards", ip_port);
+                Self {
+                    shards: Arc::new(None),
+                    ..self
+                }
+            }
+            _ => self,
+        }
+    }
+}
+
+async fn check_quotas(
+    state: &DatabaseState,
+    principal: &Principal,
+    rows_written: u64,
+) -> io::Result<()> {
+    match principal.user() {
+        Some(user) => state.usage.check_quotas(user, rows_written).await,
+        None => Ok(()),
+    }
+}
+
+async fn record_usage(
+    state: &DatabaseState,
+    principal: &Principal,
+    rows_written: u64,
+    rows_scanned: u64,
+) {
+    let Some(user) = principal.user() else {
+        return;
+    };
+
+    state.usage.record(user, rows_written, rows_scanned).await;
+}
+
+fn check_table_name(name: &str) -> io::Result<()> {
+    // Tables starting with `__` are reserved for the database itself, like the audit log.
+    if name.starts_with("__") {
+        return Err(Error::new(
+            ErrorKind::PermissionDenied,
+            format!("The table name {} is reserved", name),
+        ));
+    }
+
+    Ok(())
+}
+
+/// The header with the key identifying the retries of a request, which return the outcome of the
+/// first execution of the request instead of executing it again.
+pub const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
+
+fn idempotency_key(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(IDEMPOTENCY_KEY_HEADER)
+        .and_then(|h| h.to_str().ok())
+}
+
+/// Executes the op, unless the caller already sent a request with the same idempotency key, in
+/// which case the outcome of that request is returned.
+async fn execute_idempotent(
+    state: &DatabaseState,
+    principal: &Principal,
+    op_name: &str,
+    idempotency_key: Option<&str>,
+    request: &impl Serialize,
+    op: impl Future<Output = OpResponse>,
+) -> OpResponse {
+    let Some(idempotency_key) = idempotency_key else {
+        return op.await;
+    };
+
+    // The keys are scoped to the caller and the op, so that different callers can't replay each
+    // other's requests.
+    let key = format!(
+        "{}:{}:{}",
+        principal.session_name(),
+        op_name,
+        idempotency_key
+    );
+    let request = serde_json::to_string(request).unwrap_or_default();
+    state
+        .idempotency_keys
+        .execute(key, request, op)
+        .await
+        .unwrap_or_else(|e| {
+            info!("{}", e);
+            OpResponse::Error(e.to_string())
+        })
+}
+
+pub async fn create_table(
+    Extension(principal): Extension<Principal>,
+    Extension(origin): Extension<Origin>,
+    State(state): State<DatabaseState>,
+    headers: HeaderMap,
+    Json(request): Json<CreateTableRequest>,
+) -> Json<OpResponse> {
+    let state = state.for_origin(&origin);
+
+    let idempotency_key = idempotency_key(&headers);
+    let op = async {
+        let result = match principal.authorize(Role::Writer, Some(&request.name)) {
+            Ok(_) => {
+                execute_create_table(&state, request.clone(), &principal.name(), idempotency_key)
+                    .await
+            }
+            Err(error) => Err(error),
+        };
+
+        OpResponse::from_result(result, "Table created successfully")
+    };
+
+    Json(
+        execute_idempotent(
+            &state,
+            &principal,
+            "create_table",
+            idempotency_key,
+            &request,
+            op,
+        )
+        .await,
+    )
+}
+
+/// Creates the table on this instance and its shards, to which the idempotency key of the request
+/// is forwarded so that the retries aren't executed again by the shards either.
+pub async fn execute_create_table(
+    state: &DatabaseState,
+    request: CreateTableRequest,
+    caller: &str,
+    idempotency_key: Option<&str>,
+) -> io::Result<()> {
+    check_table_name(&request.name)?;
+    state.read_only.check_writable().await?;
+
+    // Create a future for the shard broadcast operation
+    let shard_broadcast_future = async {
+        if let Some(shards) = state.shards.deref() {
+            let create_table = CreateTable::new(&request, idempotency_key);
+            shards
+                .broadcast(create_table)
+                .await
+                .into_outputs()
+                .map_err(|e| {
+                    Error::new(
+                        ErrorKind::InvalidData,
+                        format!("Error while creating table in the shards: {}", e),
+                    )
+                })?;
+        }
+
+        Ok(())
+    }
+    .boxed();
+
+    // Create a future for the local table creation operation
+    let request = request.clone();
+    let local_create_future = async {
+        let exists = TableDefinition::exists(&state.config, &request.name).await?;
+        let encodings = request
+            .columns
+            .iter()
+            .filter_map(|c| Some((c.name.clone(), c.encoding?)))
+            .collect();
+        let bloom_filters = request
+            .columns
+            .iter()
+            .filter(|c| c.bloom_filter)
+            .map(|c| c.name.clone())
+            .collect();
+        let unique_columns = request
+            .columns
+            .iter()
+            .filter(|c| c.unique)
+            .map(|c| c.name.clone())
+            .collect();
+        let columns = request.columns.into_iter().map(|c| c.into()).collect();
+        TableDefinition::create(
+            state.config.clone(),
+            request.name.clone(),
+            columns,
+            encodings,
+            bloom_filters,
+            unique_columns,
+            request.options,
+        )
+        .await
+        .map_err(|e| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("Error while creating table in the shards: {}", e),
+            )
+        })?;
+        // A new table isn't stored on any shard until rows are inserted, while the data of an
+        // existing table might be on any shard.
+        if !exists && state.shards.is_some() {
+            state.placements.create(&request.name).await?;
+        }
+        record_audit_entry(state, caller, "create_table", &request.name, 0).await?;
+
+        Ok(())
+    }
+    .boxed();
+
+    let (shard_result, local_result): (io::Result<()>, io::Result<()>) =
+        join(shard_broadcast_future, local_create_future).await;
+    match (shard_result, local_result) {
+        (Ok(_), Ok(_)) => Ok(()),
+        (Err(e), _) => Err(Error::new(
+            e.kind(),
+            format!("Error in shard table creation: {}", e),
+        )),
+        (_, Err(e)) => Err(Error::new(
+            e.kind(),
+            format!("Error in local table creation: {}", e),
+        )),
+    }
+}
+
+pub async fn export_schema(
+    Extension(principal): Extension<Principal>,
+    State(state): State<DatabaseState>,
+) -> Result<Json<SchemaDocument>, Json<String>> {
+    let result = match principal.authorize(Role::Reader, None) {
+        Ok(_) => TableDefinition::list(&state.config).await,
+        Err(error) => Err(error),
+    };
+    let table_names = match result {
+        Ok(table_names) => table_names
+            .into_iter()
+            .filter(|t| principal.authorize(Role::Reader, Some(t)).is_ok())
+            .collect(),
+        Err(e) => {
+            info!("{}", e);
+            return Err(Json(e.to_string()));
+        }
+    };
+
+    match collect_schemas(&state, table_names).await {
+        Ok(document) => Ok(Json(document)),
+        Err(e) => {
+            info!("{}", e);
+            Err(Json(e.to_string()))
+        }
+    }
+}
+
+pub async fn export_table_schema(
+    Extension(principal): Extension<Principal>,
+    State(state): State<DatabaseState>,
+    Path(name): Path<String>,
+) -> Result<Json<SchemaDocument>, Json<String>> {
+    let result = match principal.authorize(Role::Reader, Some(&name)) {
+        Ok(_) => collect_schemas(&state, vec![name]).await,
+        Err(error) => Err(error),
+    };
+
+    match result {
+        Ok(document) => Ok(Json(document)),
+        Err(e) => {
+            info!("{}", e);
+            Err(Json(e.to_string()))
+        }
+    }
+}
+
+/// Builds the schema document of the tables, from their local definitions which are the same on
+/// all the instances of the cluster.
+async fn collect_schemas(
+    state: &DatabaseState,
+    table_names: Vec<String>,
+) -> io::Result<SchemaDocument> {
+    let mut tables = vec![];
+    for table_name in table_names {
+        if !TableDefinition::exists(&state.config, &table_name).await? {
+            return Err(Error::new(
+                ErrorKind::NotFound,
+                format!("The table {} doesn't exist", table_name),
+            ));
+        }
+
+        let table_lock = state.table_locks.get(&table_name);
+        let _guard = table_lock.read().await;
+        let table_definition =
+            TableDefinition::open(state.config.clone(), table_name.clone()).await?;
+        let columns = table_definition
+            .columns()
+            .iter()
+            .map(|c| {
+                let encoding = table_definition.encoding(c);
+                Column {
+                    encoding: Some(encoding).filter(|e| *e != ColumnEncoding::Raw),
+                    bloom_filter: table_definition.has_bloom_filter(c),
+                    unique: table_definition.is_unique(c),
+                    ..c.clone().into()
+                }
+            })
+            .collect();
+        tables.push(CreateTableRequest {
+            options: table_definition.options().clone(),
+            ..CreateTableRequest::new(table_name, columns)
+        });
+    }
+
+    Ok(SchemaDocument { tables })
+}
+
+/// Creates the tables of a schema document which don't exist yet, failing without creating any
+/// table if an existing one has different columns.
+pub async fn import_schema(
+    Extension(principal): Extension<Principal>,
+    Extension(origin): Extension<Origin>,
+    State(state): State<DatabaseState>,
+    Json(request): Json<SchemaDocument>,
+) -> Json<OpResponse> {
+    let state = state.for_origin(&origin);
+
+    let result = match execute_import_schema(&state, &principal, request).await {
+        Ok(created_tables) => {
+            let message = format!(
+                "Schema imported successfully, {} tables created",
+                created_tables
+            );
+            info!("{}", message);
+            OpResponse::Success(message)
+        }
+        Err(e) => {
+            info!("{}", e);
+            OpResponse::Error(e.to_string())
+        }
+    };
+
+    Json(result)
+}
+
+/// Imports the schema document, returning the number of tables created.
+async fn execute_import_schema(
+    state: &DatabaseState,
+    principal: &Principal,
+    request: SchemaDocument,
+) -> io::Result<usize> {
+    // All the tables are checked before creating any, so that a failed import leaves no table
+    // behind.
+    let mut missing_tables = vec![];
+    for table in request.tables {
+        principal.authorize(Role::Writer, Some(&table.name))?;
+        check_table_name(&table.name)?;
+
+        if !TableDefinition::exists(&state.config, &table.name).await? {
+            missing_tables.push(table);
+            continue;
+        }
+
+        let table_definition =
+            TableDefinition::open(state.config.clone(), table.name.clone()).await?;
+        // The columns are compared regardless of their order, which isn't kept on disk.
+        let mut columns: Vec<TableColumn> = table.columns.into_iter().map(|c| c.into()).collect();
+        let mut existing_columns = table_definition.columns().clone();
+        columns.sort();
+        existing_columns.sort();
+        if existing_columns != columns {
+            return Err(Error::new(
+                ErrorKind::AlreadyExists,
+                format!(
+                    "The table {} already exists with different columns",
+                    table.name
+                ),
+            ));
+        }
+    }
+
+    let created_tables = missing_tables.len();
+    for table in missing_tables {
+        execute_create_table(state, table, &principal.name(), None).await?;
+    }
+
+    Ok(created_tables)
+}
+
+pub async fn drop_table(
+    Extension(principal): Extension<Principal>,
+    Extension(origin): Extension<Origin>,
+    State(state): State<DatabaseState>,
+    headers: HeaderMap,
+    Json(request): Json<DropTableRequest>,
+) -> Json<OpResponse> {
+    let state = state.for_origin(&origin);
+
+    let idempotency_key = idempotency_key(&headers);
+    let op = async {
+        let result = match principal.authorize(Role::Writer, Some(&request.name)) {
+            Ok(_) => {
+                execute_drop_table(&state, request.clone(), &principal.name(), idempotency_key)
+                    .await
+            }
+            Err(error) => Err(error),
+        };
+
+        OpResponse::from_result(result, "Table dropped successfully")
+    };
+
+    Json(
+        execute_idempotent(
+            &state,
+            &principal,
+            "drop_table",
+            idempotency_key,
+            &request,
+            op,
+        )
+        .await,
+    )
+}
+
+/// Drops the table on this instance and its shards, to which the idempotency key of the request
+/// is forwarded so that the retries aren't executed again by the shards either.
+pub async fn execute_drop_table(
+    state: &DatabaseState,
+    request: DropTableRequest,
+    caller: &str,
+    idempotency_key: Option<&str>,
+) -> io::Result<()> {
+    check_table_name(&request.name)?;
+    state.read_only.check_writable().await?;
+
+    // Create a future for the shard broadcast operation
+    let shard_broadcast_future = async {
+        if let Some(shards) = state.shards.deref() {
+            let drop_table = DropTable::new(&request, idempotency_key);
+            shards.broadcast(drop_table).await.into_outputs()?;
+        }
+
+        Ok(())
+    }
+    .boxed();
+
+    // Create a future for the local table drop operation
+    let request = request.clone();
+    let local_drop_future = async {
+        let table_lock = state.table_locks.get(&request.name);
+        let _guard = table_lock.write().await;
+        TableDefinition::drop(state.config.clone(), request.name.clone()).await?;
+        state.placements.remove(&request.name).await?;
+        state.insert_sequences.remove(&request.name).await;
+        record_audit_entry(state, caller, "drop_table", &request.name, 0).await
+    }
+    .boxed();
+
+    let (shard_result, local_result): (io::Result<()>, io::Result<()>) =
+        join(shard_broadcast_future, local_drop_future).await;
+    match (shard_result, local_result) {
+        (Ok(_), Ok(_)) => Ok(()),
+        (Err(e), _) => Err(Error::new(
+            e.kind(),
+            format!("Error in shard table drop: {}", e),
+        )),
+        (_, Err(e)) => Err(Error::new(
+            e.kind(),
+            format!("Error in local table drop: {}", e),
+        )),
+    }
+}
+
+/// Removes all the rows of a table without dropping it, so that its columns are kept.
+pub async fn truncate_table(
+    Extension(principal): Extension<Principal>,
+    Extension(origin): Extension<Origin>,
+    State(state): State<DatabaseState>,
+    headers: HeaderMap,
+    Json(request): Json<TruncateTableRequest>,
+) -> Json<OpResponse> {
+    let state = state.for_origin(&origin);
+
+    let idempotency_key = idempotency_key(&headers);
+    let op = async {
+        let result = match principal.authorize(Role::Writer, Some(&request.name)) {
+            Ok(_) => {
+                execute_truncate_table(&state, request.clone(), &principal.name(), idempotency_key)
+                    .await
+            }
+            Err(error) => Err(error),
+        };
+
+        OpResponse::from_result(result, "Table truncated successfully")
+    };
+
+    Json(
+        execute_idempotent(
+            &state,
+            &principal,
+            "truncate_table",
+            idempotency_key,
+            &request,
+            op,
+        )
+        .await,
+    )
+}
+
+/// Truncates the table on this instance and its shards, to which the idempotency key of the
+/// request is forwarded so that the retries aren't executed again by the shards either.
+async fn execute_truncate_table(
+    state: &DatabaseState,
+    request: TruncateTableRequest,
+    caller: &str,
+    idempotency_key: Option<&str>,
+) -> io::Result<()> {
+    check_table_name(&request.name)?;
+    state.read_only.check_writable().await?;
+
+    // Create a future for the shard broadcast operation
+    let shard_broadcast_future = async {
+        if let Some(shards) = state.shards.deref() {
+            let truncate_table = TruncateTable::new(&request, idempotency_key);
+            shards.broadcast(truncate_table).await.into_outputs()?;
+        }
+
+        Ok(())
+    }
+    .boxed();
+
+    // Create a future for the local table truncation operation
+    let local_truncate_future = async {
+        let table_lock = state.table_locks.get(&request.name);
+        let _guard = table_lock.write().await;
+        if !TableDefinition::exists(&state.config, &request.name).await? {
+            return Err(Error::new(
+                ErrorKind::NotFound,
+                format!("The table {} does not exist", request.name),
+            ));
+        }
+
+        let table_definition =
+            TableDefinition::open(state.config.clone(), request.name.clone()).await?;
+        let mut table = table_definition.load().await?;
+        let rows = table.truncate().await?;
+        record_audit_entry(
+            state,
+            caller,
+            "truncate_table",
+            &request.name,
+            rows as usize,
+        )
+        .await
+    }
+    .boxed();
+
+    let (shard_result, local_result): (io::Result<()>, io::Result<()>) =
+        join(shard_broadcast_future, local_truncate_future).await;
+    match (shard_result, local_result) {
+        (Ok(_), Ok(_)) => Ok(()),
+        (Err(e), _) => Err(Error::new(
+            e.kind(),
+            format!("Error in shard table truncation: {}", e),
+        )),
+        (_, Err(e)) => Err(Error::new(
+            e.kind(),
+            format!("Error in local table truncation: {}", e),
+        )),
+    }
+}
+
+pub async fn create_index(
+    Extension(principal): Extension<Principal>,
+    Extension(origin): Extension<Origin>,
+    State(state): State<DatabaseState>,
+    headers: HeaderMap,
+    Json(request): Json<CreateIndexRequest>,
+) -> Json<OpResponse> {
+    let state = state.for_origin(&origin);
+
+    let idempotency_key = idempotency_key(&headers);
+    let op = async {
+        let result = match principal.authorize(Role::Writer, Some(&request.table)) {
+            Ok(_) => {
+                execute_create_index(&state, request.clone(), &principal.name(), idempotency_key)
+                    .await
+            }
+            Err(error) => Err(error),
+        };
+
+        OpResponse::from_result(result, "Index created successfully")
+    };
+
+    Json(
+        execute_idempotent(
+            &state,
+            &principal,
+            "create_index",
+            idempotency_key,
+            &request,
+            op,
+        )
+        .await,
+    )
+}
+
+/// Creates the secondary index on this instance and its shards, each of which indexes the
+/// partitions it stores.
+async fn execute_create_index(
+    state: &DatabaseState,
+    request: CreateIndexRequest,
+    caller: &str,
+    idempotency_key: Option<&str>,
+) -> io::Result<()> {
+    check_table_name(&request.table)?;
+    state.read_only.check_writable().await?;
+
+    let shard_broadcast_future = async {
+        if let Some(shards) = state.shards.deref() {
+            let create_index = CreateIndex::new(&request, idempotency_key);
+            shards.broadcast(create_index).await.into_outputs()?;
+        }
+
+        Ok(())
+    }
+    .boxed();
+
+    let local_create_future = async {
+        let table_lock = state.table_locks.get(&request.table);
+        let _guard = table_lock.write().await;
+        if !TableDefinition::exists(&state.config, &request.table).await? {
+            return Err(Error::new(
+                ErrorKind::NotFound,
+                format!("The table {} does not exist", request.table),
+            ));
+        }
+
+        let table_definition =
+            TableDefinition::open(state.config.clone(), request.table.clone()).await?;
+        let mut table = table_definition.load().await?;
+        table.create_index(&request.column).await?;
+        record_audit_entry(state, caller, "create_index", &request.table, 0).await
+    }
+    .boxed();
+
+    let (shard_result, local_result): (io::Result<()>, io::Result<()>) =
+        join(shard_broadcast_future, local_create_future).await;
+    match (shard_result, local_result) {
+        (Ok(_), Ok(_)) => Ok(()),
+        (Err(e), _) => Err(Error::new(
+            e.kind(),
+            format!("Error in shard index creation: {}", e),
+        )),
+        (_, Err(e)) => Err(Error::new(
+            e.kind(),
+            format!("Error in local index creation: {}", e),
+        )),
+    }
+}
+
+pub async fn insert(
+    Extension(principal): Extension<Principal>,
+    Extension(origin): Extension<Origin>,
+    State(state): State<DatabaseState>,
+    Json(request): Json<InsertRequest>,
+) -> Json<InsertResponse> {
+    let state = state.for_origin(&origin);
+
+    Json(insert_batch(&state, &principal, &origin, request).await)
+}
+
+/// Inserts a batch of rows sent to this instance, reporting its invalid rows.
+pub async fn insert_batch(
+    state: &DatabaseState,
+    principal: &Principal,
+    origin: &Origin,
+    mut request: InsertRequest,
+) -> InsertResponse {
+    // The sequences and the timestamps are assigned by the master, so the ones of the clients
+    // are ignored.
+    if let Origin::Client = origin {
+        request.sequence = None;
+        request.timestamp = None;
+    }
+
+    let rows = request.values.len() as u64;
+    let authorization = match principal.authorize(Role::Writer, Some(&request.into)) {
+        Ok(_) => check_quotas(state, principal, rows).await,
+        Err(error) => Err(error),
+    };
+    if let Err(e) = authorization {
+        info!("{}", e);
+        return InsertResponse::new(OpResponse::Error(e.to_string()), vec![]);
+    }
+
+    set_span_attribute("db.collection.name", || request.into.clone());
+    set_span_attribute("db.operation.batch.size", || rows.to_string());
+    let rejected_rows = match validate_insert(state, &mut request).await {
+        Ok(rejected_rows) => rejected_rows,
+        Err(e) => {
+            info!("{}", e);
+            return InsertResponse::new(OpResponse::Error(e.to_string()), vec![]);
+        }
+    };
+
+    let valid_rows = request.values.len() as u64;
+    let invalid_rows = rows - valid_rows;
+    if invalid_rows > 0 && !request.partial {
+        let error = format!(
+            "{} of the {} rows are invalid, so none was inserted",
+            invalid_rows, rows
+        );
+        info!("{}", error);
+        return InsertResponse::new(OpResponse::Error(error), rejected_rows);
+    }
+
+    let result = match valid_rows {
+        0 if !request.replace => Ok(()),
+        _ => execute_insert(state, request, &principal.name()).await,
+    };
+    if result.is_ok() {
+        record_usage(state, principal, valid_rows, 0).await;
+    }
+
+    let success = match invalid_rows {
+        0 => "Data inserted successfully".to_string(),
+        _ => format!(
+            "Data inserted successfully, with {} of the {} rows rejected",
+            invalid_rows, rows
+        ),
+    };
+    InsertResponse::new(OpResponse::from_result(result, &success), rejected_rows)
+}
+
+/// Removes the rows of the insert which can't be written to the table, returning the errors of
+/// all of them.
+async fn validate_insert(
+    state: &DatabaseState,
+    request: &mut InsertRequest,
+) -> io::Result<Vec<RowError>> {
+    check_table_name(&request.into)?;
+    let table_definition =
+        TableDefinition::open(state.config.clone(), request.into.clone()).await?;
+    let columns = parse_and_validate_columns(table_definition.columns(), &request.insert)?;
+
+    let mut rejected_rows = validate_rows(&columns, &request.values);
+    if let Some(column) = table_definition.unique_column() {
+        if let Some(position) = columns.iter().position(|c| c == column) {
+            // The rows are routed by the unique column, so that its values are only stored by the
+            // instance checking them.
+            let route_by = vec![column.name.clone()];
+            if request.route_by.as_ref().is_some_and(|r| *r != route_by) {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!(
+                        "The rows of table {} are routed by its unique column {}",
+                        request.into, column.name
+                    ),
+                ));
+            }
+            request.route_by = Some(route_by);
+
+            let invalid_rows: HashSet<usize> = rejected_rows.iter().map(|e| e.row).collect();
+            let unique_errors =
+                check_unique_values(state, request, column, position, &invalid_rows).await?;
+            rejected_rows.extend(unique_errors);
+            rejected_rows.sort_by_key(|e| e.row);
+        }
+    }
+    let invalid_rows: HashSet<usize> = rejected_rows.iter().map(|e| e.row).collect();
+    let mut row = 0;
+    request.values.retain(|_| {
+        row += 1;
+        !invalid_rows.contains(&(row - 1))
+    });
+
+    Ok(rejected_rows)
+}
+
+/// Returns the errors of the valid rows whose value of the unique column is repeated in the insert,
+/// or is already stored by the instance to which the row is routed.
+async fn check_unique_values(
+    state: &DatabaseState,
+    request: &InsertRequest,
+    column: &TableColumn,
+    position: usize,
+    invalid_rows: &HashSet<usize>,
+) -> io::Result<Vec<RowError>> {
+    let rows: Vec<(usize, &serde_json::Value)> = request
+        .values
+        .iter()
+        .enumerate()
+        .filter(|(row, _)| !invalid_rows.contains(row))
+        .map(|(row, value)| (row, &value[position]))
+        .collect();
+    let values: Vec<serde_json::Value> = rows.iter().map(|(_, v)| (*v).clone()).collect();
+
+    let mut errors = vec![];
+    let repeated: HashSet<usize> = repeated_values(column, &values).into_iter().collect();
+    for position in repeated.iter() {
+        let (row, value) = rows[*position];
+        errors.push(RowError {
+            row,
+            column: Some(column.name.clone()),
+            reason: format!("The value {} is repeated in the inserted rows", value),
+        });
+    }
+
+    // The values are checked by the instance to which their rows are routed, which is this one
+    // for the first position.
+    let n = state
+        .shards
+        .deref()
+        .as_ref()
+        .map_or(0, |s| s.number_of_shards())
+        + 1;
+    let mut routed_rows = vec![vec![]; n];
+    for (position, (row, value)) in rows.into_iter().enumerate() {
+        if !value.is_null() && !repeated.contains(&position) {
+            routed_rows[route_index(&[Some(value)], n)?].push((row, value));
+        }
+    }
+    for (index, rows) in routed_rows.into_iter().enumerate() {
+        if rows.is_empty() {
+            continue;
+        }
+
+        let check_request = CheckUniqueRequest {
+            table: request.into.clone(),
+            column: column.name.clone(),
+            values: rows.iter().map(|(_, v)| (*v).clone()).collect(),
+        };
+        let existing = match (index, state.shards.deref()) {
+            (0, _) | (_, None) => existing_unique_values(state, &check_request).await?,
+            (index, Some(shards)) => {
+                shards
+                    .unicast(index - 1, CheckUnique::new(&check_request))
+                    .await?
+                    .existing
+            }
+        };
+        for (row, value) in rows {
+            if existing.contains(value) {
+                errors.push(RowError {
+                    row,
+                    column: Some(column.name.clone()),
+                    reason: format!("The value {} already exists", value),
+                });
+            }
+        }
+    }
+
+    Ok(errors)
+}
+
+/// The number of times the insert of rows on a shard is attempted before failing.
+const INSERT_ATTEMPTS: usize = 3;
+
+pub async fn execute_insert(
+    state: &DatabaseState,
+    mut request: InsertRequest,
+    caller: &str,
+) -> io::Result<()> {
+    check_table_name(&request.into)?;
+    state.read_only.check_writable().await?;
+
+    let mut shard_requests = vec![];
+    if let Some(shards) = state.shards.deref() {
+        if state.config.clock.master_timestamps {
+            request.timestamp = Some(clock().now_secs());
+        }
+
+        let routed = request.route_by.is_some();
+        let replace = request.replace;
+        let mut requests = if routed {
+            request.split_by_key(shards.number_of_shards() + 1)?
+        } else if request.values.is_empty() {
+            vec![]
+        } else {
+            request.split(shards.number_of_shards() + 1)
+        };
+        if !requests.is_empty() {
+            request = InsertRequest {
+                replace,
+                ..requests.remove(0)
+            };
+        }
+
+        // The rows routed by key are sent to the shard matching the position of their request,
+        // with the first one being this instance, while the others are sent to the next shard in
+        // turn.
+        shard_requests = requests
+            .into_iter()
+            .enumerate()
+            .filter(|(_, request)| !request.values.is_empty())
+            .map(|(index, request)| {
+                let index = if routed {
+                    index
+                } else {
+                    shards.next_shard_index()
+                };
+                let sequence = shards.next_insert_sequence();
+                (
+                    index,
+                    InsertRequest {
+                        sequence: Some(sequence),
+                        replace,
+                        ..request
+                    },
+                )
+            })
+            .collect();
+
+        // The rows of all the shards are replaced, including the ones which receive no new rows.
+        if replace {
+            for index in 0..shards.number_of_shards() {
+                if shard_requests.iter().all(|(i, _)| *i != index) {
+                    let request = InsertRequest {
+                        sequence: Some(shards.next_insert_sequence()),
+                        replace,
+                        ..InsertRequest::new(request.insert.clone(), request.into.clone(), vec![])
+                    };
+                    shard_requests.push((index, request));
+                }
+            }
+        }
+
+        // The shards are recorded before inserting, so that their rows are queried even if the
+        // insertion fails midway.
+        let ip_ports: Vec<&str> = shard_requests
+            .iter()
+            .map(|(index, _)| shards.shard(*index).ip_port.as_str())
+            .collect();
+        state.placements.add(&request.into, &ip_ports).await?;
+    }
+
+    // Create futures for each shard insertion operation
+    let shard_insert_futures = shard_requests
+        .into_iter()
+        .map(|(index, request)| {
+            let shards = state.shards.clone();
+            async move {
+                if let Some(shards) = shards.deref() {
+                    // The insert is retried with the same sequence, so that the shard applies it
+                    // only once even if the failure happened after applying it.
+                    let mut result = shards.unicast(index, Insert::new(&request)).await;
+                    for _ in 1..INSERT_ATTEMPTS {
+                        let Err(error) = &result else {
+                            break;
+                        };
+                        info!(
+                            "Retrying the insert into {} on the shard {}: {}",
+                            request.into,
+                            shards.shard(index).ip_port,
+                            error
+                        );
+                        result = shards.unicast(index, Insert::new(&request)).await;
+                    }
+                    result.map_err(|error| {
+                        Error::new(
+                            ErrorKind::InvalidData,
+                            format!("Error while inserting data in the shards: {}", error),
+                        )
+                    })?;
+                }
+
+                Ok(())
+            }
+            .boxed()
+        })
+        .collect::<Vec<BoxFuture<io::Result<()>>>>();
+
+    // Create a future for all shard insertions
+    let shard_insert_future = async {
+        let results = join_all(shard_insert_futures).await;
+        if let Some(error) = results.into_iter().find(|r| r.is_err()) {
+            error?;
+        }
+
+        Ok(())
+    }
+    .boxed();
+
+    // Create a future for the table insertion operation
+    let request = request.clone();
+    let table_insert_future = async {
+        let table_lock = state.table_locks.get(&request.into);
+        let _guard = table_lock.write().await;
+        // The sequence is checked and recorded while holding the lock, so that a retry received
+        // while the first attempt is still running waits for it.
+        if let Some(sequence) = request.sequence {
+            if state
+                .insert_sequences
+                .is_applied(&request.into, sequence)
+                .await
+                || memtables().contains_sequence(&request.into, sequence)
+            {
+                info!(
+                    "Skipping the insert {} into {}, which was already applied",
+                    sequence, request.into
+                );
+                return Ok(());
+            }
+        }
+
+        let table_definition =
+            TableDefinition::open(state.config.clone(), request.into.clone()).await?;
+        let mut table = table_definition.load().await?;
+        if request.replace {
+            let rows = table.truncate().await?;
+            record_audit_entry(
+                state,
+                caller,
+                "truncate_table",
+                &request.into,
+                rows as usize,
+            )
+            .await?;
+        }
+        let rows = request.values.len();
+        if rows > 0 || !request.replace {
+            table
+                .insert(
+                    request.insert,
+                    request.values,
+                    request.timestamp,
+                    request.sequence,
+                )
+                .await?;
+        }
+        // The sequences of the buffered rows are recorded once they are written to the files.
+        let mut sequences = memtables().take_flushed_sequences(&request.into);
+        sequences.extend(
+            request
+                .sequence
+                .filter(|s| !memtables().contains_sequence(&request.into, *s)),
+        );
+        for sequence in sequences {
+            state.insert_sequences.record(&request.into, sequence).await;
+        }
+        record_audit_entry(state, caller, "insert", &request.into, rows).await
+    }
+    .boxed();
+
+    // Join the shard insertion and table insertion futures
+    let (shard_result, table_result): (io::Result<()>, io::Result<()>) =
+        join(shard_insert_future, table_insert_future).await;
+
+    match (shard_result, table_result) {
+        (Ok(_), Ok(_)) => Ok(()),
+        (Err(e), _) => Err(Error::new(
+            e.kind(),
+            format!("Error in shard insertion: {}", e),
+        )),
+        (_, Err(e)) => Err(Error::new(
+            e.kind(),
+            format!("Error in table insertion: {}", e),
+        )),
+    }
+}
+
+/// Deletes the rows of a table matching a condition on this instance and its shards.
+pub async fn delete_rows(
+    Extension(principal): Extension<Principal>,
+    Extension(origin): Extension<Origin>,
+    State(state): State<DatabaseState>,
+    Json(request): Json<DeleteRequest>,
+) -> Json<DeleteResponse> {
+    let state = state.for_origin(&origin);
+
+    let result = match principal.authorize(Role::Writer, Some(&request.from)) {
+        Ok(_) => execute_delete(&state, request, &principal.name()).await,
+        Err(error) => Err(error),
+    };
+
+    Json(DeleteResponse::from_result(result))
+}
+
+async fn execute_delete(
+    state: &DatabaseState,
+    mut request: DeleteRequest,
+    caller: &str,
+) -> io::Result<u64> {
+    check_table_name(&request.from)?;
+    state.read_only.check_writable().await?;
+
+    // The condition is validated before being sent to the shards.
+    request.filter = parse_expression(&request.filter)?
+        .unqualify(std::slice::from_ref(&request.from))?
+        .to_string();
+
+    // Create a future for the shard broadcast operation
+    let shard_delete_future = async {
+        let Some(shards) = state.shards.deref() else {
+            return Ok(0);
+        };
+
+        let placement = state.placements.get(&request.from).await;
+        let outputs = shards
+            .broadcast_to(Delete::new(&request), |s| {
+                placement.as_ref().is_none_or(|p| p.contains(&s.ip_port))
+            })
+            .await
+            .into_outputs()?;
+
+        Ok(outputs.iter().map(|o| o.deleted_rows).sum())
+    }
+    .boxed();
+
+    // Create a future for the local delete operation
+    let local_delete_future = async {
+        let table_lock = state.table_locks.get(&request.from);
+        let _guard = table_lock.write().await;
+        let table_definition =
+            TableDefinition::open(state.config.clone(), request.from.clone()).await?;
+        let mut table = table_definition.load().await?;
+        let deleted_rows = table
+            .delete(
+                request.filter.clone(),
+                TimeRange::new(request.since, request.until),
+            )
+            .await?;
+        record_audit_entry(
+            state,
+            caller,
+            "delete",
+            &request.from,
+            deleted_rows as usize,
+        )
+        .await?;
+
+        Ok(deleted_rows)
+    }
+    .boxed();
+
+    let (shard_result, local_result): (io::Result<u64>, io::Result<u64>) =
+        join(shard_delete_future, local_delete_future).await;
+    match (shard_result, local_result) {
+        (Ok(shard_rows), Ok(local_rows)) => Ok(shard_rows + local_rows),
+        (Err(e), _) => Err(Error::new(
+            e.kind(),
+            format!("Error in shard delete: {}", e),
+        )),
+        (_, Err(e)) => Err(Error::new(
+            e.kind(),
+            format!("Error in local delete: {}", e),
+        )),
+    }
+}
+
+/// Removes the deleted rows of a table from its files on this instance and its shards.
+pub async fn vacuum(
+    Extension(principal): Extension<Principal>,
+    Extension(origin): Extension<Origin>,
+    State(state): State<DatabaseState>,
+    Json(request): Json<VacuumRequest>,
+) -> Json<VacuumResponse> {
+    let state = state.for_origin(&origin);
+
+    let result = match principal.authorize(Role::Writer, Some(&request.table)) {
+        Ok(_) => execute_vacuum(&state, request, &principal.name()).await,
+        Err(error) => Err(error),
+    };
+
+    Json(VacuumResponse::from_result(result))
+}
+
+async fn execute_vacuum(
+    state: &DatabaseState,
+    request: VacuumRequest,
+    caller: &str,
+) -> io::Result<VacuumTotals> {
+    check_table_name(&request.table)?;
+    state.read_only.check_writable().await?;
+
+    // Create a future for the shard broadcast operation
+    let shard_vacuum_future = async {
+        let Some(shards) = state.shards.deref() else {
+            return Ok((0, 0));
+        };
+
+        let placement = state.placements.get(&request.table).await;
+        let outputs = shards
+            .broadcast_to(Vacuum::new(&request), |s| {
+                placement.as_ref().is_none_or(|p| p.contains(&s.ip_port))
+            })
+            .await
+            .into_outputs()?;
+
+        Ok(outputs.iter().fold((0, 0), |(rows, bytes), o| {
+            (rows + o.vacuumed_rows, bytes + o.reclaimed_bytes)
+        }))
+    }
+    .boxed();
+
+    // Create a future for the local vacuum operation
+    let local_vacuum_future = async {
+        let table_lock = state.table_locks.get(&request.table);
+        let _guard = table_lock.write().await;
+        let table_definition =
+            TableDefinition::open(state.config.clone(), request.table.clone()).await?;
+        let table = table_definition.load().await?;
+        let summary = table.vacuum().await?;
+        record_audit_entry(
+            state,
+            caller,
+            "vacuum",
+            &request.table,
+            summary.rows as usize,
+        )
+        .await?;
+
+        Ok((summary.rows, summary.reclaimed_bytes))
+    }
+    .boxed();
+
+    let (shard_result, local_result): (io::Result<VacuumTotals>, io::Result<VacuumTotals>) =
+        join(shard_vacuum_future, local_vacuum_future).await;
+    match (shard_result, local_result) {
+        (Ok((shard_rows, shard_bytes)), Ok((local_rows, local_bytes))) => {
+            Ok((shard_rows + local_rows, shard_bytes + local_bytes))
+        }
+        (Err(e), _) => Err(Error::new(
+            e.kind(),
+            format!("Error in shard vacuum: {}", e),
+        )),
+        (_, Err(e)) => Err(Error::new(
+            e.kind(),
+            format!("Error in local vacuum: {}", e),
+        )),
+    }
+}
+
+pub async fn query(
+    Extension(principal): Extension<Principal>,
+    Extension(origin): Extension<Origin>,
+    Extension(session): Extension<SessionSettings>,
+    State(state): State<DatabaseState>,
+    Json(request): Json<serde_json::Value>,
+) -> Response {
+    let state = state.for_origin(&origin);
+
+    // An array of queries is executed like a batch, so that several queries need a single request.
+    let request = match request {
+        serde_json::Value::Array(queries) => {
+            let queries = queries
+                .into_iter()
+                .map(|query| {
+                    serde_json::from_value(query).map_err(|e| {
+                        Error::new(ErrorKind::InvalidInput, format!("Invalid query: {}", e))
+                    })
+                })
+                .collect();
+            return Json(execute_batch(&state, &principal, &origin, &session, queries).await)
+                .into_response();
+        }
+        request => match serde_json::from_value::<QueryRequest>(request) {
+            Ok(request) => request,
+            Err(error) => {
+                return (
+                    StatusCode::UNPROCESSABLE_ENTITY,
+                    format!(
+                        "Failed to deserialize the JSON body into the target type: {}",
+                        error
+                    ),
+                )
+                    .into_response()
+            }
+        },
+    };
+
+    let system_table = match request.from.system_table() {
+        Ok(system_table) => system_table,
+        Err(error) => {
+            info!("{}", error);
+            return Json(QueryResponse::error(error.to_string())).into_response();
+        }
+    };
+
+    // The system tables aren't tables of the users, so they are readable by all readers, which
+    // only see the tables they can access.
+    if let Some(system_table) = system_table {
+        if let Err(error) = principal.authorize(Role::Reader, None) {
+            info!("{}", error);
+            return Json(QueryResponse::error(error.to_string())).into_response();
+        }
+
+        let system_query = async {
+            execute_system_query(&state, &principal, system_table, request)
+                .await
+                .unwrap_or_else(|e| QueryResponse::error(e.to_string()))
+        };
+        return execute_in_session(&state, &session, system_query)
+            .await
+            .into_session_response(&session);
+    }
+
+    let authorization = match authorize_query(&principal, &request.from) {
+        Ok(_) => check_quotas(&state, &principal, 0).await,
+        Err(error) => Err(error),
+    };
+    if let Err(error) = authorization {
+        info!("{}", error);
+        return Json(QueryResponse::error(error.to_string())).into_response();
+    }
+
+    set_span_attribute("db.query.text", || {
+        serde_json::to_string(&request).unwrap_or_default()
+    });
+    let execution = async {
+        // The query waits for its turn before loading the tables, so the waiting queries hold no
+        // resources, and the wait counts towards the timeout of the session.
+        let _permit = match state.query_admission.admit(request.priority).await {
+            Ok(permit) => permit,
+            Err(error) => {
+                info!("{}", error);
+                return QueryResponse::error(error.to_string());
+            }
+        };
+
+        execute_query(&state, request).await
+    };
+    let mut query_response = execute_in_session(&state, &session, execution).await;
+    record_usage(&state, &principal, 0, query_response.scanned_rows()).await;
+
+    // The shard which forwarded the query only needs the components of the aggregates.
+    if let Origin::Shard(_) = origin {
+        query_response = query_response.into_partial();
+    }
+
+    query_response.into_session_response(&session)
+}
+
+/// Returns which of the values of a unique column are already stored by this instance, so that the
+/// master can reject an insert duplicating them.
+pub async fn check_unique(
+    Extension(principal): Extension<Principal>,
+    Extension(origin): Extension<Origin>,
+    State(state): State<DatabaseState>,
+    Json(request): Json<CheckUniqueRequest>,
+) -> Result<Json<CheckUniqueResponse>, Json<String>> {
+    let state = state.for_origin(&origin);
+
+    if let Err(e) = principal.authorize(Role::Writer, Some(&request.table)) {
+        info!("{}", e);
+        return Err(Json(e.to_string()));
+    }
+
+    match existing_unique_values(&state, &request).await {
+        Ok(existing) => Ok(Json(CheckUniqueResponse { existing })),
+        Err(e) => {
+            info!("{}", e);
+            Err(Json(e.to_string()))
+        }
+    }
+}
+
+/// Returns the values of the unique column which are already stored by this instance.
+async fn existing_unique_values(
+    state: &DatabaseState,
+    request: &CheckUniqueRequest,
+) -> io::Result<Vec<serde_json::Value>> {
+    check_table_name(&request.table)?;
+    let table_lock = state.table_locks.get(&request.table);
+    let _guard = table_lock.read().await;
+    let table = TableDefinition::open(state.config.clone(), request.table.clone())
+        .await?
+        .load()
+        .await?;
+
+    table
+        .existing_values(&request.column, &request.values)
+        .await
+}
+
+/// Explains how the query would be run, with the columns it would scan, its predicates and
+/// aggregates, the shards it would be sent to and the rows it would scan, without running it.
+pub async fn explain(
+    Extension(principal): Extension<Principal>,
+    Extension(origin): Extension<Origin>,
+    State(state): State<DatabaseState>,
+    Json(request): Json<QueryRequest>,
+) -> Result<Json<ExplainResponse>, Json<String>> {
+    let state = state.for_origin(&origin);
+
+    if let Err(e) = authorize_query(&principal, &request.from) {
+        info!("{}", e);
+        return Err(Json(e.to_string()));
+    }
+
+    match explain_query(&state, request).await {
+        Ok(explanation) => Ok(Json(explanation)),
+        Err(e) => {
+            info!("{}", e);
+            Err(Json(e.to_string()))
+        }
+    }
+}
+
+async fn explain_query(
+    state: &DatabaseState,
+    request: QueryRequest,
+) -> io::Result<ExplainResponse> {
+    if request.from.system_table()?.is_some() {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "The system tables aren't stored, so their queries have no plan",
+        ));
+    }
+    let request = expand_wildcard(state, request).await?;
+
+    if let QuerySource::Query(subquery) = &request.from {
+        let subquery = Box::pin(explain_query(state, (**subquery).clone())).await?;
+        let (request, _) = request.normalize()?;
+        return Ok(ExplainResponse {
+            query: request,
+            tables: vec![],
+            estimated_rows: subquery.estimated_rows,
+            subquery: Some(Box::new(subquery)),
+            shards: vec![],
+        });
+    }
+
+    let (request, _) = request.normalize()?;
+    let mut tables = vec![];
+    for table_name in request.from.tables() {
+        tables.push(explain_table(state, table_name, &request).await?);
+    }
+
+    let mut shard_explanations = vec![];
+    if let Some(shards) = state.shards.deref() {
+        let placement = query_placement(state, &request.from).await;
+        let results = shards
+            .broadcast_to(Explain::new(&request), |s| {
+                placement.as_ref().is_none_or(|p| p.contains(&s.ip_port))
+            })
+            .await
+            .results;
+        for (shard, result) in results {
+            let (tables, error) = match result {
+                Ok(explanation) => (explanation.tables, None),
+                Err(error) => (vec![], Some(error.to_string())),
+            };
+            shard_explanations.push(ShardExplanation {
+                ip_port: shard.ip_port.clone(),
+                tables,
+                error,
+            });
+        }
+    }
+
+    let estimated_rows = tables
+        .iter()
+        .chain(shard_explanations.iter().flat_map(|s| s.tables.iter()))
+        .map(|t| t.estimated_rows)
+        .sum();
+    Ok(ExplainResponse {
+        query: request,
+        tables,
+        subquery: None,
+        shards: shard_explanations,
+        estimated_rows,
+    })
+}
+
+/// Explains how one of the tables of a normalized query would be queried on this instance.
+async fn explain_table(
+    state: &DatabaseState,
+    table_name: &str,
+    request: &QueryRequest,
+) -> io::Result<TableExplanation> {
+    let table_lock = state.table_locks.get(table_name);
+    let _guard = table_lock.read().await;
+    let table = TableDefinition::open(state.config.clone(), table_name.to_string())
+        .await?
+        .load()
+        .await?;
+
+    table
+        .explain(
+            request.select.clone(),
+            request.group_by.clone(),
+            request.filter.clone(),
+            request.order_by.clone(),
+            request.select_distinct,
+            TimeRange::new(request.since, request.until),
+        )
+        .await
+}
+
+/// Executes the queries of the batch concurrently, sending them to each shard in a single request.
+///
+/// Each query fails on its own, so the responses of the other queries are still returned. The
+/// responses are always in json, since the rows of the queries have different columns.
+pub async fn query_batch(
+    Extension(principal): Extension<Principal>,
+    Extension(origin): Extension<Origin>,
+    Extension(session): Extension<SessionSettings>,
+    State(state): State<DatabaseState>,
+    Json(request): Json<QueryBatchRequest>,
+) -> Json<Vec<QueryResponse>> {
+    let state = state.for_origin(&origin);
+    let queries = request.queries.into_iter().map(Ok).collect();
+
+    Json(execute_batch(&state, &principal, &origin, &session, queries).await)
+}
+
+/// Executes the queries of a batch, of which the ones which couldn't be parsed only get an error.
+async fn execute_batch(
+    state: &DatabaseState,
+    principal: &Principal,
+    origin: &Origin,
+    session: &SessionSettings,
+    queries: Vec<io::Result<QueryRequest>>,
+) -> Vec<QueryResponse> {
+    let mut batch_queries = Vec::with_capacity(queries.len());
+    for query_request in queries {
+        batch_queries.push(match query_request {
+            Ok(query_request) => prepare_batch_query(state, principal, query_request).await,
+            Err(error) => Err(error),
+        });
+    }
+
+    // The shards are queried once for the whole batch, and each query takes its own results when
+    // it runs, so that the timeout of each query covers the shards too.
+    let table_queries = batch_queries
+        .iter()
+        .enumerate()
+        .filter_map(|(index, batch_query)| match batch_query {
+            Ok(BatchQuery::Table { request, .. }) => Some((index, request.clone())),
+            _ => None,
+        })
+        .collect();
+    let shard_query_results = query_shards_in_batch(state, batch_queries.len(), table_queries)
+        .map(|results| Arc::new(Mutex::new(results)))
+        .shared();
+
+    let query_futures = batch_queries
+        .into_iter()
+        .enumerate()
+        .map(|(index, batch_query)| {
+            let shard_query_results = shard_query_results.clone();
+            async move {
+                let query_response = match batch_query {
+                    Ok(BatchQuery::System(system_table, request)) => {
+                        let system_query = async {
+                            execute_system_query(state, principal, system_table, request)
+                                .await
+                                .unwrap_or_else(|e| QueryResponse::error(e.to_string()))
+                        };
+                        execute_in_session(state, session, system_query).await
+                    }
+                    Ok(BatchQuery::Subquery(request)) => {
+                        let query_response =
+                            execute_in_session(state, session, execute_query(state, request)).await;
+                        record_usage(state, principal, 0, query_response.scanned_rows()).await;
+
+                        query_response
+                    }
+                    Ok(BatchQuery::Table {
+                        request,
+                        aliases,
+                        include_timestamps,
+                    }) => {
+                        let shard_future = async {
+                            let results = shard_query_results.await;
+                            let mut results = results.lock().unwrap();
+                            std::mem::take(&mut results[index])
+                        };
+                        let query_response = execute_in_session(
+                            state,
+                            session,
+                            execute_normalized_query(
+                                state,
+                                request,
+                                &aliases,
+                                include_timestamps,
+                                shard_future,
+                            ),
+                        )
+                        .await;
+                        record_usage(state, principal, 0, query_response.scanned_rows()).await;
+
+                        query_response
+                    }
+                    Err(error) => {
+                        info!("Invalid query in batch: {}", error);
+                        QueryResponse::error(error.to_string())
+                    }
+                };
+
+                // The shard which forwarded the batch only needs the components of the aggregates.
+                match origin {
+                    Origin::Shard(_) => query_response.into_partial(),
+                    Origin::Client => query_response.with_session_nulls(session),
+                }
+            }
+        });
+
+    join_all(query_futures).await
+}
+
+/// A query of a batch, authorized and ready to be executed.
+enum BatchQuery {
+    System(SystemTable, QueryRequest),
+    /// A query on a subquery, whose subquery is sent to the shards on its own.
+    Subquery(QueryRequest),
+    Table {
+        request: QueryRequest,
+        aliases: HashMap<String, String>,
+        include_timestamps: bool,
+    },
+}
+
+async fn prepare_batch_query(
+    state: &DatabaseState,
+    principal: &Principal,
+    request: QueryRequest,
+) -> io::Result<BatchQuery> {
+    if let Some(system_table) = request.from.system_table()? {
+        principal.authorize(Role::Reader, None)?;
+        return Ok(BatchQuery::System(system_table, request));
+    }
+
+    authorize_query(principal, &request.from)?;
+    check_quotas(state, principal, 0).await?;
+
+    if let QuerySource::Query(_) = request.from {
+        return Ok(BatchQuery::Subquery(request));
+    }
+    let request = expand_wildcard(state, request).await?;
+
+    let include_timestamps = request.include_timestamps;
+    let (mut request, aliases) = request.normalize()?;
+    split_sample(state, &mut request);
+
+    Ok(BatchQuery::Table {
+        request,
+        aliases,
+        include_timestamps,
+    })
+}
+
+/// Sends the table queries of the batch, with their index in the batch, to the shards in a single
+/// request each, returning the results of the shards for each query of the batch.
+async fn query_shards_in_batch(
+    state: &DatabaseState,
+    batch_len: usize,
+    table_queries: Vec<(usize, QueryRequest)>,
+) -> Vec<ShardQueryResults> {
+    let mut shard_query_results: Vec<_> = (0..batch_len)
+        .map(|_| ShardQueryResults::default())
+        .collect();
+    let Some(shards) = state.shards.deref() else {
+        return shard_query_results;
+    };
+    if table_queries.is_empty() {
+        return shard_query_results;
+    }
+
+    let (indexes, queries): (Vec<_>, Vec<_>) = table_queries.into_iter().unzip();
+
+    // Only the shards storing data of any of the tables are queried, if they are known.
+    let mut placements = Vec::with_capacity(queries.len());
+    for query in queries.iter() {
+        placements.push(query_placement(state, &query.from).await);
+    }
+    let query_batch = QueryBatchRequest { queries };
+    let results = shards
+        .broadcast_to(QueryBatch::new(&query_batch), |s| {
+            placements
+                .iter()
+                .any(|p| p.as_ref().is_none_or(|p| p.contains(&s.ip_port)))
+        })
+        .await
+        .results;
+
+    for (shard, result) in results {
+        match result {
+            Ok(query_responses) if query_responses.len() == indexes.len() => {
+                for (index, query_response) in indexes.iter().zip(query_responses) {
+                    shard_query_results[*index].add(shard, query_response.into_result());
+                }
+            }
+            Ok(query_responses) => {
+                for index in indexes.iter() {
+                    let error = Error::new(
+                        ErrorKind::InvalidData,
+                        format!(
+                            "{} responses were returned for {} queries",
+                            query_responses.len(),
+                            indexes.len()
+                        ),
+                    );
+                    shard_query_results[*index].add(shard, Err(error));
+                }
+            }
+            Err(error) => {
+                for index in indexes.iter() {
+                    let error = Error::new(error.kind(), error.to_string());
+                    shard_query_results[*index].add(shard, Err(error));
+                }
+            }
+        }
+    }
+
+    shard_query_results
+}
+
+/// Runs the execution of a query with the settings of the session, failing it if the session
+/// targets another database or the execution exceeds the timeout of the session.
+async fn execute_in_session(
+    state: &DatabaseState,
+    session: &SessionSettings,
+    execution: impl Future<Output = QueryResponse>,
+) -> QueryResponse {
+    if let Err(error) = session.validate(&state.config) {
+        info!("{}", error);
+        return QueryResponse::error(error.to_string());
+    }
+
+    let Some(duration) = session.timeout() else {
+        return execution.await;
+    };
+
+    match timeout(duration, execution).await {
+        Ok(query_response) => query_response,
+        Err(_) => {
+            info!("The query timed out after {} ms", duration.as_millis());
+            QueryResponse::error(format!(
+                "The query timed out after {} ms",
+                duration.as_millis()
+            ))
+        }
+    }
+}
+
+pub async fn save_query(
+    Extension(principal): Extension<Principal>,
+    Extension(origin): Extension<Origin>,
+    State(state): State<DatabaseState>,
+    Json(request): Json<SavedQuery>,
+) -> Json<OpResponse> {
+    let state = state.for_origin(&origin);
+
+    if let Err(e) = principal.authorize(Role::Writer, None) {
+        return Json(OpResponse::from_result(Err(e), ""));
+    }
+
+    // Create a future for the shard broadcast operation
+    let shard_broadcast_future = async {
+        if let Some(shards) = state.shards.deref() {
+            let save_query = SaveQuery::new(&request);
+            shards
+                .broadcast(save_query)
+                .await
+                .into_outputs()
+                .map_err(|e| {
+                    Error::new(
+                        ErrorKind::InvalidData,
+                        format!("Error while saving query in the shards: {}", e),
+                    )
+                })?;
+        }
+
+        Ok(())
+    }
+    .boxed();
+
+    // Create a future for the local save operation
+    let request = request.clone();
+    let local_save_future = async { state.saved_queries.save(request).await }.boxed();
+
+    let (shard_result, local_result): (io::Result<()>, io::Result<()>) =
+        join(shard_broadcast_future, local_save_future).await;
+    let result = match (shard_result, local_result) {
+        (Ok(_), Ok(_)) => Ok(()),
+        (Err(e), _) => Err(Error::new(
+            e.kind(),
+            format!("Error in shard query saving: {}", e),
+        )),
+        (_, Err(e)) => Err(Error::new(
+            e.kind(),
+            format!("Error in local query saving: {}", e),
+        )),
+    };
+
+    Json(OpResponse::from_result(result, "Query saved successfully"))
+}
+
+pub async fn rotate_key(
+    Extension(principal): Extension<Principal>,
+    Extension(origin): Extension<Origin>,
+    State(state): State<DatabaseState>,
+    Json(request): Json<RotateKeyRequest>,
+) -> Json<OpResponse> {
+    let state = state.for_origin(&origin);
+
+    if let Err(e) = principal.authorize(Role::Admin, None) {
+        return Json(OpResponse::from_result(Err(e), ""));
+    }
+
+    if encryption().is_none() {
+        let error = Error::new(ErrorKind::InvalidInput, "Encryption is not enabled");
+        return Json(OpResponse::from_result(Err(error), ""));
+    }
+
+    if let Some(shards) = state.shards.deref() {
+        let rotate_key = RotateKey::new(&request);
+        if let Err(e) = shards.broadcast(rotate_key).await.into_outputs() {
+            let error = Error::new(e.kind(), format!("Error in shard key rotation: {}", e));
+            return Json(OpResponse::from_result(Err(error), ""));
+        }
+    }
+
+    // The files are re-encrypted in the background, since it could take a long time.
+    spawn_key_rotation(state.clone());
+
+    Json(OpResponse::from_result(Ok(()), "Key rotation started"))
+}
+
+/// Compacts the partitions with deleted rows on the whole cluster, dropping the records of the
+/// deleted rows from their files.
+pub async fn compact(
+    Extension(principal): Extension<Principal>,
+    Extension(origin): Extension<Origin>,
+    State(state): State<DatabaseState>,
+    Json(request): Json<CompactRequest>,
+) -> Json<OpResponse> {
+    let state = state.for_origin(&origin);
+
+    let check = match principal.authorize(Role::Admin, None) {
+        Ok(_) => check_compact_request(&state, &request).await,
+        Err(error) => Err(error),
+    };
+    if let Err(e) = check {
+        return Json(OpResponse::from_result(Err(e), ""));
+    }
+
+    if let Some(shards) = state.shards.deref() {
+        let compact = Compact::new(&request);
+        if let Err(e) = shards.broadcast(compact).await.into_outputs() {
+            let error = Error::new(e.kind(), format!("Error in shard compaction: {}", e));
+            return Json(OpResponse::from_result(Err(error), ""));
+        }
+    }
+
+    // The partitions are compacted in the background, since it could take a long time.
+    spawn_manual_compaction(state.clone(), request.table);
+
+    Json(OpResponse::from_result(Ok(()), "Compaction started"))
+}
+
+async fn check_compact_request(state: &DatabaseState, request: &CompactRequest) -> io::Result<()> {
+    state.read_only.check_writable().await?;
+    let Some(table_name) = &request.table else {
+        return Ok(());
+    };
+
+    check_table_name(table_name)?;
+    if !TableDefinition::exists(&state.config, table_name).await? {
+        return Err(Error::new(
+            ErrorKind::NotFound,
+            format!("The table {} does not exist", table_name),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Puts the instance, or the whole cluster if requested to the master, in read-only mode or back.
+pub async fn set_read_only(
+    Extension(principal): Extension<Principal>,
+    Extension(origin): Extension<Origin>,
+    State(state): State<DatabaseState>,
+    Json(request): Json<ReadOnlyRequest>,
+) -> Json<OpResponse> {
+    let state = state.for_origin(&origin);
+
+    let result = match principal.authorize(Role::Admin, None) {
+        Ok(_) => execute_set_read_only(&state, &request).await,
+        Err(error) => Err(error),
+    };
+    let message = match request.read_only {
+        true => "Read-only mode enabled",
+        false => "Read-only mode disabled",
+    };
+
+    Json(OpResponse::from_result(result, message))
+}
+
+/// Sets the read-only mode of the instance, after the one of its shards if requested.
+async fn execute_set_read_only(state: &DatabaseState, request: &ReadOnlyRequest) -> io::Result<()> {
+    // The shards are switched first, so that the master never accepts writes that the shards
+    // would reject.
+    if let Some(shards) = state.shards.deref().as_ref().filter(|_| request.cluster) {
+        let set_read_only = SetReadOnly::new(request);
+        shards
+            .broadcast(set_read_only)
+            .await
+            .into_outputs()
+            .map_err(|e| {
+                Error::new(
+                    e.kind(),
+                    format!(
+                        "Error while setting the read-only mode of the shards: {}",
+                        e
+                    ),
+                )
+            })?;
+    }
+
+    let read_only_state = ReadOnlyState {
+        enabled: request.read_only,
+        reason: request.reason.clone(),
+    };
+    state.read_only.set(read_only_state).await.map_err(|e| {
+        Error::new(
+            e.kind(),
+            format!("Error while setting the read-only mode: {}", e),
+        )
+    })?;
+
+    // The buffered rows are flushed once no more inserts are accepted, so that the files contain
+    // all the rows while they are read-only.
+    if request.read_only {
+        flush_memtables(state).await.map_err(|e| {
+            Error::new(
+                e.kind(),
+                format!("Error while flushing the memtables: {}", e),
+            )
+        })?;
+    }
+
+    Ok(())
+}
+
+pub async fn get_read_only(
+    Extension(principal): Extension<Principal>,
+    State(state): State<DatabaseState>,
+) -> Result<Json<ReadOnlyState>, Json<String>> {
+    if let Err(e) = principal.authorize(Role::Admin, None) {
+        info!("{}", e);
+        return Err(Json(e.to_string()));
+    }
+
+    Ok(Json(state.read_only.get().await))
+}
+
+pub async fn save_user(
+    Extension(principal): Extension<Principal>,
+    Extension(origin): Extension<Origin>,
+    State(state): State<DatabaseState>,
+    Json(request): Json<SaveUserRequest>,
+) -> Json<OpResponse> {
+    let state = state.for_origin(&origin);
+
+    if let Err(e) = principal.authorize(Role::Admin, None) {
+        return Json(OpResponse::from_result(Err(e), ""));
+    }
+
+    // Create a future for the shard broadcast operation
+    let shard_broadcast_future = async {
+        if let Some(shards) = state.shards.deref() {
+            let save_user = SaveUser::new(&request);
+            shards
+                .broadcast(save_user)
+                .await
+                .into_outputs()
+                .map_err(|e| {
+                    Error::new(
+                        ErrorKind::InvalidData,
+                        format!("Error while saving user in the shards: {}", e),
+                    )
+                })?;
+        }
+
+        Ok(())
+    }
+    .boxed();
+
+    // Create a future for the local save operation
+    let request = request.clone();
+    let local_save_future = async {
+        let user = User::new(
+            request.name,
+            &request.key,
+            request.role,
+            request.allowed_tables,
+            request.quotas,
+        );
+        state.users.save(user).await
+    }
+    .boxed();
+
+    let (shard_result, local_result): (io::Result<()>, io::Result<()>) =
+        join(shard_broadcast_future, local_save_future).await;
+    let result = match (shard_result, local_result) {
+        (Ok(_), Ok(_)) => Ok(()),
+        (Err(e), _) => Err(Error::new(
+            e.kind(),
+            format!("Error in shard user saving: {}", e),
+        )),
+        (_, Err(e)) => Err(Error::new(
+            e.kind(),
+            format!("Error in local user saving: {}", e),
+        )),
+    };
+
+    Json(OpResponse::from_result(result, "User saved successfully"))
+}
+
+pub async fn list_users(
+    Extension(principal): Extension<Principal>,
+    State(state): State<DatabaseState>,
+) -> Result<Json<Vec<User>>, Json<String>> {
+    if let Err(e) = principal.authorize(Role::Admin, None) {
+        info!("{}", e);
+        return Err(Json(e.to_string()));
+    }
+
+    Ok(Json(state.users.list().await))
+}
+
+pub async fn status(
+    Extension(principal): Extension<Principal>,
+    Extension(origin): Extension<Origin>,
+    State(state): State<DatabaseState>,
+) -> Result<Json<StatusResponse>, Json<String>> {
+    let state = state.for_origin(&origin);
+
+    if let Err(e) = principal.authorize(Role::Reader, None) {
+        info!("{}", e);
+        return Err(Json(e.to_string()));
+    }
+
+    Ok(Json(StatusResponse {
+        database_name: state.config.database_name.clone(),
+        role: <&InstanceRole as Into<&str>>::into(&state.config.instance_role).to_string(),
+        time_ms: Some(clock().now_millis()),
+        shards: collect_shard_statuses(&state).await,
+    }))
+}
+
+async fn collect_shard_statuses(state: &DatabaseState) -> Vec<ShardStatus> {
+    let mut shard_statuses = vec![];
+    if let Some(shards) = state.shards.deref() {
+        let sent_ms = clock().now_millis();
+        let results = shards.broadcast(Status).await.results;
+        let received_ms = clock().now_millis();
+
+        // The time of each shard is compared with the middle of the round trip, which is allowed
+        // on top of the configured skew since the time was read somewhere within it.
+        let midpoint_ms = (sent_ms + received_ms) / 2;
+        let max_skew_ms = state.config.clock.max_skew_ms + (received_ms - sent_ms) / 2;
+        for (shard, result) in results {
+            let clock_skew_ms = result
+                .as_ref()
+                .ok()
+                .and_then(|s| s.time_ms)
+                .map(|time_ms| time_ms as i64 - midpoint_ms as i64);
+            let warning = clock_skew_ms
+                .filter(|skew| skew.unsigned_abs() > max_skew_ms)
+                .map(|skew| {
+                    let warning = format!(
+                        "The clock of the shard {} is {}ms {} the clock of the master",
+                        shard.ip_port,
+                        skew.unsigned_abs(),
+                        if skew > 0 { "ahead of" } else { "behind" }
+                    );
+                    info!("{}", warning);
+                    warning
+                });
+
+            shard_statuses.push(ShardStatus {
+                ip_port: shard.ip_port.clone(),
+                reachable: result.is_ok(),
+                error: result.err().map(|e| e.to_string()),
+                clock_skew_ms,
+                warning,
+            });
+        }
+    }
+
+    shard_statuses
+}
+
+pub async fn list_tables(
+    Extension(principal): Extension<Principal>,
+    Extension(origin): Extension<Origin>,
+    State(state): State<DatabaseState>,
+) -> Result<Json<Vec<TableInfo>>, Json<String>> {
+    let state = state.for_origin(&origin);
+
+    if let Err(e) = principal.authorize(Role::Reader, None) {
+        info!("{}", e);
+        return Err(Json(e.to_string()));
+    }
+
+    match collect_tables(&state, &principal).await {
+        Ok(tables) => Ok(Json(tables)),
+        Err(e) => {
+            info!("{}", e);
+            Err(Json(e.to_string()))
+        }
+    }
+}
+
+pub async fn get_stats(
+    Extension(principal): Extension<Principal>,
+) -> Result<Json<Vec<ColumnStats>>, Json<String>> {
+    if let Err(e) = principal.authorize(Role::Reader, None) {
+        info!("{}", e);
+        return Err(Json(e.to_string()));
+    }
+
+    Ok(Json(collect_column_stats(&principal)))
+}
+
+/// Suggests the encoding of each column of the table, from the cardinality of its values in the
+/// whole cluster.
+pub async fn get_compression_advice(
+    Extension(principal): Extension<Principal>,
+    State(state): State<DatabaseState>,
+    Path(name): Path<String>,
+) -> Result<Json<Vec<CompressionAdvice>>, Json<String>> {
+    let result = match principal.authorize(Role::Reader, Some(&name)) {
+        Ok(_) => collect_compression_advice(&state, &name).await,
+        Err(error) => Err(error),
+    };
+
+    match result {
+        Ok(advice) => Ok(Json(advice)),
+        Err(e) => {
+            info!("{}", e);
+            Err(Json(e.to_string()))
+        }
+    }
+}
+
+async fn collect_compression_advice(
+    state: &DatabaseState,
+    table_name: &str,
+) -> io::Result<Vec<CompressionAdvice>> {
+    if !TableDefinition::exists(&state.config, table_name).await? {
+        return Err(Error::new(
+            ErrorKind::NotFound,
+            format!("The table {} doesn't exist", table_name),
+        ));
+    }
+
+    let table_definition =
+        TableDefinition::open(state.config.clone(), table_name.to_string()).await?;
+    let mut advice = Vec::with_capacity(table_definition.columns().len());
+    for column in table_definition.columns() {
+        let (rows, distinct_values) = count_distinct_values(state, table_name, column).await?;
+        advice.push(CompressionAdvice::new(
+            column,
+            table_definition.encoding(column),
+            rows,
+            distinct_values,
+        ));
+    }
+
+    Ok(advice)
+}
+
+/// Returns the number of rows and of distinct values of the column in the whole cluster, by
+/// counting the rows of each value.
+async fn count_distinct_values(
+    state: &DatabaseState,
+    table_name: &str,
+    column: &TableColumn,
+) -> io::Result<(u64, u64)> {
+    let request = QueryRequest {
+        select: vec![format!("count({})", column.name)],
+        from: QuerySource::Table(table_name.to_string()),
+        group_by: Some(vec![column.name.clone()]),
+        since: None,
+        until: None,
+        filter: None,
+        order_by: None,
+        select_distinct: false,
+        include_timestamps: false,
+        row_timestamps: false,
+        sample: None,
+        // The scan of the whole column must not delay the queries of the users on the shards.
+        priority: QueryPriority::Low,
+    };
+
+    match execute_query(state, request).await {
+        QueryResponse::WithAggregatedData { aggregates, .. } => {
+            let rows = aggregates
+                .iter()
+                .filter_map(|a| a.first()?.value.as_ref()?.as_u64())
+                .sum();
+            Ok((rows, aggregates.len() as u64))
+        }
+        QueryResponse::Empty { errors, .. } if !errors.is_empty() => Err(Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "Error while counting the values of {}: {}",
+                column.name,
+                errors.join(", ")
+            ),
+        )),
+        _ => Ok((0, 0)),
+    }
+}
+
+pub async fn get_session(
+    Extension(principal): Extension<Principal>,
+    State(state): State<DatabaseState>,
+) -> Result<Json<SessionSettings>, Json<String>> {
+    if let Err(e) = principal.authorize(Role::Reader, None) {
+        info!("{}", e);
+        return Err(Json(e.to_string()));
+    }
+
+    Ok(Json(state.sessions.get(&principal.session_name()).await))
+}
+
+/// Replaces the session settings of the caller, which apply to its following queries.
+pub async fn set_session(
+    Extension(principal): Extension<Principal>,
+    State(state): State<DatabaseState>,
+    Json(request): Json<SessionSettings>,
+) -> Json<OpResponse> {
+    let validation = principal
+        .authorize(Role::Reader, None)
+        .and_then(|_| request.validate(&state.config));
+    if let Err(e) = validation {
+        return Json(OpResponse::from_result(Err(e), ""));
+    }
+
+    state.sessions.set(principal.session_name(), request).await;
+
+    Json(OpResponse::from_result(
+        Ok(()),
+        "Session updated successfully",
+    ))
+}
 
-        let aggregate_components = aggregate_data
-            .components
-            .into_iter()
-            .map(|v| Self::build_column_and_column_value(column, v).1)
-            .collect();
+/// Exposes the write metrics of the columns in the Prometheus text format.
+pub async fn get_metrics(
+    Extension(principal): Extension<Principal>,
+) -> Result<String, Json<String>> {
+    if let Err(e) = principal.authorize(Role::Reader, None) {
+        info!("{}", e);
+        return Err(Json(e.to_string()));
+    }
 
-        (aggregate_column, column_value, aggregate_components)
+    let column_stats = collect_column_stats(&principal);
+    let mut metrics = String::new();
+    write_counter(
+        &mut metrics,
+        "distribuito_column_bytes_written_total",
+        "The bytes written to the files of the column.",
+        &column_stats,
+        |stats| stats.bytes_written,
+    );
+    write_counter(
+        &mut metrics,
+        "distribuito_column_records_written_total",
+        "The records written to the files of the column.",
+        &column_stats,
+        |stats| stats.records_written,
+    );
+
+    Ok(metrics)
+}
+
+/// Collects the writes to the columns of this instance, for the tables which the principal can
+/// read.
+fn collect_column_stats(principal: &Principal) -> Vec<ColumnStats> {
+    column_writes()
+        .into_iter()
+        .filter(|(table, _, _)| principal.authorize(Role::Reader, Some(table)).is_ok())
+        .map(|(table, column, writes)| ColumnStats {
+            table,
+            column,
+            bytes_written: writes.bytes_written,
+            records_written: writes.records_written,
+        })
+        .collect()
+}
+
+fn write_counter(
+    metrics: &mut String,
+    name: &str,
+    help: &str,
+    column_stats: &[ColumnStats],
+    value: impl Fn(&ColumnStats) -> u64,
+) {
+    metrics.push_str(&format!("# HELP {} {}\n", name, help));
+    metrics.push_str(&format!("# TYPE {} counter\n", name));
+    for stats in column_stats {
+        metrics.push_str(&format!(
+            "{}{{table=\"{}\",column=\"{}\"}} {}\n",
+            name,
+            escape_label(&stats.table),
+            escape_label(&stats.column),
+            value(stats)
+        ));
+    }
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Lists the tables which the principal can read, with their number of rows in the cluster.
+async fn collect_tables(
+    state: &DatabaseState,
+    principal: &Principal,
+) -> io::Result<Vec<TableInfo>> {
+    // Create a future for the shard broadcast operation
+    let shard_broadcast_future = async {
+        match state.shards.deref() {
+            Some(shards) => shards.broadcast(ListTables).await.into_outputs(),
+            None => Ok(vec![]),
+        }
+    }
+    .boxed();
+
+    // Create a future for the local listing operation
+    let local_list_future = async {
+        let mut tables = vec![];
+        for table_name in TableDefinition::list(&state.config).await? {
+            if principal
+                .authorize(Role::Reader, Some(&table_name))
+                .is_err()
+            {
+                continue;
+            }
+
+            let table_lock = state.table_locks.get(&table_name);
+            let _guard = table_lock.read().await;
+            let table_definition =
+                TableDefinition::open(state.config.clone(), table_name.clone()).await?;
+            let table = table_definition.load().await?;
+            tables.push(TableInfo {
+                name: table_name,
+                columns: table.columns().iter().map(|c| c.clone().into()).collect(),
+                rows: table.row_count(),
+            });
+        }
+
+        Ok(tables)
+    }
+    .boxed();
+
+    let (shard_result, local_result): (
+        io::Result<Vec<Vec<TableInfo>>>,
+        io::Result<Vec<TableInfo>>,
+    ) = join(shard_broadcast_future, local_list_future).await;
+    match (shard_result, local_result) {
+        (Ok(shard_tables), Ok(mut tables)) => {
+            // The rows of each table are spread across the shards, so we sum them up.
+            for shard_table in shard_tables.into_iter().flatten() {
+                if let Some(table) = tables.iter_mut().find(|t| t.name == shard_table.name) {
+                    table.rows += shard_table.rows;
+                }
+            }
+
+            Ok(tables)
+        }
+        (Err(e), _) => Err(Error::new(
+            e.kind(),
+            format!("Error in shard table listing: {}", e),
+        )),
+        (_, Err(e)) => Err(Error::new(
+            e.kind(),
+            format!("Error in local table listing: {}", e),
+        )),
+    }
+}
+
+/// Runs a query on one of the system tables, whose rows are built from the state of the cluster
+/// instead of being read from disk.
+async fn execute_system_query(
+    state: &DatabaseState,
+    principal: &Principal,
+    system_table: SystemTable,
+    request: QueryRequest,
+) -> io::Result<QueryResponse> {
+    if request.include_timestamps {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "The rows of the system tables have no timestamps",
+        ));
     }
 
-    pub fn empty() -> Self {
-        Self::Empty { errors: vec![] }
+    let request = expand_wildcard(state, request).await?;
+    let (request, aliases) = request.normalize()?;
+    info!(
+        "Querying the system table {}",
+        <&SystemTable as Into<&str>>::into(&system_table)
+    );
+
+    let rows = match system_table {
+        SystemTable::Tables => collect_tables(state, principal)
+            .await?
+            .into_iter()
+            .filter_map(|t| {
+                system_table.row(vec![
+                    ColumnValue::String(t.name),
+                    ColumnValue::Integer(t.rows as i64),
+                    ColumnValue::Integer(t.columns.len() as i64),
+                ])
+            })
+            .collect(),
+        SystemTable::Columns => collect_tables(state, principal)
+            .await?
+            .into_iter()
+            .flat_map(|t| {
+                t.columns.into_iter().filter_map(move |c| {
+                    let ty: TableColumnType = c.ty.into();
+                    system_table.row(vec![
+                        ColumnValue::String(t.name.clone()),
+                        ColumnValue::String(c.name),
+                        ColumnValue::String(<&str>::from(&ty).to_string()),
+                    ])
+                })
+            })
+            .collect(),
+        SystemTable::Shards => collect_shard_statuses(state)
+            .await
+            .into_iter()
+            .filter_map(|s| {
+                system_table.row(vec![
+                    ColumnValue::String(s.ip_port),
+                    ColumnValue::Integer(s.reachable as i64),
+                    s.error.map_or(ColumnValue::Null, ColumnValue::String),
+                ])
+            })
+            .collect(),
+    };
+
+    let plan = QueryPlan::new(
+        &system_table.columns(),
+        request.select,
+        request.group_by,
+        request.filter,
+        request.order_by,
+        request.select_distinct,
+    )?;
+    let schema = plan.schema();
+    let query_result = plan.execute(rows)?;
+    check_result_size(&state.config, &query_result)?;
+
+    // The rows of the system tables aren't stored, so they have no ids.
+    let mut query_response =
+        serialize_query_result(query_result, &schema, false).with_aliases(&aliases);
+    if let QueryResponse::WithData { row_ids, .. } = &mut query_response {
+        row_ids.clear();
     }
+
+    Ok(query_response)
 }
 
-#[derive(Debug, Clone)]
-pub struct DatabaseState {
-    pub config: Arc<Config>,
-    pub shards: Arc<Option<Shards>>,
+pub async fn get_usage(
+    Extension(principal): Extension<Principal>,
+    State(state): State<DatabaseState>,
+) -> Json<HashMap<String, UserUsage>> {
+    // Admins can see the usage of all users, whereas the other users only their own.
+    let user_name = principal
+        .user()
+        .filter(|u| u.role < Role::Admin)
+        .map(|u| u.name.as_str());
+
+    Json(state.usage.get(user_name).await)
 }
 
-pub async fn create_table(
+pub async fn delete_user(
+    Extension(principal): Extension<Principal>,
+    Extension(origin): Extension<Origin>,
     State(state): State<DatabaseState>,
-    Json(request): Json<CreateTableRequest>,
-) -> Json<String> {
+    Path(name): Path<String>,
+) -> Json<OpResponse> {
+    let state = state.for_origin(&origin);
+
+    if let Err(e) = principal.authorize(Role::Admin, None) {
+        return Json(OpResponse::from_result(Err(e), ""));
+    }
+
     // Create a future for the shard broadcast operation
     let shard_broadcast_future = async {
         if let Some(shards) = state.shards.deref() {
-            let create_table = CreateTable::new(&request);
-            shards.broadcast(create_table).await.map_err(|e| {
-                Error::new(
-                    ErrorKind::InvalidData,
-                    format!("Error while creating table in the shards: {}", e),
-                )
-            })?;
+            let delete_user = DeleteUser::new(&name);
+            shards
+                .broadcast(delete_user)
+                .await
+                .into_outputs()
+                .map_err(|e| {
+                    Error::new(
+                        ErrorKind::InvalidData,
+                        format!("Error while deleting user in the shards: {}", e),
+                    )
+                })?;
         }
 
         Ok(())
     }
     .boxed();
 
-    // Create a future for the local table creation operation
-    let request = request.clone();
-    let local_create_future = async {
-        let columns = request.columns.into_iter().map(|c| c.into()).collect();
-        TableDefinition::create(state.config.clone(), request.name, columns)
+    // Create a future for the local delete operation
+    let local_delete_future = async { state.users.delete(&name).await }.boxed();
+
+    let (shard_result, local_result): (io::Result<()>, io::Result<()>) =
+        join(shard_broadcast_future, local_delete_future).await;
+    let result = match (shard_result, local_result) {
+        (Ok(_), Ok(_)) => Ok(()),
+        (Err(e), _) => Err(Error::new(
+            e.kind(),
+            format!("Error in shard user deletion: {}", e),
+        )),
+        (_, Err(e)) => Err(Error::new(
+            e.kind(),
+            format!("Error in local user deletion: {}", e),
+        )),
+    };
+
+    Json(OpResponse::from_result(result, "User deleted successfully"))
+}
+
+pub async fn run_query(
+    Extension(principal): Extension<Principal>,
+    Extension(origin): Extension<Origin>,
+    State(state): State<DatabaseState>,
+    Extension(session): Extension<SessionSettings>,
+    Path(name): Path<String>,
+    Json(request): Json<RunQueryRequest>,
+) -> Response {
+    let state = state.for_origin(&origin);
+
+    let query_request = match state.saved_queries.get(&name).await {
+        Ok(saved_query) => saved_query.instantiate(&request.params),
+        Err(error) => Err(error),
+    }
+    .and_then(|query_request| {
+        authorize_query(&principal, &query_request.from)?;
+        Ok(query_request)
+    });
+    let query_request = match query_request {
+        Ok(query_request) => check_quotas(&state, &principal, 0)
             .await
-            .map_err(|e| {
-                Error::new(
-                    ErrorKind::InvalidData,
-                    format!("Error while creating table in the shards: {}", e),
-                )
-            })?;
+            .map(|_| query_request),
+        Err(error) => Err(error),
+    };
 
-        Ok(())
+    match query_request {
+        Ok(query_request) => {
+            let query_response =
+                execute_in_session(&state, &session, execute_query(&state, query_request)).await;
+            record_usage(&state, &principal, 0, query_response.scanned_rows()).await;
+
+            query_response.into_session_response(&session)
+        }
+        Err(error) => {
+            info!("Error while running saved query '{}': {}", name, error);
+            Json(QueryResponse::error(error.to_string())).into_response()
+        }
     }
-    .boxed();
+}
 
-    let (shard_result, local_result): (io::Result<()>, io::Result<()>) =
-        join(shard_broadcast_future, local_create_future).await;
-    match (shard_result, local_result) {
-        (Ok(_), Ok(_)) => {
-            info!("Table created successfully");
-            Json("Table created successfully".to_string())
+/// Prepares a query whose `$1`-style placeholders are bound to the parameters of each of its
+/// executions, so that it's parsed only once and its parameters can't change its expressions.
+pub async fn prepare(
+    Extension(principal): Extension<Principal>,
+    State(state): State<DatabaseState>,
+    Json(request): Json<QueryRequest>,
+) -> Result<Json<PrepareResponse>, Json<String>> {
+    let expressions = authorize_query(&principal, &request.from)
+        .and_then(|_| request.from.validate())
+        .and_then(|_| request.parse_expressions());
+    let expressions = match expressions {
+        Ok(expressions) => expressions,
+        Err(e) => {
+            info!("{}", e);
+            return Err(Json(e.to_string()));
         }
-        (Err(e), _) => {
-            info!("Error in shard table creation: {}", e);
-            Json(format!("Error in shard table creation: {}", e))
+    };
+
+    let parameters = expressions.parameters();
+    let id = state
+        .prepared_queries
+        .prepare(principal.session_name(), request, expressions);
+    info!("Prepared query {} with {} parameters", id, parameters);
+
+    Ok(Json(PrepareResponse { id, parameters }))
+}
+
+/// Executes a prepared query with the parameters bound to its placeholders.
+pub async fn execute(
+    Extension(principal): Extension<Principal>,
+    Extension(origin): Extension<Origin>,
+    State(state): State<DatabaseState>,
+    Extension(session): Extension<SessionSettings>,
+    Json(request): Json<ExecuteRequest>,
+) -> Response {
+    let state = state.for_origin(&origin);
+
+    let query_request = state
+        .prepared_queries
+        .get(&principal.session_name(), request.id)
+        .and_then(|prepared_query| {
+            let parameters = prepared_query.expressions.parameters();
+            if request.params.len() != parameters {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!(
+                        "The prepared query {} has {} parameters, but {} were supplied",
+                        request.id,
+                        parameters,
+                        request.params.len()
+                    ),
+                ));
+            }
+
+            let params = request
+                .params
+                .iter()
+                .map(parameter_value)
+                .collect::<io::Result<Vec<_>>>()?;
+            let query_request = prepared_query
+                .request
+                .bind_parameters(&prepared_query.expressions, &params)?;
+            authorize_query(&principal, &query_request.from)?;
+            Ok(query_request)
+        });
+    let query_request = match query_request {
+        Ok(query_request) => check_quotas(&state, &principal, 0)
+            .await
+            .map(|_| query_request),
+        Err(error) => Err(error),
+    };
+
+    match query_request {
+        Ok(query_request) => {
+            let query_response =
+                execute_in_session(&state, &session, execute_query(&state, query_request)).await;
+            record_usage(&state, &principal, 0, query_response.scanned_rows()).await;
+
+            query_response.into_session_response(&session)
         }
-        (_, Err(e)) => {
-            info!("Error in local table creation: {}", e);
-            Json(format!("Error in local table creation: {}", e))
+        Err(error) => {
+            info!(
+                "Error while executing prepared query {}: {}",
+                request.id, error
+            );
+            Json(QueryResponse::error(error.to_string())).into_response()
         }
     }
 }
 
-pub async fn insert(
-    State(state): State<DatabaseState>,
-    Json(mut request): Json<InsertRequest>,
-) -> Json<String> {
-    let mut requests = vec![];
-    if let Some(shards) = state.shards.deref() {
-        requests = request.split(shards.number_of_shards() + 1);
-        request = requests.remove(0);
+/// Converts a parameter of a prepared query to the literal bound to its placeholder.
+fn parameter_value(value: &serde_json::Value) -> io::Result<ColumnValue> {
+    match value {
+        serde_json::Value::String(value) => Ok(ColumnValue::String(value.clone())),
+        serde_json::Value::Number(number) => number
+            .as_i64()
+            .map(ColumnValue::Integer)
+            .or_else(|| number.as_f64().map(ColumnValue::Float))
+            .ok_or_else(|| {
+                Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("The parameter {} is out of range", number),
+                )
+            }),
+        value => Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("The parameter {} must be a string or a number", value),
+        )),
     }
+}
 
-    // Create futures for each shard insertion operation
-    let shard_insert_futures = requests
-        .into_iter()
-        .map(|request| {
-            let shards = state.shards.clone();
-            async move {
-                if let Some(shards) = shards.deref() {
-                    let insert = Insert::new(&request);
-                    shards.rr_unicast(insert).await.map_err(|error| {
-                        Error::new(
-                            ErrorKind::InvalidData,
-                            format!("Error while inserting data in the shards: {}", error),
-                        )
-                    })?;
-                }
+/// The results of a query on the shards, with the errors of the shards which failed.
+#[derive(Default)]
+struct ShardQueryResults {
+    query_results: Vec<QueryResult>,
+    scan_stats: ScanStats,
+    errors: Vec<String>,
+}
 
-                Ok(())
+impl ShardQueryResults {
+    fn add(&mut self, shard: &Shard, result: io::Result<QueryResponse>) {
+        match result {
+            Ok(query_response) => {
+                self.scan_stats = self.scan_stats + query_response.scan_stats();
+                self.query_results.push(query_response.to_query_result());
+            }
+            Err(error) => {
+                info!(
+                    "Error while querying data from the shard {}: {}",
+                    shard.ip_port, error
+                );
+                self.errors
+                    .push(format!("shard {}: {}", shard.ip_port, error));
             }
-            .boxed()
-        })
-        .collect::<Vec<BoxFuture<io::Result<()>>>>();
-
-    // Create a future for all shard insertions
-    let shard_insert_future = async {
-        let results = join_all(shard_insert_futures).await;
-        if let Some(error) = results.into_iter().find(|r| r.is_err()) {
-            error?;
         }
+    }
+}
 
-        Ok(())
+/// Replaces each `*` selected by the query with the columns of its source, which are only the
+/// grouped columns if the query is grouped by some of them.
+async fn expand_wildcard(
+    state: &DatabaseState,
+    mut request: QueryRequest,
+) -> io::Result<QueryRequest> {
+    let is_wildcard = |item: &String| item.trim() == "*";
+    if !request.select.iter().any(is_wildcard) {
+        return Ok(request);
     }
-    .boxed();
 
-    // Create a future for the table insertion operation
-    let request = request.clone();
-    let table_insert_future = async {
-        let table_definition = TableDefinition::open(state.config.clone(), request.into).await?;
-        let mut table = table_definition.load().await?;
-        table.insert(request.insert, request.values).await?;
-        Ok(())
+    let mut columns = source_columns(state, &request.from).await?;
+    if let Some(group_by) = request
+        .group_by
+        .as_ref()
+        .filter(|_| !request.groups_by_all())
+    {
+        columns.retain(|c| group_by.contains(c));
     }
-    .boxed();
+    request.select = request
+        .select
+        .into_iter()
+        .flat_map(|item| match is_wildcard(&item) {
+            true => columns.clone(),
+            false => vec![item],
+        })
+        .collect();
 
-    // Join the shard insertion and table insertion futures
-    let (shard_result, table_result): (io::Result<()>, io::Result<()>) =
-        join(shard_insert_future, table_insert_future).await;
+    Ok(request)
+}
 
-    match (shard_result, table_result) {
-        (Ok(_), Ok(_)) => {
-            info!("Data inserted successfully");
-            Json("Data inserted successfully".to_string())
-        }
-        (Err(e), _) => {
-            info!("Error in shard insertion: {}", e);
-            Json(format!("Error in shard insertion: {}", e))
+/// Returns the names of the columns of the rows read by a query.
+async fn source_columns(state: &DatabaseState, source: &QuerySource) -> io::Result<Vec<String>> {
+    if let Some(system_table) = source.system_table()? {
+        return Ok(system_table.columns().into_iter().map(|c| c.name).collect());
+    }
+
+    match source {
+        // The columns of a subquery are its selected expressions, named by their alias if any.
+        QuerySource::Query(subquery) => {
+            let subquery = Box::pin(expand_wildcard(state, (**subquery).clone())).await?;
+            subquery
+                .select
+                .iter()
+                .map(|item| {
+                    let item = parse_select_item(item)?;
+                    Ok(item.alias.unwrap_or_else(|| item.expression.to_string()))
+                })
+                .collect()
         }
-        (_, Err(e)) => {
-            info!("Error in table insertion: {}", e);
-            Json(format!("Error in table insertion: {}", e))
+        // The tables of a union have the same columns, so the first one describes them all.
+        _ => {
+            let Some(table_name) = source.tables().first() else {
+                return Ok(vec![]);
+            };
+            let table_definition =
+                TableDefinition::open(state.config.clone(), table_name.clone()).await?;
+            Ok(table_definition
+                .columns()
+                .iter()
+                .map(|c| c.name.clone())
+                .collect())
         }
     }
 }
 
-pub async fn query(
-    State(state): State<DatabaseState>,
-    Json(request): Json<QueryRequest>,
-) -> Json<QueryResponse> {
+pub async fn execute_query(state: &DatabaseState, request: QueryRequest) -> QueryResponse {
+    let request = match expand_wildcard(state, request).await {
+        Ok(request) => request,
+        Err(error) => {
+            info!("Invalid query: {}", error);
+            return QueryResponse::error(error.to_string());
+        }
+    };
+
+    if let QuerySource::Query(_) = request.from {
+        return execute_subquery(state, request)
+            .await
+            .unwrap_or_else(|e| QueryResponse::error(e.to_string()));
+    }
+
+    let include_timestamps = request.include_timestamps;
+    let (mut request, aliases) = match request.normalize() {
+        Ok(normalized) => normalized,
+        Err(error) => {
+            info!("Invalid query: {}", error);
+            return QueryResponse::error(error.to_string());
+        }
+    };
+    split_sample(state, &mut request);
+
     // Create a future for the broadcast operation
     let broadcast_future = async {
-        let mut shard_query_results = vec![];
+        let mut shard_query_results = ShardQueryResults::default();
         if let Some(shards) = state.shards.deref() {
+            // Only the shards storing data of the tables are queried, if they are known.
+            let placement = query_placement(state, &request.from).await;
             let query = Query::new(&request);
-            match shards.broadcast(query).await {
-                Ok(query_responses) => {
-                    for query_response in query_responses {
-                        shard_query_results.push(query_response.to_query_result());
-                    }
-                }
-                Err(error) => {
-                    info!("Error while querying data from the shards: {}", error);
-                }
+            let results = shards
+                .broadcast_to(query, |s| {
+                    placement.as_ref().is_none_or(|p| p.contains(&s.ip_port))
+                })
+                .await
+                .results;
+            for (shard, result) in results {
+                shard_query_results.add(shard, result);
             }
         }
 
@@ -458,90 +4347,397 @@ pub async fn query(
     }
     .boxed();
 
+    execute_normalized_query(
+        state,
+        request.clone(),
+        &aliases,
+        include_timestamps,
+        broadcast_future,
+    )
+    .await
+}
+
+/// Queries one of the tables of a normalized query on this instance.
+async fn query_table(
+    state: &DatabaseState,
+    table_name: &str,
+    request: &QueryRequest,
+) -> io::Result<(QueryResult, QuerySchema, ScanStats)> {
+    let _permit = state.query_slots.acquire(table_name).await?;
+    let table_lock = state.table_locks.get(table_name);
+    let _guard = table_lock.read().await;
+    let table_definition =
+        TableDefinition::open(state.config.clone(), table_name.to_string()).await;
+    match table_definition {
+        Ok(table_def) => match table_def.load().await {
+            Ok(mut table) => {
+                if let Some(sample) = request.sample {
+                    table.sample(sample);
+                }
+                let time_range = TimeRange::new(request.since, request.until);
+                let query_result = table
+                    .query(
+                        request.select.clone(),
+                        request.group_by.clone(),
+                        request.filter.clone(),
+                        request.order_by.clone(),
+                        request.select_distinct,
+                        time_range,
+                    )
+                    .await;
+                let scan_stats = ScanStats {
+                    scanned_rows: table.scanned_rows(),
+                    skipped_records: table.skipped_records(),
+                    pruned_segments: table.pruned_segments(),
+                    pruned_partitions: table.pruned_partitions(),
+                };
+                query_result.map(|(r, schema)| (r, schema, scan_stats))
+            }
+            Err(error) => {
+                info!("Could not load table");
+                Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("Could not load table: {}", error),
+                ))
+            }
+        },
+        Err(error) => {
+            info!("Could not open table");
+            Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("Could not open table: {}", error),
+            ))
+        }
+    }
+}
+
+/// Splits the rows sampled by the query among the instances of the cluster, which sample their own
+/// rows.
+fn split_sample(state: &DatabaseState, request: &mut QueryRequest) {
+    let instances = state
+        .shards
+        .deref()
+        .as_ref()
+        .map_or(1, |s| s.number_of_shards() + 1);
+    request.sample = request.sample.map(|s| s.split(instances));
+}
+
+/// Checks that the principal can read all the tables of the query.
+fn authorize_query(principal: &Principal, source: &QuerySource) -> io::Result<()> {
+    for table in source.tables() {
+        principal.authorize(Role::Reader, Some(table))?;
+    }
+
+    Ok(())
+}
+
+/// Returns the shards storing data of any of the queried tables, or `None` if they aren't known for
+/// one of the tables, in which case all the shards are queried.
+async fn query_placement(state: &DatabaseState, source: &QuerySource) -> Option<BTreeSet<String>> {
+    let mut placement = BTreeSet::new();
+    for table in source.tables() {
+        placement.extend(state.placements.get(table).await?);
+    }
+
+    Some(placement)
+}
+
+/// Executes a query on the result of its subquery, which is executed first on the whole cluster.
+async fn execute_subquery(
+    state: &DatabaseState,
+    request: QueryRequest,
+) -> io::Result<QueryResponse> {
+    if request.include_timestamps || request.since.is_some() || request.until.is_some() {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "The rows of a subquery have no timestamps, so the time range must be set on the subquery",
+        ));
+    }
+
+    let (request, aliases) = request.normalize()?;
+    let QuerySource::Query(subquery) = request.from else {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "The query doesn't read a subquery",
+        ));
+    };
+
+    let subquery_response = Box::pin(execute_query(state, *subquery)).await;
+    if let QueryResponse::Empty { errors, .. } = &subquery_response {
+        if !errors.is_empty() {
+            return Ok(subquery_response);
+        }
+    }
+    let scan_stats = subquery_response.scan_stats();
+    let (columns, rows) = subquery_response.into_subquery_rows();
+
+    let plan = QueryPlan::new(
+        &columns,
+        request.select,
+        request.group_by,
+        request.filter,
+        request.order_by,
+        request.select_distinct,
+    )?;
+    let schema = plan.schema();
+    let query_result = plan.execute(rows)?;
+    check_result_size(&state.config, &query_result)?;
+
+    Ok(serialize_query_result(query_result, &schema, false)
+        .with_aliases(&aliases)
+        .with_scan_stats(scan_stats))
+}
+
+/// Executes a normalized query on the local table, merging its result with the results of the
+/// shards.
+async fn execute_normalized_query(
+    state: &DatabaseState,
+    request: QueryRequest,
+    aliases: &HashMap<String, String>,
+    include_timestamps: bool,
+    broadcast_future: impl Future<Output = ShardQueryResults>,
+) -> QueryResponse {
+    // The results of the shards are sorted again once merged with the local ones.
+    let order_by = request
+        .order_by
+        .iter()
+        .flatten()
+        .map(|o| parse_order_item(o))
+        .collect::<io::Result<Vec<_>>>();
+    // The rows of each shard are distinct, but the same rows can come from several shards.
+    let distinct = request.select_distinct;
+    let row_timestamps = request.row_timestamps;
+
     // Create a future for the table query operation
-    let request = request.clone();
     let table_query_future = async {
-        let table_definition = TableDefinition::open(state.config.clone(), request.from).await;
-        match table_definition {
-            Ok(table_def) => match table_def.load().await {
-                Ok(mut table) => table.query(request.select, request.group_by).await,
-                Err(_) => {
-                    info!("Could not load table");
-                    Err(Error::new(ErrorKind::InvalidData, "Could not load table"))
+        // The results of the tables of a union are concatenated, like the ones of the shards.
+        let tables = request.from.tables();
+        let mut table_query_result: Option<(QueryResult, QuerySchema, ScanStats)> = None;
+        for table in tables {
+            let (result, schema, scan_stats) = query_table(state, table, &request).await?;
+            table_query_result = match table_query_result {
+                None => Some((result, schema, scan_stats)),
+                Some((query_result, query_schema, query_scan_stats)) => {
+                    query_schema.check_compatible(&schema).map_err(|e| {
+                        Error::new(
+                            e.kind(),
+                            format!(
+                                "The tables {} and {} can't be queried together: {}",
+                                tables[0], table, e
+                            ),
+                        )
+                    })?;
+                    Some((
+                        query_result.merge(result)?,
+                        query_schema,
+                        query_scan_stats + scan_stats,
+                    ))
                 }
-            },
-            Err(_) => {
-                info!("Could not open table");
-                Err(Error::new(ErrorKind::InvalidData, "Could not open table"))
-            }
+            };
         }
+
+        table_query_result.ok_or(Error::new(
+            ErrorKind::InvalidInput,
+            "At least one table must be queried",
+        ))
     }
     .boxed();
 
-    let (shard_query_results, table_query_result) =
-        join(broadcast_future, table_query_future).await;
+    let (
+        ShardQueryResults {
+            query_results: shard_query_results,
+            scan_stats: shard_scan_stats,
+            errors: shard_errors,
+        },
+        table_query_result,
+    ) = join(broadcast_future, table_query_future).await;
+    if !shard_errors.is_empty() {
+        let scan_stats = shard_scan_stats
+            + table_query_result
+                .as_ref()
+                .map_or(ScanStats::default(), |(_, _, scan_stats)| *scan_stats);
+        return QueryResponse::error(format!("Error in shard query: {}", shard_errors.join(", ")))
+            .with_scan_stats(scan_stats);
+    }
+
     match table_query_result {
-        Ok(mut query_result) => {
+        Ok((mut query_result, schema, scan_stats)) => {
+            let scan_stats = scan_stats + shard_scan_stats;
             for shard_query_result in shard_query_results {
                 match query_result.merge(shard_query_result) {
                     Ok(merged_result) => query_result = merged_result,
-                    Err(_) => {
+                    Err(error) => {
                         info!("Merging of query results failed");
-                        return Json(QueryResponse::empty());
+                        return QueryResponse::error(format!(
+                            "Error while merging the query results: {}",
+                            error
+                        ))
+                        .with_scan_stats(scan_stats);
                     }
                 }
             }
-            Json(serialize_query_result(query_result))
+            if distinct {
+                query_result.dedup();
+            }
+
+            if let Err(error) = check_result_size(&state.config, &query_result) {
+                info!("{}", error);
+                return QueryResponse::error(error.to_string()).with_scan_stats(scan_stats);
+            }
+
+            match &order_by {
+                Ok(order_by) => query_result.sort(order_by),
+                Err(error) => {
+                    return QueryResponse::error(format!("Invalid order by: {}", error))
+                        .with_scan_stats(scan_stats)
+                }
+            }
+
+            let query_response =
+                serialize_query_result(query_result, &schema, row_timestamps).with_aliases(aliases);
+            match include_timestamps {
+                true => query_response.with_timestamp_columns(),
+                false => query_response,
+            }
+            .with_scan_stats(scan_stats)
         }
         Err(error) => {
             info!("Error while querying table: {}", error);
-            Json(QueryResponse::empty())
+            QueryResponse::error(format!("Error in local query: {}", error))
+                .with_scan_stats(shard_scan_stats)
+        }
+    }
+}
+
+/// Checks that the result is within the limits of the config, before it's serialized into a
+/// response which could take more memory than the instance has.
+fn check_result_size(config: &Config, query_result: &QueryResult) -> io::Result<()> {
+    let Some(result_limit) = &config.result_limit else {
+        return Ok(());
+    };
+
+    let rows = query_result.len() as u64;
+    if let Some(max_rows) = result_limit.max_rows.filter(|max_rows| rows > *max_rows) {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "The result is too large, with {} rows over the limit of {}, add filters to the query",
+                rows, max_rows
+            ),
+        ));
+    }
+
+    if let Some(max_bytes) = result_limit.max_bytes {
+        let size = query_result.estimated_size() as u64;
+        if size > max_bytes {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "The result is too large, with about {} bytes over the limit of {}, add filters",
+                    size, max_bytes
+                ),
+            ));
         }
     }
+
+    Ok(())
 }
 
-fn serialize_query_result(query_result: QueryResult) -> QueryResponse {
+fn serialize_query_result(
+    query_result: QueryResult,
+    schema: &QuerySchema,
+    row_timestamps: bool,
+) -> QueryResponse {
+    if query_result.is_empty() {
+        return serialize_schema(schema);
+    }
+
     match query_result {
-        QueryResult::Rows(rows) => serialize_rows(rows),
-        QueryResult::AggregatedRows(aggregated_rows) => serialize_aggregated_rows(aggregated_rows),
+        QueryResult::Rows(rows) => serialize_rows(rows, row_timestamps),
+        QueryResult::AggregatedRows(aggregated_rows) => {
+            serialize_aggregated_rows(aggregated_rows, schema)
+        }
+    }
+}
+
+/// Serializes a result without rows, keeping its columns so that they can still be shown.
+fn serialize_schema(schema: &QuerySchema) -> QueryResponse {
+    let columns = schema.columns.iter().map(|c| c.clone().into()).collect();
+    if !schema.is_aggregated() {
+        return QueryResponse::WithData {
+            columns,
+            data: vec![],
+            row_ids: vec![],
+            timestamps: vec![],
+            scan_stats: ScanStats::default(),
+        };
+    }
+
+    QueryResponse::WithAggregatedData {
+        columns,
+        aggregate_columns: serialize_aggregate_columns(schema),
+        data: vec![],
+        aggregates: vec![],
+        scan_stats: ScanStats::default(),
     }
 }
 
-fn serialize_rows(rows: Vec<Row<ColumnValue>>) -> QueryResponse {
+fn serialize_rows(rows: Vec<Row<ColumnValue>>, row_timestamps: bool) -> QueryResponse {
     let columns = rows[0].columns().into_iter().map(|c| c.into()).collect();
 
+    let row_ids = rows.iter().map(|r| r.index_id()).collect();
+    let timestamps = match row_timestamps {
+        true => rows.iter().map(|r| r.timestamp()).collect(),
+        false => vec![],
+    };
+
     QueryResponse::WithData {
         columns,
         data: serialize_rows_data(rows),
+        row_ids,
+        timestamps,
+        scan_stats: ScanStats::default(),
     }
 }
 
-fn serialize_aggregated_rows(aggregated_rows: Vec<AggregatedRow<ColumnValue>>) -> QueryResponse {
-    let first_row = &aggregated_rows[0];
-    let columns = first_row.columns().into_iter().map(|c| c.into()).collect();
-    let aggregate_columns = first_row
-        .aggregate_columns()
+/// Serializes the aggregated rows, typing the aggregates like the schema rather than like their
+/// values, which are null when no value was aggregated, e.g. when a filter matches no row.
+fn serialize_aggregated_rows(
+    aggregated_rows: Vec<AggregatedRow<ColumnValue>>,
+    schema: &QuerySchema,
+) -> QueryResponse {
+    let columns = aggregated_rows[0]
+        .columns()
         .into_iter()
-        .map(|(a, c)| {
-            // We add the type of the column which was used to build the aggregate.
-            let source_ty = Some(a.1.ty.into());
-            Column {
-                name: a.into(),
-                ty: c.into(),
-                source_ty,
-            }
-        })
+        .map(|c| c.into())
         .collect();
 
     let (data, aggregates) = serialize_aggregated_rows_data(aggregated_rows);
     QueryResponse::WithAggregatedData {
         columns,
-        aggregate_columns,
+        aggregate_columns: serialize_aggregate_columns(schema),
         data,
         aggregates,
+        scan_stats: ScanStats::default(),
     }
 }
 
+/// Serializes the aggregates of the schema, with the type of the column used to build each of them.
+fn serialize_aggregate_columns(schema: &QuerySchema) -> Vec<Column> {
+    schema
+        .aggregate_columns
+        .iter()
+        .map(|(a, ty)| Column {
+            name: a.clone().into(),
+            ty: (*ty).into(),
+            source_ty: Some(a.1.ty.into()),
+            encoding: None,
+            bloom_filter: false,
+            unique: false,
+        })
+        .collect()
+}
+
 fn serialize_rows_data(rows: Vec<Row<ColumnValue>>) -> Vec<Vec<serde_json::Value>> {
     let mut serialized_data = Vec::with_capacity(rows.len());
     for row in rows {
@@ -576,7 +4772,7 @@ fn serialize_aggregated_rows_data(
         let mut serialized_aggregate_values = Vec::with_capacity(aggregate_values.len());
         for (aggregate_value, aggregate_components) in aggregate_values {
             let serialized_aggregate = AggregateData {
-                value: aggregate_value.into(),
+                value: Some(aggregate_value.into()),
                 components: aggregate_components.into_iter().map(|a| a.into()).collect(),
             };
             serialized_aggregate_values.push(serialized_aggregate);
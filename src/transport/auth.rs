@@ -0,0 +1,103 @@
+use std::io::{Error, ErrorKind};
+use std::net::SocketAddr;
+
+use axum::extract::{ConnectInfo, Request, State};
+use axum::http::header::AUTHORIZATION;
+use axum::http::StatusCode;
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use tokio::io;
+
+use crate::system::users::{Role, User};
+use crate::transport::api::DatabaseState;
+
+/// The caller of a request.
+#[derive(Debug, Clone)]
+pub enum Principal {
+    /// The caller when authentication is disabled, identified by its address.
+    Anonymous(SocketAddr),
+    User(User),
+}
+
+impl Principal {
+    pub fn name(&self) -> String {
+        match self {
+            Principal::Anonymous(address) => address.to_string(),
+            Principal::User(user) => user.name.clone(),
+        }
+    }
+
+    /// Returns the name of the session of the caller, which is shared by all the requests of a user
+    /// or, if authentication is disabled, of an ip address.
+    pub fn session_name(&self) -> String {
+        match self {
+            Principal::Anonymous(address) => address.ip().to_string(),
+            Principal::User(user) => user.name.clone(),
+        }
+    }
+
+    /// Returns the user of the caller, if authenticated.
+    pub fn user(&self) -> Option<&User> {
+        match self {
+            Principal::Anonymous(_) => None,
+            Principal::User(user) => Some(user),
+        }
+    }
+
+    /// Checks that the caller has at least `role` and can access `table_name`, if supplied.
+    pub fn authorize(&self, role: Role, table_name: Option<&str>) -> io::Result<()> {
+        let Principal::User(user) = self else {
+            return Ok(());
+        };
+
+        if user.role < role {
+            return Err(Error::new(
+                ErrorKind::PermissionDenied,
+                format!("The user '{}' is not allowed to do this", user.name),
+            ));
+        }
+
+        if let Some(table_name) = table_name.filter(|t| !user.can_access(t)) {
+            return Err(Error::new(
+                ErrorKind::PermissionDenied,
+                format!(
+                    "The user '{}' is not allowed to access table {}",
+                    user.name, table_name
+                ),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// Middleware which authenticates the caller via the bearer key of the request, if authentication
+/// is enabled, and makes the [`Principal`] available to the handlers.
+pub async fn authenticate(
+    ConnectInfo(address): ConnectInfo<SocketAddr>,
+    State(state): State<DatabaseState>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let principal = if state.users.is_enabled() {
+        let key = request
+            .headers()
+            .get(AUTHORIZATION)
+            .and_then(|h| h.to_str().ok())
+            .and_then(|h| h.strip_prefix("Bearer "));
+        let Some(key) = key else {
+            return (StatusCode::UNAUTHORIZED, Json("Missing api key")).into_response();
+        };
+
+        match state.users.authenticate(key).await {
+            Some(user) => Principal::User(user),
+            None => return (StatusCode::UNAUTHORIZED, Json("Invalid api key")).into_response(),
+        }
+    } else {
+        Principal::Anonymous(address)
+    };
+
+    request.extensions_mut().insert(principal);
+    next.run(request).await
+}
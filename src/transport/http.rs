@@ -1,26 +1,77 @@
+use crate::system::telemetry::{current_span, Span, SpanKind, TRACEPARENT_HEADER};
+use crate::transport::api::IDEMPOTENCY_KEY_HEADER;
+use crate::transport::origin::ORIGIN_HEADER;
+use crate::transport::role::MASTER_TOKEN_HEADER;
 use crate::transport::shard::Shard;
 use crate::transport::shard_op::ShardOp;
 use serde::{Deserialize, Serialize};
 use std::io;
 use std::io::{Error, ErrorKind};
 
-pub async fn post<I: Serialize, O: for<'a> Deserialize<'a>>(
+pub async fn send<I: Serialize, O: for<'a> Deserialize<'a>>(
     shard: &Shard,
     shard_op: &impl ShardOp<I, O>,
+) -> io::Result<O> {
+    // The request to the shard is traced as a child of the request being handled, if traced.
+    let Some(parent) = current_span() else {
+        return send_request(shard, shard_op, None).await;
+    };
+
+    let span = Span::start(
+        format!("{} {}", shard_op.method(), shard_op.url(shard)),
+        SpanKind::Client,
+        Some(parent),
+    );
+    span.set_attribute("server.address", shard.ip_port.clone());
+    let result = send_request(shard, shard_op, Some(&span)).await;
+    span.finish(result.as_ref().err().map(|e| e.to_string()));
+
+    result
+}
+
+async fn send_request<I: Serialize, O: for<'a> Deserialize<'a>>(
+    shard: &Shard,
+    shard_op: &impl ShardOp<I, O>,
+    span: Option<&Span>,
 ) -> io::Result<O> {
     let url = shard_op.url(shard);
-    let response = shard
+    let mut request = shard
         .client
-        .post(url)
-        .json(shard_op.input())
-        .send()
-        .await
-        .map_err(|e| {
-            Error::new(
-                ErrorKind::Other,
-                format!("Error while sending the request: {}", e),
-            )
-        })?;
+        .request(shard_op.method(), url)
+        .header(ORIGIN_HEADER, &shard.origin)
+        .json(shard_op.input());
+    // Requests between instances are authenticated with the admin key, which is shared by all
+    // instances of the cluster.
+    if let Some(admin_key) = &shard.admin_key {
+        request = request.bearer_auth(admin_key);
+    }
+    if let Some(master_token) = &shard.master_token {
+        request = request.header(MASTER_TOKEN_HEADER, master_token);
+    }
+    if let Some(idempotency_key) = shard_op.idempotency_key() {
+        request = request.header(IDEMPOTENCY_KEY_HEADER, idempotency_key);
+    }
+    if let Some(span) = span {
+        request = request.header(TRACEPARENT_HEADER, span.context().traceparent());
+    }
+
+    let response = request.send().await.map_err(|e| {
+        Error::new(
+            ErrorKind::Other,
+            format!("Error while sending the request: {}", e),
+        )
+    })?;
+
+    // A shard which fails before reaching the handler, e.g. because of the authentication, replies
+    // with a plain error status instead of a response envelope.
+    let status = response.status();
+    if !status.is_success() {
+        let body = response.text().await.unwrap_or_default();
+        return Err(Error::other(format!(
+            "The shard replied with status {}: {}",
+            status, body
+        )));
+    }
 
     response.json().await.map_err(|e| {
         Error::new(
@@ -0,0 +1,146 @@
+use std::convert::Infallible;
+
+use axum::body::{Body, BodyDataStream, Bytes};
+use axum::extract::State;
+use axum::http::header::CONTENT_TYPE;
+use axum::response::{IntoResponse, Response};
+use axum::Extension;
+use futures::channel::mpsc::{channel, Sender};
+use futures::{SinkExt, StreamExt};
+use log::info;
+use serde::Serialize;
+
+use crate::transport::api::{
+    insert_batch, DatabaseState, InsertRequest, InsertResponse, OpResponse,
+};
+use crate::transport::auth::Principal;
+use crate::transport::origin::Origin;
+
+/// The maximum size of a batch of the stream, so that a client which never ends a batch can't
+/// exhaust the memory.
+const MAX_BATCH_BYTES: usize = 16 * 1024 * 1024;
+
+/// The number of acks buffered while the client doesn't read them, after which the batches aren't
+/// read either.
+const ACKS_BUFFER: usize = 16;
+
+/// The ack of a batch of the stream.
+#[derive(Debug, Serialize)]
+struct IngestAck {
+    /// The position of the batch in the stream, starting from 0.
+    batch: u64,
+    #[serde(flatten)]
+    response: InsertResponse,
+    /// The number of batches from the start of the stream which were written, from which a client
+    /// can resume the stream if it broke.
+    watermark: u64,
+}
+
+/// Ingests a stream of batches, each being an insert request on its own line, replying with a
+/// stream of acks, one per batch and in the same order, while the batches are still being sent.
+///
+/// The batches are written one at a time, and the stream is closed after the first batch which
+/// fails, so that the client can resend all the batches after the watermark.
+pub async fn ingest(
+    Extension(principal): Extension<Principal>,
+    Extension(origin): Extension<Origin>,
+    State(state): State<DatabaseState>,
+    body: Body,
+) -> Response {
+    let state = state.for_origin(&origin);
+
+    let (acks, acks_stream) = channel(ACKS_BUFFER);
+    tokio::spawn(ingest_batches(
+        state,
+        principal,
+        origin,
+        body.into_data_stream(),
+        acks,
+    ));
+
+    (
+        [(CONTENT_TYPE, "application/x-ndjson")],
+        Body::from_stream(acks_stream),
+    )
+        .into_response()
+}
+
+async fn ingest_batches(
+    state: DatabaseState,
+    principal: Principal,
+    origin: Origin,
+    mut stream: BodyDataStream,
+    mut acks: Sender<Result<Bytes, Infallible>>,
+) {
+    let mut buffer = vec![];
+    let mut batch = 0;
+    let mut watermark = 0;
+    loop {
+        let response = match next_batch(&mut stream, &mut buffer).await {
+            Ok(Some(line)) => match serde_json::from_slice::<InsertRequest>(&line) {
+                Ok(request) => insert_batch(&state, &principal, &origin, request).await,
+                Err(e) => InsertResponse::new(
+                    OpResponse::Error(format!("The batch {} is malformed: {}", batch, e)),
+                    vec![],
+                ),
+            },
+            Ok(None) => break,
+            Err(error) => InsertResponse::new(OpResponse::Error(error), vec![]),
+        };
+
+        let failed = response.is_error();
+        if !failed {
+            watermark = batch + 1;
+        }
+
+        let ack = IngestAck {
+            batch,
+            response,
+            watermark,
+        };
+        let mut line = serde_json::to_vec(&ack).unwrap_or_default();
+        line.push(b'\n');
+        if acks.send(Ok(Bytes::from(line))).await.is_err() {
+            info!(
+                "The client of the stream disconnected after {} batches",
+                watermark
+            );
+            return;
+        }
+
+        if failed {
+            break;
+        }
+        batch += 1;
+    }
+
+    info!("Ingested a stream of {} batches", watermark);
+}
+
+/// Returns the next non-empty line of the stream, or `None` once the stream ended.
+async fn next_batch(
+    stream: &mut BodyDataStream,
+    buffer: &mut Vec<u8>,
+) -> Result<Option<Vec<u8>>, String> {
+    loop {
+        if let Some(end) = buffer.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = buffer.drain(..=end).collect();
+            if line.trim_ascii().is_empty() {
+                continue;
+            }
+            return Ok(Some(line));
+        }
+
+        if buffer.len() > MAX_BATCH_BYTES {
+            return Err(format!("A batch is larger than {} bytes", MAX_BATCH_BYTES));
+        }
+
+        match stream.next().await {
+            Some(Ok(chunk)) => buffer.extend_from_slice(&chunk),
+            Some(Err(e)) => return Err(format!("Error while reading the stream: {}", e)),
+            // The last batch might not end with a new line.
+            None if buffer.trim_ascii().is_empty() => return Ok(None),
+            None => return Ok(Some(std::mem::take(buffer))),
+        }
+    }
+}
@@ -1,4 +1,12 @@
 pub mod api;
+pub mod auth;
 pub mod http;
+pub mod ingest;
+pub mod origin;
+pub mod pool;
+pub mod role;
+pub mod session;
 pub mod shard;
 pub mod shard_op;
+pub mod trace;
+pub mod ui;
@@ -0,0 +1,51 @@
+use axum::extract::{Request, State};
+use axum::http::StatusCode;
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use log::info;
+
+use crate::transport::api::DatabaseState;
+
+/// The header with the address of the instance which forwarded a request to a shard.
+pub const ORIGIN_HEADER: &str = "x-distribuito-origin";
+
+/// The sender of a request.
+#[derive(Debug, Clone)]
+pub enum Origin {
+    Client,
+    /// Another instance of the cluster, identified by its address.
+    Shard(String),
+}
+
+/// Middleware which detects the requests forwarded by other instances and makes the [`Origin`]
+/// available to the handlers, so that they are never forwarded again.
+///
+/// Requests forwarded by the instance itself are rejected, since they would loop forever.
+pub async fn detect_origin(
+    State(state): State<DatabaseState>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let origin = request
+        .headers()
+        .get(ORIGIN_HEADER)
+        .and_then(|h| h.to_str().ok())
+        .map(|h| h.to_string());
+
+    let origin = match origin {
+        Some(origin) if origin == state.config.database_ip_port => {
+            info!("Rejecting a request forwarded by the instance to itself");
+            return (
+                StatusCode::LOOP_DETECTED,
+                Json("The request was forwarded to the instance which sent it"),
+            )
+                .into_response();
+        }
+        Some(origin) => Origin::Shard(origin),
+        None => Origin::Client,
+    };
+
+    request.extensions_mut().insert(origin);
+    next.run(request).await
+}
@@ -0,0 +1,87 @@
+use axum::extract::{Request, State};
+use axum::http::StatusCode;
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use log::info;
+use tokio::io;
+use tokio::runtime::{Builder, Handle, Runtime};
+
+use crate::config::Pools;
+use crate::transport::api::DatabaseState;
+
+/// The runtimes on which the queries and the writes run, if the instance has separate pools.
+#[derive(Debug, Default)]
+pub struct WorkerPools {
+    read: Option<Handle>,
+    write: Option<Handle>,
+}
+
+impl WorkerPools {
+    pub fn new(config: Option<&Pools>) -> io::Result<Self> {
+        let Some(config) = config else {
+            return Ok(Self::default());
+        };
+
+        info!(
+            "Running the queries on {} threads and the writes on {} threads",
+            config.read_threads, config.write_threads
+        );
+
+        Ok(Self {
+            read: Some(build_runtime("distribuito-read", config.read_threads)?),
+            write: Some(build_runtime("distribuito-write", config.write_threads)?),
+        })
+    }
+}
+
+/// Builds a runtime which lives as long as the instance, returning its handle.
+fn build_runtime(name: &str, threads: usize) -> io::Result<Handle> {
+    let runtime = Builder::new_multi_thread()
+        .worker_threads(threads)
+        .thread_name(name)
+        .enable_all()
+        .build()?;
+    // The runtime is leaked, since dropping it from the main runtime when the instance stops
+    // would panic.
+    let runtime: &'static Runtime = Box::leak(Box::new(runtime));
+
+    Ok(runtime.handle().clone())
+}
+
+/// Middleware which runs the queries on the read pool, if any.
+pub async fn run_in_read_pool(
+    State(state): State<DatabaseState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    run_in_pool(state.pools.read.as_ref(), request, next).await
+}
+
+/// Middleware which runs the writes on the write pool, if any.
+pub async fn run_in_write_pool(
+    State(state): State<DatabaseState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    run_in_pool(state.pools.write.as_ref(), request, next).await
+}
+
+async fn run_in_pool(pool: Option<&Handle>, request: Request, next: Next) -> Response {
+    let Some(pool) = pool else {
+        return next.run(request).await;
+    };
+
+    let path = request.uri().path().to_string();
+    match pool.spawn(next.run(request)).await {
+        Ok(response) => response,
+        Err(error) => {
+            info!("Error while handling {} in its pool: {}", path, error);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(format!("Error while handling the request: {}", error)),
+            )
+                .into_response()
+        }
+    }
+}
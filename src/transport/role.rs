@@ -0,0 +1,59 @@
+use axum::extract::{Request, State};
+use axum::http::header::LOCATION;
+use axum::http::StatusCode;
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use log::info;
+
+use crate::config::InstanceRole;
+use crate::transport::api::DatabaseState;
+
+/// The header with the token proving that a request comes from the master.
+pub const MASTER_TOKEN_HEADER: &str = "x-distribuito-master-token";
+
+/// Middleware which rejects the writes to a slave not carrying the master token, if the slave
+/// writes are restricted, since they would make the data of the slave diverge from the cluster.
+///
+/// External clients are redirected to the master if its address is known.
+pub async fn enforce_role(
+    State(state): State<DatabaseState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let InstanceRole::Slave = state.config.instance_role else {
+        return next.run(request).await;
+    };
+    let Some(slave_writes) = &state.config.slave_writes else {
+        return next.run(request).await;
+    };
+
+    let master_token = request
+        .headers()
+        .get(MASTER_TOKEN_HEADER)
+        .and_then(|h| h.to_str().ok());
+    if master_token == Some(slave_writes.master_token.as_str()) {
+        return next.run(request).await;
+    }
+
+    info!(
+        "Rejecting the write to {} which doesn't come from the master",
+        request.uri().path()
+    );
+    match &slave_writes.master_ip_port {
+        Some(master_ip_port) => (
+            StatusCode::TEMPORARY_REDIRECT,
+            [(
+                LOCATION,
+                format!("http://{}{}", master_ip_port, request.uri().path()),
+            )],
+            Json("Writes must be sent to the master"),
+        )
+            .into_response(),
+        None => (
+            StatusCode::FORBIDDEN,
+            Json("Writes must be sent to the master"),
+        )
+            .into_response(),
+    }
+}
@@ -0,0 +1,88 @@
+use axum::extract::{Request, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::{Extension, Json};
+use log::info;
+
+use crate::system::session::{NullHandling, OutputFormat, SessionSettings};
+use crate::transport::api::DatabaseState;
+use crate::transport::auth::Principal;
+use crate::transport::origin::Origin;
+
+/// The headers which override the session settings for a single request.
+pub const TIMEOUT_MS_HEADER: &str = "x-distribuito-timeout-ms";
+pub const OUTPUT_FORMAT_HEADER: &str = "x-distribuito-output-format";
+pub const NULL_HANDLING_HEADER: &str = "x-distribuito-null-handling";
+pub const DATABASE_HEADER: &str = "x-distribuito-database";
+
+/// Middleware which resolves the session settings of the caller, overridden by the ones in the
+/// headers of the request, and makes them available to the handlers.
+///
+/// It must run after [`authenticate`](crate::transport::auth::authenticate), since the sessions
+/// belong to the principals. The requests forwarded by other instances use the default settings,
+/// since the instance which forwarded them applies the settings to the merged results.
+pub async fn resolve_session(
+    State(state): State<DatabaseState>,
+    Extension(principal): Extension<Principal>,
+    Extension(origin): Extension<Origin>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    if let Origin::Shard(_) = origin {
+        request.extensions_mut().insert(SessionSettings::default());
+        return next.run(request).await;
+    }
+
+    let overrides = match parse_headers(request.headers()) {
+        Ok(overrides) => overrides,
+        Err(error) => {
+            info!("{}", error);
+            return (StatusCode::BAD_REQUEST, Json(error)).into_response();
+        }
+    };
+
+    let settings = state
+        .sessions
+        .get(&principal.session_name())
+        .await
+        .merge(overrides);
+
+    request.extensions_mut().insert(settings);
+    next.run(request).await
+}
+
+fn parse_headers(headers: &HeaderMap) -> Result<SessionSettings, String> {
+    let header = |name: &str| -> Result<Option<&str>, String> {
+        headers
+            .get(name)
+            .map(|h| {
+                h.to_str()
+                    .map_err(|_| format!("The header {} is not valid text", name))
+            })
+            .transpose()
+    };
+
+    let timeout_ms = header(TIMEOUT_MS_HEADER)?
+        .map(|h| {
+            h.parse::<u64>()
+                .map_err(|_| format!("The timeout {} is not a number of milliseconds", h))
+        })
+        .transpose()?;
+    let output_format = header(OUTPUT_FORMAT_HEADER)?
+        .map(|h| OutputFormat::from_name(h).ok_or(format!("The output format {} is unknown", h)))
+        .transpose()?;
+    let null_handling = header(NULL_HANDLING_HEADER)?
+        .map(|h| {
+            NullHandling::from_name(h).ok_or(format!("The null handling mode {} is unknown", h))
+        })
+        .transpose()?;
+    let database = header(DATABASE_HEADER)?.map(|h| h.to_string());
+
+    Ok(SessionSettings {
+        timeout_ms,
+        output_format,
+        null_handling,
+        database,
+    })
+}
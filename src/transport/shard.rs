@@ -1,24 +1,48 @@
 use crate::config::Config;
-use crate::transport::http::post;
+use crate::transport::http::send;
 use crate::transport::shard_op::ShardOp;
-use futures::future::join_all;
+use futures::future::{join, join_all, select, Either};
 use log::info;
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
+use std::io::Error;
+use std::pin::pin;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use tokio::io;
+use tokio::time::sleep;
 
 #[derive(Debug)]
 pub struct Shard {
     pub ip_port: String,
+    /// The address on which the shard serves its admin endpoints.
+    pub admin_ip_port: String,
     pub client: Client,
+    pub admin_key: Option<String>,
+    /// The address of this instance, sent along the requests to the shard.
+    pub origin: String,
+    /// The token proving to the shard that the requests come from its master.
+    pub master_token: Option<String>,
+    /// The instances holding a copy of the data of the shard.
+    pub replicas: Vec<Shard>,
+    /// The time after which the read only ops are also sent to a replica, if hedging is enabled.
+    hedge_after: Option<Duration>,
+    next_replica: AtomicUsize,
 }
 
 impl Shard {
-    fn new(ip_port: String) -> Self {
+    fn new(ip_port: String, config: &Config) -> Self {
         Self {
+            admin_ip_port: ip_port.clone(),
             ip_port,
             client: Client::new(),
+            admin_key: config.admin_key.clone(),
+            origin: config.database_ip_port.clone(),
+            master_token: config.slave_writes.as_ref().map(|s| s.master_token.clone()),
+            replicas: vec![],
+            hedge_after: None,
+            next_replica: AtomicUsize::new(0),
         }
     }
 
@@ -26,7 +50,103 @@ impl Shard {
         &self,
         shard_op: &impl ShardOp<I, O>,
     ) -> io::Result<O> {
-        post(self, shard_op).await
+        if shard_op.is_read_only() {
+            return match (self.hedge_after, self.next_replica()) {
+                (Some(hedge_after), Some(replica)) => {
+                    self.hedged_call(shard_op, replica, hedge_after).await
+                }
+                _ => self.call_once(shard_op).await,
+            };
+        }
+
+        // The ops modifying the data are applied to the replicas too, so that they can serve the
+        // reads of the shard.
+        let replica_futures = self.replicas.iter().map(|r| r.call_once(shard_op));
+        let (output, replica_results) =
+            join(self.call_once(shard_op), join_all(replica_futures)).await;
+        for (replica, result) in self.replicas.iter().zip(replica_results) {
+            result
+                .map_err(|e| Error::new(e.kind(), format!("replica {}: {}", replica.ip_port, e)))?;
+        }
+
+        output
+    }
+
+    /// Sends the op to the shard and, if it doesn't reply within `hedge_after`, to `replica` too,
+    /// returning the first successful output.
+    async fn hedged_call<I: Serialize, O: for<'a> Deserialize<'a>>(
+        &self,
+        shard_op: &impl ShardOp<I, O>,
+        replica: &Shard,
+        hedge_after: Duration,
+    ) -> io::Result<O> {
+        let primary = pin!(self.call_once(shard_op));
+        let primary = match select(primary, pin!(sleep(hedge_after))).await {
+            Either::Left((Ok(output), _)) => return Ok(output),
+            Either::Left((Err(error), _)) => {
+                info!(
+                    "Shard op to '{}' failed, retrying on the replica '{}': {}",
+                    self.ip_port, replica.ip_port, error
+                );
+                return replica.call_once(shard_op).await;
+            }
+            Either::Right((_, primary)) => primary,
+        };
+
+        info!(
+            "Shard op to '{}' is slow, hedging it on the replica '{}'",
+            self.ip_port, replica.ip_port
+        );
+        let hedged = pin!(replica.call_once(shard_op));
+        match select(primary, hedged).await {
+            Either::Left((Ok(output), _)) | Either::Right((Ok(output), _)) => Ok(output),
+            Either::Left((Err(_), hedged)) => hedged.await,
+            Either::Right((Err(_), primary)) => primary.await,
+        }
+    }
+
+    async fn call_once<I: Serialize, O: for<'a> Deserialize<'a>>(
+        &self,
+        shard_op: &impl ShardOp<I, O>,
+    ) -> io::Result<O> {
+        let output = send(self, shard_op).await?;
+        shard_op.check_output(output)
+    }
+
+    /// Returns the replicas in turn, so that the hedged ops are spread among them.
+    fn next_replica(&self) -> Option<&Shard> {
+        if self.replicas.is_empty() {
+            return None;
+        }
+
+        let index = self.next_replica.fetch_add(1, Ordering::Relaxed);
+        self.replicas.get(index % self.replicas.len())
+    }
+}
+
+/// The results of a shard op sent to multiple shards, one for each shard.
+#[derive(Debug)]
+pub struct ShardResults<'a, O> {
+    pub results: Vec<(&'a Shard, io::Result<O>)>,
+}
+
+impl<O> ShardResults<'_, O> {
+    /// Returns the outputs of all shards, or an error listing the shards which failed.
+    pub fn into_outputs(self) -> io::Result<Vec<O>> {
+        let mut outputs = Vec::with_capacity(self.results.len());
+        let mut errors = vec![];
+        for (shard, result) in self.results {
+            match result {
+                Ok(output) => outputs.push(output),
+                Err(error) => errors.push(format!("shard {}: {}", shard.ip_port, error)),
+            }
+        }
+
+        if !errors.is_empty() {
+            return Err(Error::other(errors.join(", ")));
+        }
+
+        Ok(outputs)
     }
 }
 
@@ -34,18 +154,38 @@ impl Shard {
 pub struct Shards {
     shards: Vec<Shard>,
     next_index: Mutex<u64>,
+    /// The sequence of the next insert sent to a shard, which starts from the current time in
+    /// microseconds so that it keeps increasing across restarts without being persisted.
+    next_insert_sequence: AtomicU64,
 }
 
 impl Shards {
     pub fn new(config: &Config) -> Self {
         let mut shards = Vec::new();
         for instance in config.instances.iter() {
-            shards.push(Shard::new(instance.ip_port.clone()));
+            let mut shard = Shard::new(instance.ip_port.clone(), config);
+            if let Some(admin_ip_port) = &instance.admin_ip_port {
+                shard.admin_ip_port = admin_ip_port.clone();
+            }
+            shard.replicas = instance
+                .replicas
+                .iter()
+                .map(|r| Shard::new(r.clone(), config))
+                .collect();
+            shard.hedge_after = config
+                .hedging
+                .as_ref()
+                .map(|h| Duration::from_millis(h.after_ms));
+            shards.push(shard);
         }
 
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
         Self {
             shards,
             next_index: Mutex::new(0),
+            next_insert_sequence: AtomicU64::new(now.as_micros() as u64),
         }
     }
 
@@ -56,10 +196,20 @@ impl Shards {
     pub async fn broadcast<I: Serialize, O: for<'a> Deserialize<'a>>(
         &self,
         shard_op: impl ShardOp<I, O>,
-    ) -> io::Result<Vec<O>> {
+    ) -> ShardResults<'_, O> {
+        self.broadcast_to(shard_op, |_| true).await
+    }
+
+    /// Sends the shard op to the shards for which `filter` returns true.
+    pub async fn broadcast_to<I: Serialize, O: for<'a> Deserialize<'a>>(
+        &self,
+        shard_op: impl ShardOp<I, O>,
+        filter: impl Fn(&Shard) -> bool,
+    ) -> ShardResults<'_, O> {
+        let shards: Vec<_> = self.shards.iter().filter(|s| filter(s)).collect();
+
         // Create a collection of futures representing each shard operation.
-        let futures: Vec<_> = self
-            .shards
+        let futures: Vec<_> = shards
             .iter()
             .map(|shard| {
                 info!("Broadcasting shard op to '{}'", shard_op.url(shard));
@@ -67,31 +217,42 @@ impl Shards {
             }) // Generate the future for each shard call.
             .collect();
 
-        // Wait for all futures to complete.
+        // Wait for all futures to complete, keeping the result of each shard.
         let results = join_all(futures).await;
 
-        // Collect the results, returning an error if any call failed.
-        results.into_iter().collect::<Result<Vec<_>, _>>()
+        ShardResults {
+            results: shards.into_iter().zip(results).collect(),
+        }
     }
 
-    pub async fn rr_unicast<I: Serialize, O: for<'a> Deserialize<'a>>(
+    /// Sends the shard op to the shard at `index`.
+    pub async fn unicast<I: Serialize, O: for<'a> Deserialize<'a>>(
         &self,
+        index: usize,
         shard_op: impl ShardOp<I, O>,
     ) -> io::Result<O> {
-        let shard = self.next_shard();
+        let shard = &self.shards[index];
         info!("Sending shard op to '{}'", shard_op.url(shard));
 
-        let result = shard.call(&shard_op).await?;
+        shard.call(&shard_op).await
+    }
+
+    pub fn shard(&self, index: usize) -> &Shard {
+        &self.shards[index]
+    }
 
-        Ok(result)
+    /// Returns a new sequence identifying an insert sent to a shard.
+    pub fn next_insert_sequence(&self) -> u64 {
+        self.next_insert_sequence.fetch_add(1, Ordering::Relaxed)
     }
 
-    fn next_shard(&self) -> &Shard {
+    /// Returns the index of the next shard in round robin order.
+    pub fn next_shard_index(&self) -> usize {
         let mut next_index = self.next_index.lock().unwrap();
-        let shard = &self.shards[*next_index as usize];
+        let index = *next_index as usize;
 
         *next_index = (*next_index + 1u64) % self.shards.len() as u64;
 
-        shard
+        index
     }
 }
@@ -0,0 +1,27 @@
+use crate::transport::api::{CheckUniqueRequest, CheckUniqueResponse};
+use crate::transport::shard::Shard;
+use crate::transport::shard_op::{build_url, ShardOp};
+
+pub struct CheckUnique<'a> {
+    request: &'a CheckUniqueRequest,
+}
+
+impl<'a> CheckUnique<'a> {
+    pub fn new(request: &'a CheckUniqueRequest) -> Self {
+        Self { request }
+    }
+}
+
+impl<'a> ShardOp<CheckUniqueRequest, CheckUniqueResponse> for CheckUnique<'a> {
+    fn input(&self) -> &CheckUniqueRequest {
+        self.request
+    }
+
+    fn url(&self, shard: &Shard) -> String {
+        build_url(&shard.ip_port, "check_unique")
+    }
+
+    fn is_read_only(&self) -> bool {
+        true
+    }
+}
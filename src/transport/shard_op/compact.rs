@@ -0,0 +1,28 @@
+use crate::transport::api::{CompactRequest, OpResponse};
+use crate::transport::shard::Shard;
+use crate::transport::shard_op::{build_url, ShardOp};
+use std::io;
+
+pub struct Compact<'a> {
+    request: &'a CompactRequest,
+}
+
+impl<'a> Compact<'a> {
+    pub fn new(request: &'a CompactRequest) -> Self {
+        Self { request }
+    }
+}
+
+impl<'a> ShardOp<CompactRequest, OpResponse> for Compact<'a> {
+    fn input(&self) -> &CompactRequest {
+        self.request
+    }
+
+    fn url(&self, shard: &Shard) -> String {
+        build_url(&shard.admin_ip_port, "admin/compact")
+    }
+
+    fn check_output(&self, output: OpResponse) -> io::Result<OpResponse> {
+        output.into_result()
+    }
+}
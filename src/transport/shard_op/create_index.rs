@@ -0,0 +1,36 @@
+use crate::transport::api::{CreateIndexRequest, OpResponse};
+use crate::transport::shard::Shard;
+use crate::transport::shard_op::{build_url, ShardOp};
+use std::io;
+
+pub struct CreateIndex<'a> {
+    request: &'a CreateIndexRequest,
+    idempotency_key: Option<&'a str>,
+}
+
+impl<'a> CreateIndex<'a> {
+    pub fn new(request: &'a CreateIndexRequest, idempotency_key: Option<&'a str>) -> Self {
+        Self {
+            request,
+            idempotency_key,
+        }
+    }
+}
+
+impl<'a> ShardOp<CreateIndexRequest, OpResponse> for CreateIndex<'a> {
+    fn input(&self) -> &CreateIndexRequest {
+        self.request
+    }
+
+    fn url(&self, shard: &Shard) -> String {
+        build_url(&shard.ip_port, "create_index")
+    }
+
+    fn idempotency_key(&self) -> Option<&str> {
+        self.idempotency_key
+    }
+
+    fn check_output(&self, output: OpResponse) -> io::Result<OpResponse> {
+        output.into_result()
+    }
+}
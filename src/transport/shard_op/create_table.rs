@@ -1,18 +1,23 @@
-use crate::transport::api::CreateTableRequest;
+use crate::transport::api::{CreateTableRequest, OpResponse};
 use crate::transport::shard::Shard;
 use crate::transport::shard_op::{build_url, ShardOp};
+use std::io;
 
 pub struct CreateTable<'a> {
     request: &'a CreateTableRequest,
+    idempotency_key: Option<&'a str>,
 }
 
 impl<'a> CreateTable<'a> {
-    pub fn new(request: &'a CreateTableRequest) -> Self {
-        Self { request }
+    pub fn new(request: &'a CreateTableRequest, idempotency_key: Option<&'a str>) -> Self {
+        Self {
+            request,
+            idempotency_key,
+        }
     }
 }
 
-impl<'a> ShardOp<CreateTableRequest, String> for CreateTable<'a> {
+impl<'a> ShardOp<CreateTableRequest, OpResponse> for CreateTable<'a> {
     fn input(&self) -> &CreateTableRequest {
         self.request
     }
@@ -20,4 +25,12 @@ impl<'a> ShardOp<CreateTableRequest, String> for CreateTable<'a> {
     fn url(&self, shard: &Shard) -> String {
         build_url(&shard.ip_port, "create_table")
     }
+
+    fn idempotency_key(&self) -> Option<&str> {
+        self.idempotency_key
+    }
+
+    fn check_output(&self, output: OpResponse) -> io::Result<OpResponse> {
+        output.into_result()
+    }
 }
@@ -0,0 +1,28 @@
+use crate::transport::api::{DeleteRequest, DeleteResponse};
+use crate::transport::shard::Shard;
+use crate::transport::shard_op::{build_url, ShardOp};
+use std::io;
+
+pub struct Delete<'a> {
+    request: &'a DeleteRequest,
+}
+
+impl<'a> Delete<'a> {
+    pub fn new(request: &'a DeleteRequest) -> Self {
+        Self { request }
+    }
+}
+
+impl<'a> ShardOp<DeleteRequest, DeleteResponse> for Delete<'a> {
+    fn input(&self) -> &DeleteRequest {
+        self.request
+    }
+
+    fn url(&self, shard: &Shard) -> String {
+        build_url(&shard.ip_port, "delete")
+    }
+
+    fn check_output(&self, output: DeleteResponse) -> io::Result<DeleteResponse> {
+        output.into_result()
+    }
+}
@@ -0,0 +1,34 @@
+use reqwest::Method;
+
+use crate::transport::api::OpResponse;
+use crate::transport::shard::Shard;
+use crate::transport::shard_op::{build_url, ShardOp};
+use std::io;
+
+pub struct DeleteUser<'a> {
+    name: &'a String,
+}
+
+impl<'a> DeleteUser<'a> {
+    pub fn new(name: &'a String) -> Self {
+        Self { name }
+    }
+}
+
+impl<'a> ShardOp<String, OpResponse> for DeleteUser<'a> {
+    fn input(&self) -> &String {
+        self.name
+    }
+
+    fn url(&self, shard: &Shard) -> String {
+        build_url(&shard.admin_ip_port, &format!("admin/users/{}", self.name))
+    }
+
+    fn method(&self) -> Method {
+        Method::DELETE
+    }
+
+    fn check_output(&self, output: OpResponse) -> io::Result<OpResponse> {
+        output.into_result()
+    }
+}
@@ -0,0 +1,36 @@
+use crate::transport::api::{DropTableRequest, OpResponse};
+use crate::transport::shard::Shard;
+use crate::transport::shard_op::{build_url, ShardOp};
+use std::io;
+
+pub struct DropTable<'a> {
+    request: &'a DropTableRequest,
+    idempotency_key: Option<&'a str>,
+}
+
+impl<'a> DropTable<'a> {
+    pub fn new(request: &'a DropTableRequest, idempotency_key: Option<&'a str>) -> Self {
+        Self {
+            request,
+            idempotency_key,
+        }
+    }
+}
+
+impl<'a> ShardOp<DropTableRequest, OpResponse> for DropTable<'a> {
+    fn input(&self) -> &DropTableRequest {
+        self.request
+    }
+
+    fn url(&self, shard: &Shard) -> String {
+        build_url(&shard.ip_port, "drop_table")
+    }
+
+    fn idempotency_key(&self) -> Option<&str> {
+        self.idempotency_key
+    }
+
+    fn check_output(&self, output: OpResponse) -> io::Result<OpResponse> {
+        output.into_result()
+    }
+}
@@ -0,0 +1,27 @@
+use crate::transport::api::{ExplainResponse, QueryRequest};
+use crate::transport::shard::Shard;
+use crate::transport::shard_op::{build_url, ShardOp};
+
+pub struct Explain<'a> {
+    request: &'a QueryRequest,
+}
+
+impl<'a> Explain<'a> {
+    pub fn new(request: &'a QueryRequest) -> Self {
+        Self { request }
+    }
+}
+
+impl<'a> ShardOp<QueryRequest, ExplainResponse> for Explain<'a> {
+    fn input(&self) -> &QueryRequest {
+        self.request
+    }
+
+    fn url(&self, shard: &Shard) -> String {
+        build_url(&shard.ip_port, "explain")
+    }
+
+    fn is_read_only(&self) -> bool {
+        true
+    }
+}
@@ -1,6 +1,7 @@
-use crate::transport::api::InsertRequest;
+use crate::transport::api::{InsertRequest, InsertResponse};
 use crate::transport::shard::Shard;
 use crate::transport::shard_op::{build_url, ShardOp};
+use std::io;
 
 pub struct Insert<'a> {
     request: &'a InsertRequest,
@@ -12,7 +13,7 @@ impl<'a> Insert<'a> {
     }
 }
 
-impl<'a> ShardOp<InsertRequest, String> for Insert<'a> {
+impl<'a> ShardOp<InsertRequest, InsertResponse> for Insert<'a> {
     fn input(&self) -> &InsertRequest {
         &self.request
     }
@@ -20,4 +21,8 @@ impl<'a> ShardOp<InsertRequest, String> for Insert<'a> {
     fn url(&self, shard: &Shard) -> String {
         build_url(&shard.ip_port, "insert")
     }
+
+    fn check_output(&self, output: InsertResponse) -> io::Result<InsertResponse> {
+        output.into_result()
+    }
 }
@@ -0,0 +1,25 @@
+use reqwest::Method;
+
+use crate::transport::api::TableInfo;
+use crate::transport::shard::Shard;
+use crate::transport::shard_op::{build_url, ShardOp};
+
+pub struct ListTables;
+
+impl ShardOp<(), Vec<TableInfo>> for ListTables {
+    fn input(&self) -> &() {
+        &()
+    }
+
+    fn url(&self, shard: &Shard) -> String {
+        build_url(&shard.ip_port, "tables")
+    }
+
+    fn is_read_only(&self) -> bool {
+        true
+    }
+
+    fn method(&self) -> Method {
+        Method::GET
+    }
+}
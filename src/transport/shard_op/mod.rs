@@ -1,9 +1,27 @@
+pub mod check_unique;
+pub mod compact;
+pub mod create_index;
 pub mod create_table;
+pub mod delete;
+pub mod delete_user;
+pub mod drop_table;
+pub mod explain;
 pub mod insert;
+pub mod list_tables;
 pub mod query;
+pub mod query_batch;
+pub mod read_only;
+pub mod rotate_key;
+pub mod save_query;
+pub mod save_user;
+pub mod status;
+pub mod truncate_table;
+pub mod vacuum;
 
 use crate::transport::shard::Shard;
+use reqwest::Method;
 use serde::{Deserialize, Serialize};
+use std::io;
 
 pub fn build_url(ip_port: &str, path: &str) -> String {
     format!("http://{}/{}", ip_port, path)
@@ -17,4 +35,24 @@ where
     fn input(&self) -> &I;
 
     fn url(&self, shard: &Shard) -> String;
+
+    fn method(&self) -> Method {
+        Method::POST
+    }
+
+    /// Whether the op doesn't modify the data of the shard, so that it can be served by any of its
+    /// replicas instead of being applied to all of them.
+    fn is_read_only(&self) -> bool {
+        false
+    }
+
+    /// The key sent along the op, so that the shard executes it only once even if it's retried.
+    fn idempotency_key(&self) -> Option<&str> {
+        None
+    }
+
+    /// Checks the output returned by a shard, turning the errors it reports into an error.
+    fn check_output(&self, output: O) -> io::Result<O> {
+        Ok(output)
+    }
 }
@@ -1,6 +1,7 @@
 use crate::transport::api::{QueryRequest, QueryResponse};
 use crate::transport::shard::Shard;
 use crate::transport::shard_op::{build_url, ShardOp};
+use std::io;
 
 pub struct Query<'a> {
     request: &'a QueryRequest,
@@ -20,4 +21,12 @@ impl<'a> ShardOp<QueryRequest, QueryResponse> for Query<'a> {
     fn url(&self, shard: &Shard) -> String {
         build_url(&shard.ip_port, "query")
     }
+
+    fn is_read_only(&self) -> bool {
+        true
+    }
+
+    fn check_output(&self, output: QueryResponse) -> io::Result<QueryResponse> {
+        output.into_result()
+    }
 }
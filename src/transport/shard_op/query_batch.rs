@@ -0,0 +1,27 @@
+use crate::transport::api::{QueryBatchRequest, QueryResponse};
+use crate::transport::shard::Shard;
+use crate::transport::shard_op::{build_url, ShardOp};
+
+pub struct QueryBatch<'a> {
+    request: &'a QueryBatchRequest,
+}
+
+impl<'a> QueryBatch<'a> {
+    pub fn new(request: &'a QueryBatchRequest) -> Self {
+        Self { request }
+    }
+}
+
+impl<'a> ShardOp<QueryBatchRequest, Vec<QueryResponse>> for QueryBatch<'a> {
+    fn input(&self) -> &QueryBatchRequest {
+        self.request
+    }
+
+    fn url(&self, shard: &Shard) -> String {
+        build_url(&shard.ip_port, "query_batch")
+    }
+
+    fn is_read_only(&self) -> bool {
+        true
+    }
+}
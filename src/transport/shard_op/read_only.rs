@@ -0,0 +1,28 @@
+use crate::transport::api::{OpResponse, ReadOnlyRequest};
+use crate::transport::shard::Shard;
+use crate::transport::shard_op::{build_url, ShardOp};
+use std::io;
+
+pub struct SetReadOnly<'a> {
+    request: &'a ReadOnlyRequest,
+}
+
+impl<'a> SetReadOnly<'a> {
+    pub fn new(request: &'a ReadOnlyRequest) -> Self {
+        Self { request }
+    }
+}
+
+impl<'a> ShardOp<ReadOnlyRequest, OpResponse> for SetReadOnly<'a> {
+    fn input(&self) -> &ReadOnlyRequest {
+        self.request
+    }
+
+    fn url(&self, shard: &Shard) -> String {
+        build_url(&shard.admin_ip_port, "admin/read_only")
+    }
+
+    fn check_output(&self, output: OpResponse) -> io::Result<OpResponse> {
+        output.into_result()
+    }
+}
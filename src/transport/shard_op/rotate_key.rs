@@ -0,0 +1,28 @@
+use crate::transport::api::{OpResponse, RotateKeyRequest};
+use crate::transport::shard::Shard;
+use crate::transport::shard_op::{build_url, ShardOp};
+use std::io;
+
+pub struct RotateKey<'a> {
+    request: &'a RotateKeyRequest,
+}
+
+impl<'a> RotateKey<'a> {
+    pub fn new(request: &'a RotateKeyRequest) -> Self {
+        Self { request }
+    }
+}
+
+impl<'a> ShardOp<RotateKeyRequest, OpResponse> for RotateKey<'a> {
+    fn input(&self) -> &RotateKeyRequest {
+        self.request
+    }
+
+    fn url(&self, shard: &Shard) -> String {
+        build_url(&shard.admin_ip_port, "rotate_key")
+    }
+
+    fn check_output(&self, output: OpResponse) -> io::Result<OpResponse> {
+        output.into_result()
+    }
+}
@@ -0,0 +1,29 @@
+use crate::system::saved_query::SavedQuery;
+use crate::transport::api::OpResponse;
+use crate::transport::shard::Shard;
+use crate::transport::shard_op::{build_url, ShardOp};
+use std::io;
+
+pub struct SaveQuery<'a> {
+    saved_query: &'a SavedQuery,
+}
+
+impl<'a> SaveQuery<'a> {
+    pub fn new(saved_query: &'a SavedQuery) -> Self {
+        Self { saved_query }
+    }
+}
+
+impl<'a> ShardOp<SavedQuery, OpResponse> for SaveQuery<'a> {
+    fn input(&self) -> &SavedQuery {
+        self.saved_query
+    }
+
+    fn url(&self, shard: &Shard) -> String {
+        build_url(&shard.ip_port, "save_query")
+    }
+
+    fn check_output(&self, output: OpResponse) -> io::Result<OpResponse> {
+        output.into_result()
+    }
+}
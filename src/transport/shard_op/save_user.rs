@@ -0,0 +1,28 @@
+use crate::transport::api::{OpResponse, SaveUserRequest};
+use crate::transport::shard::Shard;
+use crate::transport::shard_op::{build_url, ShardOp};
+use std::io;
+
+pub struct SaveUser<'a> {
+    request: &'a SaveUserRequest,
+}
+
+impl<'a> SaveUser<'a> {
+    pub fn new(request: &'a SaveUserRequest) -> Self {
+        Self { request }
+    }
+}
+
+impl<'a> ShardOp<SaveUserRequest, OpResponse> for SaveUser<'a> {
+    fn input(&self) -> &SaveUserRequest {
+        self.request
+    }
+
+    fn url(&self, shard: &Shard) -> String {
+        build_url(&shard.admin_ip_port, "admin/users")
+    }
+
+    fn check_output(&self, output: OpResponse) -> io::Result<OpResponse> {
+        output.into_result()
+    }
+}
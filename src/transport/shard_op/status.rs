@@ -0,0 +1,25 @@
+use reqwest::Method;
+
+use crate::transport::api::StatusResponse;
+use crate::transport::shard::Shard;
+use crate::transport::shard_op::{build_url, ShardOp};
+
+pub struct Status;
+
+impl ShardOp<(), StatusResponse> for Status {
+    fn input(&self) -> &() {
+        &()
+    }
+
+    fn url(&self, shard: &Shard) -> String {
+        build_url(&shard.ip_port, "status")
+    }
+
+    fn method(&self) -> Method {
+        Method::GET
+    }
+
+    fn is_read_only(&self) -> bool {
+        true
+    }
+}
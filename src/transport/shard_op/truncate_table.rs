@@ -0,0 +1,36 @@
+use crate::transport::api::{OpResponse, TruncateTableRequest};
+use crate::transport::shard::Shard;
+use crate::transport::shard_op::{build_url, ShardOp};
+use std::io;
+
+pub struct TruncateTable<'a> {
+    request: &'a TruncateTableRequest,
+    idempotency_key: Option<&'a str>,
+}
+
+impl<'a> TruncateTable<'a> {
+    pub fn new(request: &'a TruncateTableRequest, idempotency_key: Option<&'a str>) -> Self {
+        Self {
+            request,
+            idempotency_key,
+        }
+    }
+}
+
+impl<'a> ShardOp<TruncateTableRequest, OpResponse> for TruncateTable<'a> {
+    fn input(&self) -> &TruncateTableRequest {
+        self.request
+    }
+
+    fn url(&self, shard: &Shard) -> String {
+        build_url(&shard.ip_port, "truncate_table")
+    }
+
+    fn idempotency_key(&self) -> Option<&str> {
+        self.idempotency_key
+    }
+
+    fn check_output(&self, output: OpResponse) -> io::Result<OpResponse> {
+        output.into_result()
+    }
+}
@@ -0,0 +1,28 @@
+use crate::transport::api::{VacuumRequest, VacuumResponse};
+use crate::transport::shard::Shard;
+use crate::transport::shard_op::{build_url, ShardOp};
+use std::io;
+
+pub struct Vacuum<'a> {
+    request: &'a VacuumRequest,
+}
+
+impl<'a> Vacuum<'a> {
+    pub fn new(request: &'a VacuumRequest) -> Self {
+        Self { request }
+    }
+}
+
+impl<'a> ShardOp<VacuumRequest, VacuumResponse> for Vacuum<'a> {
+    fn input(&self) -> &VacuumRequest {
+        self.request
+    }
+
+    fn url(&self, shard: &Shard) -> String {
+        build_url(&shard.ip_port, "vacuum")
+    }
+
+    fn check_output(&self, output: VacuumResponse) -> io::Result<VacuumResponse> {
+        output.into_result()
+    }
+}
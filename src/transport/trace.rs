@@ -0,0 +1,48 @@
+use std::sync::Arc;
+
+use axum::extract::{MatchedPath, Request, State};
+use axum::middleware::Next;
+use axum::response::Response;
+
+use crate::system::telemetry::{in_span, Span, SpanContext, SpanKind, TRACEPARENT_HEADER};
+use crate::transport::api::DatabaseState;
+
+/// Middleware which traces the handling of the requests, if the traces are exported.
+///
+/// The requests forwarded by the master carry its trace context, so that the spans of the shards
+/// belong to the trace of the request received by the master.
+pub async fn trace_request(
+    State(state): State<DatabaseState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if state.config.otlp.is_none() {
+        return next.run(request).await;
+    }
+
+    let parent = request
+        .headers()
+        .get(TRACEPARENT_HEADER)
+        .and_then(|h| h.to_str().ok())
+        .and_then(SpanContext::from_traceparent);
+    let path = match request.extensions().get::<MatchedPath>() {
+        Some(matched_path) => matched_path.as_str().to_string(),
+        None => request.uri().path().to_string(),
+    };
+    let span = Arc::new(Span::start(
+        format!("{} {}", request.method(), path),
+        SpanKind::Server,
+        parent,
+    ));
+    span.set_attribute("db.namespace", state.config.database_name.clone());
+
+    let response = in_span(span.clone(), next.run(request)).await;
+    span.set_attribute(
+        "http.response.status_code",
+        response.status().as_u16().to_string(),
+    );
+    let error = (!response.status().is_success()).then(|| response.status().to_string());
+    span.finish(error);
+
+    response
+}
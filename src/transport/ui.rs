@@ -0,0 +1,10 @@
+use axum::response::Html;
+
+/// The admin dashboard, bundled in the binary so that it can be served without any other file.
+const INDEX_HTML: &str = include_str!("ui/index.html");
+
+/// Serves the admin dashboard, which authenticates against the api with the key supplied by the
+/// user in the page itself.
+pub async fn ui() -> Html<&'static str> {
+    Html(INDEX_HTML)
+}